@@ -0,0 +1,29 @@
+use std::process::Command;
+
+fn main() {
+    // git hash of the build, written in the metadata of the outputs
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=NADI_GIS_GIT_HASH={hash}");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    // HEAD only changes on checkout, commits update the branch it
+    // points to (and the reflog)
+    let branch = std::fs::read_to_string("../.git/HEAD")
+        .ok()
+        .and_then(|h| Some(h.strip_prefix("ref:")?.trim().to_string()));
+    let watch = branch.map(|b| format!("../.git/{b}")).into_iter().chain([
+        "../.git/logs/HEAD".to_string(),
+        "../.git/packed-refs".to_string(),
+    ]);
+    for path in watch {
+        // missing files would rerun the build script every time
+        if std::path::Path::new(&path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+}
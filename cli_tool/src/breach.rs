@@ -0,0 +1,153 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use gdal::vector::Geometry;
+
+use crate::cliargs::CliAction;
+use crate::raster::{layer_geometries, line_samples, RasterGrid};
+use crate::utils::*;
+
+#[derive(Args)]
+pub struct CliArgs {
+    /// Distance along the stream on each side of the road to breach [default: 3 cells]
+    #[arg(short = 'D', long)]
+    distance: Option<f64>,
+    /// Extra depth to lower the breached cells by
+    #[arg(long, default_value = "0")]
+    depth: f64,
+    /// Band of the DEM
+    #[arg(short, long, default_value = "1")]
+    band: usize,
+    /// Output driver [default: based on file extension]
+    #[arg(short, long)]
+    driver: Option<String>,
+    /// Print progress
+    #[arg(short, long)]
+    verbose: bool,
+    /// DEM raster file
+    dem: PathBuf,
+    /// Streams vector file
+    #[arg(value_parser=parse_layer, value_name="STREAMS_FILE[::LAYER]")]
+    streams: (PathBuf, String),
+    /// Roads vector file
+    #[arg(value_parser=parse_layer, value_name="ROADS_FILE[::LAYER]")]
+    roads: (PathBuf, String),
+    /// Output DEM file
+    output: PathBuf,
+}
+
+impl CliAction for CliArgs {
+    fn run(mut self) -> Result<(), anyhow::Error> {
+        resolve_layer(&mut self.streams)?;
+        resolve_layer(&mut self.roads)?;
+        let mut dem = RasterGrid::open(&self.dem, self.band)?;
+        crate::manifest::input(&self.dem, None);
+        let streams = layer_geometries(&self.streams, dem.spatial_ref())?;
+        let roads = layer_geometries(&self.roads, dem.spatial_ref())?;
+        let step = dem.cell_size() / 2.0;
+        let distance = self.distance.unwrap_or(dem.cell_size() * 3.0);
+
+        // elevations at the ends of the breach are taken from the
+        // original DEM, so overlapping breaches don't affect each other
+        let original = dem.data.clone();
+        let orig = |dem: &RasterGrid, x: f64, y: f64| {
+            let (r, c) = dem.cell(x, y)?;
+            let v = original[r * dem.width + c];
+            (dem.nodata != Some(v) && !v.is_nan()).then_some(v)
+        };
+        let road_env: Vec<_> = roads.iter().map(|r| r.envelope()).collect();
+        let mut crossings = 0;
+        for stream in &streams {
+            let env = stream.envelope();
+            let points: Vec<(f64, f64)> = roads
+                .iter()
+                .zip(&road_env)
+                .filter(|(_, e)| {
+                    e.MinX <= env.MaxX
+                        && e.MaxX >= env.MinX
+                        && e.MinY <= env.MaxY
+                        && e.MaxY >= env.MinY
+                })
+                .filter_map(|(r, _)| stream.intersection(r))
+                .flat_map(|g| crossing_points(&g))
+                .collect();
+            if points.is_empty() {
+                continue;
+            }
+            let parts = line_samples(stream, step);
+            for (cx, cy) in points {
+                // part and the sample of the stream at the crossing
+                let Some((samples, k)) = parts
+                    .iter()
+                    .filter_map(|s| {
+                        let (k, d) = s
+                            .iter()
+                            .map(|p| (p.0 - cx).hypot(p.1 - cy))
+                            .enumerate()
+                            .min_by(|a, b| a.1.total_cmp(&b.1))?;
+                        Some((s, k, d))
+                    })
+                    .min_by(|a, b| a.2.total_cmp(&b.2))
+                    .map(|(s, k, _)| (s, k))
+                else {
+                    continue;
+                };
+                let center = samples[k].2;
+                let window: Vec<&(f64, f64, f64)> = samples
+                    .iter()
+                    .filter(|p| (p.2 - center).abs() <= distance)
+                    .collect();
+                let (first, last) = (window[0], window[window.len() - 1]);
+                let (za, zb) = match (orig(&dem, first.0, first.1), orig(&dem, last.0, last.1)) {
+                    (Some(a), Some(b)) => (a, b),
+                    (Some(a), None) => (a, a),
+                    (None, Some(b)) => (b, b),
+                    (None, None) => {
+                        crate::warning!("No elevation around the crossing at ({cx}, {cy})");
+                        continue;
+                    }
+                };
+                let span = last.2 - first.2;
+                for p in window {
+                    let Some((r, c)) = dem.cell(p.0, p.1) else {
+                        continue;
+                    };
+                    let frac = if span > 0.0 {
+                        (p.2 - first.2) / span
+                    } else {
+                        0.0
+                    };
+                    let z = za + (zb - za) * frac - self.depth;
+                    match dem.get(r, c) {
+                        Some(v) if v <= z => (),
+                        _ => dem.set(r, c, z),
+                    }
+                }
+                crossings += 1;
+            }
+        }
+        if self.verbose {
+            println!("Breached {crossings} road crossings");
+        }
+        dem.save(&self.output, &self.driver)?;
+        Ok(())
+    }
+}
+
+/// Points where the stream crosses the road from their intersection
+fn crossing_points(geom: &Geometry) -> Vec<(f64, f64)> {
+    if geom.geometry_count() > 0 {
+        return (0..geom.geometry_count())
+            .flat_map(|i| crossing_points(&geom.get_geometry(i)))
+            .collect();
+    }
+    let pts = geom.get_point_vec();
+    match pts.len() {
+        0 => vec![],
+        // the middle of the overlap if the road runs along the stream
+        n => vec![(
+            pts.iter().map(|p| p.0).sum::<f64>() / n as f64,
+            pts.iter().map(|p| p.1).sum::<f64>() / n as f64,
+        )],
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use clap::Args;
+
+use crate::cliargs::CliAction;
+use crate::raster::{layer_geometries, line_samples, RasterGrid};
+use crate::utils::*;
+
+#[derive(Args)]
+pub struct CliArgs {
+    /// Distance from the streams to smoothly lower the DEM in
+    #[arg(short = 'B', long, default_value = "0")]
+    buffer: f64,
+    /// Drop of the stream cells, that the buffer slopes down to
+    #[arg(short = 'S', long, default_value = "0")]
+    smooth: f64,
+    /// Extra drop of the stream cells after the smoothing
+    #[arg(short = 'D', long, default_value = "10")]
+    sharp: f64,
+    /// Band of the DEM
+    #[arg(short, long, default_value = "1")]
+    band: usize,
+    /// Output driver [default: based on file extension]
+    #[arg(short, long)]
+    driver: Option<String>,
+    /// Print progress
+    #[arg(short, long)]
+    verbose: bool,
+    /// DEM raster file
+    dem: PathBuf,
+    /// Streams vector file
+    #[arg(value_parser=parse_layer, value_name="STREAMS_FILE[::LAYER]")]
+    streams: (PathBuf, String),
+    /// Output DEM file
+    output: PathBuf,
+}
+
+impl CliAction for CliArgs {
+    fn run(mut self) -> Result<(), anyhow::Error> {
+        resolve_layer(&mut self.streams)?;
+        let mut dem = RasterGrid::open(&self.dem, self.band)?;
+        crate::manifest::input(&self.dem, None);
+        let streams = layer_geometries(&self.streams, dem.spatial_ref())?;
+        let step = dem.cell_size() / 2.0;
+
+        // stream cells with their smoothed elevation
+        let mut stream_cells: HashMap<(usize, usize), f64> = HashMap::new();
+        for (x, y, _) in streams.iter().flat_map(|s| line_samples(s, step)).flatten() {
+            let Some((r, c)) = dem.cell(x, y) else {
+                continue;
+            };
+            if let Some(z) = dem.get(r, c) {
+                stream_cells.insert((r, c), z - self.smooth);
+            }
+        }
+        if self.verbose {
+            println!("Burning {} stream cells", stream_cells.len());
+        }
+
+        // nearest stream cell of the cells in the buffer
+        let mut nearest: HashMap<(usize, usize), (f64, f64)> = HashMap::new();
+        if self.buffer > 0.0 {
+            let reach = (self.buffer / dem.cell_size()).ceil() as usize;
+            for (&(sr, sc), &zs) in &stream_cells {
+                let (sx, sy) = dem.center(sr, sc);
+                for r in sr.saturating_sub(reach)..(sr + reach + 1).min(dem.height) {
+                    for c in sc.saturating_sub(reach)..(sc + reach + 1).min(dem.width) {
+                        if stream_cells.contains_key(&(r, c)) {
+                            continue;
+                        }
+                        let (x, y) = dem.center(r, c);
+                        let d = (x - sx).hypot(y - sy);
+                        if d < self.buffer && nearest.get(&(r, c)).is_none_or(|n| d < n.0) {
+                            nearest.insert((r, c), (d, zs));
+                        }
+                    }
+                }
+            }
+        }
+        // buffer slopes linearly from the smoothed stream elevation
+        // to the original elevation at the buffer distance
+        for ((r, c), (d, zs)) in nearest {
+            let Some(z) = dem.get(r, c) else {
+                continue;
+            };
+            let zb = zs + (z - zs) * d / self.buffer;
+            if zb < z {
+                dem.set(r, c, zb);
+            }
+        }
+        for ((r, c), zs) in stream_cells {
+            dem.set(r, c, zs - self.sharp);
+        }
+        dem.save(&self.output, &self.driver)?;
+        Ok(())
+    }
+}
@@ -2,11 +2,11 @@ use std::collections::HashSet;
 use std::path::PathBuf;
 
 use crate::cliargs::CliAction;
+use crate::order::is_reversed;
 use crate::types::*;
 use crate::utils::*;
 use anyhow::Context;
 use clap::Args;
-use gdal::spatial_ref::SpatialRef;
 use gdal::vector::{
     Defn, Feature, FieldValue, Geometry, Layer, LayerAccess, LayerOptions, OGRFieldType,
 };
@@ -33,17 +33,30 @@ pub struct CliArgs {
     ///
     /// Algorithm assumes the geometry starts from upstream and goes
     /// to downstream. If it's reverse use this flag.
-    #[arg(short, long, action)]
+    #[arg(short, long, visible_alias = "flip", action)]
     reverse: bool,
+    /// Detect the direction of the streamlines and reverse them if needed
+    ///
+    /// Streams with more outlets than origins are taken as digitized
+    /// from downstream to upstream.
+    #[arg(long, action, conflicts_with = "reverse")]
+    auto_flip: bool,
+    #[command(flatten)]
+    subset: Subset,
     /// Streams vector file with streams network
-    #[arg(value_parser=parse_layer, value_name="STREAMS_FILE[:LAYER]")]
+    #[arg(value_parser=parse_layer, value_name="STREAMS_FILE[::LAYER]")]
     streams: (PathBuf, String),
 }
 
 impl CliAction for CliArgs {
-    fn run(self) -> Result<(), anyhow::Error> {
+    fn run(mut self) -> Result<(), anyhow::Error> {
+        resolve_layer(&mut self.streams)?;
         let streams_data = Dataset::open(&self.streams.0).unwrap();
         let mut streams_lyr = streams_data.layer_by_name(&self.streams.1).unwrap();
+        if self.auto_flip {
+            self.reverse = is_reversed(&mut streams_lyr)?;
+        }
+        self.subset.apply(&mut streams_lyr)?;
         let streams = get_geometries(&mut streams_lyr, &None)?;
         let nodes_count = streams_lyr.feature_count() as usize;
 
@@ -87,16 +100,23 @@ impl CliAction for CliArgs {
             .collect();
 
         if points > 0 {
-            eprintln!("Invalid Streams File: Point Geometry ({points})");
+            crate::warning!("Invalid Streams File: Point Geometry ({points})");
         }
         if outlets.len() != 1 {
-            eprintln!(
+            crate::warning!(
                 "Invalid Streams File: Need 1 Outlet (has {})",
                 outlets.len()
             );
+            // locations are only recorded for the --warnings file
+            for o in &outlets {
+                crate::manifest::diagnostic("WARN", "Outlet".into(), Some(o.coord2()), None);
+            }
         }
         if !branches.is_empty() {
-            eprintln!("Invalid Streams File: Branches ({})", branches.len());
+            crate::warning!("Invalid Streams File: Branches ({})", branches.len());
+            for b in &branches {
+                crate::manifest::diagnostic("WARN", "Branch".into(), Some(b.coord2()), None);
+            }
         }
 
         let categories = [
@@ -109,13 +129,13 @@ impl CliAction for CliArgs {
         if let Some((filename, lyr)) = &self.output {
             let mut out_data = gdal_update_or_create(&filename, &self.driver, self.overwrite)?;
             let lyr_name = lyr.as_deref().unwrap_or("nodes");
-            let sref = streams_lyr.spatial_ref();
+            let sref = OutputSrs::new(streams_lyr.spatial_ref().as_ref())?;
 
             let mut trans = false;
             // have to use trans flag here because of borrow rule;
             // uses transaction when it can to speed up the process.
             if let Ok(mut txn) = out_data.start_transaction() {
-                write_output(&categories, &mut txn, lyr_name, sref.as_ref(), self.verbose)?;
+                write_output(&categories, &mut txn, lyr_name, &sref, self.verbose)?;
                 txn.commit()?;
                 trans = true;
             };
@@ -125,7 +145,7 @@ impl CliAction for CliArgs {
                     &categories,
                     &mut out_data,
                     lyr_name,
-                    sref.as_ref(),
+                    &sref,
                     self.verbose,
                 )?;
             }
@@ -149,15 +169,16 @@ fn write_output(
     categories: &[(&str, HashSet<Point2D>)],
     ds: &mut Dataset,
     lyr: &str,
-    sref: Option<&SpatialRef>,
+    sref: &OutputSrs,
     verbose: bool,
 ) -> anyhow::Result<()> {
     let mut layer = ds.create_layer(LayerOptions {
         name: lyr,
-        srs: sref,
+        srs: sref.srs(),
         ty: gdal_sys::OGRwkbGeometryType::wkbPoint,
         ..Default::default()
     })?;
+    stamp_metadata(&mut layer)?;
     layer.create_defn_fields(&[("category", OGRFieldType::OFTString)])?;
 
     let total: usize = categories.iter().map(|(_, v)| v.len()).sum();
@@ -168,7 +189,7 @@ fn write_output(
             let mut geom = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbPoint)?;
             geom.add_point_2d(pt.coord2());
             let mut ft = Feature::new(&defn)?;
-            ft.set_geometry(geom)?;
+            ft.set_geometry(sref.geometry(geom)?)?;
             ft.set_field_string(0, cat)?;
             ft.create(&mut layer)?;
             if verbose {
@@ -0,0 +1,177 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use gdal::spatial_ref::{AxisMappingStrategy, CoordTransform, SpatialRef};
+use gdal::vector::{Defn, Feature, Geometry, LayerAccess, LayerOptions, OGRFieldType};
+use gdal::{Dataset, DriverManager};
+
+use crate::cliargs::CliAction;
+use crate::utils::*;
+
+#[derive(Args)]
+pub struct CliArgs {
+    /// Elevation interval between the contours
+    #[arg(short, long)]
+    interval: f64,
+    /// Elevation of a contour, other contours are offset from it
+    #[arg(short = 'B', long, default_value = "0")]
+    base: f64,
+    /// Band of the DEM
+    #[arg(short, long, default_value = "1")]
+    band: usize,
+    /// Basin polygons to clip the contours to
+    #[arg(long, value_parser=parse_layer, value_name="BASIN_FILE[::LAYER]")]
+    basin: Option<(PathBuf, String)>,
+    /// Output driver [default: based on file extension]
+    #[arg(short, long)]
+    driver: Option<String>,
+    /// Overwrite the output file if it exists
+    #[arg(short = 'O', long)]
+    overwrite: bool,
+    /// Print progress
+    #[arg(short, long)]
+    verbose: bool,
+    /// DEM raster file
+    dem: PathBuf,
+    /// Output file
+    #[arg(value_parser=parse_new_layer)]
+    output: (PathBuf, Option<String>),
+}
+
+impl CliAction for CliArgs {
+    fn run(mut self) -> Result<(), anyhow::Error> {
+        if let Some(l) = self.basin.as_mut() {
+            resolve_layer(l)?;
+        }
+        let dem = Dataset::open(&self.dem)?;
+        crate::manifest::input(&self.dem, None);
+        let band = dem.rasterband(self.band)?;
+        let mut sref = dem.spatial_ref().ok();
+        if let Some(s) = sref.as_mut() {
+            s.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+        }
+        let basin = match &self.basin {
+            Some(b) => Some(basin_geometry(b, sref.as_ref())?),
+            None => None,
+        };
+
+        // generate the contours in memory, and then clip them
+        let mut mem = DriverManager::get_driver_by_name("Memory")?.create_vector_only("")?;
+        let mut contours = mem.create_layer(LayerOptions {
+            name: "contours",
+            srs: sref.as_ref(),
+            ty: gdal_sys::OGRwkbGeometryType::wkbLineString,
+            ..Default::default()
+        })?;
+        contours.create_defn_fields(&[
+            ("id", OGRFieldType::OFTInteger),
+            ("elev", OGRFieldType::OFTReal),
+        ])?;
+        let nodata = band.no_data_value();
+        let err = unsafe {
+            gdal_sys::GDALContourGenerate(
+                band.c_rasterband(),
+                self.interval,
+                self.base,
+                0,
+                std::ptr::null_mut(),
+                nodata.is_some() as i32,
+                nodata.unwrap_or_default(),
+                contours.c_layer(),
+                0,
+                1,
+                None,
+                std::ptr::null_mut(),
+            )
+        };
+        if err != gdal_sys::CPLErr::CE_None {
+            anyhow::bail!("Failed to generate contours from {:?}", self.dem);
+        }
+        let lines: Vec<(f64, Geometry)> = contours
+            .features()
+            .filter_map(|f| {
+                let elev = f.field_as_double(1).ok().flatten()?;
+                let geom = f.geometry()?;
+                match &basin {
+                    Some(b) => geom.intersection(b).map(|g| (elev, g)),
+                    None => Some((elev, geom.clone())),
+                }
+            })
+            .filter(|(_, g)| !g.is_empty())
+            .collect();
+        if self.verbose {
+            println!("Writing {} contours", lines.len());
+        }
+
+        let out_srs = OutputSrs::new(sref.as_ref())?;
+        let mut out_data = gdal_update_or_create(&self.output.0, &self.driver, self.overwrite)?;
+        let lyr_name = self.output.1.as_deref().unwrap_or("contours");
+        let mut trans = false;
+        // have to use trans flag here because of borrow rule;
+        // uses transaction when it can to speed up the process.
+        if let Ok(mut txn) = out_data.start_transaction() {
+            write_contours(&lines, &mut txn, lyr_name, &out_srs)?;
+            txn.commit()?;
+            trans = true;
+        };
+        if !trans {
+            write_contours(&lines, &mut out_data, lyr_name, &out_srs)?;
+        }
+        Ok(())
+    }
+}
+
+/// Union of the basin polygons in the spatial reference of the DEM
+fn basin_geometry(
+    basin: &(PathBuf, String),
+    sref: Option<&SpatialRef>,
+) -> anyhow::Result<Geometry> {
+    let data = Dataset::open(&basin.0)?;
+    let mut lyr = data.layer_by_name(&basin.1)?;
+    let trans = match (lyr.spatial_ref(), sref) {
+        (Some(mut src), Some(dst)) => {
+            src.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+            Some(CoordTransform::new(&src, dst)?)
+        }
+        _ => None,
+    };
+    let mut union: Option<Geometry> = None;
+    for f in lyr.features() {
+        let Some(geom) = f.geometry() else {
+            continue;
+        };
+        let geom = match &trans {
+            Some(t) => geom.transform(t)?,
+            None => geom.clone(),
+        };
+        union = match union {
+            Some(u) => u.union(&geom),
+            None => Some(geom),
+        };
+    }
+    union.ok_or_else(|| anyhow::Error::msg("Basin layer doesn't have any polygons"))
+}
+
+fn write_contours(
+    lines: &[(f64, Geometry)],
+    ds: &mut Dataset,
+    lyr: &str,
+    sref: &OutputSrs,
+) -> anyhow::Result<()> {
+    let mut layer = ds.create_layer(LayerOptions {
+        name: lyr,
+        srs: sref.srs(),
+        ty: gdal_sys::OGRwkbGeometryType::wkbMultiLineString,
+        ..Default::default()
+    })?;
+    stamp_metadata(&mut layer)?;
+    layer.create_defn_fields(&[("elev", OGRFieldType::OFTReal)])?;
+    let defn = Defn::from_layer(&layer);
+    for (elev, geom) in lines {
+        let mut ft = Feature::new(&defn)?;
+        ft.set_geometry(sref.geometry(geom.clone())?)?;
+        ft.set_field_double(0, *elev)?;
+        ft.create(&mut layer)?;
+    }
+    Ok(())
+}
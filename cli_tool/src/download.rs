@@ -0,0 +1,91 @@
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+use gdal::vector::LayerAccess;
+use gdal::Dataset;
+
+use crate::utils::*;
+
+/// Extensions of the GIS files to look for inside the archives
+const GIS_EXTENSIONS: [&str; 8] = ["shp", "gpkg", "geojson", "json", "gdb", "kml", "tab", "csv"];
+
+/// Check if the downloaded contents is a zip archive
+pub fn is_zip(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"PK\x03\x04")
+}
+
+/// Save the downloaded contents to the file
+///
+/// If the contents is a zip archive, the GIS files inside it are
+/// listed along with their layers so they can be used as inputs to
+/// other commands. If `extract` is true, the archive is extracted to
+/// a directory with the same name as the file (without extension).
+pub fn save_download(bytes: &[u8], filepath: &Path, extract: bool) -> anyhow::Result<()> {
+    let mut file = create_writer(filepath)?;
+    file.write_all(bytes)?;
+    // make sure the file is closed (uploaded for cloud) before reading it back
+    file.finish()?;
+    if !is_zip(bytes) {
+        return Ok(());
+    }
+    let vsi_path = PathBuf::from(format!("/vsizip/{}", filepath.to_string_lossy()));
+    let entries = gdal::vsi::read_dir(&vsi_path, true)?;
+    let gis_files: Vec<&PathBuf> = entries
+        .iter()
+        .filter(|e| {
+            e.extension()
+                .map(|x| GIS_EXTENSIONS.contains(&x.to_string_lossy().to_lowercase().as_str()))
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let root = if extract {
+        let dir = filepath.with_extension("");
+        let mut count = 0;
+        for entry in &entries {
+            if entry.to_string_lossy().ends_with('/') {
+                continue;
+            }
+            // entries with `..` or absolute paths would be written
+            // outside the directory
+            if !entry
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+            {
+                crate::warning!("Skipping the archive entry {entry:?} outside the directory");
+                continue;
+            }
+            let contents = read_vsi_file(vsi_path.join(entry))?;
+            let out = dir.join(entry);
+            if let Some(p) = out.parent() {
+                if !is_vsi_path(p) {
+                    std::fs::create_dir_all(p)?;
+                }
+            }
+            let mut file = create_writer(&out)?;
+            file.write_all(&contents)?;
+            file.finish()?;
+            count += 1;
+        }
+        println!("Extracted {count} files to {dir:?}");
+        dir
+    } else {
+        vsi_path
+    };
+
+    if gis_files.is_empty() {
+        crate::warning!("No GIS files found in the archive {filepath:?}");
+        return Ok(());
+    }
+    println!("GIS files in the archive:");
+    for gf in gis_files {
+        let path = root.join(gf);
+        match Dataset::open(&path) {
+            Ok(data) => data
+                .layers()
+                .for_each(|l| println!("  {}::{}", path.to_string_lossy(), l.name())),
+            Err(e) => crate::warning!("  {} (Cannot open: {e})", path.to_string_lossy()),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use gdal::vector::{Layer, LayerAccess};
+
+/// FNV-1a hash, stable across runs and platforms unlike the std hasher
+pub struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Fnv {
+    pub fn update(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn hex(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+/// Content hash of the layer to detect changes between runs
+///
+/// Combines the feature count, extent, schema (field names and
+/// types) and the geometries of all the features in order.
+pub fn layer_fingerprint(lyr: &mut Layer) -> anyhow::Result<String> {
+    let mut hash = Fnv::default();
+    hash.update(&lyr.feature_count().to_le_bytes());
+    if let Ok(env) = lyr.get_extent() {
+        for v in [env.MinX, env.MinY, env.MaxX, env.MaxY] {
+            hash.update(&v.to_le_bytes());
+        }
+    }
+    for f in lyr.defn().fields() {
+        hash.update(f.name().as_bytes());
+        hash.update(&f.field_type().to_le_bytes());
+    }
+    for f in lyr.features() {
+        match f.geometry() {
+            Some(g) => hash.update(&g.wkb()?),
+            None => hash.update(&[0]),
+        }
+    }
+    Ok(hash.hex())
+}
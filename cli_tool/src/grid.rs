@@ -0,0 +1,179 @@
+use clap::Args;
+use gdal::vector::{Envelope, Geometry};
+
+/// Regular (MODFLOW style) model grid
+///
+/// Rows are numbered from the top and columns from the left, the
+/// grid is rotated counter clockwise around its lower left corner.
+/// The grid coordinates must be in the same spatial reference as the
+/// GIS files it's used with.
+#[derive(Args, Clone, Debug)]
+pub struct Grid {
+    /// Lower left corner of the grid
+    #[arg(long, value_parser=parse_pair, value_name="X,Y", allow_hyphen_values=true)]
+    pub origin: (f64, f64),
+    /// Cell size along the rows and columns
+    #[arg(long, value_parser=parse_pair, value_name="DX,DY")]
+    pub cell: (f64, f64),
+    /// Number of rows and columns
+    #[arg(long, value_parser=parse_shape, value_name="NROW,NCOL")]
+    pub shape: (usize, usize),
+    /// Counter clockwise rotation of the grid (degrees)
+    #[arg(long, default_value = "0.0", allow_hyphen_values = true)]
+    pub rotation: f64,
+}
+
+fn parse_pair(arg: &str) -> Result<(f64, f64), anyhow::Error> {
+    let (a, b) = arg
+        .split_once(',')
+        .ok_or_else(|| anyhow::Error::msg("Give two comma separated numbers"))?;
+    Ok((a.trim().parse()?, b.trim().parse()?))
+}
+
+fn parse_shape(arg: &str) -> Result<(usize, usize), anyhow::Error> {
+    let (a, b) = arg
+        .split_once(',')
+        .ok_or_else(|| anyhow::Error::msg("Give number of rows and columns as NROW,NCOL"))?;
+    Ok((a.trim().parse()?, b.trim().parse()?))
+}
+
+impl Grid {
+    /// Coordinates in the grid's frame, relative to the origin
+    pub fn to_local(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let (dx, dy) = (x - self.origin.0, y - self.origin.1);
+        (dx * cos + dy * sin, -dx * sin + dy * cos)
+    }
+
+    pub fn to_world(&self, (u, v): (f64, f64)) -> (f64, f64) {
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        (
+            self.origin.0 + u * cos - v * sin,
+            self.origin.1 + u * sin + v * cos,
+        )
+    }
+
+    /// (row, col) of the cell containing the local coordinates
+    fn local_cell(&self, u: f64, v: f64) -> Option<(usize, usize)> {
+        let col = (u / self.cell.0).floor();
+        let row = (v / self.cell.1).floor();
+        if col < 0.0 || row < 0.0 {
+            return None;
+        }
+        let (col, row) = (col as usize, row as usize);
+        if col >= self.shape.1 || row >= self.shape.0 {
+            return None;
+        }
+        Some((self.shape.0 - 1 - row, col))
+    }
+
+    /// (row, col) of the cell containing the point
+    pub fn cell(&self, pt: (f64, f64)) -> Option<(usize, usize)> {
+        let (u, v) = self.to_local(pt);
+        self.local_cell(u, v)
+    }
+
+    /// Corners of the cell, closed ring in counter clockwise order
+    pub fn cell_ring(&self, row: usize, col: usize) -> Vec<(f64, f64)> {
+        let (dx, dy) = self.cell;
+        let (u0, v0) = (col as f64 * dx, (self.shape.0 - 1 - row) as f64 * dy);
+        [
+            (u0, v0),
+            (u0 + dx, v0),
+            (u0 + dx, v0 + dy),
+            (u0, v0 + dy),
+            (u0, v0),
+        ]
+        .into_iter()
+        .map(|p| self.to_world(p))
+        .collect()
+    }
+
+    pub fn cell_geometry(&self, row: usize, col: usize) -> anyhow::Result<Geometry> {
+        let ring: Vec<String> = self
+            .cell_ring(row, col)
+            .iter()
+            .map(|(x, y)| format!("{x} {y}"))
+            .collect();
+        Ok(Geometry::from_wkt(&format!(
+            "POLYGON (({}))",
+            ring.join(", ")
+        ))?)
+    }
+
+    /// Cells that can intersect with the envelope
+    pub fn cells_within(&self, env: &Envelope) -> Vec<(usize, usize)> {
+        let corners = [
+            (env.MinX, env.MinY),
+            (env.MaxX, env.MinY),
+            (env.MaxX, env.MaxY),
+            (env.MinX, env.MaxY),
+        ]
+        .map(|p| self.to_local(p));
+        let range = |vals: [f64; 4], size: f64, n: usize| {
+            let lo = vals.iter().fold(f64::INFINITY, |a, &b| a.min(b)) / size;
+            let hi = vals.iter().fold(f64::NEG_INFINITY, |a, &b| a.max(b)) / size;
+            let lo = lo.floor().max(0.0) as usize;
+            let hi = (hi.floor().max(-1.0) + 1.0).min(n as f64) as usize;
+            lo..hi
+        };
+        let cols = range(corners.map(|c| c.0), self.cell.0, self.shape.1);
+        let rows = range(corners.map(|c| c.1), self.cell.1, self.shape.0);
+        rows.flat_map(|r| cols.clone().map(move |c| (self.shape.0 - 1 - r, c)))
+            .collect()
+    }
+
+    /// Split the line at the cell boundaries
+    ///
+    /// Returns the cells the line passes through, in order, with the
+    /// length of the line inside them. Parts outside the grid are
+    /// skipped.
+    pub fn split_line(&self, pts: &[(f64, f64)]) -> Vec<((usize, usize), f64)> {
+        let mut reaches: Vec<((usize, usize), f64)> = Vec::new();
+        for w in pts.windows(2) {
+            let (a, b) = (self.to_local(w[0]), self.to_local(w[1]));
+            let (du, dv) = (b.0 - a.0, b.1 - a.1);
+            let len = (du * du + dv * dv).sqrt();
+            if len == 0.0 {
+                continue;
+            }
+            // fractions of the segment where it crosses grid lines
+            let mut ts = vec![0.0, 1.0];
+            crossings(a.0, du, self.cell.0, &mut ts);
+            crossings(a.1, dv, self.cell.1, &mut ts);
+            ts.sort_by(|x, y| x.total_cmp(y));
+            for t in ts.windows(2) {
+                if t[1] <= t[0] {
+                    continue;
+                }
+                let mid = (t[0] + t[1]) / 2.0;
+                let Some(cell) = self.local_cell(a.0 + du * mid, a.1 + dv * mid) else {
+                    continue;
+                };
+                let l = (t[1] - t[0]) * len;
+                match reaches.last_mut() {
+                    Some((c, rl)) if *c == cell => *rl += l,
+                    _ => reaches.push((cell, l)),
+                }
+            }
+        }
+        reaches
+    }
+}
+
+fn crossings(start: f64, delta: f64, size: f64, ts: &mut Vec<f64>) {
+    if delta == 0.0 {
+        return;
+    }
+    let end = start + delta;
+    let (lo, hi) = if start < end {
+        (start, end)
+    } else {
+        (end, start)
+    };
+    let mut k = (lo / size).ceil();
+    while k * size < hi {
+        ts.push((k * size - start) / delta);
+        k += 1.0;
+    }
+}
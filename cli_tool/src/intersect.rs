@@ -0,0 +1,162 @@
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::Args;
+use gdal::vector::{Defn, Feature, LayerAccess, LayerOptions, OGRFieldType};
+use gdal::Dataset;
+
+use crate::cliargs::CliAction;
+use crate::grid::Grid;
+use crate::utils::*;
+
+#[derive(Args)]
+pub struct CliArgs {
+    /// Field to use as the feature name [default: feature index]
+    #[arg(short, long)]
+    field: Option<String>,
+    /// Output driver [default: based on file extension]
+    #[arg(short, long)]
+    driver: Option<String>,
+    /// Overwrite the output file if it exists
+    #[arg(short = 'O', long)]
+    overwrite: bool,
+    /// Print progress
+    #[arg(short, long)]
+    verbose: bool,
+    #[command(flatten)]
+    grid: Grid,
+    /// Vector file with lines or polygons
+    #[arg(value_parser=parse_layer, value_name="GIS_FILE[::LAYER]")]
+    input: (PathBuf, String),
+    /// Output file, CSV table or GIS file with cell polygons
+    #[arg(value_parser=parse_new_layer)]
+    output: (PathBuf, Option<String>),
+}
+
+/// Part of a feature inside a grid cell
+struct CellPart {
+    feature: String,
+    row: usize,
+    col: usize,
+    length: f64,
+    area: f64,
+}
+
+impl CliAction for CliArgs {
+    fn run(mut self) -> Result<(), anyhow::Error> {
+        resolve_layer(&mut self.input)?;
+        let data = Dataset::open(&self.input.0)?;
+        let mut lyr = data.layer_by_name(&self.input.1)?;
+        let features = get_geometries(&mut lyr, &self.field)?;
+        let total = features.len();
+        let mut parts = Vec::new();
+        for (i, (name, geom)) in features.iter().enumerate() {
+            // 1 for lines and 2 for polygons
+            let dim = unsafe { gdal_sys::OGR_G_GetDimension(geom.c_geometry()) };
+            for (row, col) in self.grid.cells_within(&geom.envelope()) {
+                let cell = self.grid.cell_geometry(row, col)?;
+                let Some(inter) = geom.intersection(&cell) else {
+                    continue;
+                };
+                let (length, area) = match dim {
+                    1 => (inter.length(), 0.0),
+                    2 => (0.0, inter.area()),
+                    _ => continue,
+                };
+                if length > 0.0 || area > 0.0 {
+                    parts.push(CellPart {
+                        feature: name.clone(),
+                        row,
+                        col,
+                        length,
+                        area,
+                    });
+                }
+            }
+            if self.verbose {
+                print!(
+                    "\rIntersecting Features: {}% ({} of {})",
+                    (i + 1) * 100 / total,
+                    i + 1,
+                    total
+                );
+            }
+        }
+        if self.verbose {
+            println!();
+        }
+
+        let is_csv = self
+            .output
+            .0
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+        if is_csv {
+            let mut writer = BufWriter::new(create_writer(&self.output.0)?);
+            writeln!(writer, "feature,row,col,length,area")?;
+            for p in &parts {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{}",
+                    csv_quote(&p.feature),
+                    p.row + 1,
+                    p.col + 1,
+                    p.length,
+                    p.area
+                )?;
+            }
+            return finish_writer(writer);
+        }
+
+        let mut out_data = gdal_update_or_create(&self.output.0, &self.driver, self.overwrite)?;
+        let lyr_name = self.output.1.as_deref().unwrap_or("grid-cells");
+        let sref = OutputSrs::new(lyr.spatial_ref().as_ref())?;
+        let mut trans = false;
+        // have to use trans flag here because of borrow rule;
+        // uses transaction when it can to speed up the process.
+        if let Ok(mut txn) = out_data.start_transaction() {
+            write_cells(&parts, &self.grid, &mut txn, lyr_name, &sref)?;
+            txn.commit()?;
+            trans = true;
+        };
+        if !trans {
+            write_cells(&parts, &self.grid, &mut out_data, lyr_name, &sref)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_cells(
+    parts: &[CellPart],
+    grid: &Grid,
+    ds: &mut Dataset,
+    lyr: &str,
+    sref: &OutputSrs,
+) -> anyhow::Result<()> {
+    let mut layer = ds.create_layer(LayerOptions {
+        name: lyr,
+        srs: sref.srs(),
+        ty: gdal_sys::OGRwkbGeometryType::wkbPolygon,
+        ..Default::default()
+    })?;
+    stamp_metadata(&mut layer)?;
+    layer.create_defn_fields(&[
+        ("feature", OGRFieldType::OFTString),
+        ("row", OGRFieldType::OFTInteger),
+        ("col", OGRFieldType::OFTInteger),
+        ("length", OGRFieldType::OFTReal),
+        ("area", OGRFieldType::OFTReal),
+    ])?;
+    let defn = Defn::from_layer(&layer);
+    for p in parts {
+        let mut ft = Feature::new(&defn)?;
+        ft.set_geometry(sref.geometry(grid.cell_geometry(p.row, p.col)?)?)?;
+        ft.set_field_string(0, &p.feature)?;
+        ft.set_field_integer(1, p.row as i32 + 1)?;
+        ft.set_field_integer(2, p.col as i32 + 1)?;
+        ft.set_field_double(3, p.length)?;
+        ft.set_field_double(4, p.area)?;
+        ft.create(&mut layer)?;
+    }
+    Ok(())
+}
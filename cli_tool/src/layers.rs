@@ -5,6 +5,7 @@ use gdal::vector::{LayerAccess, OGRFieldType};
 use gdal::Dataset;
 
 use crate::cliargs::CliAction;
+use crate::fingerprint::layer_fingerprint;
 
 #[derive(Args)]
 pub struct CliArgs {
@@ -14,6 +15,13 @@ pub struct CliArgs {
     /// Show attribute columns
     #[arg(short, long)]
     attributes: bool,
+    /// Show the content hash of the layers to detect changes
+    ///
+    /// The hash is computed from the feature count, extent, schema
+    /// and geometries, so pipelines can skip the stages whose inputs
+    /// haven't changed since the last run.
+    #[arg(short = 'F', long)]
+    fingerprint: bool,
     /// GIS file with points of interest
     #[arg(value_name = "GIS_FILE")]
     file: PathBuf,
@@ -22,11 +30,14 @@ pub struct CliArgs {
 impl CliAction for CliArgs {
     fn run(self) -> Result<(), anyhow::Error> {
         let file_data = Dataset::open(&self.file).unwrap();
-        for lyr in file_data.layers() {
+        for mut lyr in file_data.layers() {
             println!("{}", lyr.name());
             if self.features {
                 println!("  - Features: {}", lyr.feature_count());
             }
+            if self.fingerprint {
+                println!("  - Fingerprint: {}", layer_fingerprint(&mut lyr)?);
+            }
             if self.attributes {
                 println!("  - Fields:");
                 lyr.defn().fields().for_each(|f| {
@@ -1,9 +1,16 @@
 #![allow(unused_imports)]
 #![allow(dead_code)]
 use crate::cliargs::CliAction;
+use crate::utils::{AmbiguousLayer, EXIT_AMBIGUOUS_LAYER, QUIET_ENV};
 use clap::{Parser, Subcommand};
 
 mod cliargs;
+mod download;
+mod fingerprint;
+mod grid;
+mod manifest;
+mod raster;
+mod schema;
 mod types;
 mod utils;
 
@@ -66,21 +73,212 @@ subcommands! {
     /// main branch will get the upstream stream order, other branches
     /// will start from 0.
     order Order,
+    /// Extract the segments of a named river from the streams
+    ///
+    /// The segments with the river name (e.g. GNIS_NAME field) are
+    /// saved with their tributary level, optionally including the
+    /// tributaries upstream up to the given level.
+    river River,
     /// Find the network information from streams file between points
     network Network,
+    /// Export MODFLOW 6 SFR package stub from the ordered streams
+    ///
+    /// Streams are split into reaches at the cells of the model grid,
+    /// and the reaches are connected following the stream
+    /// network. Reach parameters other than the length are
+    /// placeholders to be filled in.
+    sfr Sfr,
+    /// Intersect lines or polygons with a regular model grid
+    ///
+    /// Reports the length (lines) or area (polygons) of each feature
+    /// inside the grid cells, as a CSV table or a layer of the cell
+    /// polygons. Row and column numbers start from 1 at the top left.
+    intersect Intersect,
+    /// Rasterize a vector layer aligned to a template raster
+    ///
+    /// Burns 1 (or the values of a field like the stream order) on
+    /// the cells covered by the geometries, useful to make streams or
+    /// basin masks for raster analyses.
+    rasterize Rasterize,
+    /// Convert the classes of a raster into polygons
+    ///
+    /// Connected cells with the same value become a polygon with the
+    /// value as a field, e.g. delineated watersheds or land cover.
+    polygonize Polygonize,
+    /// Generate elevation contours from a DEM
+    ///
+    /// Contours are generated at the given interval, and optionally
+    /// clipped to the basin polygons.
+    contours Contours,
+    /// Breach the road embankments in a DEM where streams cross them
+    ///
+    /// The DEM cells along the stream near each road crossing are
+    /// lowered to the elevations interpolated from the two sides of
+    /// the road, so the flow paths derived from the DEM continue
+    /// through the culverts instead of stopping at the roads.
+    breach Breach,
+    /// Burn the streams into a DEM before the flow routing
+    ///
+    /// AGREE style burning: the stream cells are lowered by the
+    /// smooth drop and the DEM slopes down to them within the buffer
+    /// distance, then the stream cells are lowered again by the sharp
+    /// drop, so the flow paths derived from the DEM follow the
+    /// known streams.
+    burn Burn,
+    /// Build GDAL virtual datasets (VRT) from the raster tiles or CSV
+    ///
+    /// A directory of raster tiles (e.g. DEM tiles) is mosaicked into
+    /// one VRT raster, and a CSV file is defined as a points layer from
+    /// its coordinate columns, so they can be used as single inputs to
+    /// the other commands.
+    vrt Vrt,
+    /// Bundle the layers of a study into one GeoPackage
+    ///
+    /// The streams, points, snapped points, network, nodes and basins
+    /// are copied with standardized layer names, along with the QGIS
+    /// styles and the run manifests, as a single deliverable file.
+    package Package,
+    /// Serve snapping and tracing on the streams over HTTP/JSON
+    ///
+    /// The streams are loaded once, and the endpoints `/snap`,
+    /// `/downstream` and `/upstream` (GET with x and y query
+    /// parameters) and `/network` (POST with `name,x,y` lines) can be
+    /// queried by interactive applications without re-reading the
+    /// streams file.
+    serve Serve,
+    /// Watch a directory and run the network command on new points files
+    ///
+    /// Points files dropped in the directory are processed once they
+    /// stop changing, with the network command options given after
+    /// `--` as the recipe.
+    watch Watch,
 }
 
 #[derive(Parser)]
 struct Cli {
-    /// Don't print the stderr outputs
-    #[arg(short, long, action)]
+    /// Don't print the warnings on stderr
+    ///
+    /// Errors are still printed. Setting the NADI_GIS_QUIET
+    /// environment variable has the same effect, and also silences
+    /// the warnings from the nadi plugin functions.
+    #[arg(short, long, action, global = true)]
     quiet: bool,
+    /// GDAL configuration options as KEY=VALUE (can be repeated)
+    ///
+    /// Use it to pass the credentials and settings for the virtual
+    /// file systems, e.g. `--config AWS_PROFILE=work` for outputs
+    /// in `/vsis3/` or `--config GOOGLE_APPLICATION_CREDENTIALS=key.json`
+    /// for `/vsigs/`. Environment variables are used when not given.
+    #[arg(short, long, value_parser=parse_config, global=true)]
+    config: Vec<(String, String)>,
+    /// Write the output geometries in this spatial reference (e.g. EPSG:2264)
+    ///
+    /// Geometries are reprojected from the spatial reference of the
+    /// inputs before writing the vector outputs.
+    #[arg(long = "t_srs", global = true, value_name = "SRS")]
+    t_srs: Option<String>,
+    /// Max angle step (degrees) to linearize the curve geometries
+    ///
+    /// Curve geometries (CompoundCurve, MultiCurve, ...) in the
+    /// streams are converted to lines when read; 0 uses the GDAL
+    /// default (OGR_ARC_STEPSIZE config, 4 degrees if not set).
+    #[arg(long, global = true, default_value = "0", value_name = "DEGREES")]
+    curve_step: f64,
+    /// Numbers in the text fields use comma as the decimal separator
+    ///
+    /// For the CSV files from the locales like `12,5` for 12.5; the
+    /// dots are then taken as the thousands separators. Used for the
+    /// lon/lat columns of the points and the numeric fields.
+    #[arg(long, global = true, action)]
+    decimal_comma: bool,
+    /// Encoding of the text fields in the shapefiles (e.g. ISO-8859-1, CP932)
+    ///
+    /// Sets the GDAL `SHAPE_ENCODING` option, so the DBF fields are
+    /// recoded from it to UTF-8 when read, and written in it, for the
+    /// shapefiles without the right codepage. Use an empty value to
+    /// disable the recoding.
+    #[arg(long, global = true, value_name = "ENCODING")]
+    encoding: Option<String>,
+    /// Write a JSON record of the run for provenance
+    ///
+    /// Records the arguments, input files with their layers and
+    /// fingerprints, the output files, warnings and the timing of
+    /// the run; written even if the command fails.
+    #[arg(long, global = true, value_name = "JSON_FILE")]
+    manifest: Option<std::path::PathBuf>,
+    /// Write the warnings and errors of the run to a CSV/JSON file
+    ///
+    /// Each row has the level, message, and the coordinates and FID
+    /// of the feature when the warning is about one (e.g. outlets,
+    /// points not snapped); GDAL warnings are included. JSON is used
+    /// for the files with `.json` extension.
+    #[arg(long, global = true, value_name = "FILE")]
+    warnings: Option<std::path::PathBuf>,
     /// Command to run
     #[command(subcommand)]
     action: Action,
 }
 
+fn parse_config(arg: &str) -> Result<(String, String), anyhow::Error> {
+    let (key, val) = arg
+        .split_once('=')
+        .ok_or(anyhow::Error::msg("Config should be in KEY=VALUE format"))?;
+    Ok((key.to_string(), val.to_string()))
+}
+
 fn main() -> anyhow::Result<()> {
+    // before parsing, so the warnings while parsing are silenced too
+    if std::env::var_os(QUIET_ENV).is_some() {
+        utils::set_quiet(true);
+    }
     let args = Cli::parse();
-    args.action.run()
+    if args.quiet {
+        utils::set_quiet(true);
+    }
+    if utils::is_quiet() || args.warnings.is_some() {
+        // GDAL warnings are printed by its own error handler
+        gdal::config::set_error_handler(|err, _, msg| {
+            use gdal::errors::CplErrType;
+            match err {
+                CplErrType::Failure | CplErrType::Fatal => {
+                    eprintln!("ERROR: {msg}");
+                    manifest::diagnostic("ERROR", format!("GDAL: {msg}"), None, None);
+                }
+                CplErrType::Warning => {
+                    if !utils::is_quiet() {
+                        eprintln!("Warning: {msg}");
+                    }
+                    manifest::diagnostic("WARN", format!("GDAL: {msg}"), None, None);
+                }
+                _ => (),
+            }
+        });
+    }
+    for (key, val) in &args.config {
+        gdal::config::set_config_option(key, val)?;
+    }
+    utils::set_target_srs(args.t_srs.clone());
+    utils::set_curve_step(args.curve_step);
+    utils::set_decimal_comma(args.decimal_comma);
+    if let Some(enc) = &args.encoding {
+        gdal::config::set_config_option("SHAPE_ENCODING", enc)?;
+    }
+    let started = std::time::SystemTime::now();
+    let timer = std::time::Instant::now();
+    let result = args.action.run();
+    if let Some(path) = args.warnings {
+        manifest::write_warnings(&path, &result)?;
+    }
+    if let Some(path) = args.manifest {
+        manifest::write(&path, started, timer.elapsed(), &result)?;
+    }
+    if let Some(e) = result
+        .as_ref()
+        .err()
+        .and_then(|e| e.downcast_ref::<AmbiguousLayer>())
+    {
+        eprintln!("{e}");
+        std::process::exit(EXIT_AMBIGUOUS_LAYER);
+    }
+    result
 }
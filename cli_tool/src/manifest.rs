@@ -0,0 +1,241 @@
+//! Record of the inputs, outputs and warnings of a run
+//!
+//! The commands register the files they read and write here, and
+//! `--manifest` writes them as JSON along with the arguments and
+//! timing, so the workflow systems can track the provenance of the
+//! generated files. The warnings can also be written separately as
+//! a CSV/JSON table with their locations using `--warnings`.
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use gdal::Dataset;
+
+use crate::fingerprint::{layer_fingerprint, Fnv};
+use crate::utils::{create_writer, csv_quote, read_vsi_file, run_args};
+
+struct Manifest {
+    inputs: Vec<(PathBuf, Option<String>)>,
+    outputs: Vec<PathBuf>,
+    warnings: Vec<Diagnostic>,
+}
+
+/// Warning/error of the run with the location it's about
+struct Diagnostic {
+    level: &'static str,
+    message: String,
+    location: Option<(f64, f64)>,
+    fid: Option<u64>,
+}
+
+static MANIFEST: Mutex<Manifest> = Mutex::new(Manifest {
+    inputs: Vec::new(),
+    outputs: Vec::new(),
+    warnings: Vec::new(),
+});
+
+/// Register an input file, with the layer for vector files
+pub fn input<P: AsRef<Path>>(path: P, layer: Option<&str>) {
+    let entry = (path.as_ref().to_path_buf(), layer.map(String::from));
+    if let Ok(mut m) = MANIFEST.lock() {
+        if !m.inputs.contains(&entry) {
+            m.inputs.push(entry);
+        }
+    }
+}
+
+/// Register an output file
+pub fn output<P: AsRef<Path>>(path: P) {
+    let path = path.as_ref().to_path_buf();
+    if let Ok(mut m) = MANIFEST.lock() {
+        if !m.outputs.contains(&path) {
+            m.outputs.push(path);
+        }
+    }
+}
+
+pub fn warning(msg: String) {
+    diagnostic("WARN", msg, None, None);
+}
+
+/// Register a diagnostic with the location and FID of the feature
+pub fn diagnostic(
+    level: &'static str,
+    message: String,
+    location: Option<(f64, f64)>,
+    fid: Option<u64>,
+) {
+    if let Ok(mut m) = MANIFEST.lock() {
+        m.warnings.push(Diagnostic {
+            level,
+            message: message.trim().to_string(),
+            location,
+            fid,
+        });
+    }
+}
+
+/// Fingerprint of the layer, or of the file contents for rasters
+fn fingerprint(path: &Path, layer: &Option<String>) -> anyhow::Result<String> {
+    match layer {
+        Some(l) => {
+            let data = Dataset::open(path)?;
+            let mut lyr = data.layer_by_name(l)?;
+            layer_fingerprint(&mut lyr)
+        }
+        None => {
+            let mut hash = Fnv::default();
+            hash.update(&read_vsi_file(path)?);
+            Ok(hash.hex())
+        }
+    }
+}
+
+pub fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_path(p: &Path) -> String {
+    json_str(&p.to_string_lossy())
+}
+
+/// Write the manifest of the run as JSON
+pub fn write(
+    path: &Path,
+    started: SystemTime,
+    elapsed: Duration,
+    result: &anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let m = MANIFEST
+        .lock()
+        .map_err(|_| anyhow::Error::msg("Manifest is not available"))?;
+    let args: Vec<String> = run_args().iter().map(|a| json_str(a)).collect();
+    let inputs: Vec<String> = m
+        .inputs
+        .iter()
+        .map(|(p, l)| {
+            let layer = l.as_deref().map(json_str).unwrap_or("null".into());
+            let fp = fingerprint(p, l)
+                .map(|f| json_str(&f))
+                .unwrap_or("null".into());
+            format!(
+                "    {{\"path\": {}, \"layer\": {layer}, \"fingerprint\": {fp}}}",
+                json_path(p)
+            )
+        })
+        .collect();
+    let outputs: Vec<String> = m
+        .outputs
+        .iter()
+        .map(|p| {
+            format!(
+                "    {{\"path\": {}, \"exists\": {}}}",
+                json_path(p),
+                crate::utils::path_exists(p)
+            )
+        })
+        .collect();
+    let warnings: Vec<String> = m
+        .warnings
+        .iter()
+        .map(|w| format!("    {}", json_str(&w.message)))
+        .collect();
+    let started = started
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let error = match result {
+        Ok(_) => "null".to_string(),
+        Err(e) => json_str(&format!("{e:#}")),
+    };
+    // creating the writer registers the manifest as an output
+    drop(m);
+
+    let mut writer = create_writer(path)?;
+    writeln!(writer, "{{")?;
+    writeln!(
+        writer,
+        "  \"version\": {},",
+        json_str(env!("CARGO_PKG_VERSION"))
+    )?;
+    writeln!(writer, "  \"arguments\": [{}],", args.join(", "))?;
+    writeln!(writer, "  \"started\": {started},")?;
+    writeln!(writer, "  \"duration\": {:.3},", elapsed.as_secs_f64())?;
+    writeln!(writer, "  \"success\": {},", result.is_ok())?;
+    writeln!(writer, "  \"error\": {error},")?;
+    writeln!(writer, "  \"inputs\": [\n{}\n  ],", inputs.join(",\n"))?;
+    writeln!(writer, "  \"outputs\": [\n{}\n  ],", outputs.join(",\n"))?;
+    writeln!(writer, "  \"warnings\": [\n{}\n  ]", warnings.join(",\n"))?;
+    writeln!(writer, "}}")?;
+    writer.finish()
+}
+
+/// Write the warnings and errors of the run as CSV, or JSON for
+/// files with `.json` extension
+pub fn write_warnings(path: &Path, result: &anyhow::Result<()>) -> anyhow::Result<()> {
+    if let Err(e) = result {
+        diagnostic("ERROR", format!("{e:#}"), None, None);
+    }
+    let m = MANIFEST
+        .lock()
+        .map_err(|_| anyhow::Error::msg("Manifest is not available"))?;
+    let json = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    let num = |v: Option<f64>| v.map(|v| v.to_string());
+    let rows: Vec<String> = m
+        .warnings
+        .iter()
+        .map(|w| {
+            let (x, y) = (num(w.location.map(|l| l.0)), num(w.location.map(|l| l.1)));
+            let fid = w.fid.map(|f| f.to_string());
+            if json {
+                let null = |v: Option<String>| v.unwrap_or("null".into());
+                format!(
+                    "  {{\"level\": {}, \"message\": {}, \"x\": {}, \"y\": {}, \"fid\": {}}}",
+                    json_str(w.level),
+                    json_str(&w.message),
+                    null(x),
+                    null(y),
+                    null(fid)
+                )
+            } else {
+                format!(
+                    "{},{},{},{},{}",
+                    w.level,
+                    csv_quote(&w.message),
+                    x.unwrap_or_default(),
+                    y.unwrap_or_default(),
+                    fid.unwrap_or_default()
+                )
+            }
+        })
+        .collect();
+    drop(m);
+
+    let mut writer = create_writer(path)?;
+    if json {
+        writeln!(writer, "[\n{}\n]", rows.join(",\n"))?;
+    } else {
+        writeln!(writer, "level,message,x,y,fid")?;
+        for r in rows {
+            writeln!(writer, "{r}")?;
+        }
+    }
+    writer.finish()
+}
@@ -12,9 +12,12 @@ use gdal::vector::{
 use gdal::{Dataset, Driver, DriverManager, GdalOpenFlags, Metadata};
 
 use itertools::Itertools;
+use rstar::primitives::{GeomWithData, Line};
 use rstar::RTree;
 
 use crate::cliargs::CliAction;
+use crate::fingerprint::layer_fingerprint;
+use crate::order::is_reversed;
 use crate::types::*;
 use crate::utils::*;
 
@@ -55,23 +58,64 @@ pub struct CliArgs {
     ///
     /// Algorithm assumes the geometry starts from upstream and goes
     /// to downstream. If it's reverse use this flag.
-    #[arg(short, long, action)]
+    #[arg(short, long, visible_alias = "flip", action)]
     reverse: bool,
+    /// Detect the direction of the streamlines and reverse them if needed
+    ///
+    /// Streams with more outlets than origins are taken as digitized
+    /// from downstream to upstream.
+    #[arg(long, action, conflicts_with = "reverse")]
+    auto_flip: bool,
     /// Threashold distance for the snapping to streams
-    #[arg(short = 'T', long)]
-    threshold: Option<f64>,
+    ///
+    /// In the units of the streams layer, or with a unit suffix like
+    /// `100m`, `2km`, `50ft` or `0.001deg`.
+    #[arg(short = 'T', long, value_parser=parse_distance)]
+    threshold: Option<Distance>,
     /// Only save endpoints in the network GIS file
     #[arg(short, long)]
     endpoints: bool,
     /// Print progress
     #[arg(short, long)]
     verbose: bool,
+    /// Snap the points onto the stream lines within this radius
+    ///
+    /// Points are placed at the nearest location on the streams
+    /// (not only the vertices), splitting the stream segment there,
+    /// so that the points don't need to be snapped beforehand. Points
+    /// farther than the radius are left as they are and use the
+    /// nearest vertex like usual. Takes unit suffixes like
+    /// --threshold, the snap distances are saved in the --snap-line
+    /// file.
+    #[arg(short = 'A', long, value_parser=parse_distance)]
+    auto_snap: Option<Distance>,
     /// if provided save the movement of point during snapping in a file
     #[arg(short, long, value_parser=parse_new_layer)]
     snap_line: Option<(PathBuf, Option<String>)>,
     /// Nodes file, if provided save the nodes of the graph as points with nodeid
     #[arg(short = 'N', long, value_parser=parse_new_layer)]
     nodes: Option<(PathBuf, Option<String>)>,
+    /// Cache file to reuse the results of the previous run
+    ///
+    /// If the streams haven't changed since the last run, the points
+    /// that haven't moved reuse their snapped location and
+    /// connection, and only the new/moved points and the ones
+    /// affected by them are traced. The network GIS file (without
+    /// --endpoints) still needs all the points traced for the
+    /// geometry. The cache is updated with the results of this run.
+    #[arg(short = 'I', long)]
+    incremental: Option<PathBuf>,
+    /// Field in the streams to choose the branch for the points at confluences
+    ///
+    /// Points snapped to a confluence belong to the downstream reach,
+    /// so the points upstream in all the branches connect to it. If
+    /// this field (e.g. drainage area) is given, such points are
+    /// moved one vertex upstream onto the branch with the larger
+    /// value instead.
+    #[arg(short = 'C', long)]
+    confluence_field: Option<String>,
+    #[command(flatten)]
+    subset: Subset,
     /// Points file with points of interest
     #[arg(value_parser=parse_layer, value_name="POINTS_FILE[::LAYER]")]
     points: (PathBuf, String),
@@ -81,12 +125,19 @@ pub struct CliArgs {
 }
 
 impl CliAction for CliArgs {
-    fn run(self) -> Result<(), anyhow::Error> {
+    fn run(mut self) -> Result<(), anyhow::Error> {
+        resolve_layer(&mut self.points)?;
+        resolve_layer(&mut self.streams)?;
         let points_data = Dataset::open(&self.points.0).unwrap();
-        let points = points_data.layer_by_name(&self.points.1).unwrap();
+        let mut points = points_data.layer_by_name(&self.points.1).unwrap();
+        self.subset.apply(&mut points)?;
 
         let streams_data = Dataset::open(&self.streams.0).unwrap();
-        let streams = streams_data.layer_by_name(&self.streams.1).unwrap();
+        let mut streams = streams_data.layer_by_name(&self.streams.1).unwrap();
+        if self.auto_flip {
+            self.reverse = is_reversed(&mut streams)?;
+        }
+        self.subset.apply(&mut streams)?;
 
         if self.ignore_spatial_ref || check_spatial_ref(&points, &streams).is_ok() {
             self.connections(points, streams)?;
@@ -97,16 +148,74 @@ impl CliAction for CliArgs {
 }
 
 impl CliArgs {
+    /// Files written by the command
+    pub fn outputs(&self) -> Vec<PathBuf> {
+        [
+            self.output.as_ref(),
+            self.network.as_ref().map(|n| &n.0),
+            self.snap_line.as_ref().map(|n| &n.0),
+            self.nodes.as_ref().map(|n| &n.0),
+            self.incremental.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect()
+    }
+
     fn connections(&self, mut points_lyr: Layer, mut streams_lyr: Layer) -> anyhow::Result<()> {
+        let header = match &self.incremental {
+            Some(_) => RunCache::header(&layer_fingerprint(&mut streams_lyr)?, self),
+            None => String::new(),
+        };
+        let cache = match &self.incremental {
+            Some(path) => RunCache::load(path, &header)?,
+            None => RunCache::default(),
+        };
+        let out_srs = OutputSrs::new(streams_lyr.spatial_ref().as_ref())?;
+        let threshold = self
+            .threshold
+            .map(|t| t.in_units(streams_lyr.spatial_ref().as_ref()));
         let points: Vec<(String, Point2D)> = self.points(&mut points_lyr)?;
-        let streams = self.edges(&mut streams_lyr)?;
+        let mut streams = self.edges(&mut streams_lyr)?;
         if points.is_empty() || streams.is_empty() {
             return Ok(());
         }
+        if let Some(radius) = self.auto_snap {
+            let radius = radius.in_units(streams_lyr.spatial_ref().as_ref());
+            let count = auto_snap(&points, &mut streams, radius);
+            if self.verbose {
+                println!(
+                    "\nSnapped {count} of {} points onto the streams",
+                    points.len()
+                );
+            }
+        }
+        let originals: HashMap<String, Point2D> = points.iter().cloned().collect();
         if self.verbose {
             println!("\nRunning Rstar algorithm")
         }
-        let points = self.rstar(points, &streams)?;
+        let mut points = self.rstar(points, &streams, &cache, &out_srs, threshold)?;
+        let values = match &self.confluence_field {
+            Some(f) => Some(vertex_values(&mut streams_lyr, f, self.take, self.reverse)?),
+            None => None,
+        };
+        at_confluences(&mut points, &streams, values.as_ref());
+
+        // locations where the tracing results of the previous run
+        // can't be reused: new/moved points and the removed ones
+        let reuse = self.incremental.is_some() && (self.endpoints || self.network.is_none());
+        let mut changed: HashSet<Point2D> = HashSet::new();
+        for (name, loc) in &points {
+            if !cache.unchanged(name, &originals[name], loc) {
+                changed.insert(loc.clone());
+            }
+        }
+        for (name, (_, snapped, _)) in &cache.points {
+            if points.get(name) != Some(snapped) {
+                changed.insert(snapped.clone());
+            }
+        }
 
         // if multiple points have the same nearest point in the stream network, process them here.
         let mut points_temp_dir: HashMap<&Point2D, Vec<&str>> = HashMap::new();
@@ -169,19 +278,55 @@ impl CliArgs {
         let mut outlets = vec![];
         let mut progress = 0;
         let total = points_nodes.len();
-        for pt in points_nodes.keys() {
-            let outlet: Option<&Point2D> = find_outlet(
-                pt,
-                &points_nodes,
-                &streams,
-                100000,
-                &mut points_touched_edges,
-                self.endpoints,
-            );
-            if let Some(o) = outlet {
-                str_edges.insert(points_nodes[pt].1, points_nodes[o].0);
-            } else {
-                outlets.push(pt);
+        // trace the changed locations first, so the ones affected by
+        // them are known before reusing the others
+        let locations: Vec<&&Point2D> = points_nodes
+            .keys()
+            .sorted_by_key(|pt| !changed.contains(**pt))
+            .collect();
+        let mut affected: HashSet<Point2D> = HashSet::new();
+        let mut outlet_of: HashMap<&Point2D, Option<Point2D>> = HashMap::new();
+        for pt in locations {
+            let cached = points_nodes[*pt].0;
+            let cached = cache.points.get(cached).map(|c| c.2.clone());
+            let reused = match cached {
+                Some(Some(o))
+                    if reuse
+                        && !changed.contains(*pt)
+                        && !changed.contains(&o)
+                        && !affected.contains(&o)
+                        && points_nodes.contains_key(&o) =>
+                {
+                    str_edges.insert(points_nodes[*pt].1, points_nodes[&o].0);
+                    outlet_of.insert(*pt, Some(o));
+                    true
+                }
+                // new points could be downstream of the old outlet
+                Some(None) if reuse && changed.is_empty() => {
+                    outlets.push(*pt);
+                    outlet_of.insert(*pt, None);
+                    true
+                }
+                _ => false,
+            };
+            if !reused {
+                let outlet: Option<&Point2D> = find_outlet(
+                    pt,
+                    &points_nodes,
+                    &streams,
+                    100000,
+                    &mut points_touched_edges,
+                    self.endpoints,
+                );
+                if let Some(o) = outlet {
+                    str_edges.insert(points_nodes[*pt].1, points_nodes[o].0);
+                    if changed.contains(*pt) {
+                        affected.insert(o.clone());
+                    }
+                } else {
+                    outlets.push(*pt);
+                }
+                outlet_of.insert(*pt, outlet.cloned());
             }
             if self.verbose {
                 progress += 1;
@@ -198,20 +343,34 @@ impl CliArgs {
         }
 
         if outlets.len() > 1 {
-            eprintln!("\nMultiple Outlets Found:");
+            crate::warning!("\nMultiple Outlets Found:");
             for o in outlets {
-                eprintln!("{} {} -> None", points_nodes[o].1, o);
+                crate::warning_at!(
+                    Some(o.coord2()),
+                    None,
+                    "{} {} -> None",
+                    points_nodes[o].1,
+                    o
+                );
             }
         } else {
-            eprintln!(
+            crate::warning!(
                 "\nOutlet: {} {} -> None",
-                points_nodes[outlets[0]].1, outlets[0]
+                points_nodes[outlets[0]].1,
+                outlets[0]
             );
         }
 
+        if let Some(path) = &self.incremental {
+            let entries = points.iter().map(|(name, loc)| {
+                let outlet = outlet_of.get(loc).cloned().flatten();
+                (name.as_str(), &originals[name], loc, outlet)
+            });
+            RunCache::save(path, &header, entries)?;
+        }
+
         if let Some(outfile) = &self.output {
-            let file = File::create(outfile)?;
-            let mut writer = BufWriter::new(file);
+            let mut writer = BufWriter::new(create_writer(outfile)?);
             for (k, v) in &str_edges {
                 match (valid_node_name(k), valid_node_name(v)) {
                     (true, true) => writeln!(writer, "{k} -> {v}")?,
@@ -220,6 +379,7 @@ impl CliArgs {
                     (false, false) => writeln!(writer, "\"{k}\" -> \"{v}\"")?,
                 }
             }
+            finish_writer(writer)?;
         } else {
             for (k, v) in &str_edges {
                 match (valid_node_name(k), valid_node_name(v)) {
@@ -237,9 +397,11 @@ impl CliArgs {
             let save = |d: &mut Dataset| -> anyhow::Result<()> {
                 let mut layer = d.create_layer(LayerOptions {
                     name: out.1.as_ref().unwrap_or(&"network".to_string()),
+                    srs: out_srs.srs(),
                     ty: gdal_sys::OGRwkbGeometryType::wkbLineString,
                     ..Default::default()
                 })?;
+                stamp_metadata(&mut layer)?;
                 layer.create_defn_fields(&[
                     ("start", OGRFieldType::OFTString),
                     ("end", OGRFieldType::OFTString),
@@ -252,7 +414,7 @@ impl CliArgs {
                         edge_geom.add_point_2d(points[*start].coord2());
                         edge_geom.add_point_2d(points[*end].coord2());
                         let mut ft = Feature::new(&defn)?;
-                        ft.set_geometry(edge_geom)?;
+                        ft.set_geometry(out_srs.geometry(edge_geom)?)?;
                         ft.set_field_string(0, start)?;
                         ft.set_field_string(1, end)?;
                         ft.create(&mut layer)?;
@@ -275,7 +437,7 @@ impl CliArgs {
                         }
                         edge_geom.add_point_2d(end_pt.coord2());
                         let mut ft = Feature::new(&defn)?;
-                        ft.set_geometry(edge_geom)?;
+                        ft.set_geometry(out_srs.geometry(edge_geom)?)?;
                         ft.set_field_string(0, start)?;
                         ft.set_field_string(1, end)?;
                         ft.create(&mut layer)?;
@@ -329,8 +491,8 @@ impl CliArgs {
                     Some(g) => Point2D::new3(g.get_point(0)),
                     None => {
                         // TODO: make it check for geometry column and get this sorted out
-                        let x = f.field_as_double(x_field.clone()?)?;
-                        let y = f.field_as_double(y_field.clone()?)?;
+                        let x = field_as_number(&f, x_field.clone()?)?;
+                        let y = field_as_number(&f, y_field.clone()?)?;
                         if let (Some(x), Some(y)) = (x, y) {
                             Point2D::new2((x, y))
                         } else {
@@ -339,7 +501,17 @@ impl CliArgs {
                     }
                 }?;
                 let name = if let Some(namef) = name_field {
-                    f.field_as_string(namef)?.unwrap_or(format!("Unnamed_{i}"))
+                    match f.field_as_string(namef)? {
+                        Some(n) => n,
+                        None => {
+                            crate::warning_at!(
+                                Some(geom.coord2()),
+                                f.fid(),
+                                "Point without name, using Unnamed_{i}"
+                            );
+                            format!("Unnamed_{i}")
+                        }
+                    }
                 } else {
                     i.to_string()
                 };
@@ -361,6 +533,9 @@ impl CliArgs {
         &self,
         points: Vec<(String, Point2D)>,
         edges: &HashMap<Point2D, Point2D>,
+        cache: &RunCache,
+        out_srs: &OutputSrs,
+        threshold: Option<f64>,
     ) -> anyhow::Result<HashMap<String, Point2D>> {
         let mut points_closest: HashMap<String, Point2D> = HashMap::with_capacity(points.len());
         let mut progress: usize = 0;
@@ -368,20 +543,31 @@ impl CliArgs {
         if self.verbose {
             println!("Loading Points in RTree");
         }
-        let pts: HashSet<_> = edges.iter().flat_map(|(k, v)| vec![k, v]).collect();
-        let pts: Vec<_> = pts.into_iter().map(|k| k.coord2()).collect();
-        let all_points = RTree::bulk_load(pts);
-        let sq_threshold = self.threshold.map(|t| t.powi(2));
+        // the tree is only built if there are points to snap
+        let mut tree = None;
+        let sq_threshold = threshold.map(|t| t.powi(2));
 
         let mut err = HashSet::new();
         let mut snapped = Vec::with_capacity(points.len());
         for (k, p) in points {
+            if let Some((orig, loc, _)) = cache.points.get(&k) {
+                if *orig == p {
+                    snapped.push((k.clone(), p.coord2(), loc.coord2()));
+                    points_closest.insert(k, loc.clone());
+                    continue;
+                }
+            }
+            let all_points = tree.get_or_insert_with(|| {
+                let pts: HashSet<_> = edges.iter().flat_map(|(k, v)| vec![k, v]).collect();
+                let pts: Vec<_> = pts.into_iter().map(|k| k.coord2()).collect();
+                RTree::bulk_load(pts)
+            });
             let place = match all_points.nearest_neighbor(&p.coord2()) {
                 Some(p) => p,
                 None => {
                     // only happens if the tree is empty I think (doc not present)
-                    eprintln!("{:?}", p.coord2());
-                    eprintln!("{:?}", all_points.iter().next());
+                    crate::warning!("{:?}", p.coord2());
+                    crate::warning!("{:?}", all_points.iter().next());
                     err.insert(k);
                     continue;
                 }
@@ -390,6 +576,11 @@ impl CliArgs {
             let min_pt = Point2D::new2(*place).unwrap();
             if let Some(t) = sq_threshold {
                 if p.sq_dist(&min_pt) > t {
+                    crate::warning_at!(
+                        Some(p.coord2()),
+                        None,
+                        "Point {k} not snapped: farther than the threshold from the streams"
+                    );
                     err.insert(k);
                     continue;
                 }
@@ -417,12 +608,15 @@ impl CliArgs {
                 delete_layer(d, lyr_name).ok();
                 let mut layer = d.create_layer(LayerOptions {
                     name: lyr_name,
+                    srs: out_srs.srs(),
                     ty: gdal_sys::OGRwkbGeometryType::wkbLineString,
                     ..Default::default()
                 })?;
+                stamp_metadata(&mut layer)?;
                 layer.create_defn_fields(&[
                     ("name", OGRFieldType::OFTString),
                     ("error", OGRFieldType::OFTString),
+                    ("distance", OGRFieldType::OFTReal),
                 ])?;
                 let defn = Defn::from_layer(&layer);
                 for (name, start, end) in &snapped {
@@ -430,9 +624,11 @@ impl CliArgs {
                     geom.add_point_2d(*start);
                     geom.add_point_2d(*end);
                     let mut ft = Feature::new(&defn)?;
-                    ft.set_geometry(geom)?;
+                    ft.set_geometry(out_srs.geometry(geom)?)?;
                     ft.set_field_string(0, name)?;
                     ft.set_field_string(1, if err.contains(name) { "yes" } else { "no" })?;
+                    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+                    ft.set_field_double(2, (dx * dx + dy * dy).sqrt())?;
                     ft.create(&mut layer)?;
                 }
                 Ok(())
@@ -465,7 +661,7 @@ impl CliArgs {
     }
 }
 
-fn read_stream_points(
+pub fn read_stream_points(
     layer: &mut Layer,
     verbose: bool,
     take: usize,
@@ -481,6 +677,8 @@ fn read_stream_points(
     for f in layer.features() {
         match f.geometry() {
             Some(g) => {
+                let lin = linearize(g);
+                let g = lin.as_ref().unwrap_or(g);
                 let mut pts = Vec::new();
                 let gc = g.geometry_count();
                 if gc > 0 {
@@ -540,6 +738,264 @@ fn edges_from_pts(pts: &[(f64, f64, f64)], take: usize, reverse: bool) -> Vec<(P
     }
 }
 
+/// Field value of the stream feature at its vertices, except the last one
+///
+/// The last vertex is the confluence shared with the other streams,
+/// so the upstream vertices identify the branches.
+fn vertex_values(
+    layer: &mut Layer,
+    field: &str,
+    take: usize,
+    reverse: bool,
+) -> anyhow::Result<HashMap<Point2D, f64>> {
+    let fid = layer
+        .defn()
+        .field_index(field)
+        .with_context(|| format!("Field {field} not found in the streams"))?;
+    let mut values = HashMap::new();
+    for f in layer.features() {
+        let (Some(g), Some(val)) = (f.geometry(), f.field_as_double(fid)?) else {
+            continue;
+        };
+        let lin = linearize(g);
+        let g = lin.as_ref().unwrap_or(g);
+        let parts = if g.geometry_count() > 0 {
+            (0..g.geometry_count())
+                .map(|i| g.get_geometry(i).get_point_vec())
+                .collect()
+        } else {
+            vec![g.get_point_vec()]
+        };
+        for pts in parts.iter().filter(|p| p.len() > 1) {
+            for (start, _) in edges_from_pts(pts, take, reverse) {
+                values.insert(start, val);
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Handle the points snapped at the confluences
+///
+/// Such points belong to the downstream reach and get the points of
+/// all the upstream branches; with the branch values, they are moved
+/// upstream onto the branch with the largest value.
+fn at_confluences(
+    points: &mut HashMap<String, Point2D>,
+    edges: &HashMap<Point2D, Point2D>,
+    values: Option<&HashMap<Point2D, f64>>,
+) {
+    let mut upstream: HashMap<&Point2D, Vec<&Point2D>> = HashMap::new();
+    for (a, b) in edges {
+        upstream.entry(b).or_default().push(a);
+    }
+    for (name, loc) in points.iter_mut() {
+        let Some(branches) = upstream.get(&*loc).filter(|b| b.len() > 1) else {
+            continue;
+        };
+        let branch = values.and_then(|v| {
+            branches
+                .iter()
+                .filter_map(|b| Some((v.get(*b)?, *b)))
+                .max_by(|a, b| a.0.total_cmp(b.0))
+                .map(|b| b.1)
+        });
+        match branch {
+            Some(b) => {
+                crate::warning!("Point {name} at confluence {loc} moved upstream to {b}");
+                *loc = b.clone();
+            }
+            None => crate::warning!(
+                "Point {name} is at confluence {loc}, it includes all {} upstream branches",
+                branches.len()
+            ),
+        }
+    }
+}
+
+/// Place the points on the nearest location of the stream segments
+///
+/// The segments with points on them are split at the points, so the
+/// tracing goes through them. Returns the number of points snapped.
+fn auto_snap(
+    points: &[(String, Point2D)],
+    edges: &mut HashMap<Point2D, Point2D>,
+    radius: f64,
+) -> usize {
+    let segments: Vec<GeomWithData<Line<[f64; 2]>, Point2D>> = edges
+        .iter()
+        .map(|(a, b)| {
+            let (ax, ay) = a.coord2();
+            let (bx, by) = b.coord2();
+            GeomWithData::new(Line::new([ax, ay], [bx, by]), a.clone())
+        })
+        .collect();
+    let tree = RTree::bulk_load(segments);
+    // upstream end of the segment -> points on it with their fraction along it
+    let mut on_segment: HashMap<Point2D, Vec<(f64, Point2D)>> = HashMap::new();
+    let mut count = 0;
+    for (_, pt) in points {
+        let (x, y) = pt.coord2();
+        let Some(seg) = tree.nearest_neighbor(&[x, y]) else {
+            continue;
+        };
+        let ([ax, ay], [bx, by]) = (seg.geom().from, seg.geom().to);
+        let (dx, dy) = (bx - ax, by - ay);
+        let len2 = dx * dx + dy * dy;
+        let t = if len2 == 0.0 {
+            0.0
+        } else {
+            (((x - ax) * dx + (y - ay) * dy) / len2).clamp(0.0, 1.0)
+        };
+        let Ok(snapped) = Point2D::new2((ax + t * dx, ay + t * dy)) else {
+            continue;
+        };
+        if snapped.dist(pt) > radius {
+            continue;
+        }
+        count += 1;
+        if t > 0.0 && t < 1.0 {
+            on_segment
+                .entry(seg.data.clone())
+                .or_default()
+                .push((t, snapped));
+        }
+    }
+    for (start, mut pts) in on_segment {
+        let Some(end) = edges.get(&start).cloned() else {
+            continue;
+        };
+        pts.sort_by(|a, b| a.0.total_cmp(&b.0));
+        pts.dedup_by(|a, b| a.1 == b.1);
+        let mut prev = start;
+        for (_, p) in pts {
+            edges.insert(prev, p.clone());
+            prev = p;
+        }
+        edges.insert(prev, end);
+    }
+    count
+}
+
+/// Snapping and tracing results of the previous run
+#[derive(Default)]
+struct RunCache {
+    /// point name -> (point, snapped location, outlet location)
+    points: HashMap<String, (Point2D, Point2D, Option<Point2D>)>,
+}
+
+impl RunCache {
+    /// Inputs other than the points the results depend on
+    ///
+    /// Any change in the streams, or in the options used for snapping
+    /// and tracing, invalidates the whole cache.
+    fn header(fingerprint: &str, args: &CliArgs) -> String {
+        format!(
+            "streams\t{fingerprint}\t{}\t{}\t{:?}\t{:?}\t{:?}\t{:?}",
+            args.take,
+            args.reverse,
+            args.threshold,
+            args.auto_snap,
+            args.confluence_field,
+            args.subset
+        )
+    }
+
+    fn load(path: &PathBuf, header: &str) -> anyhow::Result<Self> {
+        if !path_exists(path) {
+            return Ok(Self::default());
+        }
+        let contents = String::from_utf8(read_vsi_file(path)?)?;
+        let mut lines = contents.lines();
+        if lines.next() != Some(header) {
+            crate::warning!("Streams or options changed since the last run, processing all points");
+            return Ok(Self::default());
+        }
+        let parse = |x: &str, y: &str| -> anyhow::Result<Point2D> {
+            Point2D::new2((x.parse()?, y.parse()?))
+        };
+        let mut points = HashMap::new();
+        for line in lines {
+            let row: Vec<&str> = line.split('\t').collect();
+            let [name, x, y, sx, sy, ox, oy] = row[..] else {
+                bail!("Invalid line in cache file {path:?}: {line}");
+            };
+            let name = unescape_name(name);
+            let outlet = if ox.is_empty() {
+                None
+            } else {
+                Some(parse(ox, oy)?)
+            };
+            points.insert(name, (parse(x, y)?, parse(sx, sy)?, outlet));
+        }
+        Ok(Self { points })
+    }
+
+    fn save<'a>(
+        path: &PathBuf,
+        header: &str,
+        entries: impl Iterator<Item = (&'a str, &'a Point2D, &'a Point2D, Option<Point2D>)>,
+    ) -> anyhow::Result<()> {
+        let mut writer = BufWriter::new(create_writer(path)?);
+        writeln!(writer, "{header}")?;
+        for (name, pt, loc, outlet) in entries {
+            let (x, y) = pt.coord2();
+            let (sx, sy) = loc.coord2();
+            let (ox, oy) = outlet
+                .map(|o| {
+                    let (ox, oy) = o.coord2();
+                    (ox.to_string(), oy.to_string())
+                })
+                .unwrap_or_default();
+            let name = escape_name(name);
+            writeln!(writer, "{name}\t{x}\t{y}\t{sx}\t{sy}\t{ox}\t{oy}")?;
+        }
+        finish_writer(writer)
+    }
+
+    /// Point is in the cache at the same location and snapped to the same place
+    fn unchanged(&self, name: &str, pt: &Point2D, loc: &Point2D) -> bool {
+        self.points
+            .get(name)
+            .is_some_and(|(p, l, _)| p == pt && l == loc)
+    }
+}
+
+/// Escape the point name to keep it in a single field of the cache file
+fn escape_name(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reverse of [`escape_name`]
+fn unescape_name(name: &str) -> String {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
 fn valid_node_name(n: &str) -> bool {
     let mut chars = n.chars();
     match chars.next() {
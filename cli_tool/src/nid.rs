@@ -4,11 +4,16 @@ use std::{fs::File, path::PathBuf};
 use clap::{Args, ValueEnum, ValueHint};
 
 use crate::cliargs::CliAction;
+use crate::download::save_download;
+use crate::utils::path_exists;
 
 #[derive(Args)]
 pub struct CliArgs {
     #[arg(short, long, action)]
     url: bool,
+    /// Extract the downloaded file if it is a zip archive
+    #[arg(short = 'x', long, action)]
+    extract: bool,
     #[arg(short, long, value_hint=ValueHint::FilePath, default_value="nid-dams.gpkg")]
     output_file: PathBuf,
 }
@@ -24,13 +29,12 @@ impl CliAction for CliArgs {
                 return Err(anyhow::Error::msg(format!("HTTP Error: {}", resp.status())));
             }
             if let Some(_size) = resp.content_length() {
-                if self.output_file.exists() {
+                if path_exists(&self.output_file) {
                     // check for file size to not re-download it
                 }
             }
-            let mut file = File::create(self.output_file).unwrap();
             // TODO, make it stream (async?)
-            file.write_all(&resp.bytes()?)?;
+            save_download(&resp.bytes()?, &self.output_file, self.extract)?;
         }
         Ok(())
     }
@@ -1,16 +1,17 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::path::PathBuf;
 
 use crate::types::Point2D;
 use anyhow::Context;
 use clap::Args;
-use gdal::spatial_ref::SpatialRef;
 use gdal::vector::{
     Defn, Feature, FieldDefn, FieldValue, Geometry, Layer, LayerAccess, LayerOptions, OGRFieldType,
 };
 use gdal::{Dataset, DriverManager, DriverType};
 
 use crate::cliargs::CliAction;
+use crate::raster::RasterSampler;
+use crate::schema::{ogr_type, Schema};
 use crate::types::*;
 use crate::utils::*;
 
@@ -29,11 +30,66 @@ pub struct CliArgs {
     ///
     /// Algorithm assumes the geometry starts from upstream and goes
     /// to downstream. If it's reverse use this flag.
-    #[arg(short, long, action)]
+    #[arg(short, long, visible_alias = "flip", action)]
     reverse: bool,
+    /// Detect the direction of the streamlines and reverse them if needed
+    ///
+    /// Streams with more outlets than origins are taken as digitized
+    /// from downstream to upstream.
+    #[arg(long, action, conflicts_with = "reverse")]
+    auto_flip: bool,
+    #[command(flatten)]
+    subset: Subset,
+    /// TOML file to rename/retype the fields copied from streams
+    #[arg(short, long)]
+    schema: Option<PathBuf>,
+    /// Report the segments shorter than this length and the zero-length ones
+    ///
+    /// Such segments (often digitizing artifacts) are merged with
+    /// their neighbors: their ends are joined so the streams through
+    /// them stay connected, and they are excluded from the ordering
+    /// (empty order). Takes unit suffixes like `10m`.
+    #[arg(short, long, value_parser=parse_distance)]
+    min_length: Option<Distance>,
+    /// Tag the mainstem segments in a `mainstem` field (1 or 0)
+    ///
+    /// The mainstem is traced upstream from each outlet, following
+    /// the branch with the longest upstream path at each confluence,
+    /// or with the largest value of --mainstem-field.
+    #[arg(short = 'M', long, action)]
+    mainstem: bool,
+    /// Label the segments with hierarchical ids in a `tributary` field
+    ///
+    /// The mainstem from the first outlet is `1`, the tributaries
+    /// joining it are `1.1`, `1.2`, ... from downstream to upstream,
+    /// their tributaries `1.1.1` and so on. The branches are chosen
+    /// like --mainstem.
+    #[arg(long, action)]
+    tributary_ids: bool,
+    /// Field to choose the mainstem branch with (e.g. drainage area)
+    #[arg(long)]
+    mainstem_field: Option<String>,
+    /// Add the `sinuosity` field (length / straight distance of the ends)
+    #[arg(long, action)]
+    sinuosity: bool,
+    /// DEM to calculate the `slope` field of the segments from
+    ///
+    /// Elevations are sampled at the ends of the segments, the
+    /// streams should be in the spatial reference of the DEM.
+    #[arg(long)]
+    dem: Option<PathBuf>,
+    /// Field with the accumulated drainage area for the `stream_power` field
+    ///
+    /// The stream power proxy is the drainage area times the slope,
+    /// so it needs --dem.
+    #[arg(long, requires = "dem")]
+    area_field: Option<String>,
+    /// Save only the mainstem segments in this output file as well
+    #[arg(long, value_parser=parse_new_layer, requires = "mainstem")]
+    mainstem_output: Option<(PathBuf, Option<String>)>,
 
     /// Streams vector file with streams network
-    #[arg(value_parser=parse_layer, value_name="STREAMS_FILE[:LAYER]")]
+    #[arg(value_parser=parse_layer, value_name="STREAMS_FILE[::LAYER]")]
     streams: (PathBuf, String),
     /// Output file
     #[arg(value_parser=parse_new_layer)]
@@ -41,14 +97,26 @@ pub struct CliArgs {
 }
 
 impl CliAction for CliArgs {
-    fn run(self) -> Result<(), anyhow::Error> {
+    fn run(mut self) -> Result<(), anyhow::Error> {
+        resolve_layer(&mut self.streams)?;
         let streams_data = Dataset::open(&self.streams.0).unwrap();
         let mut streams_lyr = streams_data.layer_by_name(&self.streams.1).unwrap();
-        let points = get_endpoints(&mut streams_lyr, self.verbose, self.reverse)?;
+        if self.auto_flip {
+            self.reverse = is_reversed(&mut streams_lyr)?;
+        }
+        self.subset.apply(&mut streams_lyr)?;
+        // segments are the parts of the features, `features` has the
+        // index of the feature of each segment
+        let (points, features) = get_endpoints(&mut streams_lyr, self.verbose, self.reverse)?;
         if points.is_empty() {
-            eprintln!("Empty file, nothing to do.");
+            crate::warning!("Empty file, nothing to do.");
             return Ok(());
         }
+        let short = match self.min_length {
+            Some(len) => short_segments(&mut streams_lyr, len)?,
+            None => HashSet::new(),
+        };
+        let points = merge_short(points, &short);
         if self.verbose {
             println!("\nCreating HashMap from points")
         }
@@ -57,7 +125,13 @@ impl CliAction for CliArgs {
         if self.verbose {
             println!("\nCreating Edges")
         }
-        let edges: HashMap<&Point2D, &Point2D> = points.iter().rev().map(|(s, e)| (s, e)).collect();
+        let edges: HashMap<&Point2D, &Point2D> = points
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(i, _)| !short.contains(i))
+            .map(|(_, (s, e))| (s, e))
+            .collect();
         if self.verbose {
             println!("\nDetecting leaf nodes")
         }
@@ -93,22 +167,72 @@ impl CliAction for CliArgs {
             }
         }
 
+        let labels = if self.mainstem || self.tributary_ids {
+            let weights = match &self.mainstem_field {
+                Some(f) => field_values(&mut streams_lyr, f, &features)?,
+                None => upstream_lengths(&points, &short, &segment_lengths(&mut streams_lyr)),
+            };
+            Some(tributary_ids(&points, &short, &weights))
+        } else {
+            None
+        };
+        // the outputs are per feature, a feature is on the mainstem if
+        // any of its parts are, and takes the label of its first part
+        let mainstem: Option<HashSet<usize>> = labels.as_ref().filter(|_| self.mainstem).map(|l| {
+            l.iter()
+                .filter(|(_, id)| !id.contains('.'))
+                .map(|(i, _)| features[*i])
+                .collect()
+        });
+        let tributary: Option<HashMap<usize, String>> =
+            labels.filter(|_| self.tributary_ids).map(|l| {
+                let mut parts: Vec<(usize, String)> = l.into_iter().collect();
+                parts.sort_by_key(|(i, _)| *i);
+                let mut tributary = HashMap::new();
+                for (i, id) in parts {
+                    tributary.entry(features[i]).or_insert(id);
+                }
+                tributary
+            });
+
+        let metrics = self.reach_metrics(&mut streams_lyr)?;
+
+        let schema = self
+            .schema
+            .as_ref()
+            .map(Schema::from_file)
+            .transpose()?
+            .unwrap_or_default();
         let lyr_name = self.output.1.as_deref().unwrap_or("ordered-stream");
-        let sref = streams_lyr.spatial_ref();
+        let sref = OutputSrs::new(streams_lyr.spatial_ref().as_ref())?;
 
         let mut out_data = gdal_update_or_create(&self.output.0, &self.driver, self.overwrite)?;
 
-        let order: Vec<i64> = points.iter().map(|(a, b)| order[&(a, b)] as i64).collect();
+        // highest order of the parts of each feature
+        let mut feature_order: Vec<Option<i64>> = vec![None; features.last().map_or(0, |l| l + 1)];
+        for (i, (a, b)) in points.iter().enumerate() {
+            if short.contains(&i) {
+                continue;
+            }
+            let o = &mut feature_order[features[i]];
+            *o = (*o).max(Some(order[&(a, b)] as i64));
+        }
+        let order = feature_order;
         let mut trans = false;
         // have to use trans flag here because of borrow rule;
         // uses transaction when it can to speed up the process.
         if let Ok(mut txn) = out_data.start_transaction() {
             write_layer(
                 &order,
+                mainstem.as_ref(),
+                tributary.as_ref(),
+                &metrics,
+                false,
                 &mut txn,
                 &mut streams_lyr,
+                &schema,
                 lyr_name,
-                sref.as_ref(),
+                &sref,
                 self.verbose,
             )?;
             txn.commit()?;
@@ -118,10 +242,32 @@ impl CliAction for CliArgs {
         if !trans {
             write_layer(
                 &order,
+                mainstem.as_ref(),
+                tributary.as_ref(),
+                &metrics,
+                false,
                 &mut out_data,
                 &mut streams_lyr,
+                &schema,
                 lyr_name,
-                sref.as_ref(),
+                &sref,
+                self.verbose,
+            )?;
+        }
+
+        if let Some(out) = &self.mainstem_output {
+            let mut ms_data = gdal_update_or_create(&out.0, &self.driver, self.overwrite)?;
+            write_layer(
+                &order,
+                mainstem.as_ref(),
+                tributary.as_ref(),
+                &metrics,
+                true,
+                &mut ms_data,
+                &mut streams_lyr,
+                &schema,
+                out.1.as_deref().unwrap_or("mainstem"),
+                &sref,
                 self.verbose,
             )?;
         }
@@ -130,20 +276,105 @@ impl CliAction for CliArgs {
     }
 }
 
+impl CliArgs {
+    /// Sinuosity, slope and stream power of the features, by the index of the features
+    fn reach_metrics(
+        &self,
+        layer: &mut Layer,
+    ) -> anyhow::Result<Vec<(&'static str, Vec<Option<f64>>)>> {
+        if !self.sinuosity && self.dem.is_none() {
+            return Ok(vec![]);
+        }
+        let dem = self
+            .dem
+            .as_ref()
+            .map(|d| RasterSampler::open(d, 1))
+            .transpose()?;
+        let area = match &self.area_field {
+            Some(f) => Some(layer.defn().field_index(f)?),
+            None => None,
+        };
+        let (mut sinuosity, mut slope, mut power) = (vec![], vec![], vec![]);
+        for f in layer.features() {
+            let Some(g) = f.geometry() else {
+                sinuosity.push(None);
+                slope.push(None);
+                power.push(None);
+                continue;
+            };
+            let lin = linearize(g);
+            let g = lin.as_ref().unwrap_or(g);
+            // first and last points of the multi-geometries
+            let (first, last) = match g.geometry_count() {
+                0 => (g.clone(), g.clone()),
+                n => (g.get_geometry(0).clone(), g.get_geometry(n - 1).clone()),
+            };
+            if first.point_count() == 0 || last.point_count() == 0 {
+                sinuosity.push(None);
+                slope.push(None);
+                power.push(None);
+                continue;
+            }
+            let first = first.get_point(0);
+            let last = last.get_point((last.point_count() - 1) as i32);
+            let (mut up, mut down) = ((first.0, first.1), (last.0, last.1));
+            if self.reverse {
+                (up, down) = (down, up);
+            }
+            let length = g.length();
+            let straight = ((up.0 - down.0).powi(2) + (up.1 - down.1).powi(2)).sqrt();
+            sinuosity.push((straight > 0.0).then(|| length / straight));
+            let s = match &dem {
+                Some(dem) if length > 0.0 => {
+                    match (dem.sample(up.0, up.1)?, dem.sample(down.0, down.1)?) {
+                        (Some(e1), Some(e2)) => Some((e1 - e2) / length),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            slope.push(s);
+            let a = match area {
+                Some(idx) => field_as_number(&f, idx)?,
+                None => None,
+            };
+            power.push(s.zip(a).map(|(s, a)| s * a));
+        }
+        let mut metrics = vec![];
+        if self.sinuosity {
+            metrics.push(("sinuosity", sinuosity));
+        }
+        if self.dem.is_some() {
+            metrics.push(("slope", slope));
+        }
+        if self.area_field.is_some() {
+            metrics.push(("stream_power", power));
+        }
+        Ok(metrics)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn write_layer(
-    order: &[i64],
+    order: &[Option<i64>],
+    mainstem: Option<&HashSet<usize>>,
+    tributary: Option<&HashMap<usize, String>>,
+    metrics: &[(&str, Vec<Option<f64>>)],
+    only_mainstem: bool,
     out_data: &mut Dataset,
     streams_lyr: &mut Layer,
+    schema: &Schema,
     lyr_name: &str,
-    sref: Option<&SpatialRef>,
+    sref: &OutputSrs,
     verbose: bool,
 ) -> anyhow::Result<()> {
-    let layer = out_data.create_layer(LayerOptions {
+    let mut layer = out_data.create_layer(LayerOptions {
         name: lyr_name,
-        srs: sref,
+        srs: sref.srs(),
         ty: gdal_sys::OGRwkbGeometryType::wkbLineString,
         ..Default::default()
     })?;
+    stamp_metadata(&mut layer)?;
 
     let fields_defn = streams_lyr
         .defn()
@@ -151,8 +382,14 @@ fn write_layer(
         .map(|field| (field.name(), field.field_type(), field.width()))
         .collect::<Vec<_>>();
     for fd in &fields_defn {
-        let field_defn = FieldDefn::new(&fd.0, fd.1)?;
-        field_defn.set_width(fd.2);
+        let field_defn = match schema.field_type(&fd.0) {
+            Some(ty) => FieldDefn::new(schema.rename(&fd.0), ogr_type(ty)?)?,
+            None => {
+                let field_defn = FieldDefn::new(schema.rename(&fd.0), fd.1)?;
+                field_defn.set_width(fd.2);
+                field_defn
+            }
+        };
         field_defn.add_to_layer(&layer)?;
     }
 
@@ -161,19 +398,57 @@ fn write_layer(
         .defn()
         .field_index("order")
         .expect("Just added order field");
+    let mid = match mainstem {
+        Some(_) => {
+            FieldDefn::new("mainstem", OGRFieldType::OFTInteger)?.add_to_layer(&layer)?;
+            layer.defn().field_index("mainstem").ok()
+        }
+        None => None,
+    };
+    let tid = match tributary {
+        Some(_) => {
+            FieldDefn::new("tributary", OGRFieldType::OFTString)?.add_to_layer(&layer)?;
+            layer.defn().field_index("tributary").ok()
+        }
+        None => None,
+    };
+    let mut metric_ids = Vec::with_capacity(metrics.len());
+    for (name, _) in metrics {
+        FieldDefn::new(name, OGRFieldType::OFTReal)?.add_to_layer(&layer)?;
+        metric_ids.push(layer.defn().field_index(name)?);
+    }
     let defn = Defn::from_layer(&layer);
     let total = streams_lyr.feature_count();
     let mut progress = 0;
     for (i, feat) in streams_lyr.features().enumerate() {
+        let on_mainstem = mainstem.is_some_and(|m| m.contains(&i));
+        if only_mainstem && !on_mainstem {
+            continue;
+        }
         let mut ft = Feature::new(&defn)?;
-        ft.set_geometry(feat.geometry().unwrap().clone())?;
+        if let Some(g) = feat.geometry() {
+            ft.set_geometry(sref.geometry(g.clone())?)?;
+        }
         // TODO: do a proper field copy
         for (j, _fd) in fields_defn.iter().enumerate() {
             if let Some(value) = feat.field(j)? {
                 ft.set_field(j, &value)?;
             }
         }
-        ft.set_field_integer64(fid, order[i])?;
+        if let Some(o) = order.get(i).copied().flatten() {
+            ft.set_field_integer64(fid, o)?;
+        }
+        if let Some(m) = mid {
+            ft.set_field_integer(m, on_mainstem as i32)?;
+        }
+        for ((_, values), id) in metrics.iter().zip(&metric_ids) {
+            if let Some(v) = values.get(i).copied().flatten() {
+                ft.set_field_double(*id, v)?;
+            }
+        }
+        if let Some(id) = tributary.and_then(|t| t.get(&i)) {
+            ft.set_field_string(tid.expect("Added with tributary"), id)?;
+        }
         ft.create(&layer)?;
 
         if verbose {
@@ -184,45 +459,262 @@ fn write_layer(
     Ok(())
 }
 
+/// Index of the segments shorter than the given length, or with zero length
+fn short_segments(layer: &mut Layer, min_length: Distance) -> anyhow::Result<HashSet<usize>> {
+    let min_length = min_length.in_units(layer.spatial_ref().as_ref());
+    let mut short = HashSet::new();
+    // same indices as get_endpoints
+    let mut i = 0;
+    for f in layer.features() {
+        let Some(g) = f.geometry() else {
+            continue;
+        };
+        for part in geometry_parts(g) {
+            let len = part.length();
+            if len == 0.0 || len < min_length {
+                let (x, y, _) = part.get_point(0);
+                crate::warning_at!(
+                    Some((x, y)),
+                    f.fid(),
+                    "Short segment (FID {}) of length {len}, merged with the neighbors",
+                    f.fid().map(|f| f.to_string()).unwrap_or_default()
+                );
+                short.insert(i);
+            }
+            i += 1;
+        }
+    }
+    if !short.is_empty() {
+        crate::warning!("{} short segments excluded from ordering", short.len());
+    }
+    Ok(short)
+}
+
+/// Join the ends of the short segments, so the streams stay connected
+///
+/// The downstream end of each short segment is replaced by its
+/// upstream end in all the segments.
+fn merge_short(points: Vec<(Point2D, Point2D)>, short: &HashSet<usize>) -> Vec<(Point2D, Point2D)> {
+    if short.is_empty() {
+        return points;
+    }
+    let alias: HashMap<Point2D, Point2D> = short
+        .iter()
+        .map(|&i| (points[i].1.clone(), points[i].0.clone()))
+        .filter(|(e, s)| e != s)
+        .collect();
+    let resolve = |p: &Point2D| {
+        let mut p = p;
+        // chain of short segments, the limit is for the loops
+        for _ in 0..alias.len() {
+            match alias.get(p) {
+                Some(s) => p = s,
+                None => break,
+            }
+        }
+        p.clone()
+    };
+    points
+        .iter()
+        .enumerate()
+        .map(|(i, (s, e))| {
+            if short.contains(&i) {
+                (s.clone(), e.clone())
+            } else {
+                (resolve(s), resolve(e))
+            }
+        })
+        .collect()
+}
+
+/// Lengths of the segments, same indices as get_endpoints
+fn segment_lengths(layer: &mut Layer) -> Vec<f64> {
+    layer
+        .features()
+        .filter_map(|f| f.geometry().map(geometry_parts))
+        .flatten()
+        .map(|g| g.length())
+        .collect()
+}
+
+/// Values of the field for the segments, same indices as get_endpoints
+///
+/// The segments take the value of their feature, `features` is the
+/// feature index of the segments from get_endpoints.
+fn field_values(layer: &mut Layer, field: &str, features: &[usize]) -> anyhow::Result<Vec<f64>> {
+    let idx = layer.defn().field_index(field)?;
+    let values = layer
+        .features()
+        .map(|f| Ok(field_as_number(&f, idx)?.unwrap_or_default()))
+        .collect::<anyhow::Result<Vec<f64>>>()?;
+    Ok(features.iter().map(|&i| values[i]).collect())
+}
+
+/// Segments ending at each point, excluding the short ones
+fn upstream_segments<'a>(
+    points: &'a [(Point2D, Point2D)],
+    short: &HashSet<usize>,
+) -> HashMap<&'a Point2D, Vec<usize>> {
+    let mut upstream: HashMap<&Point2D, Vec<usize>> = HashMap::new();
+    for (i, (_, e)) in points.iter().enumerate() {
+        if !short.contains(&i) {
+            upstream.entry(e).or_default().push(i);
+        }
+    }
+    upstream
+}
+
+/// Length of the longest path upstream of each segment, including itself
+fn upstream_lengths(
+    points: &[(Point2D, Point2D)],
+    short: &HashSet<usize>,
+    lengths: &[f64],
+) -> Vec<f64> {
+    let upstream = upstream_segments(points, short);
+    let mut acc: Vec<Option<f64>> = vec![None; points.len()];
+    let mut visiting = HashSet::new();
+    // iterative post order, as the recursion can overflow the stack
+    // on long rivers
+    for i in 0..points.len() {
+        let mut stack = vec![(i, false)];
+        while let Some((j, done)) = stack.pop() {
+            if acc[j].is_some() {
+                continue;
+            }
+            let ups = upstream
+                .get(&points[j].0)
+                .map(|u| u.as_slice())
+                .unwrap_or(&[]);
+            if done {
+                let up = ups.iter().filter_map(|&u| acc[u]).fold(0.0, f64::max);
+                acc[j] = Some(lengths.get(j).copied().unwrap_or_default() + up);
+            } else if visiting.insert(j) {
+                stack.push((j, true));
+                stack.extend(
+                    ups.iter()
+                        .filter(|&&u| acc[u].is_none())
+                        .map(|&u| (u, false)),
+                );
+            }
+        }
+    }
+    acc.into_iter().map(Option::unwrap_or_default).collect()
+}
+
+/// Hierarchical tributary ids of the segments
+///
+/// The stem from each outlet is traced upstream following the branch
+/// with the largest weight at the confluences; the other branches
+/// are the tributaries of the stem, numbered from downstream to
+/// upstream (largest weight first at the same confluence).
+fn tributary_ids(
+    points: &[(Point2D, Point2D)],
+    short: &HashSet<usize>,
+    weights: &[f64],
+) -> HashMap<usize, String> {
+    let upstream = upstream_segments(points, short);
+    let starts: HashSet<&Point2D> = points
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !short.contains(i))
+        .map(|(_, (s, _))| s)
+        .collect();
+    let weight = |i: &usize| weights.get(*i).copied().unwrap_or_default();
+    let mut stems: VecDeque<(usize, String)> = points
+        .iter()
+        .enumerate()
+        .filter(|(i, (_, e))| !short.contains(i) && !starts.contains(e))
+        .enumerate()
+        .map(|(n, (i, _))| (i, (n + 1).to_string()))
+        .collect();
+    let mut labels = HashMap::new();
+    while let Some((start, label)) = stems.pop_front() {
+        let mut count = 0;
+        let mut cur = Some(start);
+        while let Some(c) = cur.take() {
+            if labels.contains_key(&c) {
+                break;
+            }
+            labels.insert(c, label.clone());
+            let mut ups: Vec<usize> = upstream.get(&points[c].0).cloned().unwrap_or_default();
+            ups.sort_by(|a, b| weight(b).total_cmp(&weight(a)));
+            let mut ups = ups.into_iter();
+            cur = ups.next();
+            for trib in ups {
+                count += 1;
+                stems.push_back((trib, format!("{label}.{count}")));
+            }
+        }
+    }
+    labels
+}
+
+/// Check if the streams are digitized from downstream to upstream
+///
+/// A stream network has a few outlets and many origins, so having
+/// more outlets than origins means the streamlines are reversed.
+pub fn is_reversed(layer: &mut Layer) -> anyhow::Result<bool> {
+    let (points, _) = get_endpoints(layer, false, false)?;
+    let starts: HashSet<&Point2D> = points.iter().map(|(s, _)| s).collect();
+    let ends: HashSet<&Point2D> = points.iter().map(|(_, e)| e).collect();
+    let origins = starts.difference(&ends).count();
+    let outlets = ends.difference(&starts).count();
+    let reversed = outlets > origins;
+    if reversed {
+        crate::warning!(
+            "Streams look digitized from downstream to upstream \
+             ({outlets} outlets, {origins} origins), reversing them"
+        );
+    }
+    Ok(reversed)
+}
+
+/// Parts of the (multi-)geometry as line strings
+fn geometry_parts(g: &Geometry) -> Vec<Geometry> {
+    let lin = linearize(g);
+    let g = lin.as_ref().unwrap_or(g);
+    match g.geometry_count() {
+        0 => vec![g.clone()],
+        n => (0..n).map(|j| g.get_geometry(j).clone()).collect(),
+    }
+}
+
+/// Start and end points of the segments, and the feature index of each
+///
+/// Each part of the multi-geometries is a segment, and the features
+/// without geometry are skipped, so the feature index (position in
+/// the layer) of the segments is returned to map them back.
 pub fn get_endpoints(
     layer: &mut Layer,
     verbose: bool,
     reverse: bool,
-) -> Result<Vec<(Point2D, Point2D)>, anyhow::Error> {
+) -> Result<(Vec<(Point2D, Point2D)>, Vec<usize>), anyhow::Error> {
     let total = layer.feature_count() as usize;
-    layer
-        .features()
-        .enumerate()
-        .filter_map(|(i, f)| {
-            if verbose {
-                print!(
-                    "\rReading Geometries: {}% ({} of {})",
-                    i * 100 / total,
-                    i,
-                    total
-                );
-            }
-            f.geometry().map(|g1| {
-                let gc = g1.geometry_count();
-                // for handling multi-geometry as well
-                if gc > 0 {
-                    (0..gc)
-                        .map(|j| {
-                            let g = g1.get_geometry(j);
-                            (g.get_point(0), g.get_point((g.point_count() - 1) as i32))
-                        })
-                        .collect()
-                } else {
-                    vec![(g1.get_point(0), g1.get_point((g1.point_count() - 1) as i32))]
-                }
-            })
-        })
-        .flatten()
-        .map(|(mut a, mut b)| {
+    let mut points = Vec::new();
+    let mut features = Vec::new();
+    for (i, f) in layer.features().enumerate() {
+        if verbose {
+            print!(
+                "\rReading Geometries: {}% ({} of {})",
+                i * 100 / total,
+                i,
+                total
+            );
+        }
+        let Some(g) = f.geometry() else {
+            continue;
+        };
+        for part in geometry_parts(g) {
+            let (mut a, mut b) = (
+                part.get_point(0),
+                part.get_point((part.point_count() - 1) as i32),
+            );
             if reverse {
                 (a, b) = (b, a);
             }
-            Ok((Point2D::new3(a)?, Point2D::new3(b)?))
-        })
-        .collect()
+            points.push((Point2D::new3(a)?, Point2D::new3(b)?));
+            features.push(i);
+        }
+    }
+    Ok((points, features))
 }
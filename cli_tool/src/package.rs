@@ -0,0 +1,242 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use gdal::vector::{Defn, Feature, FieldDefn, Layer, LayerAccess, LayerOptions, OGRFieldType};
+use gdal::Dataset;
+
+use crate::cliargs::CliAction;
+use crate::utils::*;
+
+#[derive(Args)]
+pub struct CliArgs {
+    /// Streams (subset) used in the study
+    #[arg(long, value_parser=parse_layer, value_name="FILE[::LAYER]")]
+    streams: Option<(PathBuf, String)>,
+    /// Points of interest (gauges, dams, ...)
+    #[arg(long, value_parser=parse_layer, value_name="FILE[::LAYER]")]
+    points: Option<(PathBuf, String)>,
+    /// Points snapped to the streams
+    #[arg(long, value_parser=parse_layer, value_name="FILE[::LAYER]")]
+    snapped: Option<(PathBuf, String)>,
+    /// Connections of the network
+    #[arg(long, value_parser=parse_layer, value_name="FILE[::LAYER]")]
+    network: Option<(PathBuf, String)>,
+    /// Nodes of the network with their attributes
+    #[arg(long, value_parser=parse_layer, value_name="FILE[::LAYER]")]
+    nodes: Option<(PathBuf, String)>,
+    /// Basin polygons of the nodes
+    #[arg(long, value_parser=parse_layer, value_name="FILE[::LAYER]")]
+    basins: Option<(PathBuf, String)>,
+    /// QGIS style (QML file) of a packaged layer as LAYER=QML_FILE
+    ///
+    /// Saved in the `layer_styles` table, so QGIS loads the layer
+    /// with the style by default. Can be repeated.
+    #[arg(short, long, value_parser=parse_style, value_name="LAYER=QML_FILE")]
+    style: Vec<(String, PathBuf)>,
+    /// JSON manifest of the runs (from `--manifest`) to include
+    ///
+    /// Saved in the `manifest` table with the file name and the
+    /// contents. Can be repeated.
+    #[arg(short, long, value_name = "JSON_FILE")]
+    run_manifest: Vec<PathBuf>,
+    /// Overwrite the output file if it exists
+    #[arg(short = 'O', long)]
+    overwrite: bool,
+    /// Print progress
+    #[arg(short, long)]
+    verbose: bool,
+    /// Output GeoPackage file
+    output: PathBuf,
+}
+
+fn parse_style(arg: &str) -> Result<(String, PathBuf), anyhow::Error> {
+    let (layer, file) = arg.split_once('=').ok_or(anyhow::Error::msg(
+        "Style should be in LAYER=QML_FILE format",
+    ))?;
+    Ok((layer.to_string(), PathBuf::from(file)))
+}
+
+impl CliAction for CliArgs {
+    fn run(mut self) -> Result<(), anyhow::Error> {
+        for l in [
+            &mut self.streams,
+            &mut self.points,
+            &mut self.snapped,
+            &mut self.network,
+            &mut self.nodes,
+            &mut self.basins,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            resolve_layer(l)?;
+        }
+        let layers: Vec<(&str, &(PathBuf, String))> = [
+            ("streams", &self.streams),
+            ("points", &self.points),
+            ("snapped", &self.snapped),
+            ("network", &self.network),
+            ("nodes", &self.nodes),
+            ("basins", &self.basins),
+        ]
+        .into_iter()
+        .filter_map(|(name, l)| Some((name, l.as_ref()?)))
+        .collect();
+        if layers.is_empty() {
+            return Err(anyhow::Error::msg("No layers given to package"));
+        }
+        if let Some((name, _)) = self
+            .style
+            .iter()
+            .find(|(name, _)| !layers.iter().any(|l| l.0 == name))
+        {
+            return Err(anyhow::Error::msg(format!(
+                "Style given for {name:?} which is not packaged"
+            )));
+        }
+
+        let mut out_data =
+            gdal_update_or_create(&self.output, &Some("GPKG".to_string()), self.overwrite)?;
+        let mut trans = false;
+        // have to use trans flag here because of borrow rule;
+        // uses transaction when it can to speed up the process.
+        if let Ok(mut txn) = out_data.start_transaction() {
+            self.write_package(&layers, &mut txn)?;
+            txn.commit()?;
+            trans = true;
+        };
+        if !trans {
+            self.write_package(&layers, &mut out_data)?;
+        }
+        Ok(())
+    }
+}
+
+impl CliArgs {
+    fn write_package(
+        &self,
+        layers: &[(&str, &(PathBuf, String))],
+        out_data: &mut Dataset,
+    ) -> anyhow::Result<()> {
+        for (name, (file, lyr_name)) in layers {
+            let data = Dataset::open(file)?;
+            crate::manifest::input(file, Some(lyr_name));
+            let mut lyr = data.layer_by_name(lyr_name)?;
+            let count = copy_layer(&mut lyr, out_data, name)?;
+            if self.verbose {
+                println!("{name}: {count} features from {file:?}");
+            }
+        }
+        if !self.style.is_empty() {
+            self.write_styles(out_data)?;
+        }
+        if !self.run_manifest.is_empty() {
+            let layer = out_data.create_layer(LayerOptions {
+                name: "manifest",
+                ty: gdal_sys::OGRwkbGeometryType::wkbNone,
+                ..Default::default()
+            })?;
+            layer.create_defn_fields(&[
+                ("file", OGRFieldType::OFTString),
+                ("json", OGRFieldType::OFTString),
+            ])?;
+            let defn = Defn::from_layer(&layer);
+            for file in &self.run_manifest {
+                let json = std::fs::read_to_string(file)?;
+                let mut ft = Feature::new(&defn)?;
+                ft.set_field_string(0, &file.to_string_lossy())?;
+                ft.set_field_string(1, &json)?;
+                ft.create(&layer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Save the QML styles in the table QGIS reads the default styles from
+    fn write_styles(&self, out_data: &mut Dataset) -> anyhow::Result<()> {
+        let layer = match out_data.layer_by_name("layer_styles") {
+            Ok(l) => l,
+            Err(_) => {
+                let layer = out_data.create_layer(LayerOptions {
+                    name: "layer_styles",
+                    ty: gdal_sys::OGRwkbGeometryType::wkbNone,
+                    ..Default::default()
+                })?;
+                layer.create_defn_fields(&[
+                    ("f_table_catalog", OGRFieldType::OFTString),
+                    ("f_table_schema", OGRFieldType::OFTString),
+                    ("f_table_name", OGRFieldType::OFTString),
+                    ("f_geometry_column", OGRFieldType::OFTString),
+                    ("styleName", OGRFieldType::OFTString),
+                    ("styleQML", OGRFieldType::OFTString),
+                    ("styleSLD", OGRFieldType::OFTString),
+                    ("useAsDefault", OGRFieldType::OFTInteger),
+                    ("description", OGRFieldType::OFTString),
+                    ("owner", OGRFieldType::OFTString),
+                ])?;
+                layer
+            }
+        };
+        let defn = Defn::from_layer(&layer);
+        let idx = |f: &str| defn.field_index(f);
+        for (name, file) in &self.style {
+            let qml = std::fs::read_to_string(file)?;
+            let mut ft = Feature::new(&defn)?;
+            ft.set_field_string(idx("f_table_catalog")?, "")?;
+            ft.set_field_string(idx("f_table_schema")?, "")?;
+            ft.set_field_string(idx("f_table_name")?, name)?;
+            ft.set_field_string(idx("f_geometry_column")?, "geom")?;
+            ft.set_field_string(idx("styleName")?, name)?;
+            ft.set_field_string(idx("styleQML")?, &qml)?;
+            ft.set_field_string(idx("styleSLD")?, "")?;
+            ft.set_field_integer(idx("useAsDefault")?, 1)?;
+            ft.set_field_string(idx("description")?, &file.to_string_lossy())?;
+            ft.create(&layer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Copy the features of the layer with all the fields, returns the count
+fn copy_layer(src: &mut Layer, out_data: &mut Dataset, name: &str) -> anyhow::Result<usize> {
+    let sref = OutputSrs::new(src.spatial_ref().as_ref())?;
+    let ty = src
+        .defn()
+        .geom_fields()
+        .next()
+        .map(|g| g.field_type())
+        .unwrap_or(gdal_sys::OGRwkbGeometryType::wkbUnknown);
+    let mut layer = out_data.create_layer(LayerOptions {
+        name,
+        srs: sref.srs(),
+        ty,
+        ..Default::default()
+    })?;
+    stamp_metadata(&mut layer)?;
+    let fields_defn = src
+        .defn()
+        .fields()
+        .map(|field| (field.name(), field.field_type(), field.width()))
+        .collect::<Vec<_>>();
+    for fd in &fields_defn {
+        let field_defn = FieldDefn::new(&fd.0, fd.1)?;
+        field_defn.set_width(fd.2);
+        field_defn.add_to_layer(&layer)?;
+    }
+    let defn = Defn::from_layer(&layer);
+    let mut count = 0;
+    for feat in src.features() {
+        let mut ft = Feature::new(&defn)?;
+        if let Some(geom) = feat.geometry() {
+            ft.set_geometry(sref.geometry(geom.clone())?)?;
+        }
+        for j in 0..fields_defn.len() {
+            if let Some(value) = feat.field(j)? {
+                ft.set_field(j, &value)?;
+            }
+        }
+        ft.create(&layer)?;
+        count += 1;
+    }
+    Ok(count)
+}
@@ -0,0 +1,126 @@
+use std::ffi::{c_char, CString};
+use std::path::PathBuf;
+
+use clap::Args;
+use gdal::raster::RasterBand;
+use gdal::spatial_ref::SpatialRef;
+use gdal::vector::{Defn, Feature, LayerAccess, LayerOptions, OGRFieldType};
+use gdal::{Dataset, DriverManager};
+
+use crate::cliargs::CliAction;
+use crate::utils::*;
+
+#[derive(Args)]
+pub struct CliArgs {
+    /// Band of the raster with the classes
+    #[arg(short, long, default_value = "1")]
+    band: usize,
+    /// Name of the field for the class value
+    #[arg(short, long, default_value = "class")]
+    field: String,
+    /// Use 8 connectedness instead of 4 to group cells
+    #[arg(short, long)]
+    eight: bool,
+    /// Output driver [default: based on file extension]
+    #[arg(short, long)]
+    driver: Option<String>,
+    /// Overwrite the output file if it exists
+    #[arg(short = 'O', long)]
+    overwrite: bool,
+    /// Raster file with the classes (e.g. watershed, land cover)
+    raster: PathBuf,
+    /// Output file
+    #[arg(value_parser=parse_new_layer)]
+    output: (PathBuf, Option<String>),
+}
+
+impl CliAction for CliArgs {
+    fn run(self) -> Result<(), anyhow::Error> {
+        let raster = Dataset::open(&self.raster)?;
+        crate::manifest::input(&self.raster, None);
+        let band = raster.rasterband(self.band)?;
+        let sref = raster.spatial_ref().ok();
+        let out_srs = OutputSrs::new(sref.as_ref())?;
+
+        let mut out_data = gdal_update_or_create(&self.output.0, &self.driver, self.overwrite)?;
+        let lyr_name = self.output.1.as_deref().unwrap_or("polygons");
+        let mut trans = false;
+        // have to use trans flag here because of borrow rule;
+        // uses transaction when it can to speed up the process.
+        if let Ok(mut txn) = out_data.start_transaction() {
+            self.write_polygons(&band, &mut txn, lyr_name, sref.as_ref(), &out_srs)?;
+            txn.commit()?;
+            trans = true;
+        };
+        if !trans {
+            self.write_polygons(&band, &mut out_data, lyr_name, sref.as_ref(), &out_srs)?;
+        }
+        Ok(())
+    }
+}
+
+impl CliArgs {
+    fn write_polygons(
+        &self,
+        band: &RasterBand,
+        ds: &mut Dataset,
+        lyr: &str,
+        sref: Option<&SpatialRef>,
+        out_srs: &OutputSrs,
+    ) -> anyhow::Result<()> {
+        // polygonize in memory, so they can be reprojected when saving
+        let mut mem = DriverManager::get_driver_by_name("Memory")?.create_vector_only("")?;
+        let mut polygons = mem.create_layer(LayerOptions {
+            name: "polygons",
+            srs: sref,
+            ty: gdal_sys::OGRwkbGeometryType::wkbPolygon,
+            ..Default::default()
+        })?;
+        polygons.create_defn_fields(&[(&self.field, OGRFieldType::OFTInteger)])?;
+        let conn = CString::new("8CONNECTED=8")?;
+        let mut options = [conn.as_ptr() as *mut c_char, std::ptr::null_mut()];
+        let options = if self.eight {
+            options.as_mut_ptr()
+        } else {
+            std::ptr::null_mut()
+        };
+        // the mask band excludes the nodata cells from the polygons
+        let err = unsafe {
+            let c_band = band.c_rasterband();
+            gdal_sys::GDALPolygonize(
+                c_band,
+                gdal_sys::GDALGetMaskBand(c_band),
+                polygons.c_layer(),
+                0,
+                options,
+                None,
+                std::ptr::null_mut(),
+            )
+        };
+        if err != gdal_sys::CPLErr::CE_None {
+            anyhow::bail!("Failed to polygonize the raster {:?}", self.raster);
+        }
+
+        let mut layer = ds.create_layer(LayerOptions {
+            name: lyr,
+            srs: out_srs.srs(),
+            ty: gdal_sys::OGRwkbGeometryType::wkbPolygon,
+            ..Default::default()
+        })?;
+        stamp_metadata(&mut layer)?;
+        layer.create_defn_fields(&[(&self.field, OGRFieldType::OFTInteger)])?;
+        let defn = Defn::from_layer(&layer);
+        for f in polygons.features() {
+            let Some(geom) = f.geometry() else {
+                continue;
+            };
+            let mut ft = Feature::new(&defn)?;
+            ft.set_geometry(out_srs.geometry(geom.clone())?)?;
+            if let Some(v) = f.field_as_integer(0)? {
+                ft.set_field_integer(0, v)?;
+            }
+            ft.create(&mut layer)?;
+        }
+        Ok(())
+    }
+}
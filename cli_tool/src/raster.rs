@@ -0,0 +1,249 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use gdal::raster::Buffer;
+use gdal::spatial_ref::{AxisMappingStrategy, CoordTransform, SpatialRef};
+use gdal::vector::{Geometry, LayerAccess};
+use gdal::{Dataset, DriverManager, DriverType};
+
+use crate::utils::{linearize, stamp_metadata};
+
+/// Sample the values of a raster band at the given coordinates
+///
+/// Coordinates should be in the spatial reference of the raster.
+pub struct RasterSampler {
+    data: Dataset,
+    band: usize,
+    inv_transform: [f64; 6],
+    nodata: Option<f64>,
+}
+
+impl RasterSampler {
+    pub fn open<P: AsRef<Path>>(file: P, band: usize) -> Result<Self> {
+        let data = Dataset::open(file.as_ref())
+            .with_context(|| format!("Cannot open raster {:?}", file.as_ref()))?;
+        let inv_transform = invert_geo_transform(&data.geo_transform()?)
+            .context("Raster geo transform is not invertible")?;
+        let nodata = data.rasterband(band)?.no_data_value();
+        Ok(Self {
+            data,
+            band,
+            inv_transform,
+            nodata,
+        })
+    }
+
+    pub fn dataset(&self) -> &Dataset {
+        &self.data
+    }
+
+    /// Row and column of the cell containing the coordinates
+    pub fn cell(&self, x: f64, y: f64) -> Option<(usize, usize)> {
+        let t = &self.inv_transform;
+        let col = (t[0] + x * t[1] + y * t[2]).floor();
+        let row = (t[3] + x * t[4] + y * t[5]).floor();
+        let (ncol, nrow) = self.data.raster_size();
+        if col < 0.0 || row < 0.0 || col >= ncol as f64 || row >= nrow as f64 {
+            None
+        } else {
+            Some((row as usize, col as usize))
+        }
+    }
+
+    /// Value of the cell, None if the cell is nodata
+    pub fn cell_value(&self, row: usize, col: usize) -> Result<Option<f64>> {
+        let band = self.data.rasterband(self.band)?;
+        let buf = band.read_as::<f64>((col as isize, row as isize), (1, 1), (1, 1), None)?;
+        let val = buf.data()[0];
+        match self.nodata {
+            Some(nd) if nd == val || (nd.is_nan() && val.is_nan()) => Ok(None),
+            _ => Ok(Some(val)),
+        }
+    }
+
+    /// Value at the coordinates, None if outside the raster or nodata
+    pub fn sample(&self, x: f64, y: f64) -> Result<Option<f64>> {
+        match self.cell(x, y) {
+            Some((row, col)) => self.cell_value(row, col),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Invert the affine geo transform, to go from coordinates to pixel/line
+pub fn invert_geo_transform(gt: &[f64; 6]) -> Option<[f64; 6]> {
+    let det = gt[1] * gt[5] - gt[2] * gt[4];
+    if det == 0.0 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    Some([
+        (gt[2] * gt[3] - gt[0] * gt[5]) * inv_det,
+        gt[5] * inv_det,
+        -gt[2] * inv_det,
+        (-gt[1] * gt[3] + gt[0] * gt[4]) * inv_det,
+        -gt[4] * inv_det,
+        gt[1] * inv_det,
+    ])
+}
+
+/// Whole raster band in memory, to modify the cells and save it
+pub struct RasterGrid {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f64>,
+    pub nodata: Option<f64>,
+    transform: [f64; 6],
+    inv_transform: [f64; 6],
+    sref: Option<SpatialRef>,
+}
+
+impl RasterGrid {
+    pub fn open<P: AsRef<Path>>(file: P, band: usize) -> Result<Self> {
+        let data = Dataset::open(file.as_ref())
+            .with_context(|| format!("Cannot open raster {:?}", file.as_ref()))?;
+        let transform = data.geo_transform()?;
+        let inv_transform =
+            invert_geo_transform(&transform).context("Raster geo transform is not invertible")?;
+        let mut sref = data.spatial_ref().ok();
+        if let Some(s) = sref.as_mut() {
+            s.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+        }
+        let rb = data.rasterband(band)?;
+        let (width, height) = data.raster_size();
+        let nodata = rb.no_data_value();
+        let buf = rb.read_as::<f64>((0, 0), (width, height), (width, height), None)?;
+        Ok(Self {
+            width,
+            height,
+            data: buf.data().to_vec(),
+            nodata,
+            transform,
+            inv_transform,
+            sref,
+        })
+    }
+
+    pub fn spatial_ref(&self) -> Option<&SpatialRef> {
+        self.sref.as_ref()
+    }
+
+    /// Smaller of the cell width and height
+    pub fn cell_size(&self) -> f64 {
+        self.transform[1].abs().min(self.transform[5].abs())
+    }
+
+    /// Row and column of the cell containing the coordinates
+    pub fn cell(&self, x: f64, y: f64) -> Option<(usize, usize)> {
+        let t = &self.inv_transform;
+        let col = (t[0] + x * t[1] + y * t[2]).floor();
+        let row = (t[3] + x * t[4] + y * t[5]).floor();
+        if col < 0.0 || row < 0.0 || col >= self.width as f64 || row >= self.height as f64 {
+            None
+        } else {
+            Some((row as usize, col as usize))
+        }
+    }
+
+    /// Coordinates of the center of the cell
+    pub fn center(&self, row: usize, col: usize) -> (f64, f64) {
+        let t = &self.transform;
+        let (c, r) = (col as f64 + 0.5, row as f64 + 0.5);
+        (t[0] + c * t[1] + r * t[2], t[3] + c * t[4] + r * t[5])
+    }
+
+    /// Value of the cell, None if the cell is nodata
+    pub fn get(&self, row: usize, col: usize) -> Option<f64> {
+        let val = self.data[row * self.width + col];
+        match self.nodata {
+            Some(nd) if nd == val || (nd.is_nan() && val.is_nan()) => None,
+            _ => Some(val),
+        }
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, val: f64) {
+        self.data[row * self.width + col] = val;
+    }
+
+    /// Save the grid as a single band raster
+    pub fn save(&self, file: &Path, driver: &Option<String>) -> Result<()> {
+        let driver = if let Some(d) = driver {
+            DriverManager::get_driver_by_name(d)?
+        } else {
+            DriverManager::get_output_driver_for_dataset_name(file, DriverType::Raster)
+                .context("Driver not found for the output filename")?
+        };
+        crate::manifest::output(file);
+        let mut out = driver.create_with_band_type::<f32, _>(file, self.width, self.height, 1)?;
+        out.set_geo_transform(&self.transform)?;
+        if let Some(s) = &self.sref {
+            out.set_spatial_ref(s)?;
+        }
+        stamp_metadata(&mut out)?;
+        let mut band = out.rasterband(1)?;
+        band.set_no_data_value(self.nodata)?;
+        let data = self.data.iter().map(|v| *v as f32).collect();
+        let mut buf = Buffer::new((self.width, self.height), data);
+        band.write((0, 0), (self.width, self.height), &mut buf)?;
+        Ok(())
+    }
+}
+
+/// Geometries of the layer in the given spatial reference, curves linearized
+pub fn layer_geometries(
+    input: &(PathBuf, String),
+    sref: Option<&SpatialRef>,
+) -> Result<Vec<Geometry>> {
+    let data = Dataset::open(&input.0)?;
+    crate::manifest::input(&input.0, Some(&input.1));
+    let mut lyr = data.layer_by_name(&input.1)?;
+    let trans = match (lyr.spatial_ref(), sref) {
+        (Some(mut s), Some(t)) => {
+            s.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+            Some(CoordTransform::new(&s, t)?)
+        }
+        _ => None,
+    };
+    let mut geometries = Vec::new();
+    for f in lyr.features() {
+        let Some(geom) = f.geometry() else {
+            continue;
+        };
+        let lin = linearize(geom);
+        let geom = lin.as_ref().unwrap_or(geom);
+        geometries.push(match &trans {
+            Some(t) => geom.transform(t)?,
+            None => geom.clone(),
+        });
+    }
+    Ok(geometries)
+}
+
+/// Points along the lines of the geometry at the given spacing
+///
+/// Each part of a multi-geometry is a separate list of the points
+/// with their distance from the start of the part.
+pub fn line_samples(geom: &Geometry, step: f64) -> Vec<Vec<(f64, f64, f64)>> {
+    if geom.geometry_count() > 0 {
+        return (0..geom.geometry_count())
+            .flat_map(|i| line_samples(&geom.get_geometry(i), step))
+            .collect();
+    }
+    let pts = geom.get_point_vec();
+    let Some(&(x0, y0, _)) = pts.first() else {
+        return vec![];
+    };
+    let mut samples = vec![(x0, y0, 0.0)];
+    let mut dist = 0.0;
+    for w in pts.windows(2) {
+        let (dx, dy) = (w[1].0 - w[0].0, w[1].1 - w[0].1);
+        let len = dx.hypot(dy);
+        let n = (len / step).ceil().max(1.0) as usize;
+        for i in 1..=n {
+            let f = i as f64 / n as f64;
+            samples.push((w[0].0 + dx * f, w[0].1 + dy * f, dist + len * f));
+        }
+        dist += len;
+    }
+    vec![samples]
+}
@@ -0,0 +1,112 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::Args;
+use gdal::raster::{rasterize, RasterizeOptions};
+use gdal::spatial_ref::{AxisMappingStrategy, CoordTransform};
+use gdal::vector::LayerAccess;
+use gdal::{Dataset, DriverManager, DriverType};
+
+use crate::cliargs::CliAction;
+use crate::utils::*;
+
+#[derive(Args)]
+pub struct CliArgs {
+    /// Field with the values to burn [default: burn 1]
+    #[arg(short, long)]
+    field: Option<String>,
+    /// Burn all the cells touched by the geometries
+    #[arg(short, long)]
+    all_touched: bool,
+    /// Value for the cells without any geometries
+    #[arg(short, long, default_value = "0")]
+    nodata: f64,
+    /// Output driver [default: based on file extension]
+    #[arg(short, long)]
+    driver: Option<String>,
+    /// Print progress
+    #[arg(short, long)]
+    verbose: bool,
+    /// Raster to take the extent, resolution and projection from
+    #[arg(short, long)]
+    template: PathBuf,
+    /// Vector file to rasterize
+    #[arg(value_parser=parse_layer, value_name="GIS_FILE[::LAYER]")]
+    input: (PathBuf, String),
+    /// Output raster file
+    output: PathBuf,
+}
+
+impl CliAction for CliArgs {
+    fn run(mut self) -> Result<(), anyhow::Error> {
+        resolve_layer(&mut self.input)?;
+        let template = Dataset::open(&self.template)?;
+        crate::manifest::input(&self.template, None);
+        let (width, height) = template.raster_size();
+        let mut tsref = template.spatial_ref()?;
+        tsref.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+
+        let data = Dataset::open(&self.input.0)?;
+        let mut lyr = data.layer_by_name(&self.input.1)?;
+        let fid = match &self.field {
+            Some(f) => Some(lyr.defn().field_index(f)?),
+            None => None,
+        };
+        let trans = match lyr.spatial_ref() {
+            Some(mut sref) => {
+                sref.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+                Some(CoordTransform::new(&sref, &tsref)?)
+            }
+            None => {
+                crate::warning!("Input layer doesn't have spatial reference, assuming raster's");
+                None
+            }
+        };
+        let mut geometries = Vec::new();
+        let mut values = Vec::new();
+        for f in lyr.features() {
+            let Some(geom) = f.geometry() else {
+                continue;
+            };
+            let value = match fid {
+                Some(i) => match field_as_number(&f, i)? {
+                    Some(v) => v,
+                    None => continue,
+                },
+                None => 1.0,
+            };
+            let geom = match &trans {
+                Some(t) => geom.transform(t)?,
+                None => geom.clone(),
+            };
+            geometries.push(geom);
+            values.push(value);
+        }
+        if self.verbose {
+            println!("Rasterizing {} features", geometries.len());
+        }
+
+        let driver = if let Some(d) = &self.driver {
+            DriverManager::get_driver_by_name(d)?
+        } else {
+            DriverManager::get_output_driver_for_dataset_name(&self.output, DriverType::Raster)
+                .context("Driver not found for the output filename")?
+        };
+        crate::manifest::output(&self.output);
+        let mut out = driver.create_with_band_type::<f32, _>(&self.output, width, height, 1)?;
+        out.set_geo_transform(&template.geo_transform()?)?;
+        out.set_spatial_ref(&tsref)?;
+        stamp_metadata(&mut out)?;
+        {
+            let mut band = out.rasterband(1)?;
+            band.set_no_data_value(Some(self.nodata))?;
+            band.fill(self.nodata, None)?;
+        }
+        let options = RasterizeOptions {
+            all_touched: self.all_touched,
+            ..Default::default()
+        };
+        rasterize(&mut out, &[1], &geometries, &values, Some(options))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+
+use clap::Args;
+use gdal::vector::{Defn, Feature, FieldDefn, Layer, LayerAccess, LayerOptions, OGRFieldType};
+use gdal::Dataset;
+
+use crate::cliargs::CliAction;
+use crate::order::is_reversed;
+use crate::types::*;
+use crate::utils::*;
+
+#[derive(Args)]
+pub struct CliArgs {
+    /// Field with the river names
+    #[arg(short, long, default_value = "GNIS_NAME")]
+    name_field: String,
+    /// Include the tributaries up to this level
+    ///
+    /// Streams flowing into the river are level 1, streams flowing
+    /// into those are level 2 and so on. The level goes up where the
+    /// name of the stream changes, so the unnamed headwaters are in
+    /// the level of the stream they flow into.
+    #[arg(short, long, default_value = "0")]
+    tributaries: usize,
+    /// reverse the direction of streamlines
+    ///
+    /// Algorithm assumes the geometry starts from upstream and goes
+    /// to downstream. If it's reverse use this flag.
+    #[arg(short, long, visible_alias = "flip", action)]
+    reverse: bool,
+    /// Detect the direction of the streamlines and reverse them if needed
+    #[arg(long, action, conflicts_with = "reverse")]
+    auto_flip: bool,
+    /// Output driver [default: based on file extension]
+    #[arg(short, long)]
+    driver: Option<String>,
+    /// Overwrite the output file if it exists
+    #[arg(short = 'O', long)]
+    overwrite: bool,
+    /// Print progress
+    #[arg(short, long)]
+    verbose: bool,
+    /// Name of the river (case insensitive)
+    river: String,
+    /// Streams vector file with streams network
+    #[arg(value_parser=parse_layer, value_name="STREAMS_FILE[::LAYER]")]
+    streams: (PathBuf, String),
+    /// Output file
+    #[arg(value_parser=parse_new_layer)]
+    output: (PathBuf, Option<String>),
+}
+
+/// Stream segment with the name and the end points
+struct Segment {
+    name: String,
+    start: Point2D,
+    end: Point2D,
+}
+
+impl CliAction for CliArgs {
+    fn run(mut self) -> Result<(), anyhow::Error> {
+        resolve_layer(&mut self.streams)?;
+        let streams_data = Dataset::open(&self.streams.0)?;
+        let mut streams_lyr = streams_data.layer_by_name(&self.streams.1)?;
+        if self.auto_flip {
+            self.reverse = is_reversed(&mut streams_lyr)?;
+        }
+        let segments = self.segments(&mut streams_lyr)?;
+        let levels = self.levels(&segments);
+        if levels.is_empty() {
+            crate::warning!("River {:?} not found in the streams", self.river);
+            return Ok(());
+        }
+        if self.verbose {
+            println!("Extracting {} of {} segments", levels.len(), segments.len());
+        }
+
+        let lyr_name = self.output.1.as_deref().unwrap_or("river");
+        let sref = OutputSrs::new(streams_lyr.spatial_ref().as_ref())?;
+        let mut out_data = gdal_update_or_create(&self.output.0, &self.driver, self.overwrite)?;
+        let mut trans = false;
+        // have to use trans flag here because of borrow rule;
+        // uses transaction when it can to speed up the process.
+        if let Ok(mut txn) = out_data.start_transaction() {
+            write_layer(&levels, &mut txn, &mut streams_lyr, lyr_name, &sref)?;
+            txn.commit()?;
+            trans = true;
+        };
+        if !trans {
+            write_layer(&levels, &mut out_data, &mut streams_lyr, lyr_name, &sref)?;
+        }
+        Ok(())
+    }
+}
+
+impl CliArgs {
+    /// Segments in the order of the features, None for the ones without geometry
+    fn segments(&self, layer: &mut Layer) -> anyhow::Result<Vec<Option<Segment>>> {
+        let idx = layer.defn().field_index(&self.name_field)?;
+        layer
+            .features()
+            .map(|f| {
+                let Some(g) = f.geometry() else {
+                    return Ok(None);
+                };
+                let lin = linearize(g);
+                let g = lin.as_ref().unwrap_or(g);
+                // first and last points of the multi-geometries
+                let (first, last) = match g.geometry_count() {
+                    0 => (g.clone(), g.clone()),
+                    n => (g.get_geometry(0).clone(), g.get_geometry(n - 1).clone()),
+                };
+                if first.point_count() == 0 || last.point_count() == 0 {
+                    return Ok(None);
+                }
+                let mut start = Point2D::new3(first.get_point(0))?;
+                let mut end = Point2D::new3(last.get_point((last.point_count() - 1) as i32))?;
+                if self.reverse {
+                    (start, end) = (end, start);
+                }
+                let name = f.field_as_string(idx)?.unwrap_or_default();
+                Ok(Some(Segment { name, start, end }))
+            })
+            .collect()
+    }
+
+    /// Tributary level of the segments of the river and its tributaries
+    fn levels(&self, segments: &[Option<Segment>]) -> HashMap<usize, usize> {
+        let mut upstream: HashMap<&Point2D, Vec<usize>> = HashMap::new();
+        for (i, s) in segments.iter().enumerate() {
+            if let Some(s) = s {
+                upstream.entry(&s.end).or_default().push(i);
+            }
+        }
+        let river = self.river.trim().to_lowercase();
+        let mut levels: HashMap<usize, usize> = HashMap::new();
+        let mut queue = VecDeque::new();
+        for (i, s) in segments.iter().enumerate() {
+            if s.as_ref()
+                .is_some_and(|s| s.name.trim().to_lowercase() == river)
+            {
+                levels.insert(i, 0);
+                queue.push_back(i);
+            }
+        }
+        // breadth first, so the segments get their lowest level
+        while let Some(i) = queue.pop_front() {
+            let Some(seg) = &segments[i] else {
+                continue;
+            };
+            let level = levels[&i];
+            for &u in upstream.get(&seg.start).into_iter().flatten() {
+                let Some(up) = &segments[u] else {
+                    continue;
+                };
+                let lvl = level + (up.name != seg.name) as usize;
+                if lvl <= self.tributaries && !levels.contains_key(&u) {
+                    levels.insert(u, lvl);
+                    queue.push_back(u);
+                }
+            }
+        }
+        levels
+    }
+}
+
+fn write_layer(
+    levels: &HashMap<usize, usize>,
+    out_data: &mut Dataset,
+    streams_lyr: &mut Layer,
+    lyr_name: &str,
+    sref: &OutputSrs,
+) -> anyhow::Result<()> {
+    let mut layer = out_data.create_layer(LayerOptions {
+        name: lyr_name,
+        srs: sref.srs(),
+        ty: gdal_sys::OGRwkbGeometryType::wkbLineString,
+        ..Default::default()
+    })?;
+    stamp_metadata(&mut layer)?;
+
+    let fields_defn = streams_lyr
+        .defn()
+        .fields()
+        .map(|field| (field.name(), field.field_type(), field.width()))
+        .collect::<Vec<_>>();
+    for fd in &fields_defn {
+        let field_defn = FieldDefn::new(&fd.0, fd.1)?;
+        field_defn.set_width(fd.2);
+        field_defn.add_to_layer(&layer)?;
+    }
+    FieldDefn::new("level", OGRFieldType::OFTInteger)?.add_to_layer(&layer)?;
+    let lid = layer
+        .defn()
+        .field_index("level")
+        .expect("Just added level field");
+    let defn = Defn::from_layer(&layer);
+    for (i, feat) in streams_lyr.features().enumerate() {
+        let (Some(level), Some(geom)) = (levels.get(&i), feat.geometry()) else {
+            continue;
+        };
+        let mut ft = Feature::new(&defn)?;
+        ft.set_geometry(sref.geometry(geom.clone())?)?;
+        for j in 0..fields_defn.len() {
+            if let Some(value) = feat.field(j)? {
+                ft.set_field(j, &value)?;
+            }
+        }
+        ft.set_field_integer(lid, *level as i32)?;
+        ft.create(&layer)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use gdal::vector::OGRFieldType;
+use toml::{Table, Value};
+
+/// Types the fields can be converted to
+pub const FIELD_TYPES: [&str; 6] = ["String", "Integer", "Float", "Bool", "Date", "DateTime"];
+
+/// New name and type of a field
+#[derive(Clone, Debug)]
+pub struct FieldMap {
+    pub name: String,
+    pub ty: Option<String>,
+}
+
+/// Mapping of field names to new names and types
+///
+/// The mapping file is a TOML file where keys are the field names in
+/// the GIS file, and values are either the new name, or a table with
+/// `name` and/or `type` keys.
+///
+/// ```toml
+/// GNIS_NAME = "name"
+/// TotDASqKm = { name = "drainage_area", type = "Float" }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Schema(HashMap<String, FieldMap>);
+
+impl Schema {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let contents = std::fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Cannot read schema file {:?}", path.as_ref()))?;
+        let table: Table = toml::from_str(&contents)
+            .with_context(|| format!("Invalid schema file {:?}", path.as_ref()))?;
+        let fields = table
+            .into_iter()
+            .map(|(k, v)| {
+                let fm = match v {
+                    Value::String(name) => FieldMap { name, ty: None },
+                    Value::Table(t) => FieldMap {
+                        name: t
+                            .get("name")
+                            .and_then(Value::as_str)
+                            .unwrap_or(&k)
+                            .to_string(),
+                        ty: t.get("type").and_then(Value::as_str).map(String::from),
+                    },
+                    v => {
+                        return Err(anyhow::Error::msg(format!(
+                            "Invalid schema for field {k}: {v}"
+                        )))
+                    }
+                };
+                if let Some(ty) = fm.ty.as_deref().filter(|t| !FIELD_TYPES.contains(t)) {
+                    return Err(anyhow::Error::msg(format!(
+                        "Type {ty} of field {k} not supported. Use {}",
+                        FIELD_TYPES.join(", ")
+                    )));
+                }
+                Ok((k, fm))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self(fields))
+    }
+
+    /// New name of the field, same name if it's not in the mapping
+    pub fn rename<'a>(&'a self, field: &'a str) -> &'a str {
+        self.0.get(field).map(|f| f.name.as_str()).unwrap_or(field)
+    }
+
+    /// New type of the field, if it needs to be converted
+    pub fn field_type(&self, field: &str) -> Option<&str> {
+        self.0.get(field).and_then(|f| f.ty.as_deref())
+    }
+}
+
+/// OGR field type from the type name, one of [`FIELD_TYPES`]
+pub fn ogr_type(name: &str) -> Result<OGRFieldType::Type> {
+    Ok(match name {
+        "String" => OGRFieldType::OFTString,
+        "Integer" => OGRFieldType::OFTInteger64,
+        "Float" => OGRFieldType::OFTReal,
+        // GDAL stores booleans as integers
+        "Bool" => OGRFieldType::OFTInteger,
+        "Date" => OGRFieldType::OFTDate,
+        "DateTime" => OGRFieldType::OFTDateTime,
+        t => anyhow::bail!("Type {t} not supported. Use {}", FIELD_TYPES.join(", ")),
+    })
+}
@@ -0,0 +1,318 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Args;
+use gdal::Dataset;
+use rstar::RTree;
+
+use crate::cliargs::CliAction;
+use crate::manifest::json_str;
+use crate::network::read_stream_points;
+use crate::types::Point2D;
+use crate::utils::*;
+
+#[derive(Args)]
+pub struct CliArgs {
+    /// Address to listen on
+    #[arg(short, long, default_value = "127.0.0.1:8080")]
+    address: String,
+    /// Take every nth point from the stream geometry
+    #[arg(short, long, default_value = "1")]
+    take: usize,
+    /// reverse the direction of streamlines
+    #[arg(short, long, action)]
+    reverse: bool,
+    /// Threashold distance for the snapping to streams
+    ///
+    /// In the units of the streams layer, or with a unit suffix like
+    /// `100m`, `2km`, `50ft` or `0.001deg`.
+    #[arg(short = 'T', long, value_parser=parse_distance)]
+    threshold: Option<Distance>,
+    /// Print the requests
+    #[arg(short, long)]
+    verbose: bool,
+    /// Streams vector file with streams network
+    #[arg(value_parser=parse_layer, value_name="STREAMS_FILE[::LAYER]")]
+    streams: (PathBuf, String),
+}
+
+impl CliAction for CliArgs {
+    fn run(mut self) -> Result<(), anyhow::Error> {
+        resolve_layer(&mut self.streams)?;
+        let streams_data = Dataset::open(&self.streams.0)?;
+        let mut streams_lyr = streams_data.layer_by_name(&self.streams.1)?;
+        let graph = Graph::new(
+            read_stream_points(&mut streams_lyr, self.verbose, self.take, self.reverse)?,
+            self.threshold
+                .map(|t| t.in_units(streams_lyr.spatial_ref().as_ref())),
+        );
+        let listener = TcpListener::bind(&self.address)?;
+        println!("Serving {:?} at http://{}", self.streams.0, self.address);
+        for stream in listener.incoming() {
+            let res = stream
+                .map_err(anyhow::Error::from)
+                .and_then(|s| graph.handle(s, self.verbose));
+            if let Err(e) = res {
+                crate::warning!("Request failed: {e}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Streams graph loaded once, and queried for each request
+struct Graph {
+    /// downstream node of each node
+    edges: HashMap<Point2D, Point2D>,
+    /// upstream nodes of each node
+    upstream: HashMap<Point2D, Vec<Point2D>>,
+    tree: RTree<(f64, f64)>,
+    threshold: Option<f64>,
+}
+
+/// HTTP status and JSON body of the response
+type Response = (u16, String);
+
+/// Largest request body accepted, larger ones get a 413 response
+const MAX_BODY: usize = 1 << 20;
+/// Time to wait on a slow client before dropping the connection
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+impl Graph {
+    fn new(streams: Vec<(Point2D, Point2D)>, threshold: Option<f64>) -> Self {
+        // same as the network command, first segment wins on branches
+        let edges: HashMap<Point2D, Point2D> = streams.into_iter().rev().collect();
+        let mut upstream: HashMap<Point2D, Vec<Point2D>> = HashMap::new();
+        for (k, v) in &edges {
+            upstream.entry(v.clone()).or_default().push(k.clone());
+        }
+        let pts: HashSet<_> = edges.iter().flat_map(|(k, v)| [k, v]).collect();
+        let tree = RTree::bulk_load(pts.into_iter().map(|p| p.coord2()).collect());
+        Self {
+            edges,
+            upstream,
+            tree,
+            threshold,
+        }
+    }
+
+    fn handle(&self, stream: TcpStream, verbose: bool) -> anyhow::Result<()> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let (method, target) = (
+            parts.next().unwrap_or_default().to_string(),
+            parts.next().unwrap_or_default().to_string(),
+        );
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((k, v)) = header.split_once(':') {
+                if k.trim().eq_ignore_ascii_case("content-length") {
+                    length = v.trim().parse()?;
+                }
+            }
+        }
+        if verbose {
+            println!("{method} {target}");
+        }
+        let (status, body) = if length > MAX_BODY {
+            error(
+                413,
+                &format!("Request body larger than {MAX_BODY} bytes is not accepted"),
+            )
+        } else {
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body)?;
+            self.route(&method, &target, &String::from_utf8_lossy(&body))
+        };
+        let mut stream = &stream;
+        write!(
+            stream,
+            "HTTP/1.1 {status} {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            if status == 200 { "OK" } else { "Error" },
+            body.len()
+        )?;
+        Ok(())
+    }
+
+    fn route(&self, method: &str, target: &str, body: &str) -> Response {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let query: HashMap<&str, &str> =
+            query.split('&').filter_map(|q| q.split_once('=')).collect();
+        match (method, path) {
+            ("GET", "/snap") => self.query_point(&query, |pt| self.snap_json(pt)),
+            ("GET", "/downstream") => self.query_point(&query, |pt| self.downstream(pt)),
+            ("GET", "/upstream") => self.query_point(&query, |pt| self.upstream(pt)),
+            ("POST", "/network") => self.network(body),
+            _ => error(
+                404,
+                "Unknown endpoint, use /snap, /downstream, /upstream or /network",
+            ),
+        }
+    }
+
+    /// Parse the `x` and `y` query parameters and snap them to the streams
+    fn query_point<F>(&self, query: &HashMap<&str, &str>, f: F) -> Response
+    where
+        F: Fn(&Point2D) -> Response,
+    {
+        let coord = |k: &str| query.get(k).and_then(|v| v.parse::<f64>().ok());
+        let Some(pt) = coord("x")
+            .zip(coord("y"))
+            .and_then(|c| Point2D::new2(c).ok())
+        else {
+            return error(400, "Give the point as x and y query parameters");
+        };
+        match self.snap(&pt) {
+            Some(s) => f(&s),
+            None => error(404, "Point is farther than the threshold from the streams"),
+        }
+    }
+
+    fn snap(&self, pt: &Point2D) -> Option<Point2D> {
+        let near = self.tree.nearest_neighbor(&pt.coord2())?;
+        let near = Point2D::new2(*near).ok()?;
+        match self.threshold {
+            Some(t) if near.dist(pt) > t => None,
+            _ => Some(near),
+        }
+    }
+
+    fn snap_json(&self, pt: &Point2D) -> Response {
+        (200, format!("{{\"snapped\": {}}}", coord_json(pt)))
+    }
+
+    /// Path from the point to the outlet
+    fn downstream(&self, pt: &Point2D) -> Response {
+        let mut visited = HashSet::new();
+        let mut path = vec![coord_json(pt)];
+        let mut cur = pt;
+        while let Some(next) = self.edges.get(cur) {
+            if !visited.insert(next) {
+                break;
+            }
+            path.push(coord_json(next));
+            cur = next;
+        }
+        (
+            200,
+            format!(
+                "{{\"snapped\": {}, \"path\": [{}]}}",
+                coord_json(pt),
+                path.join(", ")
+            ),
+        )
+    }
+
+    /// All the stream segments upstream of the point
+    fn upstream(&self, pt: &Point2D) -> Response {
+        let mut visited = HashSet::new();
+        let mut stack = vec![pt];
+        let mut segments = Vec::new();
+        while let Some(cur) = stack.pop() {
+            for up in self.upstream.get(cur).into_iter().flatten() {
+                if visited.insert(up) {
+                    segments.push(format!("[{}, {}]", coord_json(up), coord_json(cur)));
+                    stack.push(up);
+                }
+            }
+        }
+        (
+            200,
+            format!(
+                "{{\"snapped\": {}, \"segments\": [{}]}}",
+                coord_json(pt),
+                segments.join(", ")
+            ),
+        )
+    }
+
+    /// Network connections between the points in the body
+    ///
+    /// Each line of the body is a point as `name,x,y`. Points
+    /// snapping to the same location are connected in the order of
+    /// their names, like the network command.
+    fn network(&self, body: &str) -> Response {
+        let mut locations: HashMap<Point2D, Vec<&str>> = HashMap::new();
+        for line in body.lines().filter(|l| !l.trim().is_empty()) {
+            let row: Vec<&str> = line.split(',').map(|v| v.trim()).collect();
+            let pt = match row[..] {
+                [name, x, y] => x
+                    .parse()
+                    .ok()
+                    .zip(y.parse().ok())
+                    .and_then(|c| Point2D::new2(c).ok())
+                    .map(|p| (name, p)),
+                _ => None,
+            };
+            let Some((name, pt)) = pt else {
+                return error(400, &format!("Invalid point {line:?}, use name,x,y"));
+            };
+            let Some(loc) = self.snap(&pt) else {
+                return error(404, &format!("Point {name} is farther than the threshold"));
+            };
+            locations.entry(loc).or_default().push(name);
+        }
+        for names in locations.values_mut() {
+            names.sort();
+        }
+
+        let mut edges = Vec::new();
+        let mut outlets = Vec::new();
+        for (loc, names) in &locations {
+            for w in names.windows(2) {
+                edges.push((w[0], w[1]));
+            }
+            let mut visited = HashSet::new();
+            let mut cur = loc;
+            let mut outlet = None;
+            while let Some(next) = self.edges.get(cur) {
+                if !visited.insert(next) {
+                    break;
+                }
+                if let Some(n) = locations.get(next) {
+                    outlet = Some(n[0]);
+                    break;
+                }
+                cur = next;
+            }
+            let last = names[names.len() - 1];
+            match outlet {
+                Some(o) => edges.push((last, o)),
+                None => outlets.push(json_str(last)),
+            }
+        }
+        let edges: Vec<String> = edges
+            .into_iter()
+            .map(|(a, b)| format!("[{}, {}]", json_str(a), json_str(b)))
+            .collect();
+        (
+            200,
+            format!(
+                "{{\"edges\": [{}], \"outlets\": [{}]}}",
+                edges.join(", "),
+                outlets.join(", ")
+            ),
+        )
+    }
+}
+
+fn coord_json(pt: &Point2D) -> String {
+    let (x, y) = pt.coord2();
+    format!("[{x}, {y}]")
+}
+
+fn error(status: u16, msg: &str) -> Response {
+    (status, format!("{{\"error\": {}}}", json_str(msg)))
+}
@@ -0,0 +1,177 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::Args;
+use gdal::vector::LayerAccess;
+use gdal::Dataset;
+
+use crate::cliargs::CliAction;
+use crate::grid::Grid;
+use crate::types::Point2D;
+use crate::utils::*;
+
+#[derive(Args)]
+pub struct CliArgs {
+    /// Field with the stream order (from the `order` command)
+    #[arg(short, long, default_value = "order")]
+    field: String,
+    /// Model layer of the stream cells
+    #[arg(short, long, default_value = "1")]
+    layer: usize,
+    /// reverse the direction of streamlines
+    #[arg(short, long, action)]
+    reverse: bool,
+    /// Print progress
+    #[arg(short, long)]
+    verbose: bool,
+    #[command(flatten)]
+    grid: Grid,
+    /// Ordered streams vector file
+    #[arg(value_parser=parse_layer, value_name="STREAMS_FILE[::LAYER]")]
+    streams: (PathBuf, String),
+    /// Output SFR package file
+    output: PathBuf,
+}
+
+/// Stream segment, with the reaches as (row, col) and length
+struct Segment {
+    order: i64,
+    start: Point2D,
+    end: Point2D,
+    reaches: Vec<((usize, usize), f64)>,
+}
+
+impl CliAction for CliArgs {
+    fn run(mut self) -> Result<(), anyhow::Error> {
+        resolve_layer(&mut self.streams)?;
+        let streams_data = Dataset::open(&self.streams.0)?;
+        let mut streams_lyr = streams_data.layer_by_name(&self.streams.1)?;
+        let fid = streams_lyr
+            .defn()
+            .field_index(&self.field)
+            .context("Order field not found, use the output of the `order` command")?;
+
+        let mut segments = Vec::new();
+        for f in streams_lyr.features() {
+            let Some(geom) = f.geometry() else {
+                continue;
+            };
+            let lin = linearize(geom);
+            let geom = lin.as_ref().unwrap_or(geom);
+            let order = f.field_as_integer64(fid)?.unwrap_or_default();
+            let gc = geom.geometry_count();
+            let parts = if gc > 0 {
+                (0..gc)
+                    .map(|i| geom.get_geometry(i).get_point_vec())
+                    .collect()
+            } else {
+                vec![geom.get_point_vec()]
+            };
+            for part in parts {
+                let mut pts: Vec<(f64, f64)> = part.into_iter().map(|(x, y, _)| (x, y)).collect();
+                if pts.len() < 2 {
+                    continue;
+                }
+                if self.reverse {
+                    pts.reverse();
+                }
+                segments.push(Segment {
+                    order,
+                    start: Point2D::new2(pts[0])?,
+                    end: Point2D::new2(pts[pts.len() - 1])?,
+                    reaches: self.grid.split_line(&pts),
+                });
+            }
+        }
+        // upstream segments have lower order, so they come first
+        segments.sort_by_key(|s| s.order);
+        if self.verbose {
+            println!("Read {} stream segments", segments.len());
+        }
+
+        // global reach numbers (1-based) of the segments' reaches
+        let mut reach_ids: Vec<Vec<usize>> = Vec::with_capacity(segments.len());
+        let mut nreaches = 0;
+        for seg in &segments {
+            reach_ids.push((nreaches + 1..=nreaches + seg.reaches.len()).collect());
+            nreaches += seg.reaches.len();
+        }
+
+        let starts: HashMap<&Point2D, usize> = segments
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (&s.start, i))
+            .collect();
+        // first downstream segment that has reaches inside the grid
+        let outseg = |i: usize| -> Option<usize> {
+            let mut visited = HashSet::new();
+            let mut cur = i;
+            while let Some(&o) = starts.get(&segments[cur].end) {
+                if !visited.insert(o) {
+                    return None;
+                }
+                if !segments[o].reaches.is_empty() {
+                    return Some(o);
+                }
+                cur = o;
+            }
+            None
+        };
+        let mut connections: Vec<Vec<i64>> = vec![Vec::new(); nreaches];
+        for (i, ids) in reach_ids.iter().enumerate() {
+            for w in ids.windows(2) {
+                connections[w[0] - 1].push(-(w[1] as i64));
+                connections[w[1] - 1].push(w[0] as i64);
+            }
+            if let (Some(&last), Some(o)) = (ids.last(), outseg(i)) {
+                let first = reach_ids[o][0];
+                connections[last - 1].push(-(first as i64));
+                connections[first - 1].push(last as i64);
+            }
+        }
+
+        let mut writer = BufWriter::new(create_writer(&self.output)?);
+        writeln!(writer, "# SFR package generated by nadi-gis")?;
+        writeln!(
+            writer,
+            "# width, gradient, top, thickness, conductivity and roughness are placeholders"
+        )?;
+        writeln!(writer, "BEGIN OPTIONS\nEND OPTIONS\n")?;
+        writeln!(
+            writer,
+            "BEGIN DIMENSIONS\n  NREACHES {nreaches}\nEND DIMENSIONS\n"
+        )?;
+        writeln!(writer, "BEGIN PACKAGEDATA")?;
+        writeln!(
+            writer,
+            "# rno k i j rlen rwid rgrd rtp rbth rhk man ncon ustrf ndv"
+        )?;
+        let rows = segments.iter().flat_map(|s| &s.reaches);
+        for (rno, ((row, col), len)) in rows.enumerate() {
+            writeln!(
+                writer,
+                "  {} {} {} {} {len:.3} 1.0 0.001 0.0 1.0 1.0 0.035 {} 1.0 0",
+                rno + 1,
+                self.layer,
+                row + 1,
+                col + 1,
+                connections[rno].len()
+            )?;
+        }
+        writeln!(writer, "END PACKAGEDATA\n")?;
+        writeln!(writer, "BEGIN CONNECTIONDATA")?;
+        writeln!(writer, "# rno ic")?;
+        for (rno, con) in connections.iter().enumerate() {
+            let con: Vec<String> = con.iter().map(|c| c.to_string()).collect();
+            writeln!(writer, "  {} {}", rno + 1, con.join(" "))?;
+        }
+        writeln!(writer, "END CONNECTIONDATA")?;
+        finish_writer(writer)?;
+        if self.verbose {
+            println!("Wrote {nreaches} reaches to {:?}", self.output);
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,15 @@
 use std::io::Write;
 use std::{fs::File, path::PathBuf};
 
+use anyhow::Context;
 use clap::{Args, ValueEnum, ValueHint};
+use gdal::spatial_ref::SpatialRef;
+use gdal::vector::{Defn, Feature, FieldValue, Geometry, LayerAccess, LayerOptions, OGRFieldType};
+use gdal::Dataset;
 
 use crate::cliargs::CliAction;
+use crate::download::save_download;
+use crate::utils::{gdal_update_or_create, is_vsi_path, stamp_metadata, OutputSrs};
 
 #[derive(Args)]
 pub struct CliArgs {
@@ -22,12 +28,37 @@ pub struct CliArgs {
         hide_possible_values = true
     )]
     data: Vec<GeoInfo>,
+    /// Source of the flowlines (mr/hr)
+    ///
+    /// [medium resolution NHDPlus V2 (mr), high resolution NHDPlus HR (hr)]
+    ///
+    /// The flowline attributes of both are normalized to the common
+    /// fields: comid, name, reachcode, lengthkm, hydroseq,
+    /// dnhydroseq; along with the source. Fields not available in a
+    /// source are left empty (NLDI flowlines only carry the comid).
+    ///
+    /// High resolution flowlines are the ones in the NLDI basin, so
+    /// only the tributaries are available for it. Catchments are not
+    /// downloaded from NHDPlus HR, the basin is always the NLDI
+    /// (medium resolution) one.
+    #[arg(
+        short = 'r',
+        long,
+        rename_all = "lower",
+        default_value = "mr",
+        value_enum,
+        hide_possible_values = true
+    )]
+    source: Source,
     /// Display the url and exit (no download)
     #[arg(short, long, action)]
     url: bool,
     /// Display the progress
     #[arg(short, long, action)]
     verbose: bool,
+    /// Extract the downloaded files if they are zip archives
+    #[arg(short = 'x', long, action)]
+    extract: bool,
     #[arg(short, long, value_hint=ValueHint::DirPath, default_value=".")]
     output_dir: PathBuf,
 }
@@ -36,10 +67,26 @@ impl CliAction for CliArgs {
     fn run(self) -> anyhow::Result<()> {
         for site in self.site_no {
             for data in &self.data {
-                if self.url {
-                    println!("{}", data.usgs_url(&site));
-                } else {
-                    data.download(&site, &self.output_dir, self.verbose);
+                let flowlines = data.is_flowline();
+                match (self.source, flowlines) {
+                    (Source::High, _) if *data != GeoInfo::Tributaries => {
+                        anyhow::bail!(
+                            "Only tributaries are available for high resolution source, \
+                             use the medium resolution source for the basin"
+                        )
+                    }
+                    (Source::High, true) if self.url => {
+                        println!("{}", GeoInfo::Basin.usgs_url(&site));
+                        println!("{}", Source::hr_url("{BASIN_EXTENT}", 0));
+                    }
+                    (Source::High, true) => {
+                        data.download_hr(&site, &self.output_dir, self.verbose)?
+                    }
+                    _ if self.url => println!("{}", data.usgs_url(&site)),
+                    (Source::Medium, true) => {
+                        data.download_mr(&site, &self.output_dir, self.verbose)?
+                    }
+                    _ => data.download(&site, &self.output_dir, self.extract, self.verbose)?,
                 }
             }
         }
@@ -65,6 +112,10 @@ pub enum GeoInfo {
 // https://api.water.usgs.gov/nldi/linked-data/nwissite/USGS-03227500/navigation/UT?f=json
 
 impl GeoInfo {
+    pub fn is_flowline(&self) -> bool {
+        matches!(self, Self::Upstream | Self::Downstream | Self::Tributaries)
+    }
+
     pub fn usgs_query(&self) -> &str {
         match self {
             Self::Upstream => "navigate/UM?f=json",
@@ -93,16 +144,236 @@ impl GeoInfo {
         format!("https://api.water.usgs.gov/nldi/linked-data/wqp/USGS-{site_no}/{query}")
     }
 
-    pub fn download(&self, site_no: &str, dir: &PathBuf, _verbose: bool) {
+    pub fn download(
+        &self,
+        site_no: &str,
+        dir: &PathBuf,
+        extract: bool,
+        _verbose: bool,
+    ) -> anyhow::Result<()> {
         let url = self.usgs_url(site_no);
-        let bytes = reqwest::blocking::get(url).unwrap().bytes().unwrap();
+        let bytes = reqwest::blocking::get(url)?.bytes()?;
         if bytes.is_empty() {
-            eprintln!("No data");
-            return;
+            crate::warning!("No data");
+            return Ok(());
+        }
+        if !is_vsi_path(dir) {
+            let _ = std::fs::create_dir_all(dir);
         }
-        let _ = std::fs::create_dir_all(dir);
         let filepath = dir.join(self.filename(site_no));
-        let mut file = File::create(filepath).unwrap();
-        file.write_all(&bytes).unwrap();
+        save_download(&bytes, &filepath, extract)
+    }
+
+    /// Download the NLDI flowlines with normalized attributes
+    pub fn download_mr(&self, site_no: &str, dir: &PathBuf, verbose: bool) -> anyhow::Result<()> {
+        let bytes = reqwest::blocking::get(self.usgs_url(site_no))?.bytes()?;
+        let (features, _) = Source::Medium.read_features(bytes.to_vec(), None)?;
+        if verbose {
+            println!("Downloaded {} flowlines", features.1.len());
+        }
+        self.save_features(site_no, dir, Source::Medium, features)
     }
+
+    /// Download the NHDPlus HR flowlines in the NLDI basin of the site
+    pub fn download_hr(&self, site_no: &str, dir: &PathBuf, verbose: bool) -> anyhow::Result<()> {
+        let bytes = reqwest::blocking::get(GeoInfo::Basin.usgs_url(site_no))?.bytes()?;
+        let basin =
+            basin_geometry(bytes.to_vec()).context("Couldn't get the NLDI basin of the site")?;
+        let env = basin.envelope();
+        let bbox = format!("{},{},{},{}", env.MinX, env.MinY, env.MaxX, env.MaxY);
+        let mut features: Features = (vec![], vec![]);
+        let mut offset = 0;
+        loop {
+            let url = Source::hr_url(&bbox, offset);
+            let bytes = reqwest::blocking::get(url)?.bytes()?;
+            let ((fields, mut feats), count) =
+                Source::High.read_features(bytes.to_vec(), Some(&basin))?;
+            if features.0.is_empty() {
+                features.0 = fields;
+            }
+            features.1.append(&mut feats);
+            offset += count;
+            if verbose {
+                println!("Downloaded {} flowlines", features.1.len());
+            }
+            if count < HR_PAGE_SIZE {
+                break;
+            }
+        }
+        self.save_features(site_no, dir, Source::High, features)
+    }
+
+    fn save_features(
+        &self,
+        site_no: &str,
+        dir: &PathBuf,
+        source: Source,
+        (fields, feats): Features,
+    ) -> anyhow::Result<()> {
+        if feats.is_empty() {
+            crate::warning!("No data");
+            return Ok(());
+        }
+        if !is_vsi_path(dir) {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        let filepath = dir.join(self.filename(site_no));
+        // NLDI and NHDPlus HR responses are in WGS84
+        let out_srs = OutputSrs::new(Some(&SpatialRef::from_epsg(4326)?))?;
+        let mut ds = gdal_update_or_create(&filepath, &Some("GeoJSON".to_string()), true)?;
+        let mut layer = ds.create_layer(LayerOptions {
+            name: &self.filename(site_no).replace(".json", ""),
+            srs: out_srs.srs(),
+            ty: gdal_sys::OGRwkbGeometryType::wkbLineString,
+            ..Default::default()
+        })?;
+        stamp_metadata(&mut layer)?;
+        let mut defs: Vec<(&str, OGRFieldType::Type)> =
+            fields.iter().map(|(n, t)| (n.as_str(), *t)).collect();
+        defs.push(("source", OGRFieldType::OFTString));
+        layer.create_defn_fields(&defs)?;
+        let defn = Defn::from_layer(&layer);
+        for (values, geom) in feats {
+            let mut ft = Feature::new(&defn)?;
+            ft.set_geometry(out_srs.geometry(geom)?)?;
+            for (i, v) in values.iter().enumerate() {
+                if let Some(v) = v {
+                    ft.set_field(i, v)?;
+                }
+            }
+            ft.set_field_string(fields.len(), source.name())?;
+            ft.create(&mut layer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Field definitions and features with their field values
+type Features = (
+    Vec<(String, OGRFieldType::Type)>,
+    Vec<(Vec<Option<FieldValue>>, Geometry)>,
+);
+
+/// Common flowline fields both sources are normalized to
+const COMMON_FIELDS: [(&str, OGRFieldType::Type); 6] = [
+    ("comid", OGRFieldType::OFTInteger64),
+    ("name", OGRFieldType::OFTString),
+    ("reachcode", OGRFieldType::OFTString),
+    ("lengthkm", OGRFieldType::OFTReal),
+    ("hydroseq", OGRFieldType::OFTInteger64),
+    ("dnhydroseq", OGRFieldType::OFTInteger64),
+];
+
+/// Number of features to request from the NHDPlus HR service at once
+const HR_PAGE_SIZE: usize = 1000;
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Source {
+    #[value(alias = "mr")]
+    Medium,
+    #[value(alias = "hr")]
+    High,
+}
+
+impl Source {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Medium => "nhdplus-mr",
+            Self::High => "nhdplus-hr",
+        }
+    }
+
+    /// Source field names for the common fields, in the order of
+    /// [`COMMON_FIELDS`]; `None` if the source doesn't have it
+    pub fn field_map(&self) -> [Option<&str>; 6] {
+        match self {
+            Self::Medium => [Some("nhdplus_comid"), None, None, None, None, None],
+            Self::High => [
+                Some("nhdplusid"),
+                Some("gnis_name"),
+                Some("reachcode"),
+                Some("lengthkm"),
+                Some("hydroseq"),
+                Some("dnhydroseq"),
+            ],
+        }
+    }
+
+    /// Query URL of the NHDPlus HR flowlines within the bounding box
+    pub fn hr_url(bbox: &str, offset: usize) -> String {
+        format!(
+            "https://hydro.nationalmap.gov/arcgis/rest/services/NHDPlus_HR/MapServer/3/query?\
+             where=1%3D1&geometry={bbox}&geometryType=esriGeometryEnvelope&inSR=4326\
+             &spatialRel=esriSpatialRelIntersects&outFields=*&outSR=4326\
+             &resultOffset={offset}&resultRecordCount={HR_PAGE_SIZE}&f=geojson"
+        )
+    }
+
+    /// Read the GeoJSON features keeping only the common fields
+    ///
+    /// Features not intersecting `clip` are skipped, the number of
+    /// features in the response is returned along with them.
+    fn read_features(
+        &self,
+        bytes: Vec<u8>,
+        clip: Option<&Geometry>,
+    ) -> anyhow::Result<(Features, usize)> {
+        let mem = "/vsimem/nadi-gis-usgs.json";
+        gdal::vsi::create_mem_file(mem, bytes)?;
+        let features = self.read_mem_features(mem, clip);
+        gdal::vsi::unlink_mem_file(mem)?;
+        features
+    }
+
+    fn read_mem_features(
+        &self,
+        mem: &str,
+        clip: Option<&Geometry>,
+    ) -> anyhow::Result<(Features, usize)> {
+        let data = Dataset::open(mem)?;
+        let mut lyr = data.layer(0)?;
+        let count = lyr.feature_count() as usize;
+        let names: Vec<String> = lyr
+            .defn()
+            .fields()
+            .map(|f| f.name().to_lowercase())
+            .collect();
+        let idx: Vec<Option<usize>> = self
+            .field_map()
+            .iter()
+            .map(|s| s.and_then(|s| names.iter().position(|n| n == s)))
+            .collect();
+        let fields = COMMON_FIELDS
+            .iter()
+            .map(|(n, t)| (n.to_string(), *t))
+            .collect();
+        let feats = lyr
+            .features()
+            .filter_map(|f| {
+                let geom = f.geometry()?;
+                if clip.is_some_and(|c| !c.intersects(geom)) {
+                    return None;
+                }
+                let values = idx
+                    .iter()
+                    .map(|i| i.and_then(|i| f.field(i).ok().flatten()))
+                    .collect();
+                Some((values, geom.clone()))
+            })
+            .collect();
+        Ok(((fields, feats), count))
+    }
+}
+
+/// Basin polygon from the NLDI basin GeoJSON
+fn basin_geometry(bytes: Vec<u8>) -> anyhow::Result<Geometry> {
+    let mem = "/vsimem/nadi-gis-basin.json";
+    gdal::vsi::create_mem_file(mem, bytes)?;
+    let geom = Dataset::open(mem).and_then(|data| {
+        let mut lyr = data.layer(0)?;
+        let geom = lyr.features().find_map(|f| f.geometry().cloned());
+        Ok(geom)
+    });
+    gdal::vsi::unlink_mem_file(mem)?;
+    geom?.context("No basin polygon in the response")
 }
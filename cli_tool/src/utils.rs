@@ -1,60 +1,538 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{c_void, CString};
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
 
 use anyhow::Context;
 use clap::Args;
-use gdal::vector::{FieldValue, Geometry, Layer, LayerAccess, LayerOptions, OGRFieldType};
+use gdal::spatial_ref::{AxisMappingStrategy, CoordTransform, SpatialRef};
+use gdal::vector::{Feature, FieldValue, Geometry, Layer, LayerAccess, LayerOptions, OGRFieldType};
 use gdal::{Dataset, Driver, DriverManager, GdalOpenFlags, Metadata};
 
-pub fn parse_new_layer(arg: &str) -> Result<(PathBuf, Option<String>), anyhow::Error> {
-    if let Some((path, layer)) = arg.split_once("::") {
-        Ok((PathBuf::from(path), Some(layer.to_string())))
+/// Environment variable to silence the warnings
+///
+/// Set by `--quiet`, and also respected by the nadi plugin functions
+/// so batch pipelines can silence both.
+pub const QUIET_ENV: &str = "NADI_GIS_QUIET";
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Print the warning to stderr unless `--quiet` is given, and record it for the manifest
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        let msg = format!($($arg)*);
+        if !$crate::utils::is_quiet() {
+            eprintln!("{msg}");
+        }
+        $crate::manifest::warning(msg);
+    };
+}
+
+/// Print the warning about a location (and FID) of a feature
+///
+/// The location is saved with the message in the `--warnings` file.
+#[macro_export]
+macro_rules! warning_at {
+    ($loc:expr, $fid:expr, $($arg:tt)*) => {
+        let msg = format!($($arg)*);
+        if !$crate::utils::is_quiet() {
+            eprintln!("{msg}");
+        }
+        $crate::manifest::diagnostic("WARN", msg, $loc, $fid);
+    };
+}
+
+/// Numbers in the text fields use comma as the decimal separator
+static DECIMAL_COMMA: AtomicBool = AtomicBool::new(false);
+
+pub fn set_decimal_comma(comma: bool) {
+    DECIMAL_COMMA.store(comma, Ordering::Relaxed);
+}
+
+/// Parse the number with the decimal separator of `--decimal-comma`
+///
+/// The thousands separators (the other of `.`/`,`, spaces and
+/// apostrophes) are removed, so `1.234,5` is parsed as 1234.5 with
+/// the decimal comma and `1,234.5` without it.
+pub fn parse_number(text: &str) -> Option<f64> {
+    let (decimal, thousands) = if DECIMAL_COMMA.load(Ordering::Relaxed) {
+        (',', '.')
     } else {
-        Ok((PathBuf::from(arg), None))
+        ('.', ',')
+    };
+    let num: String = text
+        .trim()
+        .chars()
+        .filter(|c| *c != thousands && *c != '\'' && !c.is_whitespace())
+        .map(|c| if c == decimal { '.' } else { c })
+        .collect();
+    num.parse().ok()
+}
+
+/// Numeric value of the field, text fields are parsed with [`parse_number`]
+///
+/// GDAL reads the text like `12,5` as 12 silently, so the CSV
+/// columns with the text type are parsed here instead.
+pub fn field_as_number(f: &Feature, idx: usize) -> anyhow::Result<Option<f64>> {
+    match f.field(idx)? {
+        Some(FieldValue::StringValue(s)) => match parse_number(&s) {
+            Some(v) => Ok(Some(v)),
+            None if s.trim().is_empty() => Ok(None),
+            None => Err(anyhow::Error::msg(format!("Invalid number {s:?}"))),
+        },
+        _ => Ok(f.field_as_double(idx)?),
+    }
+}
+
+/// Max angle step (degrees) to linearize the curves, stored as f64 bits
+static CURVE_STEP: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_curve_step(degrees: f64) {
+    CURVE_STEP.store(degrees.to_bits(), Ordering::Relaxed);
+}
+
+/// Linear geometry for the curve geometries (CompoundCurve, MultiCurve, ...)
+///
+/// Returns `None` for the geometries without curves, those can be
+/// used as they are. Curves are approximated with the max angle step
+/// of `--curve-step` (GDAL default if 0).
+pub fn linearize(geom: &Geometry) -> Option<Geometry> {
+    if unsafe { gdal_sys::OGR_G_HasCurveGeometry(geom.c_geometry(), 1) } == 0 {
+        return None;
+    }
+    let step = f64::from_bits(CURVE_STEP.load(Ordering::Relaxed));
+    let wkb = unsafe {
+        let lin = gdal_sys::OGR_G_GetLinearGeometry(geom.c_geometry(), step, std::ptr::null_mut());
+        if lin.is_null() {
+            crate::warning!("Failed to linearize the curve geometry");
+            return None;
+        }
+        let mut wkb = vec![0u8; gdal_sys::OGR_G_WkbSize(lin) as usize];
+        let err =
+            gdal_sys::OGR_G_ExportToWkb(lin, gdal_sys::OGRwkbByteOrder::wkbNDR, wkb.as_mut_ptr());
+        gdal_sys::OGR_G_DestroyGeometry(lin);
+        if err != gdal_sys::OGRErr::OGRERR_NONE {
+            crate::warning!("Failed to linearize the curve geometry");
+            return None;
+        }
+        wkb
+    };
+    Geometry::from_wkb(&wkb).ok()
+}
+
+/// Meters in a degree of latitude, to approximate the distances on geographic layers
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// Distance with an optional unit suffix
+///
+/// Values without a suffix are in the units of the layer they are
+/// used with. Lengths are stored in meters, and angles in degrees.
+#[derive(Clone, Copy, Debug)]
+pub struct Distance {
+    value: f64,
+    unit: Option<DistanceUnit>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum DistanceUnit {
+    Meter,
+    Degree,
+}
+
+/// Parse the distance like `100`, `100m`, `2km`, `50ft`, `1mi` or `0.001deg`
+pub fn parse_distance(arg: &str) -> Result<Distance, anyhow::Error> {
+    let arg = arg.trim();
+    let pos = arg
+        .find(|c: char| c.is_ascii_alphabetic() && c != 'e' && c != 'E')
+        .unwrap_or(arg.len());
+    let (num, suffix) = arg.split_at(pos);
+    let num: f64 = num.trim().parse()?;
+    let (value, unit) = match suffix.trim() {
+        "" => (num, None),
+        "m" => (num, Some(DistanceUnit::Meter)),
+        "km" => (num * 1000.0, Some(DistanceUnit::Meter)),
+        "ft" => (num * 0.3048, Some(DistanceUnit::Meter)),
+        "mi" => (num * 1609.344, Some(DistanceUnit::Meter)),
+        "deg" => (num, Some(DistanceUnit::Degree)),
+        s => anyhow::bail!("Unknown distance unit {s:?}, use m, km, ft, mi or deg"),
+    };
+    Ok(Distance { value, unit })
+}
+
+impl Distance {
+    /// Distance in the units of the spatial reference
+    ///
+    /// Meters and degrees are converted approximately between each
+    /// other with a warning, as the conversion depends on the
+    /// location.
+    pub fn in_units(&self, srs: Option<&SpatialRef>) -> f64 {
+        let Some(srs) = srs else {
+            if self.unit.is_some() {
+                crate::warning!("No spatial reference to convert the distance, using it as is");
+            }
+            return self.value;
+        };
+        match (self.unit, srs.is_geographic()) {
+            (None, true) if self.value > 1.0 => {
+                crate::warning!(
+                    "Distance {} is in degrees for the geographic layer, use suffix like 100m for meters",
+                    self.value
+                );
+                self.value
+            }
+            (None, _) => self.value,
+            (Some(DistanceUnit::Meter), true) => {
+                crate::warning!(
+                    "Converting meters to degrees approximately for the geographic layer"
+                );
+                self.value / METERS_PER_DEGREE
+            }
+            (Some(DistanceUnit::Meter), false) => self.value / srs.linear_units(),
+            (Some(DistanceUnit::Degree), true) => self.value,
+            (Some(DistanceUnit::Degree), false) => {
+                crate::warning!(
+                    "Converting degrees to meters approximately for the projected layer"
+                );
+                self.value * METERS_PER_DEGREE / srs.linear_units()
+            }
+        }
+    }
+}
+
+/// Options to process a subset of the input features for quick trials
+#[derive(Args, Clone, Debug, Default)]
+pub struct Subset {
+    /// Process only the first N features of the input layers
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+    /// Process a random fraction (0-1) of the features of the input layers
+    #[arg(long, value_name = "FRACTION")]
+    sample: Option<f64>,
+    /// Seed for the random sampling, to get the same subset every run
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Sample in each cell of a grid of this size for spatially representative subsets
+    ///
+    /// Features are grouped by the grid cell of their first vertex,
+    /// and the --sample fraction and --limit (chosen randomly) apply
+    /// to each cell. Takes unit suffixes like --threshold.
+    #[arg(long, value_name = "CELL_SIZE", value_parser=parse_distance)]
+    stratify: Option<Distance>,
+}
+
+impl Subset {
+    /// Restrict the layer to the subset of the features
+    ///
+    /// The FIDs of the subset are set as the attribute filter, so all
+    /// the later reads (and feature counts) of the layer only see them.
+    pub fn apply(&self, layer: &mut Layer) -> anyhow::Result<()> {
+        if self.limit.is_none() && self.sample.is_none() {
+            return Ok(());
+        }
+        if let Some(s) = self.sample {
+            if !(s > 0.0 && s <= 1.0) {
+                anyhow::bail!("Sample fraction should be between 0 and 1, got {s}");
+            }
+        }
+        let mut rng = match self.seed {
+            Some(s) => SplitMix::new(s),
+            None => SplitMix::from_time(),
+        };
+        let mut fids = match self.stratify {
+            Some(size) => self.stratified(layer, size, &mut rng)?,
+            None => self.sequential(layer, &mut rng),
+        };
+        if fids.is_empty() {
+            // no FID is negative, so nothing is selected
+            fids.push("-1".into());
+        }
+        layer.set_attribute_filter(&format!("FID IN ({})", fids.join(",")))?;
+        Ok(())
+    }
+
+    fn sequential(&self, layer: &mut Layer, rng: &mut SplitMix) -> Vec<String> {
+        let mut fids = Vec::new();
+        for f in layer.features() {
+            let Some(fid) = f.fid() else {
+                continue;
+            };
+            if self.sample.is_some_and(|s| rng.next_f64() >= s) {
+                continue;
+            }
+            fids.push(fid.to_string());
+            if self.limit.is_some_and(|l| fids.len() >= l) {
+                break;
+            }
+        }
+        fids
+    }
+
+    fn stratified(
+        &self,
+        layer: &mut Layer,
+        size: Distance,
+        rng: &mut SplitMix,
+    ) -> anyhow::Result<Vec<String>> {
+        let size = size.in_units(layer.spatial_ref().as_ref());
+        if size <= 0.0 {
+            anyhow::bail!("Grid cell size for stratified sampling should be positive");
+        }
+        let mut cells: HashMap<(i64, i64), Vec<u64>> = HashMap::new();
+        for f in layer.features() {
+            let (Some(fid), Some(g)) = (f.fid(), f.geometry()) else {
+                continue;
+            };
+            let (x, y, _) = if g.geometry_count() > 0 {
+                g.get_geometry(0).get_point(0)
+            } else {
+                g.get_point(0)
+            };
+            let cell = ((x / size).floor() as i64, (y / size).floor() as i64);
+            cells.entry(cell).or_default().push(fid);
+        }
+        let mut cells: Vec<_> = cells.into_iter().collect();
+        // same cell order for the seed to give the same subset
+        cells.sort_unstable_by_key(|c| c.0);
+        let mut fids = Vec::new();
+        for (_, mut cell) in cells {
+            if let Some(s) = self.sample {
+                cell.retain(|_| rng.next_f64() < s);
+            }
+            if let Some(l) = self.limit {
+                // partial Fisher-Yates shuffle to pick the random ones
+                for i in 0..l.min(cell.len()) {
+                    let j = i + (rng.next_u64() % (cell.len() - i) as u64) as usize;
+                    cell.swap(i, j);
+                }
+                cell.truncate(l);
+            }
+            fids.extend(cell.iter().map(|f| f.to_string()));
+        }
+        Ok(fids)
     }
 }
 
+/// Small pseudo random number generator (SplitMix64) for sampling
+pub struct SplitMix(u64);
+
+impl SplitMix {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn from_time() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Self(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Random number in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+static TARGET_SRS: Mutex<Option<String>> = Mutex::new(None);
+
+/// Spatial reference to write the outputs in, from `--t_srs`
+pub fn set_target_srs(srs: Option<String>) {
+    if let Ok(mut t) = TARGET_SRS.lock() {
+        *t = srs;
+    }
+}
+
+/// Spatial reference of the output layers, and the reprojection to it
+pub struct OutputSrs {
+    srs: Option<SpatialRef>,
+    trans: Option<CoordTransform>,
+}
+
+impl OutputSrs {
+    /// Output spatial reference for the geometries in `src`
+    ///
+    /// Geometries are reprojected if `--t_srs` is given, otherwise
+    /// they are written in the `src` spatial reference.
+    pub fn new(src: Option<&SpatialRef>) -> anyhow::Result<Self> {
+        let target = TARGET_SRS.lock().ok().and_then(|t| t.clone());
+        let Some(def) = target else {
+            return Ok(Self {
+                srs: src.cloned(),
+                trans: None,
+            });
+        };
+        let mut dst = SpatialRef::from_definition(&def)?;
+        dst.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+        let mut src = src
+            .cloned()
+            .with_context(|| format!("Input has no spatial reference to reproject to {def}"))?;
+        src.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+        let trans = (src != dst)
+            .then(|| CoordTransform::new(&src, &dst))
+            .transpose()?;
+        Ok(Self {
+            srs: Some(dst),
+            trans,
+        })
+    }
+
+    pub fn srs(&self) -> Option<&SpatialRef> {
+        self.srs.as_ref()
+    }
+
+    pub fn geometry(&self, geom: Geometry) -> anyhow::Result<Geometry> {
+        match &self.trans {
+            Some(t) => Ok(geom.transform(t)?),
+            None => Ok(geom),
+        }
+    }
+}
+
+/// Split the `FILENAME::LAYER` argument into the path and the layer
+///
+/// Only `::` separates the layer, so the Windows drive letters
+/// (`C:\data\rivers.gpkg::streams`) are part of the path. For
+/// existing files, the split is done where the path exists so the
+/// paths containing `::` work as is; for new files `\::` can be used
+/// for a literal `::` in the path.
+pub fn split_layer(arg: &str) -> (PathBuf, Option<String>) {
+    if path_exists(arg) {
+        return (PathBuf::from(arg), None);
+    }
+    for (i, _) in arg.match_indices("::") {
+        if path_exists(&arg[..i]) {
+            return (PathBuf::from(&arg[..i]), Some(arg[i + 2..].to_string()));
+        }
+    }
+    let mut path = String::new();
+    let mut rest = arg;
+    while let Some(i) = rest.find("::") {
+        if let Some(p) = rest[..i].strip_suffix('\\') {
+            path.push_str(p);
+            path.push_str("::");
+            rest = &rest[i + 2..];
+        } else {
+            path.push_str(&rest[..i]);
+            return (PathBuf::from(path), Some(rest[i + 2..].to_string()));
+        }
+    }
+    path.push_str(rest);
+    (PathBuf::from(path), None)
+}
+
+pub fn parse_new_layer(arg: &str) -> Result<(PathBuf, Option<String>), anyhow::Error> {
+    Ok(split_layer(arg))
+}
+
+/// Exit code when a file has multiple layers and none was chosen
+pub const EXIT_AMBIGUOUS_LAYER: i32 = 3;
+
+/// File has multiple layers, and the layer to use wasn't given
+#[derive(Debug)]
+pub struct AmbiguousLayer {
+    path: PathBuf,
+    layers: Vec<String>,
+}
+
+impl std::fmt::Display for AmbiguousLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "File {:?} has multiple layers, choose one with \"FILENAME::LAYER\" (name or index)",
+            self.path
+        )?;
+        write!(f, "Available Layers:")?;
+        for (i, l) in self.layers.iter().enumerate() {
+            write!(f, "\n  {i}: {l}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AmbiguousLayer {}
+
+/// Name of the layer in the dataset, by name or by its index
+fn layer_name(data: &Dataset, layer: &str) -> Option<String> {
+    if data.layer_by_name(layer).is_ok() {
+        return Some(layer.to_string());
+    }
+    let ind: usize = layer.parse().ok()?;
+    data.layer(ind).ok().map(|l| l.name())
+}
+
+/// Take the `FILENAME::LAYER` argument as is, for [`resolve_layer`]
+///
+/// The file is not read while parsing the arguments, so the global
+/// options (GDAL config, `--quiet`, ...) apply to it; the commands
+/// resolve the layer with [`resolve_layer`] when they run.
 pub fn parse_layer(arg: &str) -> Result<(PathBuf, String), anyhow::Error> {
-    if let Some((path, layer)) = arg.split_once("::") {
-        let data = Dataset::open(path)?;
-        if data.layer_by_name(layer).is_err() {
-            if data.layer_count() == 1 {
-                let fpath = PathBuf::from(path);
+    Ok((PathBuf::from(arg), String::new()))
+}
+
+/// Split the `FILENAME::LAYER` argument from [`parse_layer`] into file path and layer name
+///
+/// Layer can be the name or the index (starting from 0) of the
+/// layer. Files with multiple layers need the layer to be specified,
+/// and the error is an [`AmbiguousLayer`] so the program can exit
+/// with [`EXIT_AMBIGUOUS_LAYER`].
+pub fn resolve_layer(arg: &mut (PathBuf, String)) -> anyhow::Result<()> {
+    let (path, layer) = split_layer(&arg.0.to_string_lossy());
+    let data = Dataset::open(&path)?;
+    let name = match layer {
+        Some(layer) => match layer_name(&data, &layer) {
+            Some(name) => name,
+            None => {
                 // if there is one layer and the layer name is the
                 // path name = files that do not support layers; in
                 // that case ignore the layer specification and return
                 // the layer
-                eprintln!("File {fpath:?} doesn't support layers; using the available data");
-                let fname = fpath
+                let fname = path
                     .file_stem()
                     .unwrap_or_default()
                     .to_string_lossy()
                     .to_string();
-                if data.layer(0).unwrap().name() == fname {
-                    return Ok((fpath, fname));
+                if data.layer_count() != 1 || data.layer(0)?.name() != fname {
+                    anyhow::bail!("Layer {layer} doesn't exist in the file {path:?}");
                 }
+                crate::warning!("File {path:?} doesn't support layers; using the available data");
+                fname
             }
-            Err(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("Layer name {layer} doesn't exist in the file {path}"),
-            )
-            .into())
-        } else {
-            Ok((PathBuf::from(path), layer.to_string()))
-        }
-    } else {
-        let data = Dataset::open(arg)?;
-        if data.layer_count() == 1 {
-            let layer = data.layer(0)?;
-            Ok((PathBuf::from(&arg), layer.name()))
-        } else {
-            eprintln!("Provide a layer name to choose layer \"FILENAME::LAYERNAME\"");
-            eprintln!("Available Layers:");
-            data.layers().for_each(|l| eprintln!("  {}", l.name()));
-            let layer = data.layer(0)?;
-            Ok((PathBuf::from(&arg), layer.name()))
+        },
+        None => {
+            if data.layer_count() != 1 {
+                return Err(AmbiguousLayer {
+                    path,
+                    layers: data.layers().map(|l| l.name()).collect(),
+                }
+                .into());
+            }
+            data.layer(0)?.name()
         }
-    }
+    };
+    crate::manifest::input(&path, Some(&name));
+    *arg = (path, name);
+    Ok(())
 }
 
 pub fn get_geometries(
@@ -72,10 +550,10 @@ pub fn get_geometries(
         .enumerate()
         .map(|(i, f)| {
             let geom = match f.geometry() {
-                Some(g) => g.clone(),
+                Some(g) => linearize(g).unwrap_or_else(|| g.clone()),
                 None => {
-                    let x = f.field_as_double(x_field.clone()?)?.unwrap();
-                    let y = f.field_as_double(y_field.clone()?)?.unwrap();
+                    let x = field_as_number(&f, x_field.clone()?)?.unwrap();
+                    let y = field_as_number(&f, y_field.clone()?)?.unwrap();
                     let mut pt = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbPoint)?;
                     pt.add_point((x, y, 0.0));
                     pt
@@ -96,13 +574,19 @@ pub fn gdal_update_or_create<P: AsRef<Path>>(
     driver: &Option<String>,
     overwrite: bool,
 ) -> anyhow::Result<Dataset> {
-    if !overwrite && filepath.as_ref().exists() {
+    crate::manifest::output(&filepath);
+    if is_cloud_path(&filepath) {
+        // object storages can't do random writes, GDAL has to write
+        // to a temporary local file and upload it on close
+        gdal::config::set_config_option("CPL_VSIL_USE_TEMP_FILE_FOR_RANDOM_WRITE", "YES")?;
+    }
+    let mut ds = if !overwrite && path_exists(&filepath) {
         let open_flags = gdal::GdalOpenFlags::GDAL_OF_UPDATE;
         let op = gdal::DatasetOptions {
             open_flags,
             ..Default::default()
         };
-        Ok(Dataset::open_ex(filepath, op)?)
+        Dataset::open_ex(filepath, op)?
     } else {
         let driver = if let Some(d) = driver {
             DriverManager::get_driver_by_name(d)?
@@ -111,8 +595,65 @@ pub fn gdal_update_or_create<P: AsRef<Path>>(
                 .context("Driver not found for the output filename")?
         };
 
-        Ok(driver.create_vector_only(filepath)?)
+        driver.create_vector_only(filepath)?
+    };
+    stamp_metadata(&mut ds)?;
+    Ok(ds)
+}
+
+/// Record the tool, version, git hash and arguments of the run in the metadata
+///
+/// Any output found later can be traced to the run that created
+/// it. The `DESCRIPTION` item is the layer description in GPKG.
+pub fn stamp_metadata<M: Metadata>(obj: &mut M) -> anyhow::Result<()> {
+    let args = run_args().join(" ");
+    let version = env!("CARGO_PKG_VERSION");
+    let hash = env!("NADI_GIS_GIT_HASH");
+    obj.set_metadata_item("NADI_GIS_TOOL", env!("CARGO_PKG_NAME"), "")?;
+    obj.set_metadata_item("NADI_GIS_VERSION", version, "")?;
+    obj.set_metadata_item("NADI_GIS_GIT_HASH", hash, "")?;
+    obj.set_metadata_item("NADI_GIS_ARGS", &args, "")?;
+    obj.set_metadata_item(
+        "DESCRIPTION",
+        &format!("Created by nadi-gis {version} ({hash}): {args}"),
+        "",
+    )?;
+    Ok(())
+}
+
+/// Quote the CSV field, doubling the quotes inside it
+pub fn csv_quote(v: &str) -> String {
+    format!("\"{}\"", v.replace('"', "\"\""))
+}
+
+/// Command line arguments of the run with the `--config` values redacted
+///
+/// The GDAL configuration options can have the credentials for the
+/// cloud storages, so only their keys are recorded in the outputs.
+pub fn run_args() -> Vec<String> {
+    let redact = |kv: &str| match kv.split_once('=') {
+        Some((k, _)) => format!("{k}=***"),
+        None => kv.to_string(),
+    };
+    let mut args = Vec::new();
+    let mut config = false;
+    for arg in std::env::args() {
+        let arg = if config {
+            redact(&arg)
+        } else if let Some(kv) = arg.strip_prefix("--config=") {
+            format!("--config={}", redact(kv))
+        } else if let Some(kv) = arg.strip_prefix("-c").filter(|kv| !kv.is_empty()) {
+            match kv.strip_prefix('=') {
+                Some(kv) => format!("-c={}", redact(kv)),
+                None => format!("-c{}", redact(kv)),
+            }
+        } else {
+            arg
+        };
+        config = arg == "--config" || arg == "-c";
+        args.push(arg);
     }
+    args
 }
 
 pub fn check_spatial_ref(points: &Layer, streams: &Layer) -> Result<(), ()> {
@@ -129,13 +670,13 @@ pub fn check_spatial_ref(points: &Layer, streams: &Layer) -> Result<(), ()> {
             }
         }
         (Some(_), None) => {
-            eprintln!("Streams layer doesn't have spatial reference");
+            crate::warning!("Streams layer doesn't have spatial reference");
         }
         (None, Some(_)) => {
-            eprintln!("Points layer doesn't have spatial reference");
+            crate::warning!("Points layer doesn't have spatial reference");
         }
         (None, None) => {
-            eprintln!("Streams and Point layers don't have spatial reference");
+            crate::warning!("Streams and Point layers don't have spatial reference");
         }
     }
     Ok(())
@@ -159,3 +700,169 @@ pub fn delete_layer(dataset: &mut Dataset, lyr: &str) -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+/// GDAL virtual file system prefixes for cloud object storages
+const CLOUD_VSI: [&str; 6] = [
+    "/vsis3/",
+    "/vsigs/",
+    "/vsiaz/",
+    "/vsiadls/",
+    "/vsioss/",
+    "/vsiswift/",
+];
+
+/// Check if the path is in one of the GDAL virtual file systems
+pub fn is_vsi_path<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref().to_string_lossy().starts_with("/vsi")
+}
+
+/// Check if the path is in a cloud object storage (S3, GCS, etc)
+pub fn is_cloud_path<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref().to_string_lossy();
+    CLOUD_VSI.iter().any(|p| path.starts_with(p))
+}
+
+/// Check if the file exists, works for GDAL virtual file systems too
+pub fn path_exists<P: AsRef<Path>>(path: P) -> bool {
+    if !is_vsi_path(&path) {
+        return path.as_ref().exists();
+    }
+    let Ok(cpath) = CString::new(path.as_ref().to_string_lossy().as_bytes()) else {
+        return false;
+    };
+    let mut stat = std::mem::MaybeUninit::<gdal_sys::VSIStatBufL>::uninit();
+    unsafe { gdal_sys::VSIStatL(cpath.as_ptr(), stat.as_mut_ptr()) == 0 }
+}
+
+/// Writer for the files in GDAL virtual file systems
+///
+/// For cloud storages, the file is uploaded when the writer is
+/// closed, use [`VsiWriter::finish`] to know if that failed.
+pub struct VsiWriter {
+    fp: *mut gdal_sys::VSILFILE,
+    path: PathBuf,
+}
+
+impl VsiWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let cpath = CString::new(path.as_ref().to_string_lossy().as_bytes())?;
+        let fp = unsafe { gdal_sys::VSIFOpenL(cpath.as_ptr(), c"wb".as_ptr()) };
+        if fp.is_null() {
+            anyhow::bail!("Cannot open {:?} for writing", path.as_ref());
+        }
+        Ok(Self {
+            fp,
+            path: path.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Close the file, returns the error if writing (uploading) it failed
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        let fp = std::mem::replace(&mut self.fp, std::ptr::null_mut());
+        if unsafe { gdal_sys::VSIFCloseL(fp) } != 0 {
+            anyhow::bail!("Failed to write {:?}", self.path);
+        }
+        Ok(())
+    }
+}
+
+impl Write for VsiWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n =
+            unsafe { gdal_sys::VSIFWriteL(buf.as_ptr() as *const c_void, 1, buf.len(), self.fp) };
+        if n < buf.len() {
+            Err(std::io::Error::other("Write to virtual file system failed"))
+        } else {
+            Ok(n)
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if unsafe { gdal_sys::VSIFFlushL(self.fp) } != 0 {
+            Err(std::io::Error::other("Flush to virtual file system failed"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Drop for VsiWriter {
+    fn drop(&mut self) {
+        if self.fp.is_null() {
+            return;
+        }
+        if unsafe { gdal_sys::VSIFCloseL(self.fp) } != 0 {
+            crate::warning!("Failed to write {:?}", self.path);
+        }
+    }
+}
+
+/// Output file, local or in GDAL virtual file systems
+pub enum OutputWriter {
+    Local(File),
+    Vsi(VsiWriter),
+}
+
+impl OutputWriter {
+    /// Close the file, returns the error if writing (uploading) it failed
+    pub fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Self::Local(mut f) => Ok(f.flush()?),
+            Self::Vsi(w) => w.finish(),
+        }
+    }
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Local(f) => f.write(buf),
+            Self::Vsi(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Local(f) => f.flush(),
+            Self::Vsi(w) => w.flush(),
+        }
+    }
+}
+
+/// Create a file for writing, local files or GDAL virtual file systems (`/vsis3/`, `/vsigs/`, ...)
+///
+/// Call [`OutputWriter::finish`] (or [`finish_writer`]) after
+/// writing to catch the errors while closing the file.
+pub fn create_writer<P: AsRef<Path>>(path: P) -> anyhow::Result<OutputWriter> {
+    crate::manifest::output(&path);
+    if is_vsi_path(&path) {
+        Ok(OutputWriter::Vsi(VsiWriter::create(path)?))
+    } else {
+        Ok(OutputWriter::Local(File::create(path)?))
+    }
+}
+
+/// Flush the buffered writer and close the file
+pub fn finish_writer(writer: BufWriter<OutputWriter>) -> anyhow::Result<()> {
+    writer.into_inner().map_err(|e| e.into_error())?.finish()
+}
+
+/// Read the whole file from GDAL virtual file systems (`/vsizip/`, `/vsis3/`, ...)
+pub fn read_vsi_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<u8>> {
+    let cpath = CString::new(path.as_ref().to_string_lossy().as_bytes())?;
+    let fp = unsafe { gdal_sys::VSIFOpenL(cpath.as_ptr(), c"rb".as_ptr()) };
+    if fp.is_null() {
+        anyhow::bail!("Cannot open {:?} for reading", path.as_ref());
+    }
+    let mut contents = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = unsafe { gdal_sys::VSIFReadL(buf.as_mut_ptr() as *mut c_void, 1, buf.len(), fp) };
+        if n == 0 {
+            break;
+        }
+        contents.extend_from_slice(&buf[..n]);
+    }
+    unsafe { gdal_sys::VSIFCloseL(fp) };
+    Ok(contents)
+}
@@ -0,0 +1,140 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Args;
+use gdal::programs::raster::build_vrt;
+use gdal::spatial_ref::SpatialRef;
+use gdal::Dataset;
+
+use crate::cliargs::CliAction;
+
+#[derive(Args)]
+pub struct CliArgs {
+    /// Extension of the raster tiles in the directory
+    #[arg(short, long, default_value = "tif")]
+    extension: String,
+    /// Column of the CSV with the x coordinates (longitude)
+    #[arg(short, long, default_value = "lon")]
+    x_field: String,
+    /// Column of the CSV with the y coordinates (latitude)
+    #[arg(short, long, default_value = "lat")]
+    y_field: String,
+    /// Spatial reference of the CSV coordinates
+    #[arg(short, long, default_value = "EPSG:4326")]
+    srs: String,
+    /// Overwrite the output file if it exists
+    #[arg(short = 'O', long)]
+    overwrite: bool,
+    /// Print progress
+    #[arg(short, long)]
+    verbose: bool,
+    /// Directory of the raster tiles, or a CSV file with coordinates
+    input: PathBuf,
+    /// Output VRT file
+    output: PathBuf,
+}
+
+impl CliAction for CliArgs {
+    fn run(self) -> Result<(), anyhow::Error> {
+        if self.output.exists() && !self.overwrite {
+            return Err(anyhow::Error::msg(format!(
+                "Output file {:?} exists, use --overwrite to replace it",
+                self.output
+            )));
+        }
+        if self.input.is_dir() {
+            self.mosaic()?;
+        } else {
+            self.csv_layer()?;
+        }
+        crate::manifest::output(&self.output);
+        Ok(())
+    }
+}
+
+impl CliArgs {
+    /// Mosaic the raster tiles in the directory into one VRT
+    fn mosaic(&self) -> anyhow::Result<()> {
+        let mut tiles: Vec<PathBuf> = std::fs::read_dir(&self.input)?
+            .map(|e| Ok(e?.path()))
+            .collect::<std::io::Result<_>>()?;
+        tiles.retain(|p| {
+            p.extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case(&self.extension))
+        });
+        tiles.sort();
+        if tiles.is_empty() {
+            return Err(anyhow::Error::msg(format!(
+                "No .{} files in {:?}",
+                self.extension, self.input
+            )));
+        }
+        if self.verbose {
+            println!("Mosaicking {} tiles", tiles.len());
+        }
+        let datasets = tiles
+            .iter()
+            .map(|t| {
+                crate::manifest::input(t, None);
+                Dataset::open(t).with_context(|| format!("Cannot open raster {t:?}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        build_vrt(Some(&self.output), &datasets, None)?;
+        Ok(())
+    }
+
+    /// VRT of the CSV with the points from the coordinate columns
+    fn csv_layer(&self) -> anyhow::Result<()> {
+        crate::manifest::input(&self.input, None);
+        let srs = SpatialRef::from_definition(&self.srs)?.to_wkt()?;
+        let name = self
+            .input
+            .file_stem()
+            .context("Input file has no name")?
+            .to_string_lossy();
+        // relative path works when the VRT is next to the CSV
+        let src = relative_to(&self.input, &self.output);
+        let xml = format!(
+            r#"<OGRVRTDataSource>
+  <OGRVRTLayer name="{}">
+    <SrcDataSource relativeToVRT="{}">{}</SrcDataSource>
+    <SrcLayer>{}</SrcLayer>
+    <GeometryType>wkbPoint</GeometryType>
+    <LayerSRS>{}</LayerSRS>
+    <GeometryField encoding="PointFromColumns" x="{}" y="{}"/>
+  </OGRVRTLayer>
+</OGRVRTDataSource>
+"#,
+            xml_escape(&name),
+            src.is_relative() as u8,
+            xml_escape(&src.to_string_lossy()),
+            xml_escape(&name),
+            xml_escape(&srs),
+            xml_escape(&self.x_field),
+            xml_escape(&self.y_field),
+        );
+        std::fs::write(&self.output, xml)?;
+        // open it once, so the errors in the columns are reported now
+        let data = Dataset::open(&self.output)?;
+        if self.verbose {
+            println!("Layer {name} with {} features", data.layer(0)?.feature_count());
+        }
+        Ok(())
+    }
+}
+
+/// Path of the file relative to the directory of the VRT if possible
+fn relative_to(file: &Path, vrt: &Path) -> PathBuf {
+    let dir = vrt.parent().unwrap_or(Path::new(""));
+    match (file.canonicalize(), dir.canonicalize()) {
+        (Ok(f), Ok(d)) => f.strip_prefix(&d).map(Path::to_path_buf).unwrap_or(f),
+        _ => file.to_path_buf(),
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
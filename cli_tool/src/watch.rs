@@ -0,0 +1,150 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use clap::{Args, Parser, ValueHint};
+
+use crate::cliargs::CliAction;
+use crate::network;
+
+#[derive(Args)]
+pub struct CliArgs {
+    /// Seconds between the checks for new files
+    #[arg(short, long, default_value = "5")]
+    interval: u64,
+    /// Extensions of the points files to process
+    #[arg(
+        short,
+        long,
+        value_delimiter = ',',
+        default_value = "gpkg,shp,geojson,json"
+    )]
+    extensions: Vec<String>,
+    /// Don't process the files already in the directory at the start
+    #[arg(short, long)]
+    skip_existing: bool,
+    /// Process the files present and exit instead of watching
+    #[arg(short, long)]
+    once: bool,
+    /// Directory to watch for the points files
+    #[arg(value_hint=ValueHint::DirPath)]
+    dir: PathBuf,
+    /// Streams vector file with streams network
+    #[arg(value_name = "STREAMS_FILE[::LAYER]")]
+    streams: String,
+    /// Options of the network command to run for each file
+    ///
+    /// `{stem}` and `{dir}` are replaced with the file name without
+    /// the extension and the watched directory, e.g. `-o
+    /// {dir}/{stem}.network -n {dir}/{stem}-network.gpkg`
+    #[arg(last = true, allow_hyphen_values = true)]
+    recipe: Vec<String>,
+}
+
+/// Network command options parsed from the recipe
+#[derive(Parser)]
+struct Recipe {
+    #[command(flatten)]
+    args: network::CliArgs,
+}
+
+impl CliAction for CliArgs {
+    fn run(self) -> Result<(), anyhow::Error> {
+        // file -> last seen (size, modified) and if it's been processed
+        let mut seen: HashMap<PathBuf, ((u64, SystemTime), bool)> = HashMap::new();
+        // outputs of the recipe, so they aren't taken as new points files
+        let mut outputs: HashSet<PathBuf> = HashSet::new();
+        let mut first = true;
+        loop {
+            for path in self.files()? {
+                if outputs.contains(&canonical(&path)) {
+                    continue;
+                }
+                // file might have been removed after listing
+                let Some(state) = std::fs::metadata(&path)
+                    .and_then(|m| Ok((m.len(), m.modified()?)))
+                    .ok()
+                else {
+                    continue;
+                };
+                match seen.get_mut(&path) {
+                    // only process files that stopped changing, so
+                    // the ones being copied aren't read half way
+                    Some((prev, done)) if *prev == state => {
+                        if !*done {
+                            *done = true;
+                            outputs.extend(self.process(&path));
+                        }
+                    }
+                    Some((prev, done)) => {
+                        *prev = state;
+                        *done = false;
+                    }
+                    None => {
+                        let skip = first && self.skip_existing;
+                        if first && !skip && self.once {
+                            outputs.extend(self.process(&path));
+                        }
+                        seen.insert(path, (state, skip || self.once));
+                    }
+                }
+            }
+            if self.once {
+                return Ok(());
+            }
+            first = false;
+            std::thread::sleep(Duration::from_secs(self.interval));
+        }
+    }
+}
+
+impl CliArgs {
+    fn files(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&self.dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.extension()
+                    .is_some_and(|e| self.extensions.iter().any(|x| e.eq_ignore_ascii_case(x)))
+            })
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+
+    /// Run the network command recipe on the points file
+    ///
+    /// Returns the files written by the recipe.
+    fn process(&self, path: &PathBuf) -> Vec<PathBuf> {
+        let stem = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let dir = self.dir.to_string_lossy();
+        let mut argv = vec!["network".to_string()];
+        argv.extend(
+            self.recipe
+                .iter()
+                .map(|a| a.replace("{stem}", &stem).replace("{dir}", &dir)),
+        );
+        argv.push(path.to_string_lossy().to_string());
+        argv.push(self.streams.clone());
+        println!("Processing {path:?}");
+        let mut outputs = Vec::new();
+        let res = Recipe::try_parse_from(argv)
+            .map_err(anyhow::Error::from)
+            .and_then(|r| {
+                outputs = r.args.outputs();
+                r.args.run()
+            });
+        if let Err(e) = res {
+            crate::warning!("Failed to process {path:?}: {e}");
+        }
+        outputs.iter().map(canonical).collect()
+    }
+}
+
+/// Absolute path to compare the recipe outputs with the listed files
+fn canonical(path: &PathBuf) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.clone())
+}
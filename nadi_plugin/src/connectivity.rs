@@ -0,0 +1,150 @@
+//! Dendritic Connectivity Index of networks fragmented by barriers
+//!
+//! Based on Cote et al. (2009), "A new measure of longitudinal
+//! connectivity for stream networks". The network is split into
+//! sections at the barriers, and the connectivity between two
+//! sections is the product of the passabilities of the barriers
+//! between them.
+
+/// Part of the network between barriers
+#[derive(Clone, Debug)]
+pub struct Section {
+    pub length: f64,
+    /// Downstream section and the passability of the barrier between them
+    pub parent: Option<(usize, f64)>,
+}
+
+/// Connectivity of all the sections with the given section
+///
+/// Sections in different trees (outlets) are not connected.
+fn connectivity(sections: &[Section], from: usize) -> Vec<f64> {
+    let mut neighbors: Vec<Vec<(usize, f64)>> = vec![Vec::new(); sections.len()];
+    for (i, s) in sections.iter().enumerate() {
+        if let Some((p, pass)) = s.parent {
+            neighbors[i].push((p, pass));
+            neighbors[p].push((i, pass));
+        }
+    }
+    let mut conn = vec![0.0; sections.len()];
+    let mut visited = vec![false; sections.len()];
+    conn[from] = 1.0;
+    visited[from] = true;
+    let mut stack = vec![from];
+    while let Some(cur) = stack.pop() {
+        for &(n, pass) in &neighbors[cur] {
+            if !visited[n] {
+                visited[n] = true;
+                conn[n] = conn[cur] * pass;
+                stack.push(n);
+            }
+        }
+    }
+    conn
+}
+
+fn total_length(sections: &[Section]) -> f64 {
+    sections.iter().map(|s| s.length).sum()
+}
+
+/// DCI for potamodromous fish (movement between any sections), 0-100
+pub fn dci_p(sections: &[Section]) -> f64 {
+    let total = total_length(sections);
+    if total <= 0.0 {
+        return 0.0;
+    }
+    let sum: f64 = (0..sections.len())
+        .map(|i| {
+            let conn = connectivity(sections, i);
+            sections
+                .iter()
+                .zip(conn)
+                .map(|(s, c)| c * sections[i].length * s.length)
+                .sum::<f64>()
+        })
+        .sum();
+    100.0 * sum / total.powi(2)
+}
+
+/// DCI for diadromous fish (movement from the outlet), 0-100
+pub fn dci_d(sections: &[Section]) -> f64 {
+    let total = total_length(sections);
+    if total <= 0.0 {
+        return 0.0;
+    }
+    let sum: f64 = sections
+        .iter()
+        .map(|s| {
+            let mut conn = 1.0;
+            let mut cur = s;
+            while let Some((p, pass)) = cur.parent {
+                conn *= pass;
+                cur = &sections[p];
+            }
+            conn * s.length
+        })
+        .sum();
+    100.0 * sum / total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Outlet section of length 2 with a chain of two upstream
+    /// sections behind barriers of passability 0.5 and 0.4
+    fn chain() -> Vec<Section> {
+        vec![
+            Section {
+                length: 2.0,
+                parent: None,
+            },
+            Section {
+                length: 1.0,
+                parent: Some((0, 0.5)),
+            },
+            Section {
+                length: 1.0,
+                parent: Some((1, 0.4)),
+            },
+        ]
+    }
+
+    #[test]
+    fn dci_known_values() {
+        let sections = chain();
+        // (2*2 + 1 + 1 + 2*(0.5*2*1 + 0.4*1*1 + 0.2*2*1)) / 4^2
+        assert!((dci_p(&sections) - 60.0).abs() < 1e-9);
+        // (2 + 0.5*1 + 0.2*1) / 4
+        assert!((dci_d(&sections) - 67.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn dci_passable_and_impassable() {
+        let mut sections = chain();
+        sections[1].parent = Some((0, 1.0));
+        sections[2].parent = Some((1, 1.0));
+        assert!((dci_p(&sections) - 100.0).abs() < 1e-9);
+        assert!((dci_d(&sections) - 100.0).abs() < 1e-9);
+        sections[1].parent = Some((0, 0.0));
+        // only the outlet section and the upstream pair are connected
+        assert!((dci_p(&sections) - 100.0 * (4.0 + 4.0) / 16.0).abs() < 1e-9);
+        assert!((dci_d(&sections) - 50.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn dci_separate_outlets() {
+        let sections = vec![
+            Section {
+                length: 1.0,
+                parent: None,
+            },
+            Section {
+                length: 1.0,
+                parent: None,
+            },
+        ];
+        assert!((dci_p(&sections) - 50.0).abs() < 1e-9);
+        assert!((dci_d(&sections) - 100.0).abs() < 1e-9);
+        assert_eq!(dci_p(&[]), 0.0);
+    }
+}
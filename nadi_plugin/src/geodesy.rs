@@ -0,0 +1,177 @@
+//! Geodesic calculations on the WGS84 ellipsoid
+//!
+//! Coordinates are in degrees and the results are in meters.
+
+/// Semi-major axis of WGS84 ellipsoid (m)
+pub const WGS84_A: f64 = 6378137.0;
+/// Flattening of WGS84 ellipsoid
+pub const WGS84_F: f64 = 1.0 / 298.257223563;
+/// Mean radius of the earth (m)
+pub const EARTH_RADIUS: f64 = 6371008.8;
+
+/// Great circle distance using haversine formula on a sphere
+pub fn haversine(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let dphi = phi2 - phi1;
+    let dlambda = (lon2 - lon1).to_radians();
+    let a = (dphi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (dlambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().min(1.0).asin()
+}
+
+/// Geodesic distance on the WGS84 ellipsoid using Vincenty's inverse formula
+///
+/// Falls back to the haversine distance for nearly antipodal points
+/// where the iteration doesn't converge.
+pub fn vincenty(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let b = WGS84_A * (1.0 - WGS84_F);
+    let l = (lon2 - lon1).to_radians();
+    let u1 = ((1.0 - WGS84_F) * lat1.to_radians().tan()).atan();
+    let u2 = ((1.0 - WGS84_F) * lat2.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..200 {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = ((cos_u2 * sin_lambda).powi(2)
+            + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2))
+        .sqrt();
+        if sin_sigma == 0.0 {
+            // coincident points
+            return 0.0;
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos_sq_alpha = 1.0 - sin_alpha.powi(2);
+        // equatorial line has cos_sq_alpha = 0
+        let cos_2sigma_m = if cos_sq_alpha == 0.0 {
+            0.0
+        } else {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos_sq_alpha
+        };
+        let c = WGS84_F / 16.0 * cos_sq_alpha * (4.0 + WGS84_F * (4.0 - 3.0 * cos_sq_alpha));
+        let lambda_prev = lambda;
+        lambda = l
+            + (1.0 - c)
+                * WGS84_F
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))));
+        if (lambda - lambda_prev).abs() < 1e-12 {
+            let u_sq = cos_sq_alpha * (WGS84_A.powi(2) - b.powi(2)) / b.powi(2);
+            let a_ =
+                1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+            let b_ = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+            let delta_sigma = b_
+                * sin_sigma
+                * (cos_2sigma_m
+                    + b_ / 4.0
+                        * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))
+                            - b_ / 6.0
+                                * cos_2sigma_m
+                                * (-3.0 + 4.0 * sin_sigma.powi(2))
+                                * (-3.0 + 4.0 * cos_2sigma_m.powi(2))));
+            return b * a_ * (sigma - delta_sigma);
+        }
+    }
+    haversine(lat1, lon1, lat2, lon2)
+}
+
+/// Geodesic length of a line from its (lon, lat) points
+pub fn line_length(pts: &[(f64, f64)]) -> f64 {
+    pts.windows(2)
+        .map(|w| vincenty(w[0].1, w[0].0, w[1].1, w[1].0))
+        .sum()
+}
+
+/// Initial bearing (degrees clockwise from north) from first point to the second
+pub fn bearing(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let dlambda = (lon2 - lon1).to_radians();
+    let y = dlambda.sin() * phi2.cos();
+    let x = phi1.cos() * phi2.sin() - phi1.sin() * phi2.cos() * dlambda.cos();
+    (y.atan2(x).to_degrees() + 360.0) % 360.0
+}
+
+/// Area of a polygon ring from its (lon, lat) points
+///
+/// Approximates the WGS84 ellipsoid with the authalic sphere (same
+/// surface area): the latitudes are converted to authalic latitudes
+/// and the spherical excess of the ring is used. It is not the exact
+/// geodesic area, but the error is small for the rings with short
+/// edges. The sign depends on the direction of the ring so the
+/// absolute value is returned.
+pub fn ring_area(pts: &[(f64, f64)]) -> f64 {
+    let e2 = WGS84_F * (2.0 - WGS84_F);
+    let e = e2.sqrt();
+    let q = |phi: f64| {
+        let s = phi.sin();
+        (1.0 - e2) * (s / (1.0 - e2 * s * s) - ((1.0 - e * s) / (1.0 + e * s)).ln() / (2.0 * e))
+    };
+    let qp = q(std::f64::consts::FRAC_PI_2);
+    let radius = WGS84_A * (qp / 2.0).sqrt();
+    // sine of the authalic latitude
+    let sin_beta = |lat: f64| (q(lat.to_radians()) / qp).clamp(-1.0, 1.0);
+    let sum: f64 = pts
+        .windows(2)
+        .map(|w| {
+            let dlambda = (w[1].0 - w[0].0).to_radians();
+            dlambda * (2.0 + sin_beta(w[0].1) + sin_beta(w[1].1))
+        })
+        .sum();
+    (sum * radius.powi(2) / 2.0).abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dms(d: f64, m: f64, s: f64) -> f64 {
+        d.signum() * (d.abs() + m / 60.0 + s / 3600.0)
+    }
+
+    #[test]
+    fn vincenty_flinders_peak_buninyong() {
+        // Geoscience Australia example for Vincenty's inverse formula
+        let (lat1, lon1) = (dms(-37.0, 57.0, 3.72030), dms(144.0, 25.0, 29.52440));
+        let (lat2, lon2) = (dms(-37.0, 39.0, 10.15610), dms(143.0, 55.0, 35.38390));
+        assert!((vincenty(lat1, lon1, lat2, lon2) - 54972.271).abs() < 1e-3);
+        assert!((vincenty(lat2, lon2, lat1, lon1) - 54972.271).abs() < 1e-3);
+    }
+
+    #[test]
+    fn vincenty_equator_and_coincident() {
+        // one degree of the equator is a degree of the semi-major axis
+        let deg = WGS84_A * std::f64::consts::PI / 180.0;
+        assert!((vincenty(0.0, 0.0, 0.0, 1.0) - deg).abs() < 1e-6);
+        assert_eq!(vincenty(10.0, 20.0, 10.0, 20.0), 0.0);
+        // nearly antipodal points still give a sensible distance
+        let d = vincenty(0.0, 0.0, 0.5, 179.7);
+        assert!(d.is_finite() && (d - haversine(0.0, 0.0, 0.5, 179.7)).abs() < 1e5);
+    }
+
+    #[test]
+    fn ring_area_graticule_cells() {
+        let cell = |lat: f64| {
+            let pts = [(0.0, lat), (1.0, lat), (1.0, lat + 1.0), (0.0, lat + 1.0)];
+            ring_area(&[&pts[..], &pts[..1]].concat())
+        };
+        // area of the 1x1 degree cells on the WGS84 ellipsoid (km2),
+        // the authalic sphere gives the exact area for these
+        assert!((cell(0.0) / 1e6 - 12308.464).abs() < 1e-3);
+        assert!((cell(60.0) / 1e6 - 6123.141).abs() < 1e-3);
+        // same area in either direction and hemisphere
+        let mut pts = vec![
+            (0.0, -1.0),
+            (0.0, 0.0),
+            (1.0, 0.0),
+            (1.0, -1.0),
+            (0.0, -1.0),
+        ];
+        assert!((ring_area(&pts) - cell(0.0)).abs() < 1e-3);
+        pts.reverse();
+        assert!((ring_area(&pts) - cell(0.0)).abs() < 1e-3);
+    }
+}
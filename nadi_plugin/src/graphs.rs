@@ -0,0 +1,878 @@
+//! Writers for the network in the graph formats of network analysis tools
+//!
+//! GraphML and GEXF can be read by igraph, networkx and Gephi; the
+//! edges are directed from each node to its output node. GraphML,
+//! GEXF and DOT files from the other tools can also be read back.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::csv_quote;
+
+/// Attribute value of a node in the graph formats
+#[derive(Clone, Debug)]
+pub enum Value {
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+}
+
+impl Value {
+    fn graphml_type(&self) -> &'static str {
+        match self {
+            Self::Integer(_) => "long",
+            Self::Float(_) => "double",
+            Self::Bool(_) => "boolean",
+            Self::String(_) => "string",
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Self::Integer(v) => v.to_string(),
+            Self::Float(v) => v.to_string(),
+            Self::Bool(v) => v.to_string(),
+            Self::String(v) => escape(v),
+        }
+    }
+}
+
+/// Node of the network with its attributes
+#[derive(Clone, Debug, Default)]
+pub struct GraphNode {
+    pub name: String,
+    pub output: Option<String>,
+    /// Values of the attributes, in the same order as the names
+    pub attrs: Vec<Option<Value>>,
+}
+
+/// Nodes with their attributes, and the edges read from a graph file
+#[derive(Clone, Debug, Default)]
+pub struct Graph {
+    pub nodes: Vec<(String, Vec<(String, Value)>)>,
+    pub edges: Vec<(String, String)>,
+}
+
+impl Graph {
+    /// Index of the node, added if it's not there
+    fn node(&mut self, name: &str) -> usize {
+        match self.nodes.iter().position(|n| n.0 == name) {
+            Some(i) => i,
+            None => {
+                self.nodes.push((name.to_string(), vec![]));
+                self.nodes.len() - 1
+            }
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Type of the attribute from the first node that has it
+fn attr_types<'a>(attrs: &[String], nodes: &'a [GraphNode]) -> Vec<Option<&'a Value>> {
+    (0..attrs.len())
+        .map(|i| nodes.iter().find_map(|n| n.attrs.get(i)?.as_ref()))
+        .collect()
+}
+
+/// Directed GraphML graph with the attributes as node data
+pub fn graphml(attrs: &[String], nodes: &[GraphNode]) -> String {
+    let mut s = String::new();
+    // writing to String doesn't fail
+    let _ = writeln!(s, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        s,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    );
+    for (i, (a, ty)) in attrs.iter().zip(attr_types(attrs, nodes)).enumerate() {
+        let ty = ty.map(Value::graphml_type).unwrap_or("string");
+        let _ = writeln!(
+            s,
+            r#"  <key id="d{i}" for="node" attr.name="{}" attr.type="{ty}"/>"#,
+            escape(a)
+        );
+    }
+    let _ = writeln!(s, r#"  <graph id="nadi" edgedefault="directed">"#);
+    for n in nodes {
+        let _ = writeln!(s, r#"    <node id="{}">"#, escape(&n.name));
+        for (i, v) in n.attrs.iter().enumerate() {
+            if let Some(v) = v {
+                let _ = writeln!(s, r#"      <data key="d{i}">{}</data>"#, v.text());
+            }
+        }
+        let _ = writeln!(s, "    </node>");
+    }
+    for n in nodes {
+        if let Some(out) = &n.output {
+            let _ = writeln!(
+                s,
+                r#"    <edge source="{}" target="{}"/>"#,
+                escape(&n.name),
+                escape(out)
+            );
+        }
+    }
+    let _ = writeln!(s, "  </graph>");
+    let _ = writeln!(s, "</graphml>");
+    s
+}
+
+/// Directed GEXF graph with the attributes as node attvalues
+pub fn gexf(attrs: &[String], nodes: &[GraphNode]) -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(s, r#"<gexf xmlns="http://gexf.net/1.3" version="1.3">"#);
+    let _ = writeln!(s, r#"  <graph defaultedgetype="directed">"#);
+    let _ = writeln!(s, r#"    <attributes class="node">"#);
+    for (i, (a, ty)) in attrs.iter().zip(attr_types(attrs, nodes)).enumerate() {
+        // GEXF has the same type names for these
+        let ty = ty.map(Value::graphml_type).unwrap_or("string");
+        let _ = writeln!(
+            s,
+            r#"      <attribute id="{i}" title="{}" type="{ty}"/>"#,
+            escape(a)
+        );
+    }
+    let _ = writeln!(s, "    </attributes>");
+    let _ = writeln!(s, "    <nodes>");
+    for n in nodes {
+        let name = escape(&n.name);
+        let _ = writeln!(s, r#"      <node id="{name}" label="{name}">"#);
+        let _ = writeln!(s, "        <attvalues>");
+        for (i, v) in n.attrs.iter().enumerate() {
+            if let Some(v) = v {
+                let _ = writeln!(s, r#"          <attvalue for="{i}" value="{}"/>"#, v.text());
+            }
+        }
+        let _ = writeln!(s, "        </attvalues>");
+        let _ = writeln!(s, "      </node>");
+    }
+    let _ = writeln!(s, "    </nodes>");
+    let _ = writeln!(s, "    <edges>");
+    for (i, n) in nodes.iter().enumerate() {
+        if let Some(out) = &n.output {
+            let _ = writeln!(
+                s,
+                r#"      <edge id="{i}" source="{}" target="{}"/>"#,
+                escape(&n.name),
+                escape(out)
+            );
+        }
+    }
+    let _ = writeln!(s, "    </edges>");
+    let _ = writeln!(s, "  </graph>");
+    let _ = writeln!(s, "</gexf>");
+    s
+}
+
+/// Adjacency matrix CSV, rows are the upstream nodes
+///
+/// The dense matrix has the node names as the header and the first
+/// column; the sparse one only lists the edges as `from,to,value`.
+pub fn adjacency(nodes: &[GraphNode], sparse: bool) -> String {
+    let mut s = String::new();
+    if sparse {
+        let _ = writeln!(s, "from,to,value");
+        for n in nodes {
+            if let Some(out) = &n.output {
+                let _ = writeln!(s, "{},{},1", csv_quote(&n.name), csv_quote(out));
+            }
+        }
+        return s;
+    }
+    let names: Vec<String> = nodes.iter().map(|n| csv_quote(&n.name)).collect();
+    let _ = writeln!(s, ",{}", names.join(","));
+    for n in nodes {
+        let row: Vec<&str> = nodes
+            .iter()
+            .map(|m| {
+                if n.output.as_deref() == Some(m.name.as_str()) {
+                    "1"
+                } else {
+                    "0"
+                }
+            })
+            .collect();
+        let _ = writeln!(s, "{},{}", csv_quote(&n.name), row.join(","));
+    }
+    s
+}
+
+/// Along-network distances between the nodes as CSV
+///
+/// `lengths` are the lengths of the edges from the nodes to their
+/// outputs. Two nodes are flow connected if one is downstream of the
+/// other; otherwise the distance is the sum of the distances from both
+/// nodes to the confluence downstream. The long table has a row for
+/// each pair, the matrix has the distance from the row node to the
+/// confluence with the column node (zero for the downstream one of
+/// flow connected nodes), so the total distance is the sum of both
+/// directions. Nodes without a common downstream node are left out.
+/// Only the `selected` nodes (indices) are in the output.
+pub fn distances(
+    nodes: &[GraphNode],
+    lengths: &HashMap<&str, f64>,
+    selected: &[usize],
+    matrix: bool,
+) -> String {
+    let outputs: HashMap<&str, &str> = nodes
+        .iter()
+        .filter_map(|n| Some((n.name.as_str(), n.output.as_deref()?)))
+        .collect();
+    // cumulative distance to each node downstream, including itself
+    let downstream = |name: &str| {
+        let mut path = HashMap::from([(name.to_string(), 0.0)]);
+        let (mut cur, mut dist) = (name, 0.0);
+        while let Some(&out) = outputs.get(cur) {
+            dist += lengths.get(cur).copied().unwrap_or_default();
+            if path.insert(out.to_string(), dist).is_some() {
+                break;
+            }
+            cur = out;
+        }
+        path
+    };
+    let paths: HashMap<usize, HashMap<String, f64>> = selected
+        .iter()
+        .map(|&i| (i, downstream(&nodes[i].name)))
+        .collect();
+    // distances from both nodes to their confluence, and if the
+    // confluence is one of them
+    let confluence = |i: usize, j: usize| {
+        let (mut cur, mut dist) = (nodes[j].name.as_str(), 0.0);
+        for _ in 0..=nodes.len() {
+            if let Some(d) = paths[&i].get(cur) {
+                let connected = cur == nodes[i].name || cur == nodes[j].name;
+                return Some((*d, dist, connected));
+            }
+            dist += lengths.get(cur).copied().unwrap_or_default();
+            cur = outputs.get(cur)?;
+        }
+        None
+    };
+
+    let mut s = String::new();
+    if matrix {
+        let names: Vec<String> = selected
+            .iter()
+            .map(|&i| csv_quote(&nodes[i].name))
+            .collect();
+        let _ = writeln!(s, ",{}", names.join(","));
+        for &i in selected {
+            let row: Vec<String> = selected
+                .iter()
+                .map(|&j| {
+                    confluence(i, j)
+                        .map(|(d, _, _)| d.to_string())
+                        .unwrap_or_default()
+                })
+                .collect();
+            let _ = writeln!(s, "{},{}", csv_quote(&nodes[i].name), row.join(","));
+        }
+        return s;
+    }
+    let _ = writeln!(
+        s,
+        "from,to,from_distance,to_distance,distance,flow_connected"
+    );
+    for (k, &i) in selected.iter().enumerate() {
+        for &j in &selected[(k + 1)..] {
+            let Some((a, b, connected)) = confluence(i, j) else {
+                continue;
+            };
+            let _ = writeln!(
+                s,
+                "{},{},{a},{b},{},{connected}",
+                csv_quote(&nodes[i].name),
+                csv_quote(&nodes[j].name),
+                a + b
+            );
+        }
+    }
+    s
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Value of the given GraphML type, or guessed from the text
+fn parse_value(text: &str, ty: &str) -> Value {
+    let t = text.trim();
+    let int = || t.parse().ok().map(Value::Integer);
+    let float = || t.parse().ok().map(Value::Float);
+    let bool = || t.parse().ok().map(Value::Bool);
+    match ty {
+        "int" | "integer" | "long" => int(),
+        "float" | "double" => float(),
+        "boolean" => bool(),
+        "string" => None,
+        _ => int().or_else(float).or_else(bool),
+    }
+    .unwrap_or_else(|| Value::String(text.to_string()))
+}
+
+/// Attributes of the XML tag
+fn tag_attrs(tag: &str) -> HashMap<String, String> {
+    let mut attrs = HashMap::new();
+    let mut rest = tag;
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].split_whitespace().last().unwrap_or_default();
+        let value = rest[eq + 1..].trim_start();
+        let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            break;
+        };
+        let Some(end) = value[1..].find(quote) else {
+            break;
+        };
+        attrs.insert(key.to_string(), unescape(&value[1..end + 1]));
+        rest = &value[end + 2..];
+    }
+    attrs
+}
+
+/// Position of the `>` closing the tag, skipping the quoted values
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    tag.char_indices().find_map(|(i, c)| {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return Some(i),
+            _ => (),
+        }
+        None
+    })
+}
+
+/// Element tag of the XML with the text following it
+struct XmlTag {
+    /// Name without the namespace prefix
+    name: String,
+    /// Closing tag like `</node>`
+    closing: bool,
+    /// Self closing tag like `<edge/>`
+    empty: bool,
+    attrs: HashMap<String, String>,
+    /// Text (including CDATA) up to the next tag
+    text: String,
+}
+
+/// Element tags of the XML
+///
+/// Comments, processing instructions and declarations are skipped,
+/// the CDATA sections are kept as they are in the text.
+fn xml_tags(text: &str) -> Result<Vec<XmlTag>, String> {
+    let mut tags: Vec<XmlTag> = Vec::new();
+    let mut rest = text;
+    loop {
+        let start = rest.find('<').unwrap_or(rest.len());
+        if let Some(t) = tags.last_mut() {
+            t.text.push_str(&unescape(&rest[..start]));
+        }
+        rest = &rest[start..];
+        if rest.is_empty() {
+            break;
+        }
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").ok_or("Unclosed CDATA in the XML")?;
+            if let Some(t) = tags.last_mut() {
+                t.text.push_str(&cdata[..end]);
+            }
+            rest = &cdata[end + 3..];
+            continue;
+        }
+        let markup = [("<!--", "-->"), ("<?", "?>"), ("<!", ">")]
+            .into_iter()
+            .find(|(s, _)| rest.starts_with(s));
+        if let Some((open, close)) = markup {
+            let end = rest[open.len()..]
+                .find(close)
+                .ok_or("Unclosed comment or declaration in the XML")?;
+            rest = &rest[open.len() + end + close.len()..];
+            continue;
+        }
+        let end = tag_end(&rest[1..]).ok_or("Unclosed tag in the XML")?;
+        let tag = &rest[1..end + 1];
+        rest = &rest[end + 2..];
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        tags.push(XmlTag {
+            name: name.rsplit(':').next().unwrap_or_default().to_string(),
+            closing: tag.starts_with('/'),
+            empty: tag.ends_with('/'),
+            attrs: tag_attrs(tag),
+            text: String::new(),
+        });
+    }
+    Ok(tags)
+}
+
+/// Read the nodes, their data and the edges from GraphML
+pub fn read_graphml(text: &str) -> Result<Graph, String> {
+    let mut graph = Graph::default();
+    // key id to the attribute name and type
+    let mut keys: HashMap<String, (String, String)> = HashMap::new();
+    let mut current = None;
+    for tag in xml_tags(text)? {
+        let attrs = &tag.attrs;
+        match (tag.name.as_str(), tag.closing) {
+            ("key", false) => {
+                if let Some(id) = attrs.get("id") {
+                    let name = attrs.get("attr.name").unwrap_or(id).to_string();
+                    let ty = attrs.get("attr.type").cloned().unwrap_or_default();
+                    keys.insert(id.to_string(), (name, ty));
+                }
+            }
+            ("node", false) => {
+                let id = attrs.get("id").ok_or("Node without id in the GraphML")?;
+                let i = graph.node(id);
+                current = (!tag.empty).then_some(i);
+            }
+            ("node", true) => current = None,
+            ("edge", false) => {
+                let (Some(s), Some(t)) = (attrs.get("source"), attrs.get("target")) else {
+                    return Err("Edge without source or target in the GraphML".into());
+                };
+                graph.edges.push((s.to_string(), t.to_string()));
+            }
+            ("data", false) if !tag.empty => {
+                let (Some(i), Some(k)) = (current, attrs.get("key")) else {
+                    continue;
+                };
+                let (name, ty) = keys.get(k).cloned().unwrap_or((k.to_string(), "".into()));
+                graph.nodes[i].1.push((name, parse_value(&tag.text, &ty)));
+            }
+            _ => (),
+        }
+    }
+    Ok(graph)
+}
+
+/// Read the nodes, their attribute values and the edges from GEXF
+pub fn read_gexf(text: &str) -> Result<Graph, String> {
+    let mut graph = Graph::default();
+    // attribute id to the title and type
+    let mut keys: HashMap<String, (String, String)> = HashMap::new();
+    let mut node_class = false;
+    let mut current = None;
+    for tag in xml_tags(text)? {
+        let attrs = &tag.attrs;
+        match (tag.name.as_str(), tag.closing) {
+            ("attributes", false) => {
+                node_class = attrs.get("class").is_some_and(|c| c == "node");
+            }
+            ("attribute", false) if node_class => {
+                if let Some(id) = attrs.get("id") {
+                    let name = attrs.get("title").unwrap_or(id).to_string();
+                    let ty = attrs.get("type").cloned().unwrap_or_default();
+                    keys.insert(id.to_string(), (name, ty));
+                }
+            }
+            ("node", false) => {
+                let id = attrs.get("id").ok_or("Node without id in the GEXF")?;
+                let i = graph.node(id);
+                current = (!tag.empty).then_some(i);
+            }
+            ("node", true) => current = None,
+            ("edge", false) => {
+                let (Some(s), Some(t)) = (attrs.get("source"), attrs.get("target")) else {
+                    return Err("Edge without source or target in the GEXF".into());
+                };
+                graph.edges.push((s.to_string(), t.to_string()));
+            }
+            ("attvalue", false) => {
+                let k = attrs.get("for").or_else(|| attrs.get("id"));
+                let (Some(i), Some(k), Some(v)) = (current, k, attrs.get("value")) else {
+                    continue;
+                };
+                let (name, ty) = keys.get(k).cloned().unwrap_or((k.to_string(), "".into()));
+                graph.nodes[i].1.push((name, parse_value(v, &ty)));
+            }
+            _ => (),
+        }
+    }
+    Ok(graph)
+}
+
+const DOT_SYMBOLS: [&str; 10] = ["{", "}", "[", "]", ";", ",", "=", ":", "->", "--"];
+
+/// Tokens of the DOT file, with a flag for the quoted ones
+fn dot_tokens(text: &str) -> Result<Vec<(String, bool)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            '/' if chars.peek() == Some(&'*') => {
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().find(|c| *c == '\n');
+            }
+            '#' => {
+                chars.by_ref().find(|c| *c == '\n');
+            }
+            '"' => {
+                let mut s = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' if chars.peek() == Some(&'"') => {
+                            s.push('"');
+                            chars.next();
+                        }
+                        '"' => break,
+                        c => s.push(c),
+                    }
+                }
+                tokens.push((s, true));
+            }
+            // HTML string like `<<b>x</b>>`, kept as it is
+            '<' => {
+                let (mut s, mut depth) = (String::new(), 1);
+                for c in chars.by_ref() {
+                    match c {
+                        '<' => depth += 1,
+                        '>' => depth -= 1,
+                        _ => (),
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                    s.push(c);
+                }
+                if depth > 0 {
+                    return Err("Unclosed HTML string in the DOT file".into());
+                }
+                tokens.push((s, true));
+            }
+            '-' if matches!(chars.peek(), Some('>' | '-')) => {
+                let n = chars.next().unwrap_or_default();
+                tokens.push((format!("-{n}"), false));
+            }
+            '{' | '}' | '[' | ']' | ';' | ',' | '=' | ':' => tokens.push((c.to_string(), false)),
+            c if c.is_alphanumeric() || matches!(c, '_' | '.' | '-') => {
+                let mut s = String::from(c);
+                while let Some(&n) = chars.peek() {
+                    if !(n.is_alphanumeric() || matches!(n, '_' | '.')) {
+                        break;
+                    }
+                    s.push(n);
+                    chars.next();
+                }
+                tokens.push((s, false));
+            }
+            c => return Err(format!("Unexpected character {c:?} in the DOT file")),
+        }
+    }
+    Ok(tokens)
+}
+
+struct DotParser {
+    tokens: Vec<(String, bool)>,
+    pos: usize,
+}
+
+impl DotParser {
+    fn is(&self, sym: &str) -> bool {
+        self.tokens
+            .get(self.pos)
+            .is_some_and(|t| !t.1 && t.0.eq_ignore_ascii_case(sym))
+    }
+
+    fn id(&mut self) -> Option<String> {
+        let (t, quoted) = self.tokens.get(self.pos)?;
+        if !quoted && DOT_SYMBOLS.contains(&t.as_str()) {
+            return None;
+        }
+        self.pos += 1;
+        Some(t.clone())
+    }
+
+    /// Node id, ignoring the port
+    fn node_id(&mut self) -> Option<String> {
+        let id = self.id()?;
+        while self.is(":") {
+            self.pos += 1;
+            self.id();
+        }
+        Some(id)
+    }
+
+    /// Attributes in the brackets like `[a=1, b="x"]`
+    fn attrs(&mut self) -> Vec<(String, String)> {
+        let mut attrs = vec![];
+        while self.is("[") {
+            self.pos += 1;
+            while self.pos < self.tokens.len() && !self.is("]") {
+                match self.id() {
+                    Some(k) if self.is("=") => {
+                        self.pos += 1;
+                        if let Some(v) = self.id() {
+                            attrs.push((k, v));
+                        }
+                    }
+                    Some(_) => (),
+                    None => self.pos += 1,
+                }
+            }
+            self.pos += 1;
+        }
+        attrs
+    }
+}
+
+/// Read the nodes, their attributes and the edges from DOT
+///
+/// Subgraphs are flattened, and the default node/edge attributes
+/// and graph attributes are ignored.
+pub fn read_dot(text: &str) -> Result<Graph, String> {
+    let mut p = DotParser {
+        tokens: dot_tokens(text)?,
+        pos: 0,
+    };
+    if p.is("strict") {
+        p.pos += 1;
+    }
+    if !(p.is("digraph") || p.is("graph")) {
+        return Err("DOT file should start with graph or digraph".into());
+    }
+    p.pos += 1;
+    if !p.is("{") {
+        p.id();
+    }
+    let mut graph = Graph::default();
+    while let Some((tok, _)) = p.tokens.get(p.pos).cloned() {
+        if p.is("{") || p.is("}") || p.is(";") || p.is(",") {
+            p.pos += 1;
+            continue;
+        }
+        if p.is("subgraph") {
+            p.pos += 1;
+            if !p.is("{") {
+                p.id();
+            }
+            continue;
+        }
+        if p.is("node") || p.is("edge") || p.is("graph") {
+            p.pos += 1;
+            p.attrs();
+            continue;
+        }
+        let first = p
+            .node_id()
+            .ok_or_else(|| format!("Unexpected {tok:?} in the DOT file"))?;
+        if p.is("=") {
+            // graph attribute
+            p.pos += 1;
+            p.id();
+            continue;
+        }
+        let mut chain = vec![first];
+        while p.is("->") || p.is("--") {
+            p.pos += 1;
+            chain.push(p.node_id().ok_or("Edge without target in the DOT file")?);
+        }
+        let attrs = p.attrs();
+        if chain.len() == 1 {
+            let i = graph.node(&chain[0]);
+            graph.nodes[i]
+                .1
+                .extend(attrs.into_iter().map(|(k, v)| (k, parse_value(&v, ""))));
+        } else {
+            for e in chain.windows(2) {
+                graph.node(&e[0]);
+                graph.node(&e[1]);
+                graph.edges.push((e[0].clone(), e[1].clone()));
+            }
+        }
+    }
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attr<'a>(graph: &'a Graph, node: &str, key: &str) -> Option<&'a Value> {
+        let (_, attrs) = graph.nodes.iter().find(|n| n.0 == node)?;
+        attrs.iter().find(|a| a.0 == key).map(|a| &a.1)
+    }
+
+    fn sample() -> (Vec<String>, Vec<GraphNode>) {
+        let node = |name: &str, output: Option<&str>, area: f64, label: &str| GraphNode {
+            name: name.to_string(),
+            output: output.map(String::from),
+            attrs: vec![
+                Some(Value::Float(area)),
+                Some(Value::String(label.to_string())),
+                Some(Value::Integer(3)),
+            ],
+        };
+        let nodes = vec![
+            node("a", Some("c"), 1.5, "A & <B>"),
+            node("b", Some("c"), 2.0, "\"quoted\""),
+            node("c", None, 4.25, "outlet"),
+        ];
+        let attrs = ["area", "label", "order"].map(String::from).to_vec();
+        (attrs, nodes)
+    }
+
+    fn check_sample(graph: &Graph) {
+        let names: Vec<&str> = graph.nodes.iter().map(|n| n.0.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert_eq!(
+            graph.edges,
+            [("a".into(), "c".into()), ("b".into(), "c".into())]
+        );
+        assert!(matches!(attr(graph, "a", "area"), Some(Value::Float(v)) if *v == 1.5));
+        assert!(matches!(attr(graph, "a", "label"), Some(Value::String(v)) if v == "A & <B>"));
+        assert!(matches!(attr(graph, "b", "label"), Some(Value::String(v)) if v == "\"quoted\""));
+        assert!(matches!(attr(graph, "c", "order"), Some(Value::Integer(3))));
+    }
+
+    #[test]
+    fn graphml_round_trip() {
+        let (attrs, nodes) = sample();
+        check_sample(&read_graphml(&graphml(&attrs, &nodes)).unwrap());
+    }
+
+    #[test]
+    fn gexf_round_trip() {
+        let (attrs, nodes) = sample();
+        check_sample(&read_gexf(&gexf(&attrs, &nodes)).unwrap());
+    }
+
+    #[test]
+    fn graphml_comments_cdata_namespaces() {
+        let text = r#"<?xml version="1.0"?>
+<!-- generated by a tool -> with a > in the comment -->
+<g:graphml xmlns:g="http://graphml.graphdrawing.org/xmlns">
+  <g:key id="d0" for="node" attr.name="name" attr.type="string"/>
+  <g:key id="d1" for="node" attr.name="area" attr.type="double"/>
+  <g:graph edgedefault="directed">
+    <g:node id="a">
+      <g:data key="d0"><![CDATA[x < y & </data>]]></g:data>
+      <g:data key="d1">1<!-- > -->.5</g:data>
+    </g:node>
+    <g:node id="b"/>
+    <g:edge source="a" target="b"><g:data key="d1">9</g:data></g:edge>
+  </g:graph>
+</g:graphml>"#;
+        let graph = read_graphml(text).unwrap();
+        assert_eq!(graph.nodes.len(), 2);
+        assert_eq!(graph.edges, [("a".into(), "b".into())]);
+        assert!(
+            matches!(attr(&graph, "a", "name"), Some(Value::String(v)) if v == "x < y & </data>")
+        );
+        assert!(matches!(attr(&graph, "a", "area"), Some(Value::Float(v)) if *v == 1.5));
+        assert!(graph.nodes[1].1.is_empty());
+    }
+
+    #[test]
+    fn graphml_quoted_gt_in_attribute() {
+        let text = r#"<graphml><graph><node id="a>b"/><node id="c"/>
+            <edge source="a>b" target="c"/></graph></graphml>"#;
+        let graph = read_graphml(text).unwrap();
+        assert_eq!(graph.edges, [("a>b".into(), "c".into())]);
+    }
+
+    #[test]
+    fn dot_html_labels_and_comments() {
+        let text = r#"// upstream to downstream
+digraph "rivers" {
+    node [shape=box];
+    /* a -> x is commented out */
+    a [label=<<b>A</b> &amp; <i>river</i>>, area=1.5];
+    b [label="B \"river\""];
+    a -> c; b -> c:n -> d;
+}"#;
+        let graph = read_dot(text).unwrap();
+        assert_eq!(
+            graph.edges,
+            [
+                ("a".into(), "c".into()),
+                ("b".into(), "c".into()),
+                ("c".into(), "d".into())
+            ]
+        );
+        assert!(
+            matches!(attr(&graph, "a", "label"), Some(Value::String(v)) if v == "<b>A</b> &amp; <i>river</i>")
+        );
+        assert!(matches!(attr(&graph, "a", "area"), Some(Value::Float(v)) if *v == 1.5));
+        assert!(matches!(attr(&graph, "b", "label"), Some(Value::String(v)) if v == "B \"river\""));
+    }
+
+    #[test]
+    fn dot_unclosed_html_label() {
+        assert!(read_dot("digraph { a [label=<<b>A</b>]; }").is_err());
+    }
+
+    /// a and b join at c, which flows to d; e is not connected
+    fn river() -> (Vec<GraphNode>, HashMap<&'static str, f64>) {
+        let node = |name: &str, output: Option<&str>| GraphNode {
+            name: name.to_string(),
+            output: output.map(String::from),
+            attrs: vec![],
+        };
+        let nodes = vec![
+            node("a", Some("c")),
+            node("b", Some("c")),
+            node("c", Some("d")),
+            node("d", None),
+            node("e", None),
+        ];
+        let lengths = HashMap::from([("a", 2.0), ("b", 3.0), ("c", 5.0)]);
+        (nodes, lengths)
+    }
+
+    #[test]
+    fn distances_long() {
+        let (nodes, lengths) = river();
+        let csv = distances(&nodes, &lengths, &[0, 1, 2, 3, 4], false);
+        let expected = r#"from,to,from_distance,to_distance,distance,flow_connected
+"a","b",2,3,5,false
+"a","c",2,0,2,true
+"a","d",7,0,7,true
+"b","c",3,0,3,true
+"b","d",8,0,8,true
+"c","d",5,0,5,true
+"#;
+        assert_eq!(csv, expected);
+        let csv = distances(&nodes, &lengths, &[3, 1], false);
+        assert!(csv.ends_with("\n\"d\",\"b\",0,8,8,true\n"));
+    }
+
+    #[test]
+    fn distances_matrix() {
+        let (nodes, lengths) = river();
+        let csv = distances(&nodes, &lengths, &[0, 1, 2, 3, 4], true);
+        let expected = r#","a","b","c","d","e"
+"a",0,2,2,7,
+"b",3,0,3,8,
+"c",0,0,0,5,
+"d",0,0,0,0,
+"e",,,,,0
+"#;
+        assert_eq!(csv, expected);
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::{Arc, Mutex};
+
+use nadi_core::anyhow::Result;
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::{RTree, AABB};
+
+/// Node point in the spatial index with the node name
+pub type IndexedNode = GeomWithData<[f64; 2], String>;
+
+/// Spatial index of the node geometries
+pub struct NodeIndex(RTree<IndexedNode>);
+
+impl NodeIndex {
+    pub fn new(points: Vec<IndexedNode>) -> Self {
+        Self(RTree::bulk_load(points))
+    }
+
+    /// Name of the node nearest to the point
+    pub fn nearest(&self, x: f64, y: f64) -> Option<&str> {
+        self.0.nearest_neighbor(&[x, y]).map(|n| n.data.as_str())
+    }
+
+    /// Names of the nodes within the radius of the point, nearest first
+    pub fn within(&self, x: f64, y: f64, radius: f64) -> Vec<&str> {
+        let mut nodes: Vec<(f64, &str)> = self
+            .0
+            .locate_within_distance([x, y], radius.powi(2))
+            .map(|n| {
+                let [nx, ny] = *n.geom();
+                ((nx - x).powi(2) + (ny - y).powi(2), n.data.as_str())
+            })
+            .collect();
+        nodes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        nodes.into_iter().map(|n| n.1).collect()
+    }
+
+    /// Names and points of the nodes inside the envelope
+    pub fn in_envelope(&self, min: [f64; 2], max: [f64; 2]) -> Vec<(&str, [f64; 2])> {
+        self.0
+            .locate_in_envelope(&AABB::from_corners(min, max))
+            .map(|n| (n.data.as_str(), *n.geom()))
+            .collect()
+    }
+}
+
+/// Spatial index of the feature envelopes with their positions
+pub struct EnvelopeIndex(RTree<GeomWithData<Rectangle<[f64; 2]>, usize>>);
+
+impl EnvelopeIndex {
+    pub fn new(envelopes: impl IntoIterator<Item = ([f64; 2], [f64; 2])>) -> Self {
+        Self(RTree::bulk_load(
+            envelopes
+                .into_iter()
+                .enumerate()
+                .map(|(i, (min, max))| GeomWithData::new(Rectangle::from_corners(min, max), i))
+                .collect(),
+        ))
+    }
+
+    /// Positions of the envelopes with the distance to the point, nearest first
+    ///
+    /// The distance is zero for the point inside an envelope, so it's
+    /// the lower bound of the distance to the feature.
+    pub fn nearest(&self, x: f64, y: f64) -> impl Iterator<Item = (usize, f64)> + '_ {
+        self.0
+            .nearest_neighbor_iter_with_distance_2(&[x, y])
+            .map(|(e, d2)| (e.data, d2.sqrt()))
+    }
+}
+
+/// Identifies the network and the geometry attribute the index is built from
+///
+/// The fingerprint is a cheap hash of the node names and the start
+/// of their geometry attribute (with the point used in the index), so
+/// the geometry changes made outside the gis functions are caught too.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CacheKey {
+    pub network: usize,
+    pub geometry: String,
+    pub fingerprint: u64,
+}
+
+static NODE_INDEX: Mutex<Option<(CacheKey, Arc<NodeIndex>)>> = Mutex::new(None);
+
+/// Get the cached node index for the key, or build and cache it
+pub fn cached_index<F>(key: CacheKey, build: F) -> Result<Arc<NodeIndex>>
+where
+    F: FnOnce() -> Result<NodeIndex>,
+{
+    let mut cache = NODE_INDEX.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((k, index)) = cache.as_ref() {
+        if *k == key {
+            return Ok(index.clone());
+        }
+    }
+    let index = Arc::new(build()?);
+    *cache = Some((key, index.clone()));
+    Ok(index)
+}
+
+/// Remove the cached node index
+///
+/// Functions that load the network or change the node geometries
+/// should call this, so the later spatial queries rebuild the index.
+pub fn invalidate_index() {
+    *NODE_INDEX.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
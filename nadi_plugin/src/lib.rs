@@ -1,19 +1,66 @@
 use nadi_core::nadi_plugin::nadi_plugin;
 
+mod connectivity;
+mod geodesy;
+mod graphs;
+mod index;
+mod lrs;
+mod models;
+mod raster;
+mod schema;
+mod streams;
+mod svg;
+
+/// Environment variable to silence the warnings from the functions
+const QUIET_ENV: &str = "NADI_GIS_QUIET";
+
+/// Print the warning to stderr, unless NADI_GIS_QUIET is set
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if std::env::var_os(crate::QUIET_ENV).is_none() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Quote the CSV field, doubling the quotes inside it
+fn csv_quote(v: &str) -> String {
+    format!("\"{}\"", v.replace('"', "\"\""))
+}
+
 #[nadi_plugin]
 mod gis {
+    use crate::connectivity::{self, Section};
+    use crate::csv_quote;
+    use crate::geodesy;
+    use crate::graphs::{self, GraphNode, Value};
+    use crate::index::{
+        cached_index, invalidate_index, CacheKey, EnvelopeIndex, IndexedNode, NodeIndex,
+    };
+    use crate::lrs::{self, Flowlines};
+    use crate::models::{self, ModelNode};
+    use crate::raster::RasterSampler;
+    use crate::schema::{Schema, FIELD_TYPES};
+    use crate::streams::StreamGraph;
+    use crate::svg::SvgMap;
     use chrono::Datelike;
+    use gdal::spatial_ref::{AxisMappingStrategy, CoordTransform, SpatialRef};
     use gdal::vector::{
-        Defn, Feature, FieldValue, Geometry, LayerAccess, LayerOptions, OGRFieldType,
+        Defn, Feature, FieldValue, Geometry, Layer, LayerAccess, LayerOptions, OGRFieldType,
     };
-    use gdal::{Dataset, DriverManager, DriverType};
-    use nadi_core::abi_stable::std_types::{RSome, RString};
+    use gdal::{Dataset, DatasetOptions, DriverManager, DriverType, Metadata};
+    use nadi_core::abi_stable::std_types::{RSome, RString, RVec};
     use nadi_core::anyhow::{Context, Result};
-    use nadi_core::attrs::{Date, DateTime, FromAttribute, FromAttributeRelaxed, HasAttributes};
-    use nadi_core::nadi_plugin::network_func;
+    use nadi_core::attrs::{
+        AttrMap, Date, DateTime, FromAttribute, FromAttributeRelaxed, HasAttributes, Time,
+    };
+    use nadi_core::nadi_plugin::{env_func, network_func};
     use nadi_core::prelude::*;
-    use std::collections::{HashMap, HashSet};
+    use std::collections::hash_map::RandomState;
+    use std::collections::{HashMap, HashSet, VecDeque};
+    use std::hash::{BuildHasher, Hasher};
     use std::path::PathBuf;
+    use std::sync::Arc;
 
     /// Load network from a GIS file
     ///
@@ -31,20 +78,12 @@ mod gis {
         layer: Option<String>,
         /// Ignore feature if it has fields with null value
         ignore_null: bool,
+        /// Encoding of the text fields (e.g. ISO-8859-1, CP932), for
+        /// the shapefile DBFs without the right codepage
+        encoding: Option<String>,
     ) -> Result<()> {
-        let data = Dataset::open(file)?;
-        let mut lyr = if let Some(lyr) = layer {
-            data.layer_by_name(&lyr)
-                .context("Given Layer doesn't exist")?
-        } else {
-            if data.layer_count() > 1 {
-                eprintln!("WARN Multiple layers found, you can choose a specific layer");
-                eprint!("WARN Available Layers:");
-                data.layers().for_each(|l| eprint!(" {:?}", l.name()));
-                eprintln!();
-            }
-            data.layer(0)?
-        };
+        let data = open_dataset(&file, encoding.as_deref())?;
+        let mut lyr = open_layer(&data, layer)?;
 
         let defn = Defn::from_layer(&lyr);
         let fid_s = defn.field_index(&source)?;
@@ -68,6 +107,372 @@ mod gis {
             .map(|(k, v)| (k.as_str(), v.as_str()))
             .collect();
         *net = Network::from_edges(&edges_str).map_err(nadi_core::anyhow::Error::msg)?;
+        invalidate_index();
+        Ok(())
+    }
+
+    /// Load network from a GraphML, GEXF or DOT file
+    ///
+    /// The edges should be directed from the nodes to their outputs,
+    /// and the node attributes (GraphML data, GEXF attvalues, DOT node
+    /// attributes) are loaded as the node attributes. The format is
+    /// detected from the file extension (.graphml, .gexf, .dot or .gv)
+    /// if not given.
+    #[network_func]
+    fn gis_load_graph(
+        net: &mut Network,
+        /// GraphML, GEXF or DOT file
+        file: PathBuf,
+        /// Format of the file: graphml, gexf or dot
+        format: Option<String>,
+    ) -> Result<()> {
+        let format = format
+            .or_else(|| Some(file.extension()?.to_string_lossy().to_lowercase()))
+            .unwrap_or_default();
+        let text = std::fs::read_to_string(&file)?;
+        let graph = match format.as_str() {
+            "graphml" | "xml" => graphs::read_graphml(&text),
+            "gexf" => graphs::read_gexf(&text),
+            "dot" | "gv" => graphs::read_dot(&text),
+            f => Err(format!(
+                "Unknown graph format {f:?}, use graphml, gexf or dot"
+            )),
+        }
+        .map_err(nadi_core::anyhow::Error::msg)?;
+        let edges: Vec<(&str, &str)> = graph
+            .edges
+            .iter()
+            .map(|(s, d)| (s.as_str(), d.as_str()))
+            .collect();
+        *net = Network::from_edges(&edges).map_err(nadi_core::anyhow::Error::msg)?;
+        invalidate_index();
+        for (name, attrs) in graph.nodes {
+            let Some(n) = net.node_by_name(&name) else {
+                warning!("WARN Node {name} without edges is not loaded");
+                continue;
+            };
+            let attrs = attrs
+                .into_iter()
+                .map(|(k, v)| (RString::from(sanitize_key(&k)), graph_value_attr(v)));
+            n.lock().attr_map_mut().extend(attrs);
+        }
+        Ok(())
+    }
+
+    /// Load network from the NLDI flowlines
+    ///
+    /// Builds the network of reaches directly from the flowlines
+    /// downloaded from NLDI (e.g. `nadi-gis usgs -d t`), without
+    /// the points and streams processing. Each flowline is a node at
+    /// its downstream end, named by its comid, and the flowlines are
+    /// connected by their end points (or `hydroseq`/`dnhydroseq` if
+    /// available). The flowline of the output is saved as the edge
+    /// geometry, and the other fields as attributes.
+    #[network_func(comid = "comid", geometry = "GEOM", edge_geometry = "EDGE_GEOM")]
+    fn gis_load_nldi(
+        net: &mut Network,
+        /// NLDI flowlines GeoJSON file
+        file: PathBuf,
+        /// Field with the comid, `nhdplus_comid` is used if not found
+        comid: String,
+        /// Attribute to save the node point in
+        geometry: String,
+        /// Attribute to save the edge geometry in
+        edge_geometry: String,
+    ) -> Result<()> {
+        let data = Dataset::open(file)?;
+        let mut lyr = data.layer(0)?;
+        let defn = Defn::from_layer(&lyr);
+        let fid = defn
+            .field_index(&comid)
+            .or_else(|_| defn.field_index("nhdplus_comid"))
+            .context("Comid field not found in the flowlines")?;
+        let hydroseq = defn
+            .field_index("hydroseq")
+            .ok()
+            .zip(defn.field_index("dnhydroseq").ok());
+        let key = |(x, y): (f64, f64)| format!("{x:.7},{y:.7}");
+
+        let mut reaches = Vec::with_capacity(lyr.feature_count() as usize);
+        for f in lyr.features() {
+            let (Some(name), Some(geom)) = (f.field_as_string(fid)?, f.geometry()) else {
+                continue;
+            };
+            let pts = line_points(geom);
+            if pts.len() < 2 {
+                continue;
+            }
+            let (start, end) = (pts[0], pts[pts.len() - 1]);
+            let seq = match hydroseq {
+                Some((h, d)) => f.field_as_integer64(h)?.zip(f.field_as_integer64(d)?),
+                None => None,
+            };
+            let attrs: Vec<(RString, Attribute)> = f
+                .fields()
+                .filter_map(|(k, v)| {
+                    Some((RString::from(sanitize_key(&k)), gis_value_to_attr(v?)?))
+                })
+                .collect();
+            reaches.push((name, key(start), key(end), seq, end, geom.wkt()?, attrs));
+        }
+
+        let starts: HashMap<&str, &str> = reaches
+            .iter()
+            .map(|r| (r.1.as_str(), r.0.as_str()))
+            .collect();
+        let seqs: HashMap<i64, &str> = reaches
+            .iter()
+            .filter_map(|r| Some((r.3?.0, r.0.as_str())))
+            .collect();
+        let mut outputs: HashMap<&str, &str> = HashMap::new();
+        for r in &reaches {
+            let out = match r.3 {
+                Some((_, dn)) => seqs.get(&dn),
+                None => starts.get(r.2.as_str()),
+            };
+            if let Some(out) = out {
+                outputs.insert(r.0.as_str(), *out);
+            }
+        }
+        let edges: Vec<(&str, &str)> = outputs.iter().map(|(k, v)| (*k, *v)).collect();
+        *net = Network::from_edges(&edges).map_err(nadi_core::anyhow::Error::msg)?;
+
+        let wkts: HashMap<&str, &str> = reaches
+            .iter()
+            .map(|r| (r.0.as_str(), r.5.as_str()))
+            .collect();
+        invalidate_index();
+        for (name, _, _, _, end, _, attrs) in &reaches {
+            let Some(n) = net.node_by_name(name) else {
+                continue;
+            };
+            let mut n = n.lock();
+            n.attr_map_mut().extend(attrs.iter().cloned());
+            n.set_attr(
+                &geometry,
+                Attribute::String(format!("POINT ({} {})", end.0, end.1).into()),
+            );
+            if let Some(wkt) = outputs.get(name.as_str()).and_then(|o| wkts.get(o)) {
+                n.set_attr(&edge_geometry, Attribute::String(wkt.to_string().into()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Load network of the USGS gauges from NLDI
+    ///
+    /// Queries the NLDI API for the gauges downstream of each site,
+    /// and connects each site to the nearest one downstream from the
+    /// given sites (the one with the most given sites downstream of
+    /// it). The site location is saved as the node geometry, and the
+    /// other site information as attributes. Needs internet access.
+    #[network_func(distance = 9999.0, geometry = "GEOM")]
+    fn gis_load_network_nldi(
+        net: &mut Network,
+        /// USGS site numbers of the gauges
+        sites: Vec<String>,
+        /// Distance (km) to search downstream along the streams
+        distance: f64,
+        /// Attribute to save the site location in
+        geometry: String,
+    ) -> Result<()> {
+        const NLDI_URL: &str = "https://api.water.usgs.gov/nldi/linked-data/nwissite";
+        let given: HashSet<&str> = sites.iter().map(String::as_str).collect();
+        let mut downstream: HashMap<&str, HashSet<String>> = HashMap::new();
+        for site in &sites {
+            let url =
+                format!("{NLDI_URL}/USGS-{site}/navigation/DM/nwissite?f=json&distance={distance}");
+            let data = Dataset::open(&url).with_context(|| {
+                format!("Couldn't get the downstream sites of {site} from NLDI")
+            })?;
+            let mut lyr = data.layer(0)?;
+            let idx = Defn::from_layer(&lyr).field_index("identifier")?;
+            let ds = lyr
+                .features()
+                .filter_map(|f| f.field_as_string(idx).ok().flatten())
+                .filter_map(|id| id.strip_prefix("USGS-").map(String::from))
+                .filter(|s| s != site && given.contains(s.as_str()))
+                .collect();
+            downstream.insert(site, ds);
+        }
+        // downstream sites are all on the same path, the nearest one
+        // has the rest of them downstream of it
+        let edges: Vec<(&str, &str)> = downstream
+            .iter()
+            .filter_map(|(site, ds)| {
+                let out = ds
+                    .iter()
+                    .max_by_key(|d| downstream.get(d.as_str()).map(|s| s.len()))?;
+                Some((*site, out.as_str()))
+            })
+            .collect();
+        *net = Network::from_edges(&edges).map_err(nadi_core::anyhow::Error::msg)?;
+
+        invalidate_index();
+        for site in &sites {
+            let Some(n) = net.node_by_name(site) else {
+                warning!("WARN Site {site} without connections is not loaded");
+                continue;
+            };
+            let data = Dataset::open(format!("{NLDI_URL}/USGS-{site}?f=json"))
+                .with_context(|| format!("Couldn't get the site {site} from NLDI"))?;
+            let mut lyr = data.layer(0)?;
+            let Some(f) = lyr.features().next() else {
+                continue;
+            };
+            let mut n = n.lock();
+            let attrs = f.fields().filter_map(|(k, v)| {
+                Some((RString::from(sanitize_key(&k)), gis_value_to_attr(v?)?))
+            });
+            n.attr_map_mut().extend(attrs);
+            if let Some(g) = f.geometry() {
+                n.set_attr(&geometry, Attribute::String(g.wkt()?.into()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Load network from the streams and the points of interest
+    ///
+    /// Unlike `gis_load_network`, which reads the connections from
+    /// an existing network file, this builds them from the streams.
+    ///
+    /// Same as the `nadi-gis network` command, without the
+    /// intermediate network file: the points are snapped to the
+    /// nearest vertex of the streams, and each point is connected to
+    /// the first point downstream of it along the streams. Points
+    /// snapped to the same vertex are connected in the order of their
+    /// names. The point fields are loaded as attributes, with the
+    /// point and the path to the output along the streams as the
+    /// geometries (in the spatial reference of the streams).
+    #[network_func(reverse = false, geometry = "GEOM", edge_geometry = "EDGE_GEOM")]
+    fn gis_load_stream_network(
+        net: &mut Network,
+        /// GIS file with the streams
+        streams: PathBuf,
+        /// GIS file with the points of interest
+        points: PathBuf,
+        /// Field in the points file with the node name
+        node: String,
+        /// layer of the streams file, first one picked by default
+        streams_layer: Option<String>,
+        /// layer of the points file, first one picked by default
+        points_layer: Option<String>,
+        /// Streams are digitized from downstream to upstream
+        reverse: bool,
+        /// Points farther than this from the streams are not loaded
+        threshold: Option<f64>,
+        /// Unit of the threshold (m, km, ft, mi, deg)
+        unit: Option<String>,
+        /// Attribute to save the node point in
+        geometry: String,
+        /// Attribute to save the edge geometry in
+        edge_geometry: String,
+    ) -> Result<()> {
+        let streams_data = Dataset::open(streams)?;
+        let mut streams_lyr = open_layer(&streams_data, streams_layer)?;
+        let sref = streams_lyr.spatial_ref();
+        let threshold = threshold
+            .map(|t| distance_in_units(t, unit.as_deref(), sref.as_ref()))
+            .transpose()?;
+        let lines = streams_lyr
+            .features()
+            .filter_map(|f| f.geometry().map(line_parts))
+            .flatten()
+            .collect();
+        let graph = StreamGraph::new(lines, reverse);
+
+        let points_data = Dataset::open(points)?;
+        let mut points_lyr = open_layer(&points_data, points_layer)?;
+        let fid = Defn::from_layer(&points_lyr).field_index(&node)?;
+        let transform = match (points_lyr.spatial_ref(), &sref) {
+            (Some(mut s), Some(t)) if s != *t => {
+                s.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+                Some(CoordTransform::new(&s, t)?)
+            }
+            _ => None,
+        };
+        let key = |(x, y): (f64, f64)| (x.to_bits(), y.to_bits());
+        let mut points = Vec::new();
+        let mut at_vertex: HashMap<(u64, u64), Vec<String>> = HashMap::new();
+        for f in points_lyr.features() {
+            let (Some(name), Some(geom)) = (f.field_as_string(fid)?, f.geometry()) else {
+                continue;
+            };
+            let (x, y, _) = reproject(geom.clone(), &transform)?.get_point(0);
+            let Some((vertex, dist)) = graph.snap((x, y)) else {
+                continue;
+            };
+            if threshold.is_some_and(|t| dist > t) {
+                warning!(
+                    "WARN Point {name} not loaded: farther than the threshold from the streams"
+                );
+                continue;
+            }
+            let attrs: Vec<(RString, Attribute)> = f
+                .fields()
+                .filter_map(|(k, v)| {
+                    Some((RString::from(sanitize_key(&k)), gis_value_to_attr(v?)?))
+                })
+                .collect();
+            at_vertex.entry(key(vertex)).or_default().push(name.clone());
+            points.push((name, (x, y), attrs));
+        }
+
+        // points at the same vertex are chained, and the last one
+        // connects to the first point at the vertex downstream
+        let mut outputs: HashMap<String, String> = HashMap::new();
+        for names in at_vertex.values_mut() {
+            names.sort();
+            for w in names.windows(2) {
+                outputs.insert(w[0].clone(), w[1].clone());
+            }
+        }
+        for (k, names) in &at_vertex {
+            let vertex = (f64::from_bits(k.0), f64::from_bits(k.1));
+            let out = graph.find_downstream(vertex, |pt| at_vertex.contains_key(&key(pt)));
+            if let Some(out) = out {
+                outputs.insert(
+                    names[names.len() - 1].clone(),
+                    at_vertex[&key(out)][0].clone(),
+                );
+            }
+        }
+        let edges: Vec<(&str, &str)> = outputs
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        *net = Network::from_edges(&edges).map_err(nadi_core::anyhow::Error::msg)?;
+
+        let locations: HashMap<&str, (f64, f64)> =
+            points.iter().map(|p| (p.0.as_str(), p.1)).collect();
+        invalidate_index();
+        for (name, (x, y), attrs) in &points {
+            let Some(n) = net.node_by_name(name) else {
+                warning!("WARN Node {name} without connections is not loaded");
+                continue;
+            };
+            let mut n = n.lock();
+            n.attr_map_mut().extend(attrs.iter().cloned());
+            n.set_attr(
+                &geometry,
+                Attribute::String(format!("POINT ({x} {y})").into()),
+            );
+            let path = outputs
+                .get(name)
+                .and_then(|o| graph.trace((*x, *y), locations[o.as_str()]));
+            if let Some(path) = path {
+                let wkt = path
+                    .iter()
+                    .map(|(x, y)| format!("{x} {y}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                n.set_attr(
+                    &edge_geometry,
+                    Attribute::String(format!("LINESTRING ({wkt})").into()),
+                );
+            }
+        }
         Ok(())
     }
 
@@ -75,7 +480,24 @@ mod gis {
     ///
     /// The function reads a GIS file in any format (CSV, GPKG, SHP,
     /// JSON, etc) and loads their fields as attributes to the nodes.
-    #[network_func(geometry = "GEOM", ignore = "", sanitize = true, err_no_node = false)]
+    /// Fields in `rename` get the new names as they are, others get
+    /// the `prefix` to avoid collisions with the existing attributes.
+    ///
+    /// When the `node` field has ids different from the node names
+    /// (e.g. COMIDs or station codes), the `alias` map or the
+    /// `crosswalk` table (any format GDAL can read, with the
+    /// `crosswalk_id` and `crosswalk_node` fields) gives the node name
+    /// of each id. Ids not in them are used as the node names.
+    #[network_func(
+        geometry = "GEOM",
+        ignore = "",
+        sanitize = true,
+        err_no_node = false,
+        rename = HashMap::new(),
+        alias = HashMap::new(),
+        crosswalk_id = "id",
+        crosswalk_node = "node"
+    )]
     fn gis_load_attrs(
         net: &mut Network,
         /// GIS file to load (can be any format GDAL can understand)
@@ -92,27 +514,62 @@ mod gis {
         sanitize: bool,
         /// Error if all nodes are not found in the GIS file
         err_no_node: bool,
+        /// TOML file mapping field names to new attribute names/types
+        schema: Option<PathBuf>,
+        /// OGR SQL WHERE clause to filter the features (e.g. "huc8 = '05010007'")
+        where_clause: Option<String>,
+        /// Prefix for the attribute names (e.g. "nhd_")
+        prefix: Option<String>,
+        /// Map of the field names to the attribute names
+        rename: HashMap<String, String>,
+        /// Map of the ids in the `node` field to the node names
+        alias: HashMap<String, String>,
+        /// GIS file with the ids and the node names
+        crosswalk: Option<PathBuf>,
+        /// Field in the crosswalk file with the ids
+        crosswalk_id: String,
+        /// Field in the crosswalk file with the node names
+        crosswalk_node: String,
+        /// Encoding of the text fields (e.g. ISO-8859-1, CP932), for
+        /// the shapefile DBFs without the right codepage
+        encoding: Option<String>,
     ) -> Result<()> {
-        let data = Dataset::open(file)?;
-        let mut lyr = if let Some(lyr) = layer {
-            data.layer_by_name(&lyr)
-                .context("Given Layer doesn't exist")?
-        } else {
-            if data.layer_count() > 1 {
-                eprintln!("WARN Multiple layers found, you can choose a specific layer");
-                eprint!("WARN Available Layers:");
-                data.layers().for_each(|l| eprint!(" {:?}", l.name()));
-                eprintln!();
+        let mut names: HashMap<String, String> = HashMap::new();
+        if let Some(cw) = crosswalk {
+            let data = open_dataset(&cw, encoding.as_deref())?;
+            let mut lyr = data.layer(0)?;
+            let defn = Defn::from_layer(&lyr);
+            let (id, node) = (
+                defn.field_index(&crosswalk_id)?,
+                defn.field_index(&crosswalk_node)?,
+            );
+            for f in lyr.features() {
+                if let (Some(i), Some(n)) = (f.field_as_string(id)?, f.field_as_string(node)?) {
+                    names.insert(i, n);
+                }
             }
-            data.layer(0)?
-        };
+        }
+        names.extend(alias);
+        let schema = schema
+            .map(Schema::from_file)
+            .transpose()?
+            .unwrap_or_default();
+        let data = open_dataset(&file, encoding.as_deref())?;
+        let mut lyr = open_layer(&data, layer)?;
+        if let Some(w) = &where_clause {
+            // filtered by the driver, so large layers aren't scanned
+            lyr.set_attribute_filter(w)?;
+        }
 
         let ignore: HashSet<String> = ignore.split(',').map(String::from).collect();
 
         let defn = Defn::from_layer(&lyr);
         let fid = defn.field_index(&node)?;
+        let times = time_fields(&defn);
+        invalidate_index();
         for f in lyr.features() {
             let name = f.field_as_string(fid)?.unwrap_or("".to_string());
+            let name = names.get(&name).cloned().unwrap_or(name);
             let n = match net.node_by_name(&name) {
                 Some(n) => n,
                 None if err_no_node => {
@@ -127,208 +584,4389 @@ mod gis {
             }
             let attrs = f
                 .fields()
+                .filter_map(|(k, v)| Some((k, gis_value_to_attr(v?)?)))
+                .chain(
+                    times
+                        .iter()
+                        .filter_map(|(i, k)| Some((k.clone(), time_attr(&f, *i)?))),
+                )
                 .filter(|(f, _)| !ignore.contains(f))
-                .filter_map(|(f, v)| {
+                .filter_map(|(f, val)| {
+                    let val = match schema.field_type(&f) {
+                        Some(ty) => convert_attr(&val, ty).unwrap_or_else(|e| {
+                            warning!("WARN Field {f} of node {name} not converted: {e}");
+                            val
+                        }),
+                        None => val,
+                    };
+                    let f = match rename.get(&f) {
+                        Some(r) => r.to_string(),
+                        None => format!(
+                            "{}{}",
+                            prefix.as_deref().unwrap_or_default(),
+                            schema.rename(&f)
+                        ),
+                    };
                     let f = if sanitize { sanitize_key(&f) } else { f };
-                    let f = RString::from(f);
-                    if let Some(val) = v {
-                        match val {
-                            FieldValue::IntegerValue(i) => Some((f, Attribute::Integer(i as i64))),
-                            FieldValue::Integer64Value(i) => Some((f, Attribute::Integer(i))),
-                            FieldValue::StringValue(i) => {
-                                Some((f, Attribute::String(RString::from(i))))
-                            }
-                            FieldValue::RealValue(i) => Some((f, Attribute::Float(i))),
-                            FieldValue::DateValue(d) => Some((
-                                f,
-                                Attribute::Date(Date::new(
-                                    d.year() as u16,
-                                    d.month() as u8,
-                                    d.day() as u8,
-                                )),
-                            )),
-                            _ => None,
-                        }
-                    } else {
-                        None
-                    }
+                    Some((RString::from(f), val))
                 });
             n.lock().attr_map_mut().extend(attrs);
         }
         Ok(())
     }
 
-    /// Save GIS file of the connections
-    #[network_func(layer = "network")]
-    fn gis_save_connections(
-        net: &Network,
+    /// Locate the nodes on the flowlines as reach and measure
+    ///
+    /// Each node point is snapped to the nearest flowline, and its id
+    /// and the measure (percentage of the length from its downstream
+    /// end, as in NHD) are saved in the `reach` and `measure`
+    /// attributes. The flowlines should be in the same spatial
+    /// reference as the node geometries, and digitized from upstream
+    /// to downstream.
+    #[network_func(
+        field = "comid",
+        geometry = "GEOM",
+        reach = "reach",
+        measure = "measure"
+    )]
+    fn gis_reach_measure(
+        net: &mut Network,
+        /// GIS file with the flowlines
         file: PathBuf,
+        /// Field in the flowlines with the reach id
+        field: String,
+        /// layer of the GIS file, first one picked by default
+        layer: Option<String>,
+        /// Skip the nodes farther than this from the flowlines
+        max_dist: Option<f64>,
+        /// Unit of max_dist (m, km, ft, mi, deg) [default: flowlines units]
+        unit: Option<String>,
+        /// Attribute with the node geometry (WKT)
         geometry: String,
-        driver: Option<String>,
-        layer: String,
-        filter: Option<Vec<bool>>,
+        /// Attribute to save the reach id in
+        reach: String,
+        /// Attribute to save the measure in
+        measure: String,
     ) -> Result<()> {
-        let driver = if let Some(d) = driver {
-            gdal::DriverManager::get_driver_by_name(&d)?
-        } else {
-            DriverManager::get_output_driver_for_dataset_name(&file, DriverType::Vector)
-                .context("Could not detect Driver for filename, try providing `driver` argument.")?
-        };
-
-        // TODO if file already exists add the layer if possible
-        let mut out_data = driver.create_vector_only(&file)?;
-        let mut layer = out_data.create_layer(LayerOptions {
-            name: &layer,
-            ty: gdal_sys::OGRwkbGeometryType::wkbLineString,
-            ..Default::default()
-        })?;
-        layer.create_defn_fields(&[
-            ("start", OGRFieldType::OFTString),
-            ("end", OGRFieldType::OFTString),
-        ])?;
-        let defn = Defn::from_layer(&layer);
-        let nodes: Vec<&Node> = if let Some(filt) = filter {
-            net.nodes()
-                .zip(filt)
-                .filter(|(_, f)| *f)
-                .map(|n| n.0)
-                .collect()
-        } else {
-            net.nodes().collect()
+        let max_dist = match max_dist {
+            Some(d) => {
+                let data = Dataset::open(&file)?;
+                let lyr = open_layer(&data, layer.clone())?;
+                Some(distance_in_units(
+                    d,
+                    unit.as_deref(),
+                    lyr.spatial_ref().as_ref(),
+                )?)
+            }
+            None => None,
         };
-        for node in nodes {
-            let n = node.lock();
-            if let RSome(out) = n.output() {
-                let start = String::try_from_attr(
-                    n.attr(&geometry)
-                        .context("Attribute for geometry not found")?,
-                )
-                .map_err(nadi_core::anyhow::Error::msg)?;
-                let end = String::try_from_attr(
-                    out.lock()
-                        .attr(&geometry)
-                        .context("Attribute for geometry not found")?,
-                )
-                .map_err(nadi_core::anyhow::Error::msg)?;
-                let start = Geometry::from_wkt(&start)?;
-                let end = Geometry::from_wkt(&end)?;
-
-                let mut edge_geometry =
-                    Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbLineString)?;
-                // add all points from start, (so it can be linestring
-                // instead of just point); and add end's first point
-                // only if it's different from last point of start
-                edge_geometry.add_point(start.get_point(0));
-                edge_geometry.add_point(end.get_point(0));
-                let mut ft = Feature::new(&defn)?;
-                ft.set_geometry(edge_geometry)?;
-                ft.set_field_string(0, n.name())?;
-                ft.set_field_string(1, out.lock().name())?;
-                ft.create(&mut layer)?;
+        let flowlines = read_flowlines(file, layer, &field)?;
+        for node in net.nodes() {
+            let mut n = node.lock();
+            let (x, y, _) = node_geometry(&n, &geometry)?.get_point(0);
+            let Some(loc) = flowlines.locate((x, y)) else {
+                continue;
+            };
+            if max_dist.is_some_and(|d| loc.offset > d) {
+                continue;
             }
+            n.set_attr(&reach, Attribute::String(loc.reach.into()));
+            n.set_attr(&measure, Attribute::Float(loc.measure));
         }
         Ok(())
     }
 
-    /// Save GIS file of the nodes
-    #[network_func(attrs=HashMap::new(), layer="nodes")]
-    fn gis_save_nodes(
-        net: &Network,
+    /// Assign the river names from the streams to the nodes and edges
+    ///
+    /// Each node gets the name (e.g. GNIS_NAME) of the stream it sits
+    /// on, and each edge the name of the stream at the middle of its
+    /// traced geometry (the node's river if not traced). The unnamed
+    /// ones inherit the names from downstream.
+    #[network_func(
+        field = "GNIS_NAME",
+        geometry = "GEOM",
+        edge_geometry = "EDGE_GEOM",
+        river = "river",
+        edge_river = "edge_river"
+    )]
+    fn gis_river_names(
+        net: &mut Network,
+        /// GIS file with the streams
         file: PathBuf,
+        /// Field in the streams with the river name
+        field: String,
+        /// layer of the GIS file, first one picked by default
+        layer: Option<String>,
+        /// Attribute with the node geometry (WKT)
         geometry: String,
-        attrs: HashMap<String, String>,
-        driver: Option<String>,
-        layer: String,
-        filter: Option<Vec<bool>>,
+        /// Attribute with the edge geometry (WKT)
+        edge_geometry: String,
+        /// Attribute to save the river name of the node in
+        river: String,
+        /// Attribute to save the river name of the edge in
+        edge_river: String,
+        /// Encoding of the text fields (e.g. ISO-8859-1, CP932), for
+        /// the shapefile DBFs without the right codepage
+        encoding: Option<String>,
     ) -> Result<()> {
-        let driver = if let Some(d) = driver {
-            gdal::DriverManager::get_driver_by_name(&d)?
-        } else {
-            DriverManager::get_output_driver_for_dataset_name(&file, DriverType::Vector)
-                .context("Could not detect Driver for filename, try providing `driver` argument.")?
+        let data = open_dataset(&file, encoding.as_deref())?;
+        let mut lyr = open_layer(&data, layer)?;
+        let fid = Defn::from_layer(&lyr).field_index(&field)?;
+        let mut lines = Vec::new();
+        for f in lyr.features() {
+            if let Some(g) = f.geometry() {
+                // unnamed streams are kept so the nodes on them aren't
+                // snapped to the named ones nearby
+                let name = f.field_as_string(fid)?.unwrap_or_default();
+                lines.push((name, line_points(g)));
+            }
+        }
+        let streams = Flowlines::new(lines);
+        let name_at = |pt: (f64, f64)| {
+            streams
+                .locate(pt)
+                .map(|l| l.reach.to_string())
+                .filter(|r| !r.trim().is_empty())
         };
 
-        // TODO if file already exists add the layer if possible
-        let mut out_data = driver.create_vector_only(&file)?;
-        let mut layer = out_data.create_layer(LayerOptions {
-            name: &layer,
-            ty: gdal_sys::OGRwkbGeometryType::wkbPoint,
-            ..Default::default()
-        })?;
-        let fields: Vec<(String, (u32, Attr2FieldValue))> = attrs
-            .into_iter()
-            .map(|(k, v)| Ok((k, type_name_to_field(&v)?)))
-            .collect::<Result<_, String>>()
-            .map_err(nadi_core::anyhow::Error::msg)?;
-        let field_types: Vec<(&str, u32)> = fields.iter().map(|(k, v)| (k.as_str(), v.0)).collect();
-        // saving shp means field names will be shortened, it'll error later, how do we fix it?
-        layer.create_defn_fields(&field_types)?;
-        let defn = Defn::from_layer(&layer);
-        let indices: HashMap<&str, usize> = fields
-            .iter()
-            .filter_map(|f| Some((f.0.as_str(), defn.field_index(&f.0).ok()?)))
-            .collect();
-        let nodes: Vec<&Node> = if let Some(filt) = filter {
-            net.nodes()
-                .zip(filt)
-                .filter(|(_, f)| *f)
-                .map(|n| n.0)
-                .collect()
-        } else {
-            net.nodes().collect()
-        };
-        for node in nodes {
+        let mut names: HashMap<String, (Option<String>, Option<String>, Option<String>)> =
+            HashMap::new();
+        for node in net.nodes() {
             let n = node.lock();
-            let node_geom = String::try_from_attr(
-                n.attr(&geometry)
-                    .context("Attribute for geometry not found")?,
-            )
-            .map_err(nadi_core::anyhow::Error::msg)?;
-            let node_geom = Geometry::from_wkt(&node_geom)?;
-            let mut ft = Feature::new(&defn)?;
-            ft.set_geometry(node_geom)?;
-            fields
-                .iter()
-                .filter_map(|(k, (_, func))| Some((k.as_str(), func(n.attr(k)?))))
-                .try_for_each(|(k, v)| ft.set_field(indices[k], &v))?;
-            ft.create(&mut layer)?;
+            let (x, y, _) = node_geometry(&n, &geometry)?.get_point(0);
+            let name = name_at((x, y));
+            let edge = if n.attr(&edge_geometry).is_some() {
+                let pts = line_points(&node_geometry(&n, &edge_geometry)?);
+                let half = line_length(&pts, false) / 2.0;
+                lrs::interpolate(&pts, half).and_then(name_at)
+            } else {
+                name.clone()
+            };
+            let out = n.output().map(|o| o.lock().name().to_string()).into();
+            names.insert(n.name().to_string(), (name, edge, out));
+        }
+        // follow the outputs till a named river for the unnamed ones
+        let downstream = |start: &str, edge: bool| {
+            let mut curr = names.get(start)?;
+            for _ in 0..names.len() {
+                let name = if edge { &curr.1 } else { &curr.0 };
+                if name.is_some() {
+                    return name.clone();
+                }
+                curr = names.get(curr.2.as_deref()?)?;
+            }
+            None
+        };
+        for node in net.nodes() {
+            let mut n = node.lock();
+            let name = n.name().to_string();
+            if let Some(r) = downstream(&name, false) {
+                n.set_attr(&river, Attribute::String(r.into()));
+            }
+            if n.output().is_some() {
+                if let Some(r) = downstream(&name, true) {
+                    n.set_attr(&edge_river, Attribute::String(r.into()));
+                }
+            }
         }
         Ok(())
     }
 
-    fn sanitize_key(k: &str) -> String {
-        k.replace(' ', "_")
+    /// Place the nodes on the flowlines from their reach and measure
+    ///
+    /// Inverse of `gis_reach_measure`, the point at the measure along
+    /// the reach is saved as the node geometry.
+    #[network_func(
+        field = "comid",
+        geometry = "GEOM",
+        reach = "reach",
+        measure = "measure"
+    )]
+    fn gis_reach_position(
+        net: &mut Network,
+        /// GIS file with the flowlines
+        file: PathBuf,
+        /// Field in the flowlines with the reach id
+        field: String,
+        /// layer of the GIS file, first one picked by default
+        layer: Option<String>,
+        /// Attribute to save the node geometry in (WKT)
+        geometry: String,
+        /// Attribute with the reach id
+        reach: String,
+        /// Attribute with the measure
+        measure: String,
+    ) -> Result<()> {
+        let flowlines = read_flowlines(file, layer, &field)?;
+        invalidate_index();
+        for node in net.nodes() {
+            let mut n = node.lock();
+            let r = n.attr(&reach).and_then(String::from_attr_relaxed);
+            let m = n.attr(&measure).and_then(f64::from_attr_relaxed);
+            let Some((x, y)) = r.zip(m).and_then(|(r, m)| flowlines.position(&r, m)) else {
+                continue;
+            };
+            n.set_attr(
+                &geometry,
+                Attribute::String(format!("POINT ({x} {y})").into()),
+            );
+        }
+        Ok(())
     }
 
-    type Attr2FieldValue = fn(&Attribute) -> FieldValue;
+    /// Overlay the linear referenced events on the network
+    ///
+    /// Events (e.g. diversions, barriers, impaired segments) addressed
+    /// by reach and measure in a table are located on the flowlines,
+    /// and their ids are saved on the edge (upstream node) containing
+    /// them as an array attribute. The event points with the edge they
+    /// belong to can be saved to a GIS file.
+    #[network_func(
+        reach = "reach",
+        measure = "measure",
+        field = "comid",
+        attr = "events",
+        geometry = "GEOM",
+        edge_geometry = "EDGE_GEOM"
+    )]
+    fn gis_load_events(
+        net: &mut Network,
+        /// GIS or CSV file with the events table
+        events: PathBuf,
+        /// GIS file with the flowlines
+        flowlines: PathBuf,
+        /// Field in the events with the reach id
+        reach: String,
+        /// Field in the events with the measure
+        measure: String,
+        /// Field in the events with the event id [default: row number]
+        id: Option<String>,
+        /// Field in the flowlines with the reach id
+        field: String,
+        /// Attribute to save the event ids in
+        attr: String,
+        /// GIS file to save the event points in
+        output: Option<PathBuf>,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Attribute with the edge geometry (WKT)
+        edge_geometry: String,
+    ) -> Result<()> {
+        let flowlines = read_flowlines(flowlines, None, &field)?;
+        let edges = net
+            .nodes()
+            .filter_map(|node| {
+                let n = node.lock();
+                edge_points(&n, &geometry, &edge_geometry)
+                    .transpose()
+                    .map(|pts| Ok((n.name().to_string(), pts?)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let edges = Flowlines::new(edges);
 
-    fn type_name_to_field(name: &str) -> Result<(u32, Attr2FieldValue), String> {
-        Ok(match name {
-            // This is a string that can be parsed back into correct Attribute
-            "Attribute" => (OGRFieldType::OFTString, |a| {
-                FieldValue::StringValue(a.to_string())
-            }),
-            "String" => (OGRFieldType::OFTString, |a| {
-                let val: String = FromAttributeRelaxed::from_attr_relaxed(a).unwrap_or_default();
-                FieldValue::StringValue(val)
-            }),
-            "Integer" => (OGRFieldType::OFTInteger64, |a| {
-                let val: i64 = FromAttributeRelaxed::from_attr_relaxed(a).unwrap_or_default();
-                FieldValue::Integer64Value(val)
-            }),
-            "Float" => (OGRFieldType::OFTReal, |a| {
-                let val: f64 = FromAttributeRelaxed::from_attr_relaxed(a).unwrap_or_default();
+        let data = Dataset::open(events)?;
+        let mut lyr = open_layer(&data, None)?;
+        let defn = Defn::from_layer(&lyr);
+        let fid_r = defn.field_index(&reach)?;
+        let fid_m = defn.field_index(&measure)?;
+        let fid_id = id.map(|i| defn.field_index(&i)).transpose()?;
+        // (event id, reach, measure, point, edge)
+        let mut located = Vec::new();
+        for (i, f) in lyr.features().enumerate() {
+            let ev = match fid_id {
+                Some(fid) => f.field_as_string(fid)?.unwrap_or_default(),
+                None => (i + 1).to_string(),
+            };
+            let (Some(r), Some(m)) = (f.field_as_string(fid_r)?, f.field_as_double(fid_m)?) else {
+                warning!("WARN Event {ev} doesn't have reach and measure");
+                continue;
+            };
+            let Some(pt) = flowlines.position(&r, m) else {
+                warning!("WARN Reach {r} of event {ev} not found in flowlines");
+                continue;
+            };
+            let edge = edges.locate(pt).map(|l| l.reach.to_string());
+            located.push((ev, r, m, pt, edge));
+        }
+
+        let mut node_events: HashMap<&str, Vec<Attribute>> = HashMap::new();
+        for (ev, _, _, _, edge) in &located {
+            if let Some(e) = edge {
+                node_events
+                    .entry(e.as_str())
+                    .or_default()
+                    .push(Attribute::String(ev.as_str().into()));
+            }
+        }
+        for (name, evs) in node_events {
+            if let Some(n) = net.node_by_name(name) {
+                n.lock().set_attr(&attr, Attribute::Array(evs.into()));
+            }
+        }
+
+        if let Some(file) = output {
+            let driver =
+                DriverManager::get_output_driver_for_dataset_name(&file, DriverType::Vector)
+                    .context("Could not detect Driver for the output filename")?;
+            prepare_output(&file)?;
+            let mut out_data = driver.create_vector_only(&file)?;
+            let mut layer = out_data.create_layer(LayerOptions {
+                name: "events",
+                ty: gdal_sys::OGRwkbGeometryType::wkbPoint,
+                ..Default::default()
+            })?;
+            layer.create_defn_fields(&[
+                ("id", OGRFieldType::OFTString),
+                ("reach", OGRFieldType::OFTString),
+                ("measure", OGRFieldType::OFTReal),
+                ("node", OGRFieldType::OFTString),
+            ])?;
+            let defn = Defn::from_layer(&layer);
+            for (ev, r, m, (x, y), edge) in &located {
+                let mut ft = Feature::new(&defn)?;
+                ft.set_geometry(Geometry::from_wkt(&format!("POINT ({x} {y})"))?)?;
+                ft.set_field_string(0, ev)?;
+                ft.set_field_string(1, r)?;
+                ft.set_field_double(2, *m)?;
+                if let Some(e) = edge {
+                    ft.set_field_string(3, e)?;
+                }
+                ft.create(&mut layer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sample a raster band at the node points and save it as attribute
+    ///
+    /// For example the elevation of the nodes from a DEM. Nodes on the
+    /// nodata cells or outside the raster get the `nodata` value if
+    /// given, or are skipped. The node geometries are reprojected to
+    /// the raster's spatial reference if `srs` is given.
+    #[network_func(band = 1, geometry = "GEOM")]
+    fn gis_load_raster_attrs(
+        net: &mut Network,
+        /// Raster file to sample
+        raster: PathBuf,
+        /// Attribute to save the value in
+        attr: String,
+        /// Band of the raster
+        band: i64,
+        /// Value for the nodes on nodata cells or outside the raster
+        nodata: Option<f64>,
+        /// Spatial reference of the node geometries
+        srs: Option<String>,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+    ) -> Result<()> {
+        let sampler = RasterSampler::open(&raster, band_index(band)?)?;
+        let transform = match srs {
+            Some(s) => {
+                let mut dst = sampler.dataset().spatial_ref()?;
+                dst.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+                Some(CoordTransform::new(&spatial_ref(&s)?, &dst)?)
+            }
+            None => None,
+        };
+        for node in net.nodes() {
+            let mut n = node.lock();
+            let geom = node_geometry(&n, &geometry)?;
+            let geom = match &transform {
+                Some(t) => geom.transform(t)?,
+                None => geom,
+            };
+            let (x, y, _) = geom.get_point(0);
+            match sampler.sample(x, y)?.or(nodata) {
+                Some(v) => {
+                    n.set_attr(&attr, Attribute::Float(v));
+                }
+                None => warning!("WARN No raster value for node {}", n.name()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Extract the time series of raster values at the nodes
+    ///
+    /// The `rasters` is either a directory of rasters with one time
+    /// step each (sorted by the filename, labeled with the file stem),
+    /// or a multiband raster with one band per time step (labeled with
+    /// the band description or number). The values at each node are
+    /// saved in `{node}.csv` files in `output_dir`, with empty values
+    /// for the nodata cells. The rasters are read in parallel, and
+    /// should all have the same spatial reference.
+    #[network_func(band = 1, extension = "tif", geometry = "GEOM")]
+    fn gis_raster_timeseries(
+        net: &Network,
+        /// Directory of the rasters, or a multiband raster
+        rasters: PathBuf,
+        /// Directory to save the CSV files in
+        output_dir: PathBuf,
+        /// Band of the rasters in the directory
+        band: i64,
+        /// Extension of the rasters in the directory
+        extension: String,
+        /// Spatial reference of the node geometries
+        srs: Option<String>,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Number of threads to read the rasters with [default: all cores]
+        threads: Option<usize>,
+    ) -> Result<()> {
+        let band = band_index(band)?;
+        // (file, band, time label) of each time step
+        let mut steps: Vec<(PathBuf, usize, String)> = Vec::new();
+        if rasters.is_dir() {
+            for entry in std::fs::read_dir(&rasters)? {
+                let path = entry?.path();
+                if path
+                    .extension()
+                    .is_some_and(|e| e.eq_ignore_ascii_case(&extension))
+                {
+                    let label = path
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    steps.push((path, band, label));
+                }
+            }
+            steps.sort();
+        } else {
+            let data = Dataset::open(&rasters)?;
+            for b in 1..=data.raster_count() {
+                let label = data
+                    .rasterband(b)?
+                    .description()
+                    .ok()
+                    .filter(|d| !d.is_empty())
+                    .unwrap_or_else(|| b.to_string());
+                steps.push((rasters.clone(), b, label));
+            }
+        }
+        let Some(first) = steps.first() else {
+            return Err(nadi_core::anyhow::Error::msg(format!(
+                "No rasters found in {rasters:?}"
+            )));
+        };
+
+        let transform = match srs {
+            Some(s) => {
+                let mut dst = Dataset::open(&first.0)?.spatial_ref()?;
+                dst.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+                Some(CoordTransform::new(&spatial_ref(&s)?, &dst)?)
+            }
+            None => None,
+        };
+        let mut points: Vec<(String, (f64, f64))> = Vec::new();
+        for node in net.nodes() {
+            let n = node.lock();
+            let Ok(geom) = node_geometry(&n, &geometry) else {
+                warning!("WARN Node {} doesn't have a geometry", n.name());
+                continue;
+            };
+            let (x, y, _) = reproject(geom, &transform)?.get_point(0);
+            points.push((n.name().to_string(), (x, y)));
+        }
+
+        let threads = threads
+            .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+            .unwrap_or(1)
+            .max(1);
+        let chunk = steps.len().div_ceil(threads);
+        let values: Vec<Vec<Option<f64>>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = steps
+                .chunks(chunk)
+                .map(|steps| {
+                    let points = &points;
+                    scope.spawn(move || -> Result<Vec<Vec<Option<f64>>>> {
+                        steps
+                            .iter()
+                            .map(|(file, band, _)| {
+                                let sampler = RasterSampler::open(file, *band)?;
+                                points
+                                    .iter()
+                                    .map(|(_, (x, y))| sampler.sample(*x, *y))
+                                    .collect()
+                            })
+                            .collect()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| {
+                    h.join().map_err(|_| {
+                        nadi_core::anyhow::Error::msg("Raster reading thread panicked")
+                    })?
+                })
+                .collect::<Result<Vec<_>>>()
+                .map(|v| v.into_iter().flatten().collect())
+        })?;
+
+        std::fs::create_dir_all(&output_dir)?;
+        for (i, (name, _)) in points.iter().enumerate() {
+            let mut csv = String::from("time,value\n");
+            for ((_, _, label), vals) in steps.iter().zip(&values) {
+                match vals[i] {
+                    Some(v) => csv.push_str(&format!("{label},{v}\n")),
+                    None => csv.push_str(&format!("{label},\n")),
+                }
+            }
+            std::fs::write(output_dir.join(format!("{name}.csv")), csv)?;
+        }
+        Ok(())
+    }
+
+    /// Save the area weights of the grid cells in the node basins
+    ///
+    /// For each node, the cells of the raster grid intersecting its
+    /// basin polygon are saved as rows of `node,row,col,area,weight`
+    /// in the CSV file, where the weight is the fraction of the basin
+    /// area in the cell. The weights can be used for the areal averages
+    /// of the gridded data without intersecting the polygons again.
+    /// Rows and columns start from 0 at the top left, like GDAL. The
+    /// basin polygons are reprojected to the raster's spatial reference
+    /// if `srs` is given, and the areas are in its units.
+    #[network_func(basin = "BASIN")]
+    fn gis_grid_weights(
+        net: &Network,
+        /// Raster file with the grid
+        raster: PathBuf,
+        /// Output CSV file
+        file: PathBuf,
+        /// Attribute with the basin polygon (WKT)
+        basin: String,
+        /// Spatial reference of the basin polygons
+        srs: Option<String>,
+    ) -> Result<()> {
+        let grid = RasterSampler::open(&raster, 1)?;
+        let transform = match srs {
+            Some(s) => {
+                let mut dst = grid.dataset().spatial_ref()?;
+                dst.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+                Some(CoordTransform::new(&spatial_ref(&s)?, &dst)?)
+            }
+            None => None,
+        };
+        let mut csv = String::from("node,row,col,area,weight\n");
+        for node in net.nodes() {
+            let n = node.lock();
+            let Ok(poly) = node_geometry(&n, &basin) else {
+                warning!("WARN Node {} doesn't have a basin polygon", n.name());
+                continue;
+            };
+            let poly = reproject(poly, &transform)?;
+            let total = poly.area();
+            if total <= 0.0 {
+                continue;
+            }
+            let env = poly.envelope();
+            for (row, col) in grid.cells_within((env.MinX, env.MinY), (env.MaxX, env.MaxY)) {
+                let mut ring = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbLinearRing)?;
+                let corners = grid.cell_corners(row, col);
+                for pt in corners.iter().chain(&corners[..1]) {
+                    ring.add_point_2d(*pt);
+                }
+                let mut cell = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbPolygon)?;
+                cell.add_geometry(ring)?;
+                let Some(area) = poly.intersection(&cell).map(|g| g.area()) else {
+                    continue;
+                };
+                if area > 0.0 {
+                    csv.push_str(&format!(
+                        "{},{row},{col},{area},{}\n",
+                        csv_quote(n.name()),
+                        area / total
+                    ));
+                }
+            }
+        }
+        std::fs::write(file, csv)?;
+        Ok(())
+    }
+
+    /// Move the node points to the centers of the raster cells they are in
+    ///
+    /// For coupling the network to the gridded model outputs. The
+    /// snapped point is saved in the `output` attribute, and the row
+    /// and column of the cell (starting from 0 at the top left, like
+    /// GDAL) in the `row` and `col` attributes. Nodes outside the raster
+    /// are skipped. The node geometries are reprojected to the
+    /// raster's spatial reference if `srs` is given, and the snapped
+    /// points are reprojected back.
+    #[network_func(geometry = "GEOM", output = "GRID_GEOM", row = "row", col = "col")]
+    fn gis_snap_to_raster(
+        net: &mut Network,
+        /// Raster file with the grid
+        raster: PathBuf,
+        /// Spatial reference of the node geometries
+        srs: Option<String>,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Attribute to save the snapped point (WKT) in
+        output: String,
+        /// Attribute to save the row of the cell in
+        row: String,
+        /// Attribute to save the column of the cell in
+        col: String,
+    ) -> Result<()> {
+        let sampler = RasterSampler::open(&raster, 1)?;
+        let transforms = match srs {
+            Some(s) => {
+                let src = spatial_ref(&s)?;
+                let mut dst = sampler.dataset().spatial_ref()?;
+                dst.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+                Some((
+                    CoordTransform::new(&src, &dst)?,
+                    CoordTransform::new(&dst, &src)?,
+                ))
+            }
+            None => None,
+        };
+        for node in net.nodes() {
+            let mut n = node.lock();
+            let geom = node_geometry(&n, &geometry)?;
+            let geom = match &transforms {
+                Some((t, _)) => geom.transform(t)?,
+                None => geom,
+            };
+            let (x, y, _) = geom.get_point(0);
+            let Some((r, c)) = sampler.cell(x, y) else {
+                warning!("WARN Node {} is outside the raster", n.name());
+                continue;
+            };
+            let mut pt = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbPoint)?;
+            pt.add_point_2d(sampler.center(r, c));
+            let pt = match &transforms {
+                Some((_, t)) => pt.transform(t)?,
+                None => pt,
+            };
+            n.set_attr(&output, Attribute::String(pt.wkt()?.into()));
+            n.set_attr(&row, Attribute::Integer(r as i64));
+            n.set_attr(&col, Attribute::Integer(c as i64));
+        }
+        invalidate_index();
+        Ok(())
+    }
+
+    /// Derive the location information of the nodes from their geometry
+    ///
+    /// Saves the longitude and latitude (WGS84) of the node point in
+    /// `lon` and `lat` attributes. If the boundary files are given, the
+    /// field value of the polygon containing the point is saved as
+    /// `timezone`, `state` and `county` attributes. Timezone boundaries
+    /// with IANA names are available from the timezone-boundary-builder
+    /// project, and state/county boundaries from the US Census.
+    #[network_func(
+        geometry = "GEOM",
+        tz_field = "tzid",
+        state_field = "NAME",
+        county_field = "NAME"
+    )]
+    fn gis_location_info(
+        net: &mut Network,
+        /// GIS file with the timezone boundary polygons
+        timezones: Option<PathBuf>,
+        /// Field in the timezones file with the timezone name
+        tz_field: String,
+        /// GIS file with the state boundary polygons
+        states: Option<PathBuf>,
+        /// Field in the states file with the state name
+        state_field: String,
+        /// GIS file with the county boundary polygons
+        counties: Option<PathBuf>,
+        /// Field in the counties file with the county name
+        county_field: String,
+        /// Spatial reference of the node geometries (EPSG code, WKT or
+        /// PROJ string), assumed to be WGS84 if not given
+        srs: Option<String>,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+    ) -> Result<()> {
+        let srs = srs.as_deref().map(spatial_ref).transpose()?;
+        let wgs84 = spatial_ref("EPSG:4326")?;
+        let boundaries = [
+            ("timezone", timezones, tz_field),
+            ("state", states, state_field),
+            ("county", counties, county_field),
+        ];
+        let datasets: Vec<(&str, Dataset, String)> = boundaries
+            .into_iter()
+            .filter_map(|(k, f, fd)| Some((k, f?, fd)))
+            .map(|(k, f, fd)| Ok((k, Dataset::open(f)?, fd)))
+            .collect::<Result<_>>()?;
+        let mut layers: Vec<(&str, Layer, &str)> = datasets
+            .iter()
+            .map(|(k, d, fd)| Ok((*k, d.layer(0)?, fd.as_str())))
+            .collect::<Result<_>>()?;
+
+        for node in net.nodes() {
+            let mut n = node.lock();
+            let mut geom = node_geometry(&n, &geometry)?;
+            if let Some(s) = &srs {
+                geom.set_spatial_ref(s.clone());
+            }
+            let (lon, lat, _) = match &srs {
+                Some(_) => geom.transform_to(&wgs84)?.get_point(0),
+                None => geom.get_point(0),
+            };
+            n.set_attr("lon", Attribute::Float(lon));
+            n.set_attr("lat", Attribute::Float(lat));
+        }
+        let index = node_index(net, &geometry)?;
+        for (key, lyr, field) in layers.iter_mut() {
+            let values = polygon_join(lyr, &index, srs.as_ref(), field)?;
+            for (name, val) in values {
+                if let Some(n) = net.node_by_name(&name) {
+                    n.lock().set_attr(key, val);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the field of the polygon containing each node
+    ///
+    /// For example the HUC8, state or county of the nodes from the
+    /// boundary polygons. The polygons are reprojected to the spatial
+    /// reference of the node geometries if both are known. Nodes
+    /// outside all polygons don't get the attribute.
+    #[network_func(geometry = "GEOM")]
+    fn gis_polygon_join(
+        net: &mut Network,
+        /// GIS file with the polygons
+        file: PathBuf,
+        /// Field of the polygons to copy
+        field: String,
+        /// layer of the GIS file, first one picked by default
+        layer: Option<String>,
+        /// Attribute to save the value in [default: `field`]
+        attr: Option<String>,
+        /// Spatial reference of the node geometries
+        srs: Option<String>,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+    ) -> Result<()> {
+        let srs = srs.as_deref().map(spatial_ref).transpose()?;
+        let data = Dataset::open(file)?;
+        let mut lyr = open_layer(&data, layer)?;
+        let index = node_index(net, &geometry)?;
+        let values = polygon_join(&mut lyr, &index, srs.as_ref(), &field)?;
+        let attr = attr.unwrap_or(field);
+        for (name, val) in values {
+            if let Some(n) = net.node_by_name(&name) {
+                n.lock().set_attr(&attr, val);
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the fields of the nearest feature to the nodes
+    ///
+    /// For example to link each node to the nearest dam or weather
+    /// station from a points layer, or the nearest road from a lines
+    /// layer. The distance to the feature is saved in `{prefix}distance`
+    /// in the units of the node geometries. Nodes without a feature
+    /// within the `distance` don't get the attributes.
+    #[network_func(geometry = "GEOM", prefix = "nearest_")]
+    fn gis_nearest_join(
+        net: &mut Network,
+        /// GIS file with the features
+        file: PathBuf,
+        /// layer of the GIS file, first one picked by default
+        layer: Option<String>,
+        /// Fields to copy [default: all]
+        fields: Option<Vec<String>>,
+        /// Maximum distance to the feature
+        distance: Option<f64>,
+        /// Unit of the distance (m, km, ft, mi, deg) [default: geometry units]
+        unit: Option<String>,
+        /// Spatial reference of the node geometries
+        srs: Option<String>,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Prefix for the attribute names
+        prefix: String,
+    ) -> Result<()> {
+        let srs = srs.as_deref().map(spatial_ref).transpose()?;
+        let distance = distance
+            .map(|d| distance_in_units(d, unit.as_deref(), srs.as_ref()))
+            .transpose()?
+            .unwrap_or(f64::INFINITY);
+        let data = Dataset::open(file)?;
+        let mut lyr = open_layer(&data, layer)?;
+        let transform = match (lyr.spatial_ref(), &srs) {
+            (Some(mut s), Some(t)) if s != *t => {
+                s.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+                Some(CoordTransform::new(&s, t)?)
+            }
+            _ => None,
+        };
+        let mut features: Vec<(Geometry, Vec<(String, Attribute)>)> = Vec::new();
+        for f in lyr.features() {
+            let Some(geom) = f.geometry() else {
+                continue;
+            };
+            let attrs = f
+                .fields()
+                .filter(|(k, _)| fields.as_ref().is_none_or(|fs| fs.contains(k)))
+                .filter_map(|(k, v)| Some((k, gis_value_to_attr(v?)?)))
+                .collect();
+            features.push((reproject(geom.clone(), &transform)?, attrs));
+        }
+        let index = EnvelopeIndex::new(features.iter().map(|(g, _)| {
+            let env = g.envelope();
+            ([env.MinX, env.MinY], [env.MaxX, env.MaxY])
+        }));
+
+        for node in net.nodes() {
+            let mut n = node.lock();
+            let Ok(geom) = node_geometry(&n, &geometry) else {
+                continue;
+            };
+            let (x, y, _) = geom.get_point(0);
+            let mut nearest: Option<(usize, f64)> = None;
+            for (i, lower) in index.nearest(x, y) {
+                if lower > distance || nearest.is_some_and(|(_, d)| lower >= d) {
+                    break;
+                }
+                let d = unsafe {
+                    gdal_sys::OGR_G_Distance(geom.c_geometry(), features[i].0.c_geometry())
+                };
+                if d >= 0.0 && d <= distance && !nearest.is_some_and(|(_, nd)| nd <= d) {
+                    nearest = Some((i, d));
+                }
+            }
+            let Some((i, d)) = nearest else {
+                continue;
+            };
+            for (k, v) in &features[i].1 {
+                n.set_attr(&sanitize_key(&format!("{prefix}{k}")), v.clone());
+            }
+            n.set_attr(&format!("{prefix}distance"), Attribute::Float(d));
+        }
+        Ok(())
+    }
+
+    /// Name of the node nearest to the given coordinates
+    ///
+    /// The coordinates should be in the same spatial reference as the
+    /// node geometries (lat/lon for geographic ones). The spatial index
+    /// of the nodes is built once and reused in the later calls.
+    #[network_func(geometry = "GEOM")]
+    fn gis_nearest_node(
+        net: &Network,
+        /// Latitude (or y coordinate) of the point
+        lat: f64,
+        /// Longitude (or x coordinate) of the point
+        lon: f64,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+    ) -> Result<Option<String>> {
+        let index = node_index(net, &geometry)?;
+        Ok(index.nearest(lon, lat).map(String::from))
+    }
+
+    /// Names of the nodes within the radius of the given coordinates
+    ///
+    /// The coordinates and radius should be in the same spatial
+    /// reference/units as the node geometries. Nodes are sorted by
+    /// their distance, nearest first.
+    #[network_func(geometry = "GEOM")]
+    fn gis_nodes_within(
+        net: &Network,
+        /// Latitude (or y coordinate) of the point
+        lat: f64,
+        /// Longitude (or x coordinate) of the point
+        lon: f64,
+        /// Search radius
+        radius: f64,
+        /// Unit of the radius (m, km, ft, mi, deg) [default: geometry units]
+        unit: Option<String>,
+        /// Spatial reference of the geometries, to convert the radius
+        srs: Option<String>,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+    ) -> Result<Vec<String>> {
+        let srs = srs.as_deref().map(spatial_ref).transpose()?;
+        let radius = distance_in_units(radius, unit.as_deref(), srs.as_ref())?;
+        let index = node_index(net, &geometry)?;
+        Ok(index
+            .within(lon, lat, radius)
+            .into_iter()
+            .map(String::from)
+            .collect())
+    }
+
+    /// Accumulate the attribute over the upstream nodes, stopping at barriers
+    ///
+    /// The accumulated value of a node is its own value and the
+    /// accumulated values of its inputs, except the inputs that are
+    /// barriers (`barrier` attribute is true). For example with the
+    /// local catchment areas and dams as barriers, it gives the
+    /// drainage area unaffected by the dams. Nodes without the
+    /// attribute are taken as zero.
+    #[network_func(barrier = "barrier")]
+    fn gis_accumulate(
+        net: &mut Network,
+        /// Attribute to accumulate
+        attr: String,
+        /// Attribute marking the barrier nodes (dams, diversions)
+        barrier: String,
+        /// Attribute to save the result in [default: `{attr}_acc`]
+        output: Option<String>,
+    ) -> Result<()> {
+        let output = output.unwrap_or_else(|| format!("{attr}_acc"));
+        let acc = upstream_first(net, |n, acc: &HashMap<String, f64>| {
+            let upstream: f64 = n
+                .inputs()
+                .iter()
+                .map(|i| i.lock())
+                .filter(|i| !is_barrier(i, &barrier))
+                .map(|i| acc[i.name()])
+                .sum();
+            let own = n
+                .attr(&attr)
+                .and_then(f64::from_attr_relaxed)
+                .unwrap_or(0.0);
+            Ok(own + upstream)
+        })?;
+        for node in net.nodes() {
+            let mut n = node.lock();
+            let val = acc[n.name()];
+            n.set_attr(&output, Attribute::Float(val));
+        }
+        Ok(())
+    }
+
+    /// Label the nodes with hierarchical tributary ids
+    ///
+    /// The mainstem from the outlet is `1`, the tributaries joining it
+    /// are `1.1`, `1.2`, ... from downstream to upstream, their
+    /// tributaries `1.1.1` and so on. At each node, the input with the
+    /// largest `weight` attribute (e.g. drainage area) continues the
+    /// stem, or the one with the most nodes upstream if not given.
+    #[network_func(attr = "tributary")]
+    fn gis_tributary_ids(
+        net: &mut Network,
+        /// Attribute to choose the stem with at the confluences
+        weight: Option<String>,
+        /// Attribute to save the id in
+        attr: String,
+    ) -> Result<()> {
+        let size = if weight.is_none() {
+            upstream_first(net, |n, size: &HashMap<String, f64>| {
+                let upstream: f64 = n.inputs().iter().map(|i| size[i.lock().name()]).sum();
+                Ok(1.0 + upstream)
+            })?
+        } else {
+            HashMap::new()
+        };
+        let weight_of = |n: &Node| {
+            let n = n.lock();
+            match &weight {
+                Some(w) => n.attr(w).and_then(f64::from_attr_relaxed).unwrap_or(0.0),
+                None => size.get(n.name()).copied().unwrap_or(0.0),
+            }
+        };
+
+        let mut stems: VecDeque<(Node, String)> = net
+            .nodes()
+            .filter(|n| n.lock().output().is_none())
+            .enumerate()
+            .map(|(i, n)| (n.clone(), (i + 1).to_string()))
+            .collect();
+        let mut labels: HashMap<String, String> = HashMap::new();
+        while let Some((start, label)) = stems.pop_front() {
+            let mut count = 0;
+            let mut cur = Some(start);
+            while let Some(node) = cur.take() {
+                let n = node.lock();
+                if labels.contains_key(n.name()) {
+                    break;
+                }
+                labels.insert(n.name().to_string(), label.clone());
+                let mut inputs: Vec<(f64, Node)> = n
+                    .inputs()
+                    .iter()
+                    .map(|i| (weight_of(i), i.clone()))
+                    .collect();
+                inputs.sort_by(|a, b| b.0.total_cmp(&a.0));
+                let mut inputs = inputs.into_iter().map(|i| i.1);
+                cur = inputs.next();
+                for trib in inputs {
+                    count += 1;
+                    stems.push_back((trib, format!("{label}.{count}")));
+                }
+            }
+        }
+        for node in net.nodes() {
+            let mut n = node.lock();
+            if let Some(l) = labels.get(n.name()) {
+                n.set_attr(&attr, Attribute::String(l.clone().into()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Nodes upstream of the node, stopping at barriers
+    ///
+    /// The barrier nodes (`barrier` attribute is true) are included,
+    /// but not the nodes upstream of them.
+    #[network_func(barrier = "barrier")]
+    fn gis_trace_upstream(
+        net: &Network,
+        /// Name of the node to trace from
+        node: String,
+        /// Attribute marking the barrier nodes (dams, diversions)
+        barrier: String,
+    ) -> Result<Vec<String>> {
+        let start = net.node_by_name(&node).context("Node not found")?;
+        let mut nodes = Vec::new();
+        let mut stack: Vec<Node> = start.lock().inputs().to_vec();
+        while let Some(cur) = stack.pop() {
+            let n = cur.lock();
+            nodes.push(n.name().to_string());
+            if !is_barrier(&n, &barrier) {
+                stack.extend(n.inputs().iter().cloned());
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// Nodes downstream of the node, up to the first barrier
+    #[network_func(barrier = "barrier")]
+    fn gis_trace_downstream(
+        net: &Network,
+        /// Name of the node to trace from
+        node: String,
+        /// Attribute marking the barrier nodes (dams, diversions)
+        barrier: String,
+    ) -> Result<Vec<String>> {
+        let start = net.node_by_name(&node).context("Node not found")?;
+        let mut nodes = Vec::new();
+        let mut cur = start.clone();
+        loop {
+            let out = match cur.lock().output() {
+                RSome(o) => o.clone(),
+                _ => break,
+            };
+            let o = out.lock();
+            nodes.push(o.name().to_string());
+            if is_barrier(&o, &barrier) {
+                break;
+            }
+            drop(o);
+            cur = out;
+        }
+        Ok(nodes)
+    }
+
+    /// Check if the nodes are flow connected (one is downstream of the other)
+    #[network_func]
+    fn gis_flow_connected(
+        net: &Network,
+        /// Name of the first node
+        node1: String,
+        /// Name of the second node
+        node2: String,
+    ) -> Result<bool> {
+        let n1 = net.node_by_name(&node1).context("Node not found")?;
+        let n2 = net.node_by_name(&node2).context("Node not found")?;
+        Ok(downstream_path(n1).contains(&node2) || downstream_path(n2).contains(&node1))
+    }
+
+    /// First node downstream of both nodes (confluence of their flows)
+    ///
+    /// It's one of the nodes if they are flow connected, and none if
+    /// they don't share an outlet.
+    #[network_func]
+    fn gis_common_downstream(
+        net: &Network,
+        /// Name of the first node
+        node1: String,
+        /// Name of the second node
+        node2: String,
+    ) -> Result<Option<String>> {
+        let n1 = net.node_by_name(&node1).context("Node not found")?;
+        let n2 = net.node_by_name(&node2).context("Node not found")?;
+        let path: HashSet<String> = downstream_path(n1).into_iter().collect();
+        Ok(downstream_path(n2).into_iter().find(|n| path.contains(n)))
+    }
+
+    /// Names of the node and the nodes downstream of it in order
+    fn downstream_path(node: &Node) -> Vec<String> {
+        let mut path = vec![node.lock().name().to_string()];
+        let mut seen: HashSet<String> = path.iter().cloned().collect();
+        let mut cur = node.clone();
+        loop {
+            let out = match cur.lock().output() {
+                RSome(o) => o.clone(),
+                _ => break,
+            };
+            let name = out.lock().name().to_string();
+            // loops shouldn't be there, but just in case
+            if !seen.insert(name.clone()) {
+                break;
+            }
+            path.push(name);
+            cur = out;
+        }
+        path
+    }
+
+    /// Dendritic Connectivity Index of the network fragmented by barriers
+    ///
+    /// The network is split into sections at the barrier nodes
+    /// (`barrier` attribute is true), using the edge lengths (see
+    /// `gis_edge_metrics`) and barrier passabilities (0 to 1, 0 if not
+    /// given). The section of each node is saved as `{prefix}section`,
+    /// and the barriers get the length of the section upstream of them
+    /// (`{prefix}upstream_length`) and the gain in the indices if they
+    /// were removed (`{prefix}p_gain` and `{prefix}d_gain`). Returns
+    /// the potamodromous and diadromous indices `[DCIp, DCId]`.
+    #[network_func(
+        length = "length",
+        barrier = "barrier",
+        passability = "passability",
+        prefix = "dci_"
+    )]
+    fn gis_dci(
+        net: &mut Network,
+        /// Attribute with the length of the edges
+        length: String,
+        /// Attribute marking the barrier nodes
+        barrier: String,
+        /// Attribute with the passability of the barriers
+        passability: String,
+        /// Prefix for the attributes to save
+        prefix: String,
+        /// CSV file to save the per barrier statistics in
+        summary: Option<PathBuf>,
+    ) -> Result<Vec<f64>> {
+        // sections are identified by the barrier at their downstream
+        // end, or the outlet node
+        let mut keys: HashMap<String, (bool, String)> = HashMap::new();
+        for node in net.nodes() {
+            let mut path = Vec::new();
+            let mut cur = node.clone();
+            let key = loop {
+                let n = cur.lock();
+                if let Some(k) = keys.get(n.name()) {
+                    break k.clone();
+                }
+                path.push(n.name().to_string());
+                let out = match n.output() {
+                    RSome(o) => o.clone(),
+                    _ => break (false, n.name().to_string()),
+                };
+                drop(n);
+                let o = out.lock();
+                if is_barrier(&o, &barrier) {
+                    break (true, o.name().to_string());
+                }
+                drop(o);
+                cur = out;
+            };
+            for p in path {
+                keys.insert(p, key.clone());
+            }
+        }
+        let mut index: HashMap<&(bool, String), usize> = HashMap::new();
+        for node in net.nodes() {
+            let k = &keys[node.lock().name()];
+            let len = index.len();
+            index.entry(k).or_insert(len);
+        }
+        let mut sections = vec![
+            Section {
+                length: 0.0,
+                parent: None
+            };
+            index.len()
+        ];
+        for node in net.nodes() {
+            let n = node.lock();
+            let sec = index[&keys[n.name()]];
+            sections[sec].length += n
+                .attr(&length)
+                .and_then(f64::from_attr_relaxed)
+                .unwrap_or(0.0);
+        }
+        // barrier name, section upstream of it
+        let mut barriers: Vec<(String, usize)> = Vec::new();
+        for (k, &sec) in &index {
+            if k.0 {
+                let b = net.node_by_name(&k.1).context("Barrier node not found")?;
+                let b = b.lock();
+                let pass = b
+                    .attr(&passability)
+                    .and_then(f64::from_attr_relaxed)
+                    .unwrap_or(0.0);
+                sections[sec].parent = Some((index[&keys[b.name()]], pass));
+                barriers.push((k.1.clone(), sec));
+            }
+        }
+        barriers.sort();
+
+        let dci_p = connectivity::dci_p(&sections);
+        let dci_d = connectivity::dci_d(&sections);
+        let mut rows = Vec::with_capacity(barriers.len());
+        for (name, sec) in &barriers {
+            let mut removed = sections.clone();
+            removed[*sec].parent = removed[*sec].parent.map(|(p, _)| (p, 1.0));
+            let gain_p = connectivity::dci_p(&removed) - dci_p;
+            let gain_d = connectivity::dci_d(&removed) - dci_d;
+            let upstream = sections[*sec].length;
+            if let Some(n) = net.node_by_name(name) {
+                let mut n = n.lock();
+                n.set_attr(
+                    &format!("{prefix}upstream_length"),
+                    Attribute::Float(upstream),
+                );
+                n.set_attr(&format!("{prefix}p_gain"), Attribute::Float(gain_p));
+                n.set_attr(&format!("{prefix}d_gain"), Attribute::Float(gain_d));
+            }
+            rows.push(format!("{},{upstream},{gain_p},{gain_d}", csv_quote(name)));
+        }
+        for node in net.nodes() {
+            let mut n = node.lock();
+            let sec = index[&keys[n.name()]];
+            n.set_attr(&format!("{prefix}section"), Attribute::Integer(sec as i64));
+        }
+        if let Some(file) = summary {
+            let mut csv = String::from("barrier,upstream_length,dci_p_gain,dci_d_gain\n");
+            for r in rows {
+                csv.push_str(&r);
+                csv.push('\n');
+            }
+            std::fs::write(file, csv)?;
+        }
+        Ok(vec![dci_p, dci_d])
+    }
+
+    /// Width function and time-area histogram of the nodes' catchments
+    ///
+    /// The width function is the number of channels at the flow
+    /// distance from the node, averaged over bins of `bin` length, and
+    /// is saved as `{prefix}width_function` array. If `velocity` is
+    /// given, the local catchment areas of the node and its upstream
+    /// nodes are binned by their travel time (distance / velocity) to
+    /// the node in bins of `time_bin`, and saved as `{prefix}time_area`
+    /// array. These are inputs for the geomorphological unit
+    /// hydrograph methods.
+    #[network_func(length = "length", area = "area", time_bin = 1.0, prefix = "")]
+    fn gis_width_function(
+        net: &mut Network,
+        /// Size of the distance bins
+        bin: f64,
+        /// Attribute with the length of the edges
+        length: String,
+        /// Attribute with the local catchment area of the nodes
+        area: String,
+        /// Flow velocity for the time-area histogram
+        velocity: Option<f64>,
+        /// Size of the time bins
+        time_bin: f64,
+        /// Prefix for the attributes to save
+        prefix: String,
+        /// Directory to save the histograms as CSV files per node
+        /// (`{node}_width.csv` and `{node}_time_area.csv`)
+        output_dir: Option<PathBuf>,
+    ) -> Result<()> {
+        if bin <= 0.0 {
+            return Err(nadi_core::anyhow::Error::msg("bin should be positive"));
+        }
+        if time_bin <= 0.0 {
+            return Err(nadi_core::anyhow::Error::msg("time_bin should be positive"));
+        }
+        if velocity.is_some_and(|v| v <= 0.0) {
+            return Err(nadi_core::anyhow::Error::msg("velocity should be positive"));
+        }
+        let float_attr = |n: &NodeInner, a: &str| n.attr(a).and_then(f64::from_attr_relaxed);
+        if let Some(dir) = &output_dir {
+            std::fs::create_dir_all(dir)?;
+        }
+        for node in net.nodes() {
+            let mut width: Vec<f64> = Vec::new();
+            let mut time_area: Vec<f64> = vec![float_attr(&node.lock(), &area).unwrap_or(0.0)];
+            // (node, flow distance of its output from the target node)
+            let mut stack: Vec<(Node, f64)> = node
+                .lock()
+                .inputs()
+                .iter()
+                .map(|i| (i.clone(), 0.0))
+                .collect();
+            while let Some((cur, start)) = stack.pop() {
+                let n = cur.lock();
+                let len = float_attr(&n, &length).unwrap_or(0.0);
+                let end = start + len;
+                // distribute the edge length over the bins it spans
+                let (first, last) = ((start / bin) as usize, (end / bin) as usize);
+                if width.len() <= last {
+                    width.resize(last + 1, 0.0);
+                }
+                for (b, w) in width.iter_mut().enumerate().take(last + 1).skip(first) {
+                    let lo = start.max(b as f64 * bin);
+                    let hi = end.min((b + 1) as f64 * bin);
+                    *w += (hi - lo).max(0.0) / bin;
+                }
+                if let (Some(v), Some(a)) = (velocity, float_attr(&n, &area)) {
+                    let t = (end / v / time_bin) as usize;
+                    if time_area.len() <= t {
+                        time_area.resize(t + 1, 0.0);
+                    }
+                    time_area[t] += a;
+                }
+                stack.extend(n.inputs().iter().map(|i| (i.clone(), end)));
+            }
+            let mut n = node.lock();
+            if let Some(dir) = &output_dir {
+                let mut csv = String::from("distance,width\n");
+                for (b, w) in width.iter().enumerate() {
+                    csv.push_str(&format!("{},{w}\n", b as f64 * bin));
+                }
+                std::fs::write(dir.join(format!("{}_width.csv", n.name())), csv)?;
+                if velocity.is_some() {
+                    let mut csv = String::from("time,area\n");
+                    for (b, a) in time_area.iter().enumerate() {
+                        csv.push_str(&format!("{},{a}\n", b as f64 * time_bin));
+                    }
+                    std::fs::write(dir.join(format!("{}_time_area.csv", n.name())), csv)?;
+                }
+            }
+            let to_attr =
+                |v: Vec<f64>| Attribute::Array(v.into_iter().map(Attribute::Float).collect());
+            n.set_attr(&format!("{prefix}width_function"), to_attr(width));
+            if velocity.is_some() {
+                n.set_attr(&format!("{prefix}time_area"), to_attr(time_area));
+            }
+        }
+        Ok(())
+    }
+
+    /// Calculate the geometry metrics of the edges from their traced geometry
+    ///
+    /// For each node with the edge geometry (LineString WKT of the
+    /// stream from the node to its output), saves the attributes:
+    /// `length` (along the stream), `straight_length` (between the
+    /// endpoints), `sinuosity` (ratio of the two) and `azimuth` (length
+    /// weighted mean direction of flow in degrees clockwise from north).
+    /// Lengths are in the geometry units, or meters if `geodesic` is true
+    /// for the geometries in lat/lon.
+    #[network_func(geometry = "EDGE_GEOM", geodesic = false, prefix = "")]
+    fn gis_edge_metrics(
+        net: &mut Network,
+        /// Attribute with the edge geometry (WKT)
+        geometry: String,
+        /// Calculate geodesic lengths in meters for lat/lon geometries
+        geodesic: bool,
+        /// Prefix for the attribute names
+        prefix: String,
+    ) -> Result<()> {
+        for node in net.nodes() {
+            let mut n = node.lock();
+            if n.attr(&geometry).is_none() {
+                continue;
+            }
+            let pts = line_points(&node_geometry(&n, &geometry)?);
+            if pts.len() < 2 {
+                continue;
+            }
+            let (start, end) = (pts[0], pts[pts.len() - 1]);
+            let length = line_length(&pts, geodesic);
+            let straight = line_length(&[start, end], geodesic);
+            // sum of the segment direction vectors weighted by length
+            let (sx, sy) = pts.windows(2).fold((0.0, 0.0), |(sx, sy), w| {
+                let (len, az) = if geodesic {
+                    (
+                        geodesy::vincenty(w[0].1, w[0].0, w[1].1, w[1].0),
+                        geodesy::bearing(w[0].1, w[0].0, w[1].1, w[1].0).to_radians(),
+                    )
+                } else {
+                    let (dx, dy) = (w[1].0 - w[0].0, w[1].1 - w[0].1);
+                    (planar_dist(w[0], w[1]), dx.atan2(dy))
+                };
+                (sx + len * az.sin(), sy + len * az.cos())
+            });
+            let azimuth = (sx.atan2(sy).to_degrees() + 360.0) % 360.0;
+            n.set_attr(&format!("{prefix}length"), Attribute::Float(length));
+            n.set_attr(
+                &format!("{prefix}straight_length"),
+                Attribute::Float(straight),
+            );
+            if straight > 0.0 {
+                n.set_attr(
+                    &format!("{prefix}sinuosity"),
+                    Attribute::Float(length / straight),
+                );
+            }
+            n.set_attr(&format!("{prefix}azimuth"), Attribute::Float(azimuth));
+        }
+        Ok(())
+    }
+
+    /// Save the length of the LineString geometry of the nodes
+    ///
+    /// Length is in the geometry units, or meters if `geodesic` is true
+    /// for the geometries in lat/lon. Multi-geometries are summed,
+    /// and the nodes without the geometry are skipped.
+    #[network_func(geometry = "EDGE_GEOM", geodesic = false, attr = "length")]
+    fn gis_geometry_length(
+        net: &mut Network,
+        /// Attribute with the geometry (WKT)
+        geometry: String,
+        /// Calculate geodesic lengths in meters for lat/lon geometries
+        geodesic: bool,
+        /// Attribute to save the length in
+        attr: String,
+    ) -> Result<()> {
+        for node in net.nodes() {
+            let mut n = node.lock();
+            if n.attr(&geometry).is_none() {
+                continue;
+            }
+            let length = line_parts(&node_geometry(&n, &geometry)?)
+                .iter()
+                .map(|pts| line_length(pts, geodesic))
+                .sum();
+            n.set_attr(&attr, Attribute::Float(length));
+        }
+        Ok(())
+    }
+
+    /// Save the area of the polygon geometry of the nodes (e.g. basin)
+    ///
+    /// Area is in the geometry units, or square meters if `geodesic`
+    /// is true for the geometries in lat/lon. The holes are excluded
+    /// and the multi-polygons are summed.
+    #[network_func(geometry = "GEOM", geodesic = false, km2 = false, attr = "area")]
+    fn gis_geometry_area(
+        net: &mut Network,
+        /// Attribute with the geometry (WKT)
+        geometry: String,
+        /// Calculate geodesic area in square meters for lat/lon geometries
+        geodesic: bool,
+        /// Divide the area by 10^6 (square meters to square kilometers)
+        km2: bool,
+        /// Attribute to save the area in
+        attr: String,
+    ) -> Result<()> {
+        for node in net.nodes() {
+            let mut n = node.lock();
+            if n.attr(&geometry).is_none() {
+                continue;
+            }
+            let geom = node_geometry(&n, &geometry)?;
+            let area = if geodesic {
+                polygon_area(&geom)
+            } else {
+                geom.area()
+            };
+            let area = if km2 { area / 1e6 } else { area };
+            n.set_attr(&attr, Attribute::Float(area));
+        }
+        Ok(())
+    }
+
+    /// Calculate the confluence angles at the junctions
+    ///
+    /// For the nodes with two or more inputs, the directions of the
+    /// input edges are taken at the given distance from the junction
+    /// (or their last segment), and saved as `junction_angle` (angle
+    /// between the two inputs with the widest angle) and
+    /// `junction_asymmetry` (difference of their deviations from the
+    /// straight continuation of the output edge) in degrees. Very
+    /// wide angles can be the braids miscoded as confluences. Angles
+    /// are planar, in the geometry coordinates.
+    #[network_func(geometry = "GEOM", edge_geometry = "EDGE_GEOM", prefix = "")]
+    fn gis_junction_angles(
+        net: &mut Network,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Attribute with the edge geometry (WKT)
+        edge_geometry: String,
+        /// Distance from the junction to take the directions at
+        distance: Option<f64>,
+        /// Prefix for the attribute names
+        prefix: String,
+    ) -> Result<()> {
+        // direction (degrees clockwise from north) from the first
+        // point towards the point at the distance along the line
+        let direction = |pts: &[(f64, f64)]| {
+            let start = *pts.first()?;
+            let end = match distance {
+                Some(d) => lrs::interpolate(pts, d)?,
+                None => *pts.iter().find(|p| **p != start)?,
+            };
+            (end != start).then(|| (end.0 - start.0).atan2(end.1 - start.1).to_degrees())
+        };
+        let angle = |a: f64, b: f64| {
+            let d = (a - b).rem_euclid(360.0);
+            d.min(360.0 - d)
+        };
+        for node in net.nodes() {
+            // the locks are released before reading the edges, as the
+            // edges without geometry need the output node
+            let inputs = node.lock().inputs().to_vec();
+            if inputs.len() < 2 {
+                continue;
+            }
+            let mut dirs = Vec::new();
+            for inp in &inputs {
+                let Some(mut pts) = edge_points(&inp.lock(), &geometry, &edge_geometry)? else {
+                    continue;
+                };
+                // pointing upstream from the junction
+                pts.reverse();
+                dirs.extend(direction(&pts));
+            }
+            let mut widest: Option<(f64, f64, f64)> = None;
+            for (i, a) in dirs.iter().enumerate() {
+                for b in &dirs[(i + 1)..] {
+                    let ang = angle(*a, *b);
+                    if !widest.is_some_and(|w| w.0 >= ang) {
+                        widest = Some((ang, *a, *b));
+                    }
+                }
+            }
+            let Some((ang, a, b)) = widest else {
+                continue;
+            };
+            let out = edge_points(&node.lock(), &geometry, &edge_geometry)?
+                .and_then(|pts| direction(&pts));
+            let mut n = node.lock();
+            n.set_attr(&format!("{prefix}junction_angle"), Attribute::Float(ang));
+            if let Some(o) = out {
+                // a tributary in the straight continuation comes from
+                // the opposite of the output direction
+                let back = o + 180.0;
+                let asym = (angle(a, back) - angle(b, back)).abs();
+                n.set_attr(
+                    &format!("{prefix}junction_asymmetry"),
+                    Attribute::Float(asym),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Calculate the elevation drop and slope of the edges from a DEM
+    ///
+    /// The DEM is sampled at the upstream and downstream ends of the
+    /// edge, which is the traced edge geometry if available, or the
+    /// straight line from the node to its output. Saves `elev_up`,
+    /// `elev_down`, `drop` and `slope` (drop per unit length) attributes
+    /// on the nodes. The geometries should be in the DEM spatial reference.
+    ///
+    /// With `monotonic`, the elevations are corrected so they never
+    /// increase downstream before calculating the slopes: each node is
+    /// lowered to the lowest corrected elevation of its inputs, and the
+    /// downstream end of the edge takes the corrected elevation of the
+    /// output node. The raw values are saved in the `elev_up_raw`,
+    /// `elev_down_raw`, `drop_raw` and `slope_raw` attributes, so noise
+    /// in the DEM doesn't give negative slopes to the routing models.
+    #[network_func(
+        band = 1,
+        geometry = "GEOM",
+        edge_geometry = "EDGE_GEOM",
+        geodesic = false,
+        monotonic = false
+    )]
+    fn gis_edge_slope(
+        net: &mut Network,
+        /// DEM raster file
+        dem: PathBuf,
+        /// Band of the raster with the elevation
+        band: i64,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Attribute with the edge geometry (WKT)
+        edge_geometry: String,
+        /// Calculate geodesic lengths in meters for lat/lon geometries
+        geodesic: bool,
+        /// Enforce the elevations to not increase downstream
+        monotonic: bool,
+    ) -> Result<()> {
+        let dem = RasterSampler::open(&dem, band_index(band)?)?;
+        // raw (upstream, downstream) elevations and the edge length
+        let mut raw: HashMap<String, (Option<f64>, Option<f64>, f64)> = HashMap::new();
+        for node in net.nodes() {
+            let n = node.lock();
+            let Some(pts) = edge_points(&n, &geometry, &edge_geometry)? else {
+                continue;
+            };
+            if pts.len() < 2 {
+                continue;
+            }
+            let (start, end) = (pts[0], pts[pts.len() - 1]);
+            let up = dem.sample(start.0, start.1)?;
+            let down = dem.sample(end.0, end.1)?;
+            raw.insert(
+                n.name().to_string(),
+                (up, down, line_length(&pts, geodesic)),
+            );
+        }
+
+        let fixed = if monotonic {
+            monotonic_elevations(net, &raw)?
+        } else {
+            HashMap::new()
+        };
+        let suffix = if monotonic { "_raw" } else { "" };
+        for node in net.nodes() {
+            let mut n = node.lock();
+            let Some(&(up, down, length)) = raw.get(n.name()) else {
+                continue;
+            };
+            let mut values = vec![(suffix, up, down)];
+            if monotonic {
+                let up_fix = fixed.get(n.name()).copied();
+                let down_fix = match n.output() {
+                    RSome(o) => fixed.get(o.lock().name()).copied(),
+                    _ => None,
+                }
+                .or(down)
+                .map(|d| up_fix.map_or(d, |u| d.min(u)));
+                values.push(("", up_fix, down_fix));
+            }
+            for (sfx, up, down) in values {
+                if let Some(e) = up {
+                    n.set_attr(&format!("elev_up{sfx}"), Attribute::Float(e));
+                }
+                if let Some(e) = down {
+                    n.set_attr(&format!("elev_down{sfx}"), Attribute::Float(e));
+                }
+                if let (Some(up), Some(down)) = (up, down) {
+                    n.set_attr(&format!("drop{sfx}"), Attribute::Float(up - down));
+                    if length > 0.0 {
+                        n.set_attr(
+                            &format!("slope{sfx}"),
+                            Attribute::Float((up - down) / length),
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Estimate the channel width at the nodes
+    ///
+    /// The width can be estimated with a regional regression on the
+    /// drainage area (`width = a * area ^ b` with `coefficients = [a,
+    /// b]`), or measured from a water mask polygon layer (e.g. NHD
+    /// Area) along a transect perpendicular to the channel at the
+    /// node. If both are given, the regression is used for nodes where
+    /// the measurement fails. The channel direction is taken from the
+    /// edge geometry, or the line to the output node.
+    #[network_func(
+        area = "drainage_area",
+        geometry = "GEOM",
+        edge_geometry = "EDGE_GEOM",
+        attr = "width"
+    )]
+    fn gis_channel_width(
+        net: &mut Network,
+        /// Attribute with the drainage area for the regression
+        area: String,
+        /// Regression coefficients `[a, b]`
+        coefficients: Option<Vec<f64>>,
+        /// GIS file with the water mask polygons
+        water_mask: Option<PathBuf>,
+        /// Layer of the water mask file, first one picked by default
+        layer: Option<String>,
+        /// Length of the transect to measure in the water mask
+        transect: Option<f64>,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Attribute with the edge geometry (WKT)
+        edge_geometry: String,
+        /// Attribute to save the width in
+        attr: String,
+    ) -> Result<()> {
+        let coefficients = match coefficients.as_deref() {
+            Some(&[a, b]) => Some((a, b)),
+            Some(_) => {
+                return Err(nadi_core::anyhow::Error::msg(
+                    "Coefficients should be [a, b] for width = a * area ^ b",
+                ))
+            }
+            None => None,
+        };
+        let mask_data = water_mask.map(Dataset::open).transpose()?;
+        let mut mask = mask_data
+            .as_ref()
+            .map(|d| open_layer(d, layer))
+            .transpose()?;
+        let transect = match (&mask, transect) {
+            (Some(_), None) => {
+                return Err(nadi_core::anyhow::Error::msg(
+                    "Transect length is needed to measure width from water mask",
+                ))
+            }
+            (_, t) => t.unwrap_or_default(),
+        };
+
+        for node in net.nodes() {
+            let mut n = node.lock();
+            let mut width = None;
+            if let Some(lyr) = mask.as_mut() {
+                if let Some(pts) = edge_points(&n, &geometry, &edge_geometry)? {
+                    if pts.len() > 1 {
+                        width = transect_width(lyr, pts[0], pts[1], transect)?;
+                    }
+                }
+            }
+            if let (None, Some((a, b))) = (width, coefficients) {
+                if let Some(area) = n.attr(&area).and_then(f64::from_attr_relaxed) {
+                    width = Some(a * area.powf(b));
+                }
+            }
+            if let Some(w) = width {
+                n.set_attr(&attr, Attribute::Float(w));
+            }
+        }
+        Ok(())
+    }
+
+    /// Extract the terrain cross-sections at the nodes from a DEM
+    ///
+    /// The cross-section is perpendicular to the channel direction
+    /// (from the edge geometry, or the line to the output node) and
+    /// centered at the node, going from the right bank to the left
+    /// bank. Stations and elevations are saved as `{attr}_station` and
+    /// `{attr}_elevation` arrays, and as `{node}.csv` files in
+    /// `output_dir` if given. Cells with nodata are skipped.
+    #[network_func(band = 1, geometry = "GEOM", edge_geometry = "EDGE_GEOM", attr = "xs")]
+    fn gis_cross_sections(
+        net: &mut Network,
+        /// DEM raster file
+        dem: PathBuf,
+        /// Total width of the cross-section
+        width: f64,
+        /// Distance between the sampling points
+        step: f64,
+        /// Band of the raster with the elevation
+        band: i64,
+        /// Directory to save the CSV files of the cross-sections
+        output_dir: Option<PathBuf>,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Attribute with the edge geometry (WKT)
+        edge_geometry: String,
+        /// Prefix of the attributes to save the cross-section in
+        attr: String,
+    ) -> Result<()> {
+        if step <= 0.0 {
+            return Err(nadi_core::anyhow::Error::msg("Step should be positive"));
+        }
+        let dem = RasterSampler::open(&dem, band_index(band)?)?;
+        if let Some(dir) = &output_dir {
+            std::fs::create_dir_all(dir)?;
+        }
+        let count = (width / step).floor() as usize;
+        for node in net.nodes() {
+            let mut n = node.lock();
+            let Some(pts) = edge_points(&n, &geometry, &edge_geometry)? else {
+                continue;
+            };
+            let Some(dir) = pts.get(1).and_then(|next| perpendicular(pts[0], *next)) else {
+                continue;
+            };
+            let center = pts[0];
+            let mut section = Vec::with_capacity(count + 1);
+            for i in 0..=count {
+                let station = i as f64 * step;
+                let t = station - width / 2.0;
+                let (x, y) = (center.0 + dir.0 * t, center.1 + dir.1 * t);
+                if let Some(elev) = dem.sample(x, y)? {
+                    section.push((station, x, y, elev));
+                }
+            }
+            if let Some(dir) = &output_dir {
+                let mut csv = String::from("station,x,y,elevation\n");
+                for (s, x, y, e) in &section {
+                    csv.push_str(&format!("{s},{x},{y},{e}\n"));
+                }
+                std::fs::write(dir.join(format!("{}.csv", n.name())), csv)?;
+            }
+            let stations: RVec<Attribute> = section.iter().map(|s| Attribute::Float(s.0)).collect();
+            let elevations: RVec<Attribute> =
+                section.iter().map(|s| Attribute::Float(s.3)).collect();
+            n.set_attr(&format!("{attr}_station"), Attribute::Array(stations));
+            n.set_attr(&format!("{attr}_elevation"), Attribute::Array(elevations));
+        }
+        Ok(())
+    }
+
+    /// Save the network as HEC-HMS basin file
+    ///
+    /// Each node gets a junction and a subbasin draining into it, and
+    /// the edges are written as reaches between the junctions. The
+    /// outlet is written as a sink. The area (km² for metric, mi² for
+    /// english units) is taken from the `area` attribute if available.
+    #[network_func(
+        title = "nadi",
+        metric = true,
+        area = "drainage_area",
+        geometry = "GEOM",
+        edge_geometry = "EDGE_GEOM"
+    )]
+    fn gis_save_hms(
+        net: &Network,
+        /// Output basin file
+        file: PathBuf,
+        /// Name of the basin model
+        title: String,
+        /// Use metric unit system
+        metric: bool,
+        /// Attribute with the local drainage area of the nodes
+        area: String,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Attribute with the edge geometry (WKT)
+        edge_geometry: String,
+    ) -> Result<()> {
+        let nodes = model_nodes(net, &geometry, &edge_geometry, &area, "", "", false)?;
+        std::fs::write(file, models::hms_basin(&title, metric, &nodes))?;
+        Ok(())
+    }
+
+    /// Save the network as HEC-RAS geometry file stub
+    ///
+    /// Each edge is written as a reach (named after the upstream node)
+    /// with its geometry, and the confluences as junctions. Nodes with
+    /// cross-sections (see `gis_cross_sections`) get a placeholder
+    /// cross-section.
+    #[network_func(
+        title = "nadi",
+        river = "River",
+        xs = "xs",
+        geometry = "GEOM",
+        edge_geometry = "EDGE_GEOM"
+    )]
+    fn gis_save_ras(
+        net: &Network,
+        /// Output geometry file (`.g01`)
+        file: PathBuf,
+        /// Title of the geometry
+        title: String,
+        /// Name of the river for the reaches
+        river: String,
+        /// Prefix of the cross-section attributes
+        xs: String,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Attribute with the edge geometry (WKT)
+        edge_geometry: String,
+    ) -> Result<()> {
+        let nodes = model_nodes(net, &geometry, &edge_geometry, "", "", &xs, false)?;
+        std::fs::write(file, models::ras_geometry(&title, &river, &nodes))?;
+        Ok(())
+    }
+
+    /// Save the network as SWMM input file skeleton
+    ///
+    /// Nodes are written as junctions (outfalls for the outlets) with
+    /// the invert elevation from the `elevation` attribute, and edges
+    /// as conduits with their lengths. Conduit slopes are added as
+    /// comments for review, SWMM calculates them from the inverts.
+    #[network_func(
+        title = "nadi",
+        elevation = "elev_up",
+        geodesic = false,
+        geometry = "GEOM",
+        edge_geometry = "EDGE_GEOM"
+    )]
+    fn gis_save_swmm(
+        net: &Network,
+        /// Output input file (`.inp`)
+        file: PathBuf,
+        /// Title of the project
+        title: String,
+        /// Attribute with the invert elevation of the nodes
+        elevation: String,
+        /// Calculate conduit lengths in meters from lat/lon
+        geodesic: bool,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Attribute with the edge geometry (WKT)
+        edge_geometry: String,
+    ) -> Result<()> {
+        let nodes = model_nodes(net, &geometry, &edge_geometry, "", &elevation, "", geodesic)?;
+        std::fs::write(file, models::swmm_inp(&title, &nodes))?;
+        Ok(())
+    }
+
+    /// Save the network as GraphML with the given node attributes
+    ///
+    /// The edges are directed from each node to its output node, and
+    /// the file can be read in igraph, networkx or Gephi.
+    #[network_func(attrs = Vec::new())]
+    fn gis_save_graphml(
+        net: &Network,
+        /// Output GraphML file
+        file: PathBuf,
+        /// Node attributes to save
+        attrs: Vec<String>,
+    ) -> Result<()> {
+        let nodes = graph_nodes(net, &attrs);
+        std::fs::write(file, graphs::graphml(&attrs, &nodes))?;
+        Ok(())
+    }
+
+    /// Save the network as GEXF with the given node attributes
+    ///
+    /// The edges are directed from each node to its output node, and
+    /// the file can be read in Gephi, igraph or networkx.
+    #[network_func(attrs = Vec::new())]
+    fn gis_save_gexf(
+        net: &Network,
+        /// Output GEXF file
+        file: PathBuf,
+        /// Node attributes to save
+        attrs: Vec<String>,
+    ) -> Result<()> {
+        let nodes = graph_nodes(net, &attrs);
+        std::fs::write(file, graphs::gexf(&attrs, &nodes))?;
+        Ok(())
+    }
+
+    /// Save the adjacency matrix of the network as CSV
+    ///
+    /// The value is 1 in the row of a node and the column of its
+    /// output node. The sparse matrix lists only the edges as
+    /// `from,to,value` rows.
+    #[network_func(sparse = false)]
+    fn gis_save_adjacency(
+        net: &Network,
+        /// Output CSV file
+        file: PathBuf,
+        /// Save as sparse matrix
+        sparse: bool,
+    ) -> Result<()> {
+        let nodes = graph_nodes(net, &[]);
+        std::fs::write(file, graphs::adjacency(&nodes, sparse))?;
+        Ok(())
+    }
+
+    /// Save the along-network distances between the nodes as CSV
+    ///
+    /// The distances are the sums of the edge lengths (`length`
+    /// attribute, e.g. from `gis_edge_metrics`) along the network.
+    /// The long table has `from`, `to`, the distances of both nodes
+    /// to their confluence, the total distance and if they are flow
+    /// connected (one is downstream of the other). The matrix has the
+    /// distance from the row node to the confluence with the column
+    /// node, as used in the spatial stream network (SSN) models.
+    #[network_func(length = "length", matrix = false)]
+    fn gis_save_distances(
+        net: &Network,
+        /// Output CSV file
+        file: PathBuf,
+        /// Attribute with the length of the edge to the output
+        length: String,
+        /// Nodes to save the distances of, all nodes by default
+        nodes: Option<Vec<String>>,
+        /// Save as matrix instead of long table
+        matrix: bool,
+    ) -> Result<()> {
+        let graph = graph_nodes(net, std::slice::from_ref(&length));
+        let lengths: HashMap<&str, f64> = graph
+            .iter()
+            .filter(|n| n.output.is_some())
+            .filter_map(|n| match n.attrs[0] {
+                Some(Value::Float(v)) => Some((n.name.as_str(), v)),
+                Some(Value::Integer(v)) => Some((n.name.as_str(), v as f64)),
+                _ => None,
+            })
+            .collect();
+        let missing = graph.iter().filter(|n| n.output.is_some()).count() - lengths.len();
+        if missing > 0 {
+            warning!("WARN {missing} edges without {length:?} are taken as zero length");
+        }
+        let selected: Vec<usize> = match &nodes {
+            Some(names) => {
+                let index: HashMap<&str, usize> = graph
+                    .iter()
+                    .enumerate()
+                    .map(|(i, n)| (n.name.as_str(), i))
+                    .collect();
+                names
+                    .iter()
+                    .map(|n| index.get(n.as_str()).copied().context("Node not found"))
+                    .collect::<Result<_>>()?
+            }
+            None => (0..graph.len()).collect(),
+        };
+        std::fs::write(file, graphs::distances(&graph, &lengths, &selected, matrix))?;
+        Ok(())
+    }
+
+    /// Save the upstream subnetwork of a node to a GIS file
+    ///
+    /// The node and all the nodes upstream of it are saved in the
+    /// `nodes` layer with the given attributes, and their edges in the
+    /// `edges` layer, using the traced edge geometry if available. The
+    /// output format should support multiple layers (e.g. GPKG).
+    #[network_func(
+        attrs = HashMap::new(),
+        geometry = "GEOM",
+        edge_geometry = "EDGE_GEOM"
+    )]
+    fn gis_save_subnetwork(
+        net: &Network,
+        /// Name of the node at the outlet of the subnetwork
+        node: String,
+        /// Output GIS file
+        file: PathBuf,
+        /// Attributes to save with their types (String, Integer, Float, ...)
+        attrs: HashMap<String, String>,
+        /// Output driver, detected from the file extension by default
+        driver: Option<String>,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Attribute with the edge geometry (WKT)
+        edge_geometry: String,
+        /// Spatial reference of the geometries
+        srs: Option<String>,
+        /// Spatial reference to reproject the geometries to before
+        /// saving (EPSG code or PROJ string)
+        target_srs: Option<String>,
+    ) -> Result<()> {
+        let (out_srs, trans) = output_srs(srs, target_srs)?;
+        let outlet = net.node_by_name(&node).context("Node not found")?;
+        let mut nodes = vec![outlet.clone()];
+        let mut i = 0;
+        while i < nodes.len() {
+            let inputs = nodes[i].lock().inputs().to_vec();
+            nodes.extend(inputs);
+            i += 1;
+        }
+
+        let driver = if let Some(d) = driver {
+            gdal::DriverManager::get_driver_by_name(&d)?
+        } else {
+            DriverManager::get_output_driver_for_dataset_name(&file, DriverType::Vector)
+                .context("Could not detect Driver for filename, try providing `driver` argument.")?
+        };
+        prepare_output(&file)?;
+        let mut out_data = driver.create_vector_only(&file)?;
+
+        let fields: Vec<(String, (u32, Attr2FieldValue))> = attrs
+            .into_iter()
+            .map(|(k, v)| Ok((k, type_name_to_field(&v)?)))
+            .collect::<Result<_, String>>()
+            .map_err(nadi_core::anyhow::Error::msg)?;
+        let mut layer = out_data.create_layer(LayerOptions {
+            name: "nodes",
+            srs: out_srs.as_ref(),
+            ty: gdal_sys::OGRwkbGeometryType::wkbPoint,
+            ..Default::default()
+        })?;
+        let mut field_types: Vec<(&str, u32)> = vec![("name", OGRFieldType::OFTString)];
+        field_types.extend(fields.iter().map(|(k, v)| (k.as_str(), v.0)));
+        layer.create_defn_fields(&field_types)?;
+        let defn = Defn::from_layer(&layer);
+        for node in &nodes {
+            let n = node.lock();
+            let mut ft = Feature::new(&defn)?;
+            ft.set_geometry(reproject(node_geometry(&n, &geometry)?, &trans)?)?;
+            ft.set_field_string(0, n.name())?;
+            for (i, (k, (_, func))) in fields.iter().enumerate() {
+                if let Some(v) = n.attr(k) {
+                    ft.set_field(i + 1, &func(v))?;
+                }
+            }
+            ft.create(&mut layer)?;
+        }
+
+        let mut layer = out_data.create_layer(LayerOptions {
+            name: "edges",
+            srs: out_srs.as_ref(),
+            ty: gdal_sys::OGRwkbGeometryType::wkbLineString,
+            ..Default::default()
+        })?;
+        layer.create_defn_fields(&[
+            ("start", OGRFieldType::OFTString),
+            ("end", OGRFieldType::OFTString),
+        ])?;
+        let defn = Defn::from_layer(&layer);
+        // the outlet's edge is outside the subnetwork
+        for node in &nodes[1..] {
+            let n = node.lock();
+            let (RSome(out), Some(pts)) = (n.output(), edge_points(&n, &geometry, &edge_geometry)?)
+            else {
+                continue;
+            };
+            let mut line = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbLineString)?;
+            for (x, y) in pts {
+                line.add_point_2d((x, y));
+            }
+            let mut ft = Feature::new(&defn)?;
+            ft.set_geometry(reproject(line, &trans)?)?;
+            ft.set_field_string(0, n.name())?;
+            ft.set_field_string(1, out.lock().name())?;
+            ft.create(&mut layer)?;
+        }
+        Ok(())
+    }
+
+    /// Save the network as a spatial stream network (.ssn folder)
+    ///
+    /// The folder has the `edges`, `sites` and `preds` shapefiles and
+    /// the `netID{n}.dat` tables of the binary ids, as read by the
+    /// SSN/SSN2 R packages. Each node with an output is a site at the
+    /// upstream end of its edge (ratio 1), and the outlet is at the
+    /// downstream end of the edge of its first input (ratio 0). SSN
+    /// expects at most two inputs at each node. The nodes in
+    /// `predictions` are saved as the prediction points.
+    #[network_func(
+        attrs = HashMap::new(),
+        geometry = "GEOM",
+        edge_geometry = "EDGE_GEOM",
+        geodesic = false
+    )]
+    fn gis_save_ssn(
+        net: &Network,
+        /// Output .ssn directory
+        dir: PathBuf,
+        /// Attributes to save with their types (String, Integer, Float, ...)
+        attrs: HashMap<String, String>,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Attribute with the edge geometry (WKT)
+        edge_geometry: String,
+        /// Calculate geodesic lengths in meters for lat/lon geometries
+        geodesic: bool,
+        /// Nodes to save as the prediction points instead of sites
+        predictions: Option<Vec<bool>>,
+        /// Spatial reference of the geometries
+        srs: Option<String>,
+    ) -> Result<()> {
+        let (out_srs, _) = output_srs(srs, None)?;
+        let nodes: Vec<Node> = net.nodes().cloned().collect();
+
+        struct SsnEdge {
+            rid: i64,
+            net_id: i64,
+            binary_id: String,
+            up_dist: f64,
+            length: f64,
+            points: Vec<(f64, f64)>,
+        }
+        let mut edges: HashMap<String, SsnEdge> = HashMap::new();
+        let mut stack: Vec<(Node, i64, String, f64)> = Vec::new();
+        let mut net_id = 0;
+        for node in nodes.iter().filter(|n| n.lock().output().is_none()) {
+            for inp in node.lock().inputs() {
+                net_id += 1;
+                stack.push((inp.clone(), net_id, "1".to_string(), 0.0));
+            }
+        }
+        while let Some((node, nid, bid, down)) = stack.pop() {
+            let n = node.lock();
+            let Some(pts) = edge_points(&n, &geometry, &edge_geometry)? else {
+                continue;
+            };
+            let length = line_length(&pts, geodesic);
+            let inputs = n.inputs();
+            if inputs.len() > 2 {
+                warning!(
+                    "WARN Node {} has {} inputs, SSN expects at most 2",
+                    n.name(),
+                    inputs.len()
+                );
+            }
+            for (i, inp) in inputs.iter().enumerate() {
+                stack.push((inp.clone(), nid, format!("{bid}{i}"), down + length));
+            }
+            let edge = SsnEdge {
+                rid: edges.len() as i64,
+                net_id: nid,
+                binary_id: bid,
+                up_dist: down + length,
+                length,
+                points: pts,
+            };
+            edges.insert(n.name().to_string(), edge);
+        }
+
+        std::fs::create_dir_all(&dir)?;
+        let driver = DriverManager::get_driver_by_name("ESRI Shapefile")?;
+        let mut out_data = driver.create_vector_only(dir.join("edges.shp"))?;
+        let mut layer = out_data.create_layer(LayerOptions {
+            name: "edges",
+            srs: out_srs.as_ref(),
+            ty: gdal_sys::OGRwkbGeometryType::wkbLineString,
+            ..Default::default()
+        })?;
+        layer.create_defn_fields(&[
+            ("rid", OGRFieldType::OFTInteger64),
+            ("netID", OGRFieldType::OFTInteger64),
+            ("upDist", OGRFieldType::OFTReal),
+            ("Length", OGRFieldType::OFTReal),
+            ("name", OGRFieldType::OFTString),
+        ])?;
+        let defn = Defn::from_layer(&layer);
+        let mut sorted: Vec<(&String, &SsnEdge)> = edges.iter().collect();
+        sorted.sort_by_key(|e| e.1.rid);
+        for (name, e) in &sorted {
+            let mut line = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbLineString)?;
+            for (x, y) in &e.points {
+                line.add_point_2d((*x, *y));
+            }
+            let mut ft = Feature::new(&defn)?;
+            ft.set_geometry(line)?;
+            ft.set_field_integer64(0, e.rid)?;
+            ft.set_field_integer64(1, e.net_id)?;
+            ft.set_field_double(2, e.up_dist)?;
+            ft.set_field_double(3, e.length)?;
+            ft.set_field_string(4, name)?;
+            ft.create(&mut layer)?;
+        }
+        for id in 1..=net_id {
+            let mut dat = String::from("\"rid\",\"binaryID\"\n");
+            for (_, e) in sorted.iter().filter(|e| e.1.net_id == id) {
+                dat.push_str(&format!("{},\"{}\"\n", e.rid, e.binary_id));
+            }
+            std::fs::write(dir.join(format!("netID{id}.dat")), dat)?;
+        }
+
+        let fields: Vec<(String, (u32, Attr2FieldValue))> = attrs
+            .into_iter()
+            .map(|(k, v)| Ok((k, type_name_to_field(&v)?)))
+            .collect::<Result<_, String>>()
+            .map_err(nadi_core::anyhow::Error::msg)?;
+        let mut field_types: Vec<(&str, u32)> = vec![
+            ("pid", OGRFieldType::OFTInteger64),
+            ("locID", OGRFieldType::OFTInteger64),
+            ("netID", OGRFieldType::OFTInteger64),
+            ("rid", OGRFieldType::OFTInteger64),
+            ("ratio", OGRFieldType::OFTReal),
+            ("upDist", OGRFieldType::OFTReal),
+            ("name", OGRFieldType::OFTString),
+        ];
+        field_types.extend(fields.iter().map(|(k, v)| (k.as_str(), v.0)));
+        let predictions = predictions.unwrap_or_default();
+        let layers: &[(&str, bool)] = if predictions.iter().any(|p| *p) {
+            &[("sites", false), ("preds", true)]
+        } else {
+            &[("sites", false)]
+        };
+        let mut pid = 0;
+        for (lyr_name, preds) in layers {
+            let mut out_data = driver.create_vector_only(dir.join(format!("{lyr_name}.shp")))?;
+            let mut layer = out_data.create_layer(LayerOptions {
+                name: lyr_name,
+                srs: out_srs.as_ref(),
+                ty: gdal_sys::OGRwkbGeometryType::wkbPoint,
+                ..Default::default()
+            })?;
+            layer.create_defn_fields(&field_types)?;
+            let defn = Defn::from_layer(&layer);
+            for (i, node) in nodes.iter().enumerate() {
+                if predictions.get(i).copied().unwrap_or(false) != *preds {
+                    continue;
+                }
+                let n = node.lock();
+                // the outlet is at the bottom of its first input's edge
+                let edge = match n.inputs().first() {
+                    Some(inp) if n.output().is_none() => edges
+                        .get(inp.lock().name())
+                        .map(|e| (e, 0.0, e.up_dist - e.length)),
+                    _ => edges.get(n.name()).map(|e| (e, 1.0, e.up_dist)),
+                };
+                let Some((e, ratio, updist)) = edge else {
+                    continue;
+                };
+                pid += 1;
+                let mut ft = Feature::new(&defn)?;
+                ft.set_geometry(node_geometry(&n, &geometry)?)?;
+                ft.set_field_integer64(0, pid)?;
+                ft.set_field_integer64(1, pid)?;
+                ft.set_field_integer64(2, e.net_id)?;
+                ft.set_field_integer64(3, e.rid)?;
+                ft.set_field_double(4, ratio)?;
+                ft.set_field_double(5, updist)?;
+                ft.set_field_string(6, n.name())?;
+                for (j, (k, (_, func))) in fields.iter().enumerate() {
+                    if let Some(v) = n.attr(k) {
+                        ft.set_field(j + 7, &func(v))?;
+                    }
+                }
+                ft.create(&mut layer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Generate the map and attributes of each node for the reports
+    ///
+    /// For each node (or the ones in the filter), an SVG map of its
+    /// upstream network is saved as `{node}.svg` in the output
+    /// directory, showing the basin (if given), the edges and the
+    /// upstream nodes with the node highlighted. The given attributes
+    /// of the nodes are saved in `nodes.csv` in the same directory.
+    #[network_func(
+        attrs = Vec::new(),
+        basin_field = "name",
+        size = 400.0,
+        labels = true,
+        geometry = "GEOM",
+        edge_geometry = "EDGE_GEOM"
+    )]
+    fn gis_node_maps(
+        net: &Network,
+        /// Directory to save the maps and the CSV in
+        output_dir: PathBuf,
+        /// Attributes to save in the CSV
+        attrs: Vec<String>,
+        /// GIS file with the basin polygons of the nodes
+        basins: Option<PathBuf>,
+        /// Field in the basins file with the node name
+        basin_field: String,
+        /// Size of the maps in pixels
+        size: f64,
+        /// Label the upstream nodes with their names
+        labels: bool,
+        /// Only generate for these nodes
+        filter: Option<Vec<bool>>,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Attribute with the edge geometry (WKT)
+        edge_geometry: String,
+    ) -> Result<()> {
+        std::fs::create_dir_all(&output_dir)?;
+        let mut basin_rings: HashMap<String, Vec<Vec<(f64, f64)>>> = HashMap::new();
+        if let Some(file) = basins {
+            let data = Dataset::open(file)?;
+            let mut lyr = open_layer(&data, None)?;
+            let fid = Defn::from_layer(&lyr).field_index(&basin_field)?;
+            for f in lyr.features() {
+                if let (Some(name), Some(g)) = (f.field_as_string(fid)?, f.geometry()) {
+                    basin_rings
+                        .entry(name)
+                        .or_default()
+                        .extend(polygon_rings(g));
+                }
+            }
+        }
+        let nodes: Vec<&Node> = if let Some(filt) = filter {
+            net.nodes()
+                .zip(filt)
+                .filter(|(_, f)| *f)
+                .map(|n| n.0)
+                .collect()
+        } else {
+            net.nodes().collect()
+        };
+
+        let mut csv = String::from("name");
+        for a in &attrs {
+            csv.push(',');
+            csv.push_str(&csv_quote(a));
+        }
+        csv.push('\n');
+        for node in nodes {
+            let mut upstream = vec![node.clone()];
+            let mut i = 0;
+            while i < upstream.len() {
+                let inputs = upstream[i].lock().inputs().to_vec();
+                upstream.extend(inputs);
+                i += 1;
+            }
+            let mut points = Vec::with_capacity(upstream.len());
+            let mut lines = Vec::with_capacity(upstream.len());
+            for (i, up) in upstream.iter().enumerate() {
+                let n = up.lock();
+                let (x, y, _) = node_geometry(&n, &geometry)?.get_point(0);
+                points.push((n.name().to_string(), (x, y)));
+                // the node's own edge is outside its upstream network
+                if i > 0 {
+                    if let Some(pts) = edge_points(&n, &geometry, &edge_geometry)? {
+                        lines.push(pts);
+                    }
+                }
+            }
+            let name = node.lock().name().to_string();
+            let rings = basin_rings.get(&name);
+            let all = points
+                .iter()
+                .map(|p| p.1)
+                .chain(lines.iter().flatten().copied())
+                .chain(rings.into_iter().flatten().flatten().copied());
+            let (min, max) = all.fold(
+                (
+                    (f64::INFINITY, f64::INFINITY),
+                    (f64::NEG_INFINITY, f64::NEG_INFINITY),
+                ),
+                |(mn, mx), (x, y)| ((mn.0.min(x), mn.1.min(y)), (mx.0.max(x), mx.1.max(y))),
+            );
+            let mut map = SvgMap::new(size, min, max);
+            for r in rings.into_iter().flatten() {
+                map.polygon(r, "lightgray", "gray");
+            }
+            for l in &lines {
+                map.polyline(l, "steelblue", 1.5);
+            }
+            for (i, (n, pt)) in points.iter().enumerate().rev() {
+                if i == 0 {
+                    map.circle(*pt, 5.0, "red", Some(n));
+                } else {
+                    map.circle(*pt, 3.0, "black", labels.then_some(n.as_str()));
+                }
+            }
+            std::fs::write(output_dir.join(format!("{name}.svg")), map.finish())?;
+
+            let n = node.lock();
+            csv.push_str(&csv_quote(&name));
+            for a in &attrs {
+                csv.push(',');
+                if let Some(v) = n.attr(a) {
+                    csv.push_str(&csv_quote(&v.to_string()));
+                }
+            }
+            csv.push('\n');
+        }
+        std::fs::write(output_dir.join("nodes.csv"), csv)?;
+        Ok(())
+    }
+
+    /// Anonymize the node locations for sharing the network
+    ///
+    /// Node points are moved randomly within the `jitter` distance,
+    /// and/or their coordinates rounded to the given `decimals`; the
+    /// `strip` attributes are removed. The connections are not
+    /// changed, so the topology stays intact. Edge geometries are
+    /// rounded the same way, but removed when jittering since they
+    /// would show the original locations.
+    ///
+    /// The offsets are derived from the `seed` and the node names, so
+    /// anyone with the seed can recover the original locations: keep
+    /// it secret, or leave it out to use a random seed from the OS.
+    /// The offsets are reproducible with the same `seed`.
+    #[network_func(
+        strip = Vec::new(),
+        geometry = "GEOM",
+        edge_geometry = "EDGE_GEOM"
+    )]
+    fn gis_anonymize(
+        net: &mut Network,
+        /// Maximum distance to move the points by, in geometry units
+        jitter: Option<f64>,
+        /// Number of decimals to round the coordinates to
+        decimals: Option<i64>,
+        /// Attributes to remove from the nodes
+        strip: Vec<String>,
+        /// Secret seed for the random offsets [default: random]
+        seed: Option<i64>,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Attribute with the edge geometry (WKT)
+        edge_geometry: String,
+    ) -> Result<()> {
+        let round = |v: f64| match decimals {
+            Some(d) => {
+                let f = 10f64.powi(d as i32);
+                (v * f).round() / f
+            }
+            None => v,
+        };
+        let seed = match seed {
+            Some(s) => s as u64,
+            // RandomState keys are drawn from the OS randomness
+            None => RandomState::new().build_hasher().finish(),
+        };
+        invalidate_index();
+        for node in net.nodes() {
+            let mut n = node.lock();
+            for k in &strip {
+                n.attr_map_mut().remove(k.as_str());
+            }
+            if n.attr(&geometry).is_some() {
+                let (mut x, mut y, _) = node_geometry(&n, &geometry)?.get_point(0);
+                if let Some(j) = jitter {
+                    let mut state = fnv1a(seed, n.name().as_bytes());
+                    // uniform in the circle: sqrt of the radius fraction
+                    let r = j * splitmix64(&mut state).sqrt();
+                    let a = std::f64::consts::TAU * splitmix64(&mut state);
+                    x += r * a.cos();
+                    y += r * a.sin();
+                }
+                let mut pt = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbPoint)?;
+                pt.add_point_2d((round(x), round(y)));
+                n.set_attr(&geometry, Attribute::String(pt.wkt()?.into()));
+            }
+            if jitter.is_some() {
+                n.attr_map_mut().remove(edge_geometry.as_str());
+            } else if decimals.is_some() && n.attr(&edge_geometry).is_some() {
+                let mut line = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbLineString)?;
+                for (x, y) in line_points(&node_geometry(&n, &edge_geometry)?) {
+                    line.add_point_2d((round(x), round(y)));
+                }
+                n.set_attr(&edge_geometry, Attribute::String(line.wkt()?.into()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Save GIS file of the connections
+    ///
+    /// The connections are straight lines between the nodes, or the
+    /// path along the streams if the streams file is given (falls back
+    /// to straight lines for the nodes not connected in the streams).
+    /// The `attrs` of the upstream and downstream nodes of each
+    /// connection are saved with `inp_` and `out_` prefixes.
+    #[network_func(attrs = HashMap::new(), layer = "network", reverse = false)]
+    fn gis_save_connections(
+        net: &Network,
+        file: PathBuf,
+        geometry: String,
+        /// Node attributes to save, with their types
+        attrs: HashMap<String, String>,
+        driver: Option<String>,
+        layer: String,
+        filter: Option<Vec<bool>>,
+        /// Streams GIS file to trace the connections along
+        streams: Option<PathBuf>,
+        /// layer of the streams file, first one picked by default
+        streams_layer: Option<String>,
+        /// Streams are digitized from downstream to upstream
+        reverse: bool,
+        /// Spatial reference of the geometries
+        srs: Option<String>,
+        /// Spatial reference to reproject the geometries to before
+        /// saving (EPSG code or PROJ string)
+        target_srs: Option<String>,
+        /// Encoding to write the text fields in (e.g. ISO-8859-1,
+        /// CP932), for the shapefile DBFs
+        encoding: Option<String>,
+    ) -> Result<()> {
+        let (out_srs, trans) = output_srs(srs, target_srs)?;
+        let graph = match streams {
+            Some(s) => {
+                let data = Dataset::open(s)?;
+                let mut lyr = open_layer(&data, streams_layer)?;
+                let lines = lyr
+                    .features()
+                    .filter_map(|f| f.geometry().map(line_parts))
+                    .flatten()
+                    .collect();
+                Some(StreamGraph::new(lines, reverse))
+            }
+            None => None,
+        };
+        let driver = if let Some(d) = driver {
+            gdal::DriverManager::get_driver_by_name(&d)?
+        } else {
+            DriverManager::get_output_driver_for_dataset_name(&file, DriverType::Vector)
+                .context("Could not detect Driver for filename, try providing `driver` argument.")?
+        };
+
+        let encoding = encoding.map(|e| format!("ENCODING={e}"));
+        let layer_options: Vec<&str> = encoding.iter().map(String::as_str).collect();
+        prepare_output(&file)?;
+        // TODO if file already exists add the layer if possible
+        let mut out_data = driver.create_vector_only(&file)?;
+        in_transaction(&mut out_data, |out_data| {
+            let mut layer = out_data.create_layer(LayerOptions {
+                name: &layer,
+                srs: out_srs.as_ref(),
+                ty: gdal_sys::OGRwkbGeometryType::wkbLineString,
+                options: Some(&layer_options),
+                ..Default::default()
+            })?;
+            layer.create_defn_fields(&[
+                ("start", OGRFieldType::OFTString),
+                ("end", OGRFieldType::OFTString),
+            ])?;
+            let fields: Vec<(String, (u32, Attr2FieldValue))> = attrs
+                .into_iter()
+                .map(|(k, v)| Ok((k, type_name_to_field(&v)?)))
+                .collect::<Result<_, String>>()
+                .map_err(nadi_core::anyhow::Error::msg)?;
+            let field_names: Vec<(String, String)> = fields
+                .iter()
+                .map(|(k, _)| (format!("inp_{k}"), format!("out_{k}")))
+                .collect();
+            let field_types: Vec<(&str, u32)> = fields
+                .iter()
+                .zip(&field_names)
+                .flat_map(|((_, v), (i, o))| [(i.as_str(), v.0), (o.as_str(), v.0)])
+                .collect();
+            layer.create_defn_fields(&field_types)?;
+            let defn = Defn::from_layer(&layer);
+            let indices: Vec<(usize, usize)> = field_names
+                .iter()
+                .map(|(i, o)| Ok((defn.field_index(i)?, defn.field_index(o)?)))
+                .collect::<Result<_>>()?;
+            let nodes: Vec<&Node> = if let Some(filt) = filter {
+                net.nodes()
+                    .zip(filt)
+                    .filter(|(_, f)| *f)
+                    .map(|n| n.0)
+                    .collect()
+            } else {
+                net.nodes().collect()
+            };
+            for node in nodes {
+                let n = node.lock();
+                if let RSome(out) = n.output() {
+                    let start = String::try_from_attr(
+                        n.attr(&geometry)
+                            .context("Attribute for geometry not found")?,
+                    )
+                    .map_err(nadi_core::anyhow::Error::msg)?;
+                    let end = String::try_from_attr(
+                        out.lock()
+                            .attr(&geometry)
+                            .context("Attribute for geometry not found")?,
+                    )
+                    .map_err(nadi_core::anyhow::Error::msg)?;
+                    let start = Geometry::from_wkt(&start)?;
+                    let end = Geometry::from_wkt(&end)?;
+
+                    let mut edge_geometry =
+                        Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbLineString)?;
+                    // add all points from start, (so it can be linestring
+                    // instead of just point); and add end's first point
+                    // only if it's different from last point of start
+                    let (sx, sy, _) = start.get_point(0);
+                    let (ex, ey, _) = end.get_point(0);
+                    let path = graph.as_ref().and_then(|g| g.trace((sx, sy), (ex, ey)));
+                    if graph.is_some() && path.is_none() {
+                        warning!(
+                            "WARN {} not connected to its output along the streams",
+                            n.name()
+                        );
+                    }
+                    for pt in path.unwrap_or_else(|| vec![(sx, sy), (ex, ey)]) {
+                        edge_geometry.add_point_2d(pt);
+                    }
+                    let mut ft = Feature::new(&defn)?;
+                    ft.set_geometry(reproject(edge_geometry, &trans)?)?;
+                    ft.set_field_string(0, n.name())?;
+                    ft.set_field_string(1, out.lock().name())?;
+                    let o = out.lock();
+                    for ((k, (_, func)), (ii, oi)) in fields.iter().zip(&indices) {
+                        if let Some(v) = n.attr(k) {
+                            ft.set_field(*ii, &func(v))?;
+                        }
+                        if let Some(v) = o.attr(k) {
+                            ft.set_field(*oi, &func(v))?;
+                        }
+                    }
+                    ft.create(&mut layer)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Save GIS file of the nodes
+    ///
+    /// With `classify`, the class index (1 to `classes`) of the
+    /// numeric attribute is saved in the `{classify}_class` field, for
+    /// the clients that only support categorized styling. The class
+    /// breaks are either quantiles or equal intervals (`method`) of the
+    /// values in the saved nodes.
+    ///
+    /// With `infer`, all the attributes of the saved nodes are saved,
+    /// with the field types inferred from their values; the types in
+    /// `attrs` override the inferred ones.
+    ///
+    /// With `elevation`, the points are saved as 3D points with the
+    /// attribute as the Z coordinate, for the profile plots and 3D
+    /// viewers. Nodes without the attribute get zero elevation.
+    #[network_func(
+        attrs = HashMap::new(),
+        layer = "nodes",
+        infer = false,
+        classes = 5,
+        method = "quantile"
+    )]
+    fn gis_save_nodes(
+        net: &Network,
+        file: PathBuf,
+        geometry: String,
+        attrs: HashMap<String, String>,
+        driver: Option<String>,
+        layer: String,
+        filter: Option<Vec<bool>>,
+        /// Save all the attributes with the inferred field types
+        infer: bool,
+        /// TOML file mapping attribute names to output field names/types
+        schema: Option<PathBuf>,
+        /// Spatial reference of the geometries
+        srs: Option<String>,
+        /// Spatial reference to reproject the geometries to before
+        /// saving (EPSG code or PROJ string)
+        target_srs: Option<String>,
+        /// Numeric attribute to classify
+        classify: Option<String>,
+        /// Number of classes
+        classes: usize,
+        /// Classification method: quantile or equal
+        method: String,
+        /// Attribute with the elevation to save as Z coordinate
+        elevation: Option<String>,
+        /// Encoding to write the text fields in (e.g. ISO-8859-1,
+        /// CP932), for the shapefile DBFs
+        encoding: Option<String>,
+    ) -> Result<()> {
+        let (out_srs, trans) = output_srs(srs, target_srs)?;
+        let schema = schema
+            .map(Schema::from_file)
+            .transpose()?
+            .unwrap_or_default();
+        let driver = if let Some(d) = driver {
+            gdal::DriverManager::get_driver_by_name(&d)?
+        } else {
+            DriverManager::get_output_driver_for_dataset_name(&file, DriverType::Vector)
+                .context("Could not detect Driver for filename, try providing `driver` argument.")?
+        };
+
+        let encoding = encoding.map(|e| format!("ENCODING={e}"));
+        let layer_options: Vec<&str> = encoding.iter().map(String::as_str).collect();
+        prepare_output(&file)?;
+        // TODO if file already exists add the layer if possible
+        let mut out_data = driver.create_vector_only(&file)?;
+        in_transaction(&mut out_data, |out_data| {
+            let mut layer = out_data.create_layer(LayerOptions {
+                name: &layer,
+                srs: out_srs.as_ref(),
+                ty: if elevation.is_some() {
+                    gdal_sys::OGRwkbGeometryType::wkbPoint25D
+                } else {
+                    gdal_sys::OGRwkbGeometryType::wkbPoint
+                },
+                options: Some(&layer_options),
+                ..Default::default()
+            })?;
+            let nodes: Vec<&Node> = if let Some(filt) = filter {
+                net.nodes()
+                    .zip(filt)
+                    .filter(|(_, f)| *f)
+                    .map(|n| n.0)
+                    .collect()
+            } else {
+                net.nodes().collect()
+            };
+            let attrs = if infer {
+                let mut inferred = infer_field_types(&nodes, &geometry);
+                inferred.extend(attrs);
+                inferred
+            } else {
+                attrs
+            };
+            let fields: Vec<(String, (u32, Attr2FieldValue))> = attrs
+                .into_iter()
+                .map(|(k, v)| {
+                    let field = type_name_to_field(schema.field_type(&k).unwrap_or(&v))?;
+                    Ok((k, field))
+                })
+                .collect::<Result<_, String>>()
+                .map_err(nadi_core::anyhow::Error::msg)?;
+            let field_types: Vec<(&str, u32)> = fields
+                .iter()
+                .map(|(k, v)| (schema.rename(k), v.0))
+                .collect();
+            // saving shp means field names will be shortened, it'll error later, how do we fix it?
+            layer.create_defn_fields(&field_types)?;
+            let class_field = classify.as_ref().map(|c| format!("{c}_class"));
+            if let Some(c) = &class_field {
+                layer.create_defn_fields(&[(c, OGRFieldType::OFTInteger)])?;
+            }
+            let defn = Defn::from_layer(&layer);
+            let indices: HashMap<&str, usize> = fields
+                .iter()
+                .filter_map(|f| Some((f.0.as_str(), defn.field_index(schema.rename(&f.0)).ok()?)))
+                .collect();
+            let class_value = |n: &Node| -> Option<f64> {
+                let c = classify.as_ref()?;
+                f64::from_attr_relaxed(n.lock().attr(c)?)
+            };
+            let breaks = match &classify {
+                Some(_) => {
+                    let values: Vec<f64> = nodes.iter().filter_map(|n| class_value(n)).collect();
+                    class_breaks(values, classes, &method)?
+                }
+                None => vec![],
+            };
+            let class_idx = class_field
+                .as_ref()
+                .map(|c| defn.field_index(c))
+                .transpose()?;
+            for node in nodes {
+                let class = class_value(node).map(|v| class_of(v, &breaks));
+                let n = node.lock();
+                let node_geom = String::try_from_attr(
+                    n.attr(&geometry)
+                        .context("Attribute for geometry not found")?,
+                )
+                .map_err(nadi_core::anyhow::Error::msg)?;
+                let node_geom = Geometry::from_wkt(&node_geom)?;
+                let mut node_geom = reproject(node_geom, &trans)?;
+                if let Some(e) = &elevation {
+                    let z = n
+                        .attr(e)
+                        .and_then(f64::from_attr_relaxed)
+                        .unwrap_or_else(|| {
+                            warning!("WARN Node {} doesn't have elevation", n.name());
+                            0.0
+                        });
+                    let (x, y, _) = node_geom.get_point(0);
+                    node_geom = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbPoint25D)?;
+                    node_geom.add_point((x, y, z));
+                }
+                let mut ft = Feature::new(&defn)?;
+                ft.set_geometry(node_geom)?;
+                if let (Some(i), Some(c)) = (class_idx, class) {
+                    ft.set_field_integer(i, c)?;
+                }
+                fields
+                    .iter()
+                    .filter_map(|(k, (_, func))| Some((k.as_str(), func(n.attr(k)?))))
+                    .try_for_each(|(k, v)| ft.set_field(indices[k], &v))?;
+                ft.create(&mut layer)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Save GIS file of the basin polygons of the nodes
+    ///
+    /// The `basin` attribute of the nodes has their polygons. With
+    /// `dissolve`, the polygons are taken as the incremental
+    /// catchments, and each node gets the union of its polygon with
+    /// the ones of all the nodes upstream as its full watershed.
+    #[network_func(
+        basin = "BASIN",
+        attrs = HashMap::new(),
+        layer = "basins",
+        dissolve = false
+    )]
+    fn gis_save_basins(
+        net: &Network,
+        file: PathBuf,
+        /// Attribute with the basin polygon (WKT)
+        basin: String,
+        attrs: HashMap<String, String>,
+        driver: Option<String>,
+        layer: String,
+        filter: Option<Vec<bool>>,
+        /// Merge the upstream catchments into the full watershed
+        dissolve: bool,
+        /// Spatial reference of the geometries
+        srs: Option<String>,
+        /// Spatial reference to reproject the geometries to before
+        /// saving (EPSG code or PROJ string)
+        target_srs: Option<String>,
+        /// Encoding to write the text fields in (e.g. ISO-8859-1,
+        /// CP932), for the shapefile DBFs
+        encoding: Option<String>,
+    ) -> Result<()> {
+        let (out_srs, trans) = output_srs(srs, target_srs)?;
+        let basins = upstream_first(net, |n, basins: &HashMap<String, Option<Geometry>>| {
+            let mut geom = match n.attr(&basin) {
+                Some(_) => Some(node_geometry(n, &basin)?),
+                None => None,
+            };
+            if dissolve {
+                for i in n.inputs() {
+                    let Some(up) = &basins[i.lock().name()] else {
+                        continue;
+                    };
+                    geom = match geom {
+                        Some(g) => Some(g.union(up).context("Failed to dissolve the basins")?),
+                        None => Some(up.clone()),
+                    };
+                }
+            }
+            Ok(geom)
+        })?;
+
+        let driver = if let Some(d) = driver {
+            gdal::DriverManager::get_driver_by_name(&d)?
+        } else {
+            DriverManager::get_output_driver_for_dataset_name(&file, DriverType::Vector)
+                .context("Could not detect Driver for filename, try providing `driver` argument.")?
+        };
+        let encoding = encoding.map(|e| format!("ENCODING={e}"));
+        let layer_options: Vec<&str> = encoding.iter().map(String::as_str).collect();
+        prepare_output(&file)?;
+        let mut out_data = driver.create_vector_only(&file)?;
+        in_transaction(&mut out_data, |out_data| {
+            let mut layer = out_data.create_layer(LayerOptions {
+                name: &layer,
+                srs: out_srs.as_ref(),
+                ty: gdal_sys::OGRwkbGeometryType::wkbMultiPolygon,
+                options: Some(&layer_options),
+                ..Default::default()
+            })?;
+            let fields: Vec<(String, (u32, Attr2FieldValue))> = attrs
+                .into_iter()
+                .map(|(k, v)| Ok((k, type_name_to_field(&v)?)))
+                .collect::<Result<_, String>>()
+                .map_err(nadi_core::anyhow::Error::msg)?;
+            layer.create_defn_fields(&[("name", OGRFieldType::OFTString)])?;
+            let field_types: Vec<(&str, u32)> =
+                fields.iter().map(|(k, v)| (k.as_str(), v.0)).collect();
+            layer.create_defn_fields(&field_types)?;
+            let defn = Defn::from_layer(&layer);
+            let indices: HashMap<&str, usize> = fields
+                .iter()
+                .filter_map(|f| Some((f.0.as_str(), defn.field_index(&f.0).ok()?)))
+                .collect();
+            let nodes: Vec<&Node> = if let Some(filt) = filter {
+                net.nodes()
+                    .zip(filt)
+                    .filter(|(_, f)| *f)
+                    .map(|n| n.0)
+                    .collect()
+            } else {
+                net.nodes().collect()
+            };
+            for node in nodes {
+                let n = node.lock();
+                let Some(Some(geom)) = basins.remove(n.name()) else {
+                    warning!("WARN {} doesn't have a basin polygon", n.name());
+                    continue;
+                };
+                let geom = if geom.geometry_type() == gdal_sys::OGRwkbGeometryType::wkbPolygon {
+                    let mut multi = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbMultiPolygon)?;
+                    multi.add_geometry(geom)?;
+                    multi
+                } else {
+                    geom
+                };
+                let mut ft = Feature::new(&defn)?;
+                ft.set_geometry(reproject(geom, &trans)?)?;
+                ft.set_field_string(0, n.name())?;
+                fields
+                    .iter()
+                    .filter_map(|(k, (_, func))| Some((k.as_str(), func(n.attr(k)?))))
+                    .try_for_each(|(k, v)| ft.set_field(indices[k], &v))?;
+                ft.create(&mut layer)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Save the nodes and connections in a single GIS file
+    ///
+    /// Writes a point layer of the nodes with their `attrs` (or all
+    /// attributes with `infer`, see `gis_save_nodes`) and a line layer
+    /// of the connections from each node to its output, using the
+    /// edge geometry if available or a straight line. Both layers are
+    /// written in one transaction when the driver supports it (e.g.
+    /// GeoPackage), so the driver should support multiple layers.
+    #[network_func(
+        geometry = "GEOM",
+        edge_geometry = "EDGE_GEOM",
+        attrs = HashMap::new(),
+        infer = false,
+        nodes_layer = "nodes",
+        edges_layer = "network"
+    )]
+    fn gis_save_network(
+        net: &Network,
+        file: PathBuf,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Attribute with the edge geometry (WKT)
+        edge_geometry: String,
+        attrs: HashMap<String, String>,
+        /// Save all the attributes with the inferred field types
+        infer: bool,
+        driver: Option<String>,
+        /// Name of the layer with the nodes
+        nodes_layer: String,
+        /// Name of the layer with the connections
+        edges_layer: String,
+        filter: Option<Vec<bool>>,
+        /// Spatial reference of the geometries
+        srs: Option<String>,
+        /// Spatial reference to reproject the geometries to before
+        /// saving (EPSG code or PROJ string)
+        target_srs: Option<String>,
+        /// Encoding to write the text fields in (e.g. ISO-8859-1,
+        /// CP932), for the shapefile DBFs
+        encoding: Option<String>,
+    ) -> Result<()> {
+        let (out_srs, trans) = output_srs(srs, target_srs)?;
+        let driver = if let Some(d) = driver {
+            gdal::DriverManager::get_driver_by_name(&d)?
+        } else {
+            DriverManager::get_output_driver_for_dataset_name(&file, DriverType::Vector)
+                .context("Could not detect Driver for filename, try providing `driver` argument.")?
+        };
+        let nodes: Vec<&Node> = if let Some(filt) = filter {
+            net.nodes()
+                .zip(filt)
+                .filter(|(_, f)| *f)
+                .map(|n| n.0)
+                .collect()
+        } else {
+            net.nodes().collect()
+        };
+        let attrs = if infer {
+            let mut inferred = infer_field_types(&nodes, &geometry);
+            inferred.remove(&edge_geometry);
+            inferred.extend(attrs);
+            inferred
+        } else {
+            attrs
+        };
+        let fields: Vec<(String, (u32, Attr2FieldValue))> = attrs
+            .into_iter()
+            .map(|(k, v)| Ok((k, type_name_to_field(&v)?)))
+            .collect::<Result<_, String>>()
+            .map_err(nadi_core::anyhow::Error::msg)?;
+        let encoding = encoding.map(|e| format!("ENCODING={e}"));
+        let layer_options: Vec<&str> = encoding.iter().map(String::as_str).collect();
+        let output = NetworkOutput {
+            nodes: &nodes,
+            geometry: &geometry,
+            edge_geometry: &edge_geometry,
+            fields: &fields,
+            srs: out_srs.as_ref(),
+            trans: &trans,
+            options: &layer_options,
+        };
+
+        prepare_output(&file)?;
+        let mut out_data = driver.create_vector_only(&file)?;
+        in_transaction(&mut out_data, |data| {
+            output.write(data, &nodes_layer, &edges_layer)
+        })
+    }
+
+    /// Nodes and the options to write them with for `gis_save_network`
+    struct NetworkOutput<'a> {
+        nodes: &'a [&'a Node],
+        geometry: &'a str,
+        edge_geometry: &'a str,
+        fields: &'a [(String, (u32, Attr2FieldValue))],
+        srs: Option<&'a SpatialRef>,
+        trans: &'a Option<CoordTransform>,
+        options: &'a [&'a str],
+    }
+
+    impl NetworkOutput<'_> {
+        fn write(&self, data: &mut Dataset, nodes_layer: &str, edges_layer: &str) -> Result<()> {
+            let mut layer = data.create_layer(LayerOptions {
+                name: nodes_layer,
+                srs: self.srs,
+                ty: gdal_sys::OGRwkbGeometryType::wkbPoint,
+                options: Some(self.options),
+                ..Default::default()
+            })?;
+            layer.create_defn_fields(&[("name", OGRFieldType::OFTString)])?;
+            let field_types: Vec<(&str, u32)> =
+                self.fields.iter().map(|(k, v)| (k.as_str(), v.0)).collect();
+            layer.create_defn_fields(&field_types)?;
+            let defn = Defn::from_layer(&layer);
+            let indices: Vec<usize> = self
+                .fields
+                .iter()
+                .map(|(k, _)| defn.field_index(k))
+                .collect::<Result<_, _>>()?;
+            for node in self.nodes {
+                let n = node.lock();
+                let mut ft = Feature::new(&defn)?;
+                ft.set_geometry(reproject(node_geometry(&n, self.geometry)?, self.trans)?)?;
+                ft.set_field_string(0, n.name())?;
+                for ((k, (_, func)), i) in self.fields.iter().zip(&indices) {
+                    if let Some(v) = n.attr(k) {
+                        ft.set_field(*i, &func(v))?;
+                    }
+                }
+                ft.create(&mut layer)?;
+            }
+
+            let mut layer = data.create_layer(LayerOptions {
+                name: edges_layer,
+                srs: self.srs,
+                ty: gdal_sys::OGRwkbGeometryType::wkbLineString,
+                options: Some(self.options),
+                ..Default::default()
+            })?;
+            layer.create_defn_fields(&[
+                ("start", OGRFieldType::OFTString),
+                ("end", OGRFieldType::OFTString),
+            ])?;
+            let defn = Defn::from_layer(&layer);
+            for node in self.nodes {
+                let n = node.lock();
+                let RSome(out) = n.output() else {
+                    continue;
+                };
+                let Some(pts) = edge_points(&n, self.geometry, self.edge_geometry)? else {
+                    continue;
+                };
+                let mut edge = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbLineString)?;
+                for pt in pts {
+                    edge.add_point_2d(pt);
+                }
+                let mut ft = Feature::new(&defn)?;
+                ft.set_geometry(reproject(edge, self.trans)?)?;
+                ft.set_field_string(0, n.name())?;
+                ft.set_field_string(1, out.lock().name())?;
+                ft.create(&mut layer)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Next random number in `[0, 1)` from the SplitMix64 generator
+    /// FNV-1a hash of the seed and the bytes, stable across the Rust releases
+    fn fnv1a(seed: u64, bytes: &[u8]) -> u64 {
+        seed.to_le_bytes()
+            .iter()
+            .chain(bytes)
+            .fold(0xcbf2_9ce4_8422_2325, |h, b| {
+                (h ^ *b as u64).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+
+    fn splitmix64(state: &mut u64) -> f64 {
+        *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Open the GIS file, recoding the text fields from the encoding
+    ///
+    /// The encoding is passed as the `ENCODING` open option, which the
+    /// shapefile driver uses to recode the DBF fields to UTF-8.
+    fn open_dataset(file: &std::path::Path, encoding: Option<&str>) -> Result<Dataset> {
+        let Some(enc) = encoding else {
+            return Ok(Dataset::open(file)?);
+        };
+        let opt = format!("ENCODING={enc}");
+        Ok(Dataset::open_ex(
+            file,
+            DatasetOptions {
+                open_options: Some(&[&opt]),
+                ..Default::default()
+            },
+        )?)
+    }
+
+    /// Run the writes in a transaction if the dataset supports it
+    ///
+    /// Creating the features one by one without a transaction is
+    /// very slow for the formats like GeoPackage.
+    fn in_transaction<F>(data: &mut Dataset, write: F) -> Result<()>
+    where
+        F: FnOnce(&mut Dataset) -> Result<()>,
+    {
+        if let Ok(mut txn) = data.start_transaction() {
+            write(&mut txn)?;
+            txn.commit()?;
+            return Ok(());
+        }
+        write(data)
+    }
+
+    /// GDAL virtual file system prefixes for cloud object storages
+    const CLOUD_VSI: [&str; 6] = [
+        "/vsis3/",
+        "/vsigs/",
+        "/vsiaz/",
+        "/vsiadls/",
+        "/vsioss/",
+        "/vsiswift/",
+    ];
+
+    /// Prepare GDAL for writing on the given path
+    ///
+    /// Cloud object storages (`/vsis3/`, `/vsigs/`, ...) can't do
+    /// random writes, so GDAL has to write to a temporary local file
+    /// and upload it on close. Credentials are read by GDAL from the
+    /// environment variables (e.g. `AWS_PROFILE`).
+    fn prepare_output(file: &std::path::Path) -> Result<()> {
+        let path = file.to_string_lossy();
+        if CLOUD_VSI.iter().any(|p| path.starts_with(p)) {
+            gdal::config::set_config_option("CPL_VSIL_USE_TEMP_FILE_FOR_RANDOM_WRITE", "YES")?;
+        }
+        Ok(())
+    }
+
+    /// Geodesic distance in meters between two points in WGS84
+    ///
+    /// Uses Vincenty's formula on the WGS84 ellipsoid.
+    #[env_func]
+    fn gis_distance_m(
+        /// Latitude of the first point
+        lat1: f64,
+        /// Longitude of the first point
+        lon1: f64,
+        /// Latitude of the second point
+        lat2: f64,
+        /// Longitude of the second point
+        lon2: f64,
+    ) -> f64 {
+        geodesy::vincenty(lat1, lon1, lat2, lon2)
+    }
+
+    /// Distance between two geometries in the given projection
+    ///
+    /// The geometries are reprojected from `srs` to the `epsg`
+    /// projection and the minimum distance between them is calculated
+    /// in the projection units.
+    #[env_func(srs = "EPSG:4326")]
+    fn gis_distance_proj(
+        /// First geometry (WKT)
+        wkt1: String,
+        /// Second geometry (WKT)
+        wkt2: String,
+        /// EPSG code of the projection to calculate the distance in
+        epsg: i64,
+        /// Spatial reference of the geometries
+        srs: String,
+    ) -> Result<f64> {
+        let srs = spatial_ref(&srs)?;
+        let proj = spatial_ref(&format!("EPSG:{epsg}"))?;
+        let mut g1 = Geometry::from_wkt(&wkt1)?;
+        let mut g2 = Geometry::from_wkt(&wkt2)?;
+        g1.set_spatial_ref(srs.clone());
+        g2.set_spatial_ref(srs);
+        let g1 = g1.transform_to(&proj)?;
+        let g2 = g2.transform_to(&proj)?;
+        Ok(unsafe { gdal_sys::OGR_G_Distance(g1.c_geometry(), g2.c_geometry()) })
+    }
+
+    /// Convert the geometry to WKT
+    ///
+    /// The geometry can be WKT, GeoJSON or hex-encoded WKB.
+    #[env_func]
+    fn gis_to_wkt(
+        /// Geometry to convert
+        geometry: String,
+    ) -> Result<String> {
+        Ok(parse_geometry(&geometry)?.wkt()?)
+    }
+
+    /// Convert the geometry to GeoJSON
+    ///
+    /// The geometry can be WKT, GeoJSON or hex-encoded WKB.
+    #[env_func]
+    fn gis_to_geojson(
+        /// Geometry to convert
+        geometry: String,
+    ) -> Result<String> {
+        Ok(parse_geometry(&geometry)?.json()?)
+    }
+
+    /// Convert the geometry to hex-encoded WKB
+    ///
+    /// The geometry can be WKT, GeoJSON or hex-encoded WKB.
+    #[env_func]
+    fn gis_to_wkb(
+        /// Geometry to convert
+        geometry: String,
+    ) -> Result<String> {
+        let wkb = parse_geometry(&geometry)?.wkb()?;
+        Ok(wkb.iter().map(|b| format!("{b:02X}")).collect())
+    }
+
+    /// Buffer polygon (WKT) around the geometry
+    ///
+    /// Distance is in the geometry units, or in the `unit` (m, km, ft,
+    /// mi, deg) converted approximately for the spatial reference
+    /// `srs` of the geometry.
+    #[env_func(segments = 30)]
+    fn gis_buffer(
+        /// Geometry (WKT, GeoJSON or hex-encoded WKB)
+        geometry: String,
+        /// Buffer distance, negative to shrink the polygons
+        distance: f64,
+        /// Unit of the distance
+        unit: Option<String>,
+        /// Spatial reference of the geometry
+        srs: Option<String>,
+        /// Number of segments for a quarter circle
+        segments: i64,
+    ) -> Result<String> {
+        let srs = srs.as_deref().map(spatial_ref).transpose()?;
+        let distance = distance_in_units(distance, unit.as_deref(), srs.as_ref())?;
+        let geom = parse_geometry(&geometry)?;
+        Ok(geom.buffer(distance, segments as u32)?.wkt()?)
+    }
+
+    /// Centroid of the geometry as point WKT
+    ///
+    /// The centroid of a concave polygon can be outside of it, use
+    /// `inside` to get a point guaranteed to be on the polygon
+    /// (e.g. for labels) instead.
+    #[env_func(inside = false)]
+    fn gis_centroid(
+        /// Geometry (WKT, GeoJSON or hex-encoded WKB)
+        geometry: String,
+        /// Get a point on the surface of the geometry
+        inside: bool,
+    ) -> Result<String> {
+        let geom = parse_geometry(&geometry)?;
+        let (x, y) = unsafe {
+            if inside {
+                let pt = gdal_sys::OGR_G_PointOnSurface(geom.c_geometry());
+                if pt.is_null() {
+                    return Err(nadi_core::anyhow::Error::msg(
+                        "Couldn't get a point on the surface of the geometry",
+                    ));
+                }
+                let xy = (gdal_sys::OGR_G_GetX(pt, 0), gdal_sys::OGR_G_GetY(pt, 0));
+                gdal_sys::OGR_G_DestroyGeometry(pt);
+                xy
+            } else {
+                let pt = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbPoint)?;
+                let err = gdal_sys::OGR_G_Centroid(geom.c_geometry(), pt.c_geometry());
+                if err != gdal_sys::OGRErr::OGRERR_NONE {
+                    return Err(nadi_core::anyhow::Error::msg(
+                        "Couldn't calculate the centroid of the geometry",
+                    ));
+                }
+                let (x, y, _) = pt.get_point(0);
+                (x, y)
+            }
+        };
+        Ok(format!("POINT ({x} {y})"))
+    }
+
+    /// Geometry from WKT, GeoJSON or hex-encoded WKB
+    fn parse_geometry(text: &str) -> Result<Geometry> {
+        let text = text.trim();
+        if text.starts_with('{') {
+            return Ok(Geometry::from_geojson(text)?);
+        }
+        let is_hex = text.len() % 2 == 0 && text.bytes().all(|b| b.is_ascii_hexdigit());
+        if !text.is_empty() && is_hex {
+            let wkb = (0..text.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&text[i..i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()?;
+            return Ok(Geometry::from_wkb(&wkb)?);
+        }
+        Ok(Geometry::from_wkt(text)?)
+    }
+
+    /// Geodesic distance in meters between the points of two nodes
+    ///
+    /// The node geometries are taken as longitude/latitude, or
+    /// reprojected from `srs` if given.
+    #[network_func(geometry = "GEOM")]
+    fn gis_node_distance(
+        net: &Network,
+        /// Name of the first node
+        node1: String,
+        /// Name of the second node
+        node2: String,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Spatial reference of the geometries
+        srs: Option<String>,
+    ) -> Result<f64> {
+        let trans = wgs84_transform(srs)?;
+        let n1 = net.node_by_name(&node1).context("Node not found")?;
+        let n2 = net.node_by_name(&node2).context("Node not found")?;
+        let (lat1, lon1) = node_latlon(&n1.lock(), &geometry, &trans)?;
+        let (lat2, lon2) = node_latlon(&n2.lock(), &geometry, &trans)?;
+        Ok(geodesy::vincenty(lat1, lon1, lat2, lon2))
+    }
+
+    /// Save the geodesic distance in meters from each node to its output
+    ///
+    /// Straight line distance between the node points, the outlet
+    /// doesn't get the attribute.
+    #[network_func(geometry = "GEOM", attr = "output_distance")]
+    fn gis_output_distance(
+        net: &mut Network,
+        /// Attribute with the node geometry (WKT)
+        geometry: String,
+        /// Spatial reference of the geometries
+        srs: Option<String>,
+        /// Attribute to save the distance in
+        attr: String,
+    ) -> Result<()> {
+        let trans = wgs84_transform(srs)?;
+        for node in net.nodes() {
+            let mut n = node.lock();
+            let (lat2, lon2) = match n.output() {
+                RSome(out) => node_latlon(&out.lock(), &geometry, &trans)?,
+                _ => continue,
+            };
+            let (lat1, lon1) = node_latlon(&n, &geometry, &trans)?;
+            n.set_attr(
+                &attr,
+                Attribute::Float(geodesy::vincenty(lat1, lon1, lat2, lon2)),
+            );
+        }
+        Ok(())
+    }
+
+    /// Transformation from `srs` to longitude/latitude (WGS84)
+    fn wgs84_transform(srs: Option<String>) -> Result<Option<CoordTransform>> {
+        match srs {
+            Some(s) => Ok(Some(CoordTransform::new(
+                &spatial_ref(&s)?,
+                &spatial_ref("EPSG:4326")?,
+            )?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Latitude and longitude of the node point
+    fn node_latlon(
+        node: &NodeInner,
+        geometry: &str,
+        trans: &Option<CoordTransform>,
+    ) -> Result<(f64, f64)> {
+        let geom = reproject(node_geometry(node, geometry)?, trans)?;
+        let (lon, lat, _) = geom.get_point(0);
+        Ok((lat, lon))
+    }
+
+    /// Versions of the plugin, GDAL and PROJ, and the enabled features
+    ///
+    /// Use it to check for the minimum capabilities before running a
+    /// long workflow.
+    #[env_func]
+    fn gis_version() -> Attribute {
+        let (mut major, mut minor, mut patch) = (0, 0, 0);
+        unsafe { gdal_sys::OSRGetPROJVersion(&mut major, &mut minor, &mut patch) };
+        let features: Vec<&str> = [("bindgen", cfg!(feature = "bindgen"))]
+            .into_iter()
+            .filter_map(|(f, enabled)| enabled.then_some(f))
+            .collect();
+        let mut map = AttrMap::new();
+        let mut set = |k: &str, v: Attribute| map.insert(RString::from(k), v);
+        set(
+            "version",
+            Attribute::String(env!("CARGO_PKG_VERSION").into()),
+        );
+        set(
+            "gdal",
+            Attribute::String(gdal::version::version_info("RELEASE_NAME").into()),
+        );
+        set(
+            "proj",
+            Attribute::String(format!("{major}.{minor}.{patch}").into()),
+        );
+        set(
+            "features",
+            Attribute::Array(
+                features
+                    .into_iter()
+                    .map(|f| Attribute::String(f.into()))
+                    .collect(),
+            ),
+        );
+        Attribute::Table(map)
+    }
+
+    /// Spatial reference of a layer (or raster) as EPSG code, WKT or PROJ string
+    ///
+    /// Use it to check the points and streams files are in the same
+    /// spatial reference before loading the network or snapping.
+    #[env_func(format = "wkt")]
+    fn gis_spatial_ref(
+        /// GIS file
+        file: PathBuf,
+        /// layer of the GIS file, first one picked by default
+        layer: Option<String>,
+        /// Output format: epsg, wkt or proj
+        format: String,
+    ) -> Result<Attribute> {
+        let data = Dataset::open(&file)?;
+        let srs = if data.layer_count() > 0 {
+            open_layer(&data, layer)?.spatial_ref()
+        } else {
+            data.spatial_ref().ok()
+        };
+        let mut srs = srs.with_context(|| format!("No spatial reference in {file:?}"))?;
+        Ok(match format.to_lowercase().as_str() {
+            "epsg" => {
+                // files like shapefile .prj don't have the code in them
+                srs.auto_identify_epsg().ok();
+                let code = srs
+                    .auth_code()
+                    .context("Spatial reference doesn't have an EPSG code")?;
+                Attribute::Integer(code as i64)
+            }
+            "wkt" => Attribute::String(srs.to_wkt()?.into()),
+            "proj" => Attribute::String(srs.to_proj4()?.into()),
+            f => {
+                return Err(nadi_core::anyhow::Error::msg(format!(
+                    "Unknown format {f}, use epsg, wkt or proj"
+                )))
+            }
+        })
+    }
+
+    /// Number of features in a layer matching the filters
+    ///
+    /// Counts the features without loading them, e.g. the gauges
+    /// inside a basin with the basin polygon as `geometry`.
+    #[env_func]
+    fn gis_count_where(
+        /// GIS file
+        file: PathBuf,
+        /// layer of the GIS file, first one picked by default
+        layer: Option<String>,
+        /// OGR SQL WHERE clause to filter the features
+        where_clause: Option<String>,
+        /// Bounding box `[xmin, ymin, xmax, ymax]` the features intersect
+        bbox: Option<Vec<f64>>,
+        /// Geometry (WKT) the features intersect, in the layer's spatial reference
+        geometry: Option<String>,
+    ) -> Result<i64> {
+        let data = Dataset::open(file)?;
+        let mut lyr = open_layer(&data, layer)?;
+        if let Some(w) = &where_clause {
+            lyr.set_attribute_filter(w)?;
+        }
+        match (bbox.as_deref(), geometry) {
+            (Some(_), Some(_)) => {
+                return Err(nadi_core::anyhow::Error::msg(
+                    "Only one of bbox and geometry can be given",
+                ))
+            }
+            (Some(&[xmin, ymin, xmax, ymax]), None) => {
+                lyr.set_spatial_filter_rect(xmin, ymin, xmax, ymax)
+            }
+            (Some(_), None) => {
+                return Err(nadi_core::anyhow::Error::msg(
+                    "bbox should be [xmin, ymin, xmax, ymax]",
+                ))
+            }
+            (None, Some(wkt)) => lyr.set_spatial_filter(&Geometry::from_wkt(&wkt)?),
+            (None, None) => (),
+        }
+        Ok(lyr.feature_count() as i64)
+    }
+
+    /// Distinct values of a field in a layer
+    ///
+    /// The values are in the order they are first seen, use it to find
+    /// the valid categories (stream types, HUC codes) for the filters.
+    #[env_func]
+    fn gis_field_unique(
+        /// GIS file
+        file: PathBuf,
+        /// layer of the GIS file, first one picked by default
+        layer: Option<String>,
+        /// Field name
+        field: String,
+    ) -> Result<Attribute> {
+        let data = Dataset::open(file)?;
+        let mut lyr = open_layer(&data, layer)?;
+        let idx = lyr.defn().field_index(&field)?;
+        let mut seen = HashSet::new();
+        let mut values = RVec::new();
+        for f in lyr.features() {
+            let Some(v) = f.field(idx)?.and_then(gis_value_to_attr) else {
+                continue;
+            };
+            if seen.insert(v.to_string()) {
+                values.push(v);
+            }
+        }
+        Ok(Attribute::Array(values))
+    }
+
+    /// Bounding box of a layer as `[xmin, ymin, xmax, ymax]`
+    ///
+    /// The extent is in the spatial reference of the layer, useful to
+    /// get the bounding box for downloads and clipping.
+    #[env_func]
+    fn gis_extent(
+        /// GIS file
+        file: PathBuf,
+        /// layer of the GIS file, first one picked by default
+        layer: Option<String>,
+    ) -> Result<Attribute> {
+        let data = Dataset::open(file)?;
+        let lyr = open_layer(&data, layer)?;
+        let env = lyr.get_extent()?;
+        Ok(Attribute::Array(
+            [env.MinX, env.MinY, env.MaxX, env.MaxY]
+                .into_iter()
+                .map(Attribute::Float)
+                .collect(),
+        ))
+    }
+
+    /// Open the given layer (name or index) of the dataset, or the first one
+    fn open_layer(data: &Dataset, layer: Option<String>) -> Result<Layer> {
+        if let Some(lyr) = layer {
+            match (data.layer_by_name(&lyr), lyr.parse::<usize>()) {
+                (Ok(l), _) => Ok(l),
+                (Err(_), Ok(ind)) => data.layer(ind).context("Given Layer index doesn't exist"),
+                (Err(e), Err(_)) => Err(e).context("Given Layer doesn't exist"),
+            }
+        } else {
+            if data.layer_count() > 1 {
+                let layers: Vec<String> =
+                    data.layers().map(|l| format!("{:?}", l.name())).collect();
+                warning!("WARN Multiple layers found, you can choose a specific layer");
+                warning!("WARN Available Layers: {}", layers.join(" "));
+            }
+            Ok(data.layer(0)?)
+        }
+    }
+
+    /// Check if the node is marked as a barrier by the attribute
+    fn is_barrier(node: &NodeInner, barrier: &str) -> bool {
+        node.attr(barrier)
+            .and_then(bool::from_attr_relaxed)
+            .unwrap_or_default()
+    }
+
+    /// Values of the nodes, each computed after the ones of its inputs
+    ///
+    /// `value` gets the node and the values computed so far, which
+    /// include all of its inputs. The nodes are visited upstream first
+    /// with an explicit stack so long rivers don't overflow the call
+    /// stack.
+    fn upstream_first<T, F>(net: &Network, mut value: F) -> Result<HashMap<String, T>>
+    where
+        F: FnMut(&NodeInner, &HashMap<String, T>) -> Result<T>,
+    {
+        let mut values: HashMap<String, T> = HashMap::new();
+        for node in net.nodes() {
+            let mut stack = vec![node.clone()];
+            while let Some(cur) = stack.last().cloned() {
+                let n = cur.lock();
+                if values.contains_key(n.name()) {
+                    stack.pop();
+                    continue;
+                }
+                let pending: Vec<Node> = n
+                    .inputs()
+                    .iter()
+                    .filter(|i| !values.contains_key(i.lock().name()))
+                    .cloned()
+                    .collect();
+                if !pending.is_empty() {
+                    stack.extend(pending);
+                    continue;
+                }
+                let v = value(&n, &values)?;
+                values.insert(n.name().to_string(), v);
+                stack.pop();
+            }
+        }
+        Ok(values)
+    }
+
+    /// Read the flowlines with their ids for linear referencing
+    fn read_flowlines(file: PathBuf, layer: Option<String>, field: &str) -> Result<Flowlines> {
+        let data = Dataset::open(file)?;
+        let mut lyr = open_layer(&data, layer)?;
+        let fid = Defn::from_layer(&lyr).field_index(field)?;
+        let mut lines = Vec::new();
+        for f in lyr.features() {
+            if let (Some(id), Some(g)) = (f.field_as_string(fid)?, f.geometry()) {
+                lines.push((id, line_points(g)));
+            }
+        }
+        Ok(Flowlines::new(lines))
+    }
+
+    /// Distance in the units of the spatial reference
+    ///
+    /// Meters and degrees are converted approximately (111.32 km per
+    /// degree) with a warning, as the conversion depends on the location.
+    fn distance_in_units(value: f64, unit: Option<&str>, srs: Option<&SpatialRef>) -> Result<f64> {
+        const METERS_PER_DEGREE: f64 = 111_320.0;
+        let (value, meters) = match unit {
+            None => {
+                if value > 1.0 && srs.is_some_and(|s| s.is_geographic()) {
+                    warning!("WARN Distance {value} is in degrees for the geographic coordinates");
+                }
+                return Ok(value);
+            }
+            Some("m") => (value, true),
+            Some("km") => (value * 1000.0, true),
+            Some("ft") => (value * 0.3048, true),
+            Some("mi") => (value * 1609.344, true),
+            Some("deg") => (value, false),
+            Some(u) => {
+                return Err(nadi_core::anyhow::Error::msg(format!(
+                    "Unknown distance unit {u:?}, use m, km, ft, mi or deg"
+                )))
+            }
+        };
+        let Some(srs) = srs else {
+            warning!("WARN No spatial reference to convert the distance, using it as is");
+            return Ok(value);
+        };
+        Ok(match (meters, srs.is_geographic()) {
+            (true, true) => {
+                warning!("WARN Converting meters to degrees approximately");
+                value / METERS_PER_DEGREE
+            }
+            (true, false) => value / srs.linear_units(),
+            (false, true) => value,
+            (false, false) => {
+                warning!("WARN Converting degrees to meters approximately");
+                value * METERS_PER_DEGREE / srs.linear_units()
+            }
+        })
+    }
+
+    /// Spatial reference from EPSG code, WKT or PROJ string, in
+    /// traditional (lon, lat) axis order
+    fn spatial_ref(def: &str) -> Result<SpatialRef> {
+        let mut srs = SpatialRef::from_definition(def)?;
+        srs.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+        Ok(srs)
+    }
+
+    /// Output spatial reference, and the reprojection from `srs` to `target`
+    ///
+    /// Bare numbers in `target` are taken as EPSG codes.
+    fn output_srs(
+        srs: Option<String>,
+        target: Option<String>,
+    ) -> Result<(Option<SpatialRef>, Option<CoordTransform>)> {
+        let src = srs.as_deref().map(spatial_ref).transpose()?;
+        let Some(target) = target else {
+            return Ok((src, None));
+        };
+        let dst = match target.trim().parse::<u32>() {
+            Ok(epsg) => spatial_ref(&format!("EPSG:{epsg}"))?,
+            Err(_) => spatial_ref(&target)?,
+        };
+        let src =
+            src.context("Spatial reference of the geometries (srs) is needed to reproject")?;
+        let trans = CoordTransform::new(&src, &dst)?;
+        Ok((Some(dst), Some(trans)))
+    }
+
+    fn reproject(geom: Geometry, trans: &Option<CoordTransform>) -> Result<Geometry> {
+        match trans {
+            Some(t) => Ok(geom.transform(t)?),
+            None => Ok(geom),
+        }
+    }
+
+    /// Raster band number from the function argument, bands start from 1
+    fn band_index(band: i64) -> Result<usize> {
+        if band < 1 {
+            return Err(nadi_core::anyhow::Error::msg(format!(
+                "Band should be 1 or larger, got {band}"
+            )));
+        }
+        Ok(band as usize)
+    }
+
+    /// Read the geometry of the node from the WKT attribute
+    fn node_geometry(node: &NodeInner, geometry: &str) -> Result<Geometry> {
+        let wkt = String::try_from_attr(
+            node.attr(geometry)
+                .context("Attribute for geometry not found")?,
+        )
+        .map_err(nadi_core::anyhow::Error::msg)?;
+        Ok(Geometry::from_wkt(&wkt)?)
+    }
+
+    /// Bytes of the geometry WKT used for the node index fingerprint
+    const INDEX_WKT_PREFIX: usize = 96;
+
+    /// Spatial index of the node points, cached for the later calls
+    ///
+    /// Nodes without the geometry attribute are not indexed. The
+    /// functions that load or change the node geometries invalidate
+    /// the cached index; for the changes made elsewhere, the key has a
+    /// fingerprint of the node names and the start of their geometry
+    /// WKT, which has the first point used in the index.
+    fn node_index(net: &Network, geometry: &str) -> Result<Arc<NodeIndex>> {
+        let fingerprint = net.nodes().fold(0, |h, node| {
+            let n = node.lock();
+            let h = fnv1a(h, n.name().as_bytes());
+            match n.attr(geometry) {
+                Some(Attribute::String(g)) => {
+                    let g = g.as_bytes();
+                    fnv1a(h, &g[..g.len().min(INDEX_WKT_PREFIX)])
+                }
+                _ => fnv1a(h, &[]),
+            }
+        });
+        let key = CacheKey {
+            network: net as *const Network as usize,
+            geometry: geometry.to_string(),
+            fingerprint,
+        };
+        cached_index(key, || {
+            let points = net
+                .nodes()
+                .filter_map(|node| {
+                    let n = node.lock();
+                    n.attr(geometry)?;
+                    Some(node_geometry(&n, geometry).map(|g| {
+                        let (x, y, _) = g.get_point(0);
+                        IndexedNode::new([x, y], n.name().to_string())
+                    }))
+                })
+                .collect::<Result<_>>()?;
+            Ok(NodeIndex::new(points))
+        })
+    }
+
+    /// Linear geometry for the curve geometries (CompoundCurve, MultiCurve, ...)
+    ///
+    /// The curves are approximated with the max angle step from the
+    /// OGR_ARC_STEPSIZE config option (4 degrees by default).
+    fn linearize(geom: &Geometry) -> Option<Geometry> {
+        if unsafe { gdal_sys::OGR_G_HasCurveGeometry(geom.c_geometry(), 1) } == 0 {
+            return None;
+        }
+        let wkb = unsafe {
+            let lin =
+                gdal_sys::OGR_G_GetLinearGeometry(geom.c_geometry(), 0.0, std::ptr::null_mut());
+            if lin.is_null() {
+                return None;
+            }
+            let mut wkb = vec![0u8; gdal_sys::OGR_G_WkbSize(lin) as usize];
+            let err = gdal_sys::OGR_G_ExportToWkb(
+                lin,
+                gdal_sys::OGRwkbByteOrder::wkbNDR,
+                wkb.as_mut_ptr(),
+            );
+            gdal_sys::OGR_G_DestroyGeometry(lin);
+            if err != gdal_sys::OGRErr::OGRERR_NONE {
+                return None;
+            }
+            wkb
+        };
+        Geometry::from_wkb(&wkb).ok()
+    }
+
+    /// Points of the line geometry, parts are joined for multi-geometry
+    fn line_points(geom: &Geometry) -> Vec<(f64, f64)> {
+        let lin = linearize(geom);
+        let geom = lin.as_ref().unwrap_or(geom);
+        let gc = geom.geometry_count();
+        let pts = if gc > 0 {
+            (0..gc)
+                .flat_map(|i| geom.get_geometry(i).get_point_vec())
+                .collect()
+        } else {
+            geom.get_point_vec()
+        };
+        pts.into_iter().map(|(x, y, _)| (x, y)).collect()
+    }
+
+    /// Points of each part of the line geometry
+    fn line_parts(geom: &Geometry) -> Vec<Vec<(f64, f64)>> {
+        let lin = linearize(geom);
+        let geom = lin.as_ref().unwrap_or(geom);
+        let to_2d = |pts: Vec<(f64, f64, f64)>| -> Vec<(f64, f64)> {
+            pts.into_iter().map(|(x, y, _)| (x, y)).collect()
+        };
+        let gc = geom.geometry_count();
+        if gc > 0 {
+            (0..gc)
+                .map(|i| to_2d(geom.get_geometry(i).get_point_vec()))
+                .collect()
+        } else {
+            vec![to_2d(geom.get_point_vec())]
+        }
+    }
+
+    /// Exterior rings of the polygons in the geometry
+    fn polygon_rings(geom: &Geometry) -> Vec<Vec<(f64, f64)>> {
+        let name = geom.geometry_name();
+        if name.starts_with("MULTI") || name.starts_with("GEOMETRYCOLLECTION") {
+            (0..geom.geometry_count())
+                .flat_map(|i| polygon_rings(&geom.get_geometry(i)))
+                .collect()
+        } else if name == "POLYGON" && geom.geometry_count() > 0 {
+            vec![line_points(&geom.get_geometry(0))]
+        } else {
+            vec![]
+        }
+    }
+
+    fn planar_dist(p1: (f64, f64), p2: (f64, f64)) -> f64 {
+        ((p2.0 - p1.0).powi(2) + (p2.1 - p1.1).powi(2)).sqrt()
+    }
+
+    /// Unit vector perpendicular to the direction from `pt` to `next`
+    ///
+    /// The vector points to the left bank when looking downstream, so
+    /// the cross-sections are from right bank to the left bank.
+    fn perpendicular(pt: (f64, f64), next: (f64, f64)) -> Option<(f64, f64)> {
+        let dist = planar_dist(pt, next);
+        if dist == 0.0 {
+            None
+        } else {
+            Some((-(next.1 - pt.1) / dist, (next.0 - pt.0) / dist))
+        }
+    }
+
+    /// Geodesic area of the polygons in lat/lon, in square meters
+    fn polygon_area(geom: &Geometry) -> f64 {
+        let name = geom.geometry_name();
+        if name.starts_with("MULTI") || name.starts_with("GEOMETRYCOLLECTION") {
+            (0..geom.geometry_count())
+                .map(|i| polygon_area(&geom.get_geometry(i)))
+                .sum()
+        } else if name == "POLYGON" {
+            // first ring is the exterior, rest are the holes
+            (0..geom.geometry_count())
+                .map(|i| {
+                    let area = geodesy::ring_area(&line_points(&geom.get_geometry(i)));
+                    if i == 0 {
+                        area
+                    } else {
+                        -area
+                    }
+                })
+                .sum()
+        } else {
+            match linearize(geom) {
+                Some(lin) if lin.geometry_name() != name => polygon_area(&lin),
+                _ => 0.0,
+            }
+        }
+    }
+
+    /// Length of the line in coordinate units, or meters if geodesic
+    fn line_length(pts: &[(f64, f64)], geodesic: bool) -> f64 {
+        if geodesic {
+            geodesy::line_length(pts)
+        } else {
+            pts.windows(2).map(|w| planar_dist(w[0], w[1])).sum()
+        }
+    }
+
+    /// Points of the edge from the node to its output
+    ///
+    /// Uses the traced edge geometry if the node has it, or a straight
+    /// line between the node and output node geometries.
+    fn edge_points(
+        node: &NodeInner,
+        geometry: &str,
+        edge_geometry: &str,
+    ) -> Result<Option<Vec<(f64, f64)>>> {
+        if node.attr(edge_geometry).is_some() {
+            return Ok(Some(line_points(&node_geometry(node, edge_geometry)?)));
+        }
+        let RSome(out) = node.output() else {
+            return Ok(None);
+        };
+        let (x1, y1, _) = node_geometry(node, geometry)?.get_point(0);
+        let (x2, y2, _) = node_geometry(&out.lock(), geometry)?.get_point(0);
+        Ok(Some(vec![(x1, y1), (x2, y2)]))
+    }
+
+    /// Attribute of the node value from the graph file
+    fn graph_value_attr(v: Value) -> Attribute {
+        match v {
+            Value::Integer(v) => Attribute::Integer(v),
+            Value::Float(v) => Attribute::Float(v),
+            Value::Bool(v) => Attribute::Bool(v),
+            Value::String(v) => Attribute::String(v.into()),
+        }
+    }
+
+    /// Names, outputs and the given attributes of the nodes for the graph file
+    fn graph_nodes(net: &Network, attrs: &[String]) -> Vec<GraphNode> {
+        let value = |a: &Attribute| match a {
+            Attribute::Integer(v) => Value::Integer(*v),
+            Attribute::Float(v) => Value::Float(*v),
+            Attribute::Bool(v) => Value::Bool(*v),
+            Attribute::String(v) => Value::String(v.to_string()),
+            a => Value::String(a.to_string()),
+        };
+        net.nodes()
+            .map(|node| {
+                let n = node.lock();
+                GraphNode {
+                    name: n.name().to_string(),
+                    output: n.output().map(|o| o.lock().name().to_string()).into(),
+                    attrs: attrs.iter().map(|a| n.attr(a).map(value)).collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// Information of the nodes for the model exports
+    ///
+    /// Empty attribute names are ignored.
+    fn model_nodes(
+        net: &Network,
+        geometry: &str,
+        edge_geometry: &str,
+        area: &str,
+        elevation: &str,
+        xs: &str,
+        geodesic: bool,
+    ) -> Result<Vec<ModelNode>> {
+        let float_attr = |n: &NodeInner, a: &str| n.attr(a).and_then(f64::from_attr_relaxed);
+        let float_array = |n: &NodeInner, a: &str| -> Option<Vec<f64>> {
+            match n.attr(a)? {
+                Attribute::Array(v) => v.iter().map(f64::from_attr_relaxed).collect(),
+                _ => None,
+            }
+        };
+        net.nodes()
+            .map(|node| {
+                let n = node.lock();
+                let (x, y, _) = node_geometry(&n, geometry)?.get_point(0);
+                let xs = float_array(&n, &format!("{xs}_station"))
+                    .zip(float_array(&n, &format!("{xs}_elevation")));
+                let line = edge_points(&n, geometry, edge_geometry)?.unwrap_or_default();
+                Ok(ModelNode {
+                    name: n.name().to_string(),
+                    point: (x, y),
+                    output: n.output().map(|o| o.lock().name().to_string()).into(),
+                    length: line_length(&line, geodesic),
+                    line,
+                    area: float_attr(&n, area),
+                    elevation: float_attr(&n, elevation),
+                    xs,
+                })
+            })
+            .collect()
+    }
+
+    /// Width of the polygons crossing the transect at the point
+    ///
+    /// The transect is perpendicular to the direction from `pt` to
+    /// `next` and centered at `pt`. Polygons pieces touching each other
+    /// along the transect are merged, and the width of the piece
+    /// containing (or nearest to) the point is returned.
+    fn transect_width(
+        lyr: &mut Layer,
+        pt: (f64, f64),
+        next: (f64, f64),
+        length: f64,
+    ) -> Result<Option<f64>> {
+        let Some(dir) = perpendicular(pt, next) else {
+            return Ok(None);
+        };
+        let half = length / 2.0;
+        let mut line = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbLineString)?;
+        line.add_point_2d((pt.0 - dir.0 * half, pt.1 - dir.1 * half));
+        line.add_point_2d((pt.0 + dir.0 * half, pt.1 + dir.1 * half));
+
+        lyr.set_spatial_filter(&line);
+        let mut intervals: Vec<(f64, f64)> = Vec::new();
+        for f in lyr.features() {
+            let Some(inter) = f.geometry().and_then(|g| g.intersection(&line)) else {
+                continue;
+            };
+            let parts = if inter.geometry_count() > 0 {
+                (0..inter.geometry_count())
+                    .map(|i| inter.get_geometry(i).get_point_vec())
+                    .collect()
+            } else {
+                vec![inter.get_point_vec()]
+            };
+            for part in parts {
+                // position of the points along the transect
+                let ts: Vec<f64> = part
+                    .iter()
+                    .map(|p| (p.0 - pt.0) * dir.0 + (p.1 - pt.1) * dir.1)
+                    .collect();
+                if ts.len() > 1 {
+                    let min = ts.iter().cloned().fold(f64::INFINITY, f64::min);
+                    let max = ts.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                    intervals.push((min, max));
+                }
+            }
+        }
+        lyr.clear_spatial_filter();
+
+        intervals.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let tol = length * 1e-9;
+        let mut merged: Vec<(f64, f64)> = Vec::new();
+        for (s, e) in intervals {
+            match merged.last_mut() {
+                Some(last) if s <= last.1 + tol => last.1 = last.1.max(e),
+                _ => merged.push((s, e)),
+            }
+        }
+        let dist_to_pt = |(s, e): &(f64, f64)| {
+            if *s <= 0.0 && *e >= 0.0 {
+                0.0
+            } else {
+                s.abs().min(e.abs())
+            }
+        };
+        Ok(merged
+            .iter()
+            .min_by(|a, b| dist_to_pt(a).total_cmp(&dist_to_pt(b)))
+            .map(|(s, e)| e - s))
+    }
+
+    /// Node elevations lowered so they never increase downstream
+    ///
+    /// Each node takes the minimum of its own upstream elevation and
+    /// the corrected elevations of its inputs; nodes without a sampled
+    /// elevation pass the lowest elevation of their inputs through.
+    fn monotonic_elevations(
+        net: &Network,
+        raw: &HashMap<String, (Option<f64>, Option<f64>, f64)>,
+    ) -> Result<HashMap<String, f64>> {
+        let fixed = upstream_first(net, |n, fixed: &HashMap<String, Option<f64>>| {
+            let own = raw.get(n.name()).and_then(|r| r.0);
+            Ok(n.inputs()
+                .iter()
+                .filter_map(|i| fixed[i.lock().name()])
+                .chain(own)
+                .reduce(f64::min))
+        })?;
+        Ok(fixed
+            .into_iter()
+            .filter_map(|(k, v)| Some((k, v?)))
+            .collect())
+    }
+
+    /// Field values of the polygons in the layer for the nodes inside them
+    ///
+    /// Polygons are reprojected to `srs` (spatial reference of the node
+    /// geometries) if given. The first polygon containing the node is
+    /// used if there are overlapping polygons.
+    fn polygon_join(
+        lyr: &mut Layer,
+        index: &NodeIndex,
+        srs: Option<&SpatialRef>,
+        field: &str,
+    ) -> Result<HashMap<String, Attribute>> {
+        let idx = lyr.defn().field_index(field)?;
+        let transform = match (lyr.spatial_ref(), srs) {
+            (Some(mut s), Some(t)) if s != *t => {
+                s.set_axis_mapping_strategy(AxisMappingStrategy::TraditionalGisOrder);
+                Some(CoordTransform::new(&s, t)?)
+            }
+            _ => None,
+        };
+        let mut values = HashMap::new();
+        for f in lyr.features() {
+            let Some(poly) = f.geometry() else {
+                continue;
+            };
+            let Some(val) = f.field(idx)?.and_then(gis_value_to_attr) else {
+                continue;
+            };
+            let poly = match &transform {
+                Some(t) => poly.transform(t)?,
+                None => poly.clone(),
+            };
+            let env = poly.envelope();
+            for (name, [x, y]) in index.in_envelope([env.MinX, env.MinY], [env.MaxX, env.MaxY]) {
+                if values.contains_key(name) {
+                    continue;
+                }
+                let mut pt = Geometry::empty(gdal_sys::OGRwkbGeometryType::wkbPoint)?;
+                pt.add_point_2d((x, y));
+                if poly.contains(&pt) {
+                    values.insert(name.to_string(), val.clone());
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// Convert the GIS field value to nadi Attribute
+    fn gis_value_to_attr(val: FieldValue) -> Option<Attribute> {
+        match val {
+            FieldValue::IntegerValue(i) => Some(Attribute::Integer(i as i64)),
+            FieldValue::Integer64Value(i) => Some(Attribute::Integer(i)),
+            FieldValue::StringValue(i) => Some(Attribute::String(RString::from(i))),
+            FieldValue::RealValue(i) => Some(Attribute::Float(i)),
+            FieldValue::DateValue(d) => Some(Attribute::Date(Date::new(
+                d.year() as u16,
+                d.month() as u8,
+                d.day() as u8,
+            ))),
+            FieldValue::DateTimeValue(d) => Some(Attribute::DateTime(d.into())),
+            FieldValue::IntegerListValue(l) => Some(Attribute::Array(
+                l.into_iter()
+                    .map(|i| Attribute::Integer(i as i64))
+                    .collect(),
+            )),
+            FieldValue::Integer64ListValue(l) => Some(Attribute::Array(
+                l.into_iter().map(Attribute::Integer).collect(),
+            )),
+            FieldValue::RealListValue(l) => Some(Attribute::Array(
+                l.into_iter().map(Attribute::Float).collect(),
+            )),
+            FieldValue::StringListValue(l) => Some(Attribute::Array(
+                l.into_iter()
+                    .map(|s| Attribute::String(RString::from(s)))
+                    .collect(),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Index and name of the Time fields
+    ///
+    /// There is no FieldValue for the Time fields, so they are skipped
+    /// by `Feature::fields` and have to be read separately.
+    fn time_fields(defn: &Defn) -> Vec<(usize, String)> {
+        defn.fields()
+            .enumerate()
+            .filter(|(_, f)| f.field_type() == OGRFieldType::OFTTime)
+            .map(|(i, f)| (i, f.name()))
+            .collect()
+    }
+
+    fn time_attr(f: &Feature, idx: usize) -> Option<Attribute> {
+        let val = f.field_as_string(idx).ok()??;
+        let time = chrono::NaiveTime::parse_from_str(&val, "%H:%M:%S%.f").ok()?;
+        Some(Attribute::Time(time.into()))
+    }
+
+    /// Upper bounds of the classes (except the last) for the values
+    fn class_breaks(mut values: Vec<f64>, classes: usize, method: &str) -> Result<Vec<f64>> {
+        if classes == 0 {
+            return Err(nadi_core::anyhow::Error::msg(
+                "Number of classes should be > 0",
+            ));
+        }
+        values.retain(|v| v.is_finite());
+        values.sort_by(f64::total_cmp);
+        let (Some(min), Some(max)) = (values.first(), values.last()) else {
+            return Ok(vec![]);
+        };
+        Ok(match method {
+            "quantile" => (1..classes)
+                .map(|i| values[(i * values.len() / classes).min(values.len() - 1)])
+                .collect(),
+            "equal" => (1..classes)
+                .map(|i| min + (max - min) * i as f64 / classes as f64)
+                .collect(),
+            m => {
+                return Err(nadi_core::anyhow::Error::msg(format!(
+                    "Unknown method {m}, use quantile or equal"
+                )))
+            }
+        })
+    }
+
+    /// Class (starting from 1) of the value for the class breaks
+    fn class_of(value: f64, breaks: &[f64]) -> i32 {
+        breaks.iter().take_while(|b| value >= **b).count() as i32 + 1
+    }
+
+    /// Convert the attribute to the given type name, one of `FIELD_TYPES`
+    fn convert_attr(a: &Attribute, ty: &str) -> Result<Attribute, String> {
+        let text = || String::try_from_attr_relaxed(a);
+        Ok(match ty {
+            "String" => Attribute::String(text()?.into()),
+            "Integer" => Attribute::Integer(i64::try_from_attr_relaxed(a)?),
+            "Float" => Attribute::Float(f64::try_from_attr_relaxed(a)?),
+            "Bool" => Attribute::Bool(bool::try_from_attr_relaxed(a)?),
+            "Date" => match a {
+                Attribute::Date(_) => a.clone(),
+                _ => {
+                    let d = chrono::NaiveDate::parse_from_str(&text()?, "%Y-%m-%d")
+                        .map_err(|e| e.to_string())?;
+                    Attribute::Date(Date::new(d.year() as u16, d.month() as u8, d.day() as u8))
+                }
+            },
+            "DateTime" => match a {
+                Attribute::DateTime(_) => a.clone(),
+                _ => {
+                    let t = text()?;
+                    let dt = chrono::DateTime::parse_from_rfc3339(&t)
+                        .or_else(|_| {
+                            chrono::NaiveDateTime::parse_from_str(&t, "%Y-%m-%d %H:%M:%S%.f")
+                                .map(|d| d.and_utc().fixed_offset())
+                        })
+                        .map_err(|e| e.to_string())?;
+                    Attribute::DateTime(dt.into())
+                }
+            },
+            t => {
+                return Err(format!(
+                    "Type {t} not supported. Use {}",
+                    FIELD_TYPES.join(", ")
+                ))
+            }
+        })
+    }
+
+    fn sanitize_key(k: &str) -> String {
+        k.replace(' ', "_")
+    }
+
+    type Attr2FieldValue = fn(&Attribute) -> FieldValue;
+
+    /// Field type names of the node attributes from their values
+    ///
+    /// Integers mixed with floats are Float, and mixed types or
+    /// arrays and tables are saved as the Attribute strings. The
+    /// geometry attribute is skipped.
+    fn infer_field_types(nodes: &[&Node], geometry: &str) -> HashMap<String, String> {
+        let mut types: HashMap<String, &str> = HashMap::new();
+        for node in nodes {
+            let n = node.lock();
+            for (k, v) in n.attr_map().iter().map(|kv| kv.into_tuple()) {
+                if k.as_str() == geometry {
+                    continue;
+                }
+                let ty = match v {
+                    Attribute::Bool(_) | Attribute::Integer(_) => "Integer",
+                    Attribute::Float(_) => "Float",
+                    Attribute::String(_) => "String",
+                    Attribute::Date(_) => "Date",
+                    Attribute::Time(_) => "Time",
+                    Attribute::DateTime(_) => "DateTime",
+                    _ => "Attribute",
+                };
+                let ty = match (types.get(k.as_str()), ty) {
+                    (None, t) => t,
+                    (Some(a), b) if *a == b => b,
+                    (Some(&"Integer"), "Float") | (Some(&"Float"), "Integer") => "Float",
+                    _ => "Attribute",
+                };
+                types.insert(k.to_string(), ty);
+            }
+        }
+        types.into_iter().map(|(k, v)| (k, v.to_string())).collect()
+    }
+
+    fn type_name_to_field(name: &str) -> Result<(u32, Attr2FieldValue), String> {
+        Ok(match name {
+            // This is a string that can be parsed back into correct Attribute
+            "Attribute" => (OGRFieldType::OFTString, |a| {
+                FieldValue::StringValue(a.to_string())
+            }),
+            "String" => (OGRFieldType::OFTString, |a| {
+                let val: String = FromAttributeRelaxed::from_attr_relaxed(a).unwrap_or_default();
+                FieldValue::StringValue(val)
+            }),
+            "Integer" => (OGRFieldType::OFTInteger64, |a| {
+                let val: i64 = FromAttributeRelaxed::from_attr_relaxed(a).unwrap_or_default();
+                FieldValue::Integer64Value(val)
+            }),
+            "Float" => (OGRFieldType::OFTReal, |a| {
+                let val: f64 = FromAttributeRelaxed::from_attr_relaxed(a).unwrap_or_default();
                 FieldValue::RealValue(val)
             }),
             "Date" => (OGRFieldType::OFTDate, |a| {
                 let val: Date = FromAttributeRelaxed::from_attr_relaxed(a).unwrap_or_default();
                 FieldValue::DateValue(val.into())
             }),
-            // // There is no FieldValue::TimeValue
-            // "Time" => (OGRFieldType::OFTTime, |a| {
-            //     let val: Time = FromAttributeRelaxed::from_attr_relaxed(a).unwrap_or_default();
-            //     FieldValue::TimeValue(val.into())
-            // }),
+            // There is no FieldValue::TimeValue, GDAL parses the string for Time fields
+            "Time" => (OGRFieldType::OFTTime, |a| {
+                let val: Time = FromAttributeRelaxed::from_attr_relaxed(a).unwrap_or_default();
+                FieldValue::StringValue(val.to_string())
+            }),
             "DateTime" => (OGRFieldType::OFTDateTime, |a| {
                 let val: DateTime = FromAttributeRelaxed::from_attr_relaxed(a).unwrap_or_default();
                 FieldValue::DateTimeValue(val.into())
@@ -336,7 +4974,7 @@ mod gis {
             // There are other types supported by gdal, that could exist as Attribute, but let's ignore them
             t => {
                 return Err(format!(
-                "Type {t} Not supported. Use String, Integer, Float, Date, DateTime or Attribute"
+                "Type {t} Not supported. Use String, Integer, Float, Date, Time, DateTime or Attribute"
             ))
             }
         })
@@ -0,0 +1,155 @@
+//! Linear referencing of the points on the flowlines
+//!
+//! Points are addressed by the reach (flowline id) and the measure,
+//! following the NHD convention: percentage of the reach length from
+//! its downstream end (0) to the upstream end (100). The flowlines
+//! are assumed to be digitized from upstream to downstream, and the
+//! distances are in the units of their coordinates.
+
+use rstar::primitives::{GeomWithData, Line};
+use rstar::{PointDistance, RTree};
+
+/// Flowline segment in the spatial index with (flowline, segment) index
+type Segment = GeomWithData<Line<[f64; 2]>, (usize, usize)>;
+
+pub struct Flowlines {
+    lines: Vec<(String, Vec<(f64, f64)>)>,
+    tree: RTree<Segment>,
+}
+
+/// Location of a point on the flowlines
+pub struct Location<'a> {
+    pub reach: &'a str,
+    pub measure: f64,
+    /// Distance of the point from the flowline
+    pub offset: f64,
+}
+
+impl Flowlines {
+    pub fn new(lines: Vec<(String, Vec<(f64, f64)>)>) -> Self {
+        let segments = lines
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (_, pts))| {
+                pts.windows(2).enumerate().map(move |(j, w)| {
+                    let line = Line::new([w[0].0, w[0].1], [w[1].0, w[1].1]);
+                    GeomWithData::new(line, (i, j))
+                })
+            })
+            .collect();
+        Self {
+            lines,
+            tree: RTree::bulk_load(segments),
+        }
+    }
+
+    /// Locate the point on the nearest flowline
+    pub fn locate(&self, (x, y): (f64, f64)) -> Option<Location<'_>> {
+        let seg = self.tree.nearest_neighbor(&[x, y])?;
+        let (i, j) = seg.data;
+        let [px, py] = seg.geom().nearest_point(&[x, y]);
+        let (reach, pts) = &self.lines[i];
+        let along = length(&pts[..=j]) + dist(pts[j], (px, py));
+        let total = length(pts);
+        let measure = if total > 0.0 {
+            100.0 * (1.0 - along / total)
+        } else {
+            0.0
+        };
+        Some(Location {
+            reach,
+            measure,
+            offset: seg.geom().distance_2(&[x, y]).sqrt(),
+        })
+    }
+
+    /// Point on the reach at the measure
+    pub fn position(&self, reach: &str, measure: f64) -> Option<(f64, f64)> {
+        let (_, pts) = self.lines.iter().find(|(r, _)| r == reach)?;
+        let along = length(pts) * (1.0 - measure.clamp(0.0, 100.0) / 100.0);
+        interpolate(pts, along)
+    }
+}
+
+fn dist(p1: (f64, f64), p2: (f64, f64)) -> f64 {
+    ((p2.0 - p1.0).powi(2) + (p2.1 - p1.1).powi(2)).sqrt()
+}
+
+fn length(pts: &[(f64, f64)]) -> f64 {
+    pts.windows(2).map(|w| dist(w[0], w[1])).sum()
+}
+
+/// Point at the distance along the line from its start
+pub fn interpolate(pts: &[(f64, f64)], along: f64) -> Option<(f64, f64)> {
+    let mut rem = along;
+    for w in pts.windows(2) {
+        let d = dist(w[0], w[1]);
+        if rem <= d && d > 0.0 {
+            let t = rem / d;
+            return Some((
+                w[0].0 + t * (w[1].0 - w[0].0),
+                w[0].1 + t * (w[1].1 - w[0].1),
+            ));
+        }
+        rem -= d;
+    }
+    pts.last().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: (f64, f64), b: (f64, f64)) -> bool {
+        dist(a, b) < 1e-9
+    }
+
+    /// Straight reach from (0, 10) down to (0, 0), and a bent one
+    /// from (10, 10) to (10, 5) then to (15, 5); both 10 units long
+    fn flowlines() -> Flowlines {
+        Flowlines::new(vec![
+            ("r1".into(), vec![(0.0, 10.0), (0.0, 0.0)]),
+            ("r2".into(), vec![(10.0, 10.0), (10.0, 5.0), (15.0, 5.0)]),
+        ])
+    }
+
+    #[test]
+    fn locate_points() {
+        let fl = flowlines();
+        let loc = fl.locate((1.0, 2.5)).unwrap();
+        assert_eq!(loc.reach, "r1");
+        assert!((loc.measure - 25.0).abs() < 1e-9);
+        assert!((loc.offset - 1.0).abs() < 1e-9);
+        let loc = fl.locate((12.0, 4.0)).unwrap();
+        assert_eq!(loc.reach, "r2");
+        assert!((loc.measure - 30.0).abs() < 1e-9);
+        assert!((loc.offset - 1.0).abs() < 1e-9);
+        // upstream end of the reach
+        let loc = fl.locate((10.0, 11.0)).unwrap();
+        assert!((loc.measure - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn position_points() {
+        let fl = flowlines();
+        assert!(close(fl.position("r1", 25.0).unwrap(), (0.0, 2.5)));
+        assert!(close(fl.position("r2", 30.0).unwrap(), (12.0, 5.0)));
+        assert!(close(fl.position("r2", 100.0).unwrap(), (10.0, 10.0)));
+        assert!(close(fl.position("r2", 0.0).unwrap(), (15.0, 5.0)));
+        // measures are clamped to the reach
+        assert!(close(fl.position("r2", 150.0).unwrap(), (10.0, 10.0)));
+        assert!(fl.position("r3", 50.0).is_none());
+    }
+
+    #[test]
+    fn locate_position_round_trip() {
+        let fl = flowlines();
+        for m in [0.0, 12.5, 50.0, 70.0, 99.0] {
+            let pt = fl.position("r2", m).unwrap();
+            let loc = fl.locate(pt).unwrap();
+            assert_eq!(loc.reach, "r2");
+            assert!((loc.measure - m).abs() < 1e-9);
+            assert!(loc.offset < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Writers for the input files of the hydrologic/hydraulic models
+//!
+//! These are skeletons generated from the network topology and
+//! geometry, the model specific parameters have to be filled in the
+//! modeling tool.
+
+use std::fmt::Write;
+
+/// Node of the network with the information the models need
+#[derive(Clone, Debug, Default)]
+pub struct ModelNode {
+    pub name: String,
+    pub point: (f64, f64),
+    pub output: Option<String>,
+    /// Points of the edge from this node to the output node
+    pub line: Vec<(f64, f64)>,
+    /// Length of the edge
+    pub length: f64,
+    /// Drainage area of the local catchment
+    pub area: Option<f64>,
+    pub elevation: Option<f64>,
+    /// Cross-section as stations and elevations
+    pub xs: Option<(Vec<f64>, Vec<f64>)>,
+}
+
+/// HEC-HMS basin file with a junction, subbasin and reach for each node
+///
+/// Subbasin `{node}-sub` drains into junction `{node}`, which is
+/// connected to the junction of the output node by reach `{node}-reach`.
+/// Outlet nodes are written as sinks.
+pub fn hms_basin(title: &str, metric: bool, nodes: &[ModelNode]) -> String {
+    let mut s = String::new();
+    // writing to String doesn't fail
+    let _ = writeln!(s, "Basin: {title}");
+    let _ = writeln!(s, "     Description: Generated by nadi-gis");
+    let _ = writeln!(s, "     Version: 4.0");
+    let _ = writeln!(
+        s,
+        "     Unit System: {}",
+        if metric { "Metric" } else { "English" }
+    );
+    let _ = writeln!(s, "     Missing Flow To Zero: No");
+    let _ = writeln!(s, "     Enable Flow Ratio: No");
+    let _ = writeln!(s, "     Compute Local Flow At Junctions: No");
+    let _ = writeln!(s, "End:\n");
+    let coords = |s: &mut String, (x, y): (f64, f64)| {
+        let _ = writeln!(s, "     Canvas X: {x}");
+        let _ = writeln!(s, "     Canvas Y: {y}");
+    };
+    for n in nodes {
+        let _ = writeln!(s, "Subbasin: {}-sub", n.name);
+        coords(&mut s, n.point);
+        if let Some(a) = n.area {
+            let _ = writeln!(s, "     Area: {a}");
+        }
+        let _ = writeln!(s, "     Downstream: {}", n.name);
+        let _ = writeln!(s, "End:\n");
+        match &n.output {
+            Some(out) => {
+                let _ = writeln!(s, "Junction: {}", n.name);
+                coords(&mut s, n.point);
+                let _ = writeln!(s, "     Downstream: {}-reach", n.name);
+                let _ = writeln!(s, "End:\n");
+
+                let end = n.line.last().copied().unwrap_or(n.point);
+                let _ = writeln!(s, "Reach: {}-reach", n.name);
+                coords(&mut s, end);
+                let _ = writeln!(s, "     From Canvas X: {}", n.point.0);
+                let _ = writeln!(s, "     From Canvas Y: {}", n.point.1);
+                let _ = writeln!(s, "     Downstream: {out}");
+                let _ = writeln!(s, "     Route: Pass Through");
+                let _ = writeln!(s, "End:\n");
+            }
+            None => {
+                let _ = writeln!(s, "Sink: {}", n.name);
+                coords(&mut s, n.point);
+                let _ = writeln!(s, "End:\n");
+            }
+        }
+    }
+    s
+}
+
+/// Format the numbers in fixed width columns as HEC-RAS geometry files do
+fn ras_fixed(s: &mut String, values: &[f64], width: usize, per_line: usize) {
+    for chunk in values.chunks(per_line) {
+        for v in chunk {
+            // leave space for the sign and decimal point
+            let int_len = format!("{v:.0}").len();
+            let prec = width.saturating_sub(int_len + 2);
+            let _ = write!(s, "{v:>width$.prec$}");
+        }
+        let _ = writeln!(s);
+    }
+}
+
+/// HEC-RAS geometry file with a reach for each edge of the network
+///
+/// All the reaches are in the river `river`, named after their
+/// upstream node. Nodes with cross-sections get a placeholder
+/// cross-section at the upstream end of their reach.
+pub fn ras_geometry(title: &str, river: &str, nodes: &[ModelNode]) -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, "Geom Title={title}");
+    let _ = writeln!(s, "Program Version=6.00");
+    let _ = writeln!(s);
+    for n in nodes.iter().filter(|n| n.line.len() > 1) {
+        let _ = writeln!(s, "River Reach={river:<16},{:<16}", n.name);
+        let _ = writeln!(s, "Reach XY= {} ", n.line.len());
+        let xy: Vec<f64> = n.line.iter().flat_map(|p| [p.0, p.1]).collect();
+        ras_fixed(&mut s, &xy, 16, 4);
+        let mid = n.line[n.line.len() / 2];
+        let _ = writeln!(s, "Rch Text X Y={},{}", mid.0, mid.1);
+        let _ = writeln!(s, "Reverse River Text= 0 ");
+        let _ = writeln!(s);
+        if let Some((stations, elevations)) = &n.xs {
+            let length = n.length;
+            let _ = writeln!(
+                s,
+                "Type RM Length L Ch R = 1 ,{length:<8.2},{length:<8.2},{length:<8.2},{length:<8.2}"
+            );
+            let _ = writeln!(s, "BEGIN DESCRIPTION:");
+            let _ = writeln!(s, "Placeholder cross-section at {}", n.name);
+            let _ = writeln!(s, "END DESCRIPTION:");
+            let _ = writeln!(s, "#Sta/Elev= {} ", stations.len());
+            let se: Vec<f64> = stations
+                .iter()
+                .zip(elevations)
+                .flat_map(|(s, e)| [*s, *e])
+                .collect();
+            ras_fixed(&mut s, &se, 8, 10);
+            let _ = writeln!(s);
+        }
+    }
+    // junctions where multiple reaches meet
+    let mut junctions: Vec<(&str, Vec<&str>)> = Vec::new();
+    for n in nodes {
+        if let Some(out) = &n.output {
+            match junctions.iter_mut().find(|j| j.0 == out) {
+                Some(j) => j.1.push(&n.name),
+                None => junctions.push((out, vec![&n.name])),
+            }
+        }
+    }
+    for (out, inputs) in junctions.iter().filter(|j| j.1.len() > 1) {
+        let _ = writeln!(s, "Junct Name={out}");
+        for inp in inputs {
+            let _ = writeln!(s, "Up River,Reach={river:<16},{inp:<16}");
+        }
+        let _ = writeln!(s, "Dn River,Reach={river:<16},{out:<16}");
+        let _ = writeln!(s);
+    }
+    s
+}
+
+/// SWMM input file skeleton with junctions and conduits
+///
+/// Each node is a junction (outfall for the outlets) with its invert
+/// elevation, and each edge a conduit named after its upstream node
+/// with the length, and the slope as a comment. The roughness and
+/// cross-sections are placeholders.
+pub fn swmm_inp(title: &str, nodes: &[ModelNode]) -> String {
+    let mut s = String::new();
+    let elev = |n: &ModelNode| n.elevation.unwrap_or(0.0);
+    let _ = writeln!(s, "[TITLE]\n{title}\n");
+    let _ = writeln!(s, "[OPTIONS]\nFLOW_UNITS CMS\nLINK_OFFSETS ELEVATION\n");
+    let _ = writeln!(s, "[JUNCTIONS]");
+    let _ = writeln!(
+        s,
+        ";;{:<14} {:<10} {:<10} {:<10} {:<10} {:<10}",
+        "Name", "Elevation", "MaxDepth", "InitDepth", "SurDepth", "Aponded"
+    );
+    for n in nodes.iter().filter(|n| n.output.is_some()) {
+        let _ = writeln!(
+            s,
+            "{:<16} {:<10.3} {:<10} {:<10} {:<10} {:<10}",
+            n.name,
+            elev(n),
+            0,
+            0,
+            0,
+            0
+        );
+    }
+    let _ = writeln!(s, "\n[OUTFALLS]");
+    let _ = writeln!(s, ";;{:<14} {:<10} {:<10}", "Name", "Elevation", "Type");
+    for n in nodes.iter().filter(|n| n.output.is_none()) {
+        let _ = writeln!(s, "{:<16} {:<10.3} FREE", n.name, elev(n));
+    }
+    let _ = writeln!(s, "\n[CONDUITS]");
+    let _ = writeln!(
+        s,
+        ";;{:<14} {:<16} {:<16} {:<12} {:<10} {:<10} {:<10}",
+        "Name", "From Node", "To Node", "Length", "Roughness", "InOffset", "OutOffset"
+    );
+    for n in nodes {
+        let Some(out) = &n.output else {
+            continue;
+        };
+        let down = nodes
+            .iter()
+            .find(|o| &o.name == out)
+            .and_then(|o| o.elevation);
+        let slope = match (n.elevation, down) {
+            (Some(up), Some(down)) if n.length > 0.0 => format!("{:.6}", (up - down) / n.length),
+            _ => "NA".to_string(),
+        };
+        let _ = writeln!(
+            s,
+            "{:<16} {:<16} {:<16} {:<12.3} {:<10} {:<10} {:<10} ;slope={slope}",
+            n.name, n.name, out, n.length, 0.01, "*", "*"
+        );
+    }
+    let _ = writeln!(s, "\n[XSECTIONS]");
+    let _ = writeln!(s, ";;{:<14} {:<12} {:<10}", "Link", "Shape", "Geom1");
+    for n in nodes.iter().filter(|n| n.output.is_some()) {
+        let _ = writeln!(s, "{:<16} {:<12} {:<10}", n.name, "CIRCULAR", 1);
+    }
+    let _ = writeln!(s, "\n[COORDINATES]");
+    let _ = writeln!(s, ";;{:<14} {:<18} {:<18}", "Node", "X-Coord", "Y-Coord");
+    for n in nodes {
+        let _ = writeln!(s, "{:<16} {:<18} {:<18}", n.name, n.point.0, n.point.1);
+    }
+    let _ = writeln!(s, "\n[VERTICES]");
+    let _ = writeln!(s, ";;{:<14} {:<18} {:<18}", "Link", "X-Coord", "Y-Coord");
+    for n in nodes
+        .iter()
+        .filter(|n| n.output.is_some() && n.line.len() > 2)
+    {
+        for (x, y) in &n.line[1..n.line.len() - 1] {
+            let _ = writeln!(s, "{:<16} {:<18} {:<18}", n.name, x, y);
+        }
+    }
+    s
+}
@@ -0,0 +1,131 @@
+use std::path::Path;
+
+use gdal::Dataset;
+use nadi_core::anyhow::{Context, Result};
+
+/// Sample the values of a raster band at the given coordinates
+///
+/// Coordinates should be in the spatial reference of the raster.
+pub struct RasterSampler {
+    data: Dataset,
+    band: usize,
+    transform: [f64; 6],
+    inv_transform: [f64; 6],
+    nodata: Option<f64>,
+}
+
+impl RasterSampler {
+    pub fn open<P: AsRef<Path>>(file: P, band: usize) -> Result<Self> {
+        let data = Dataset::open(file.as_ref())
+            .with_context(|| format!("Cannot open raster {:?}", file.as_ref()))?;
+        let transform = data.geo_transform()?;
+        let inv_transform =
+            invert_geo_transform(&transform).context("Raster geo transform is not invertible")?;
+        let nodata = data.rasterband(band)?.no_data_value();
+        Ok(Self {
+            data,
+            band,
+            transform,
+            inv_transform,
+            nodata,
+        })
+    }
+
+    pub fn dataset(&self) -> &Dataset {
+        &self.data
+    }
+
+    /// Row and column of the cell containing the coordinates
+    pub fn cell(&self, x: f64, y: f64) -> Option<(usize, usize)> {
+        let t = &self.inv_transform;
+        let col = (t[0] + x * t[1] + y * t[2]).floor();
+        let row = (t[3] + x * t[4] + y * t[5]).floor();
+        let (ncol, nrow) = self.data.raster_size();
+        if col < 0.0 || row < 0.0 || col >= ncol as f64 || row >= nrow as f64 {
+            None
+        } else {
+            Some((row as usize, col as usize))
+        }
+    }
+
+    /// Coordinates of the center of the cell
+    pub fn center(&self, row: usize, col: usize) -> (f64, f64) {
+        let t = &self.transform;
+        let (c, r) = (col as f64 + 0.5, row as f64 + 0.5);
+        (t[0] + c * t[1] + r * t[2], t[3] + c * t[4] + r * t[5])
+    }
+
+    /// Corners of the cell, clockwise from the top left for north-up rasters
+    pub fn cell_corners(&self, row: usize, col: usize) -> [(f64, f64); 4] {
+        let t = &self.transform;
+        let pt = |c: f64, r: f64| (t[0] + c * t[1] + r * t[2], t[3] + c * t[4] + r * t[5]);
+        let (c, r) = (col as f64, row as f64);
+        [
+            pt(c, r),
+            pt(c + 1.0, r),
+            pt(c + 1.0, r + 1.0),
+            pt(c, r + 1.0),
+        ]
+    }
+
+    /// Rows and columns of the cells that can overlap the envelope
+    pub fn cells_within(&self, min: (f64, f64), max: (f64, f64)) -> Vec<(usize, usize)> {
+        let t = &self.inv_transform;
+        let (ncol, nrow) = self.data.raster_size();
+        let (mut cols, mut rows) = (
+            (f64::INFINITY, f64::NEG_INFINITY),
+            (f64::INFINITY, f64::NEG_INFINITY),
+        );
+        for (x, y) in [min, (min.0, max.1), max, (max.0, min.1)] {
+            let col = (t[0] + x * t[1] + y * t[2]).floor();
+            let row = (t[3] + x * t[4] + y * t[5]).floor();
+            cols = (cols.0.min(col), cols.1.max(col));
+            rows = (rows.0.min(row), rows.1.max(row));
+        }
+        if cols.1 < 0.0 || rows.1 < 0.0 || cols.0 >= ncol as f64 || rows.0 >= nrow as f64 {
+            return vec![];
+        }
+        let clamp = |v: f64, n: usize| (v.max(0.0) as usize).min(n - 1);
+        let (c0, c1) = (clamp(cols.0, ncol), clamp(cols.1, ncol));
+        let (r0, r1) = (clamp(rows.0, nrow), clamp(rows.1, nrow));
+        (r0..=r1)
+            .flat_map(|r| (c0..=c1).map(move |c| (r, c)))
+            .collect()
+    }
+
+    /// Value of the cell, None if the cell is nodata
+    pub fn cell_value(&self, row: usize, col: usize) -> Result<Option<f64>> {
+        let band = self.data.rasterband(self.band)?;
+        let buf = band.read_as::<f64>((col as isize, row as isize), (1, 1), (1, 1), None)?;
+        let val = buf.data()[0];
+        match self.nodata {
+            Some(nd) if nd == val || (nd.is_nan() && val.is_nan()) => Ok(None),
+            _ => Ok(Some(val)),
+        }
+    }
+
+    /// Value at the coordinates, None if outside the raster or nodata
+    pub fn sample(&self, x: f64, y: f64) -> Result<Option<f64>> {
+        match self.cell(x, y) {
+            Some((row, col)) => self.cell_value(row, col),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Invert the affine geo transform, to go from coordinates to pixel/line
+pub fn invert_geo_transform(gt: &[f64; 6]) -> Option<[f64; 6]> {
+    let det = gt[1] * gt[5] - gt[2] * gt[4];
+    if det == 0.0 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    Some([
+        (gt[2] * gt[3] - gt[0] * gt[5]) * inv_det,
+        gt[5] * inv_det,
+        -gt[2] * inv_det,
+        (-gt[1] * gt[3] + gt[0] * gt[4]) * inv_det,
+        -gt[4] * inv_det,
+        gt[1] * inv_det,
+    ])
+}
@@ -0,0 +1,97 @@
+use std::collections::{HashMap, HashSet};
+
+use rstar::RTree;
+
+/// Coordinates as hashable key
+type Key = (u64, u64);
+
+fn key(p: [f64; 2]) -> Key {
+    (p[0].to_bits(), p[1].to_bits())
+}
+
+/// Stream network from the flowline vertices to trace the paths
+/// between the points along it
+pub struct StreamGraph {
+    /// downstream vertex of each vertex
+    next: HashMap<Key, [f64; 2]>,
+    tree: RTree<[f64; 2]>,
+}
+
+impl StreamGraph {
+    /// Lines should be digitized from upstream to downstream, or
+    /// reversed with `reverse`
+    pub fn new(lines: Vec<Vec<(f64, f64)>>, reverse: bool) -> Self {
+        let mut next = HashMap::new();
+        let mut vertices = HashSet::new();
+        for mut line in lines {
+            if reverse {
+                line.reverse();
+            }
+            for w in line.windows(2) {
+                let (a, b) = ([w[0].0, w[0].1], [w[1].0, w[1].1]);
+                // first line wins on branches, like the network command
+                next.entry(key(a)).or_insert(b);
+                vertices.insert(key(a));
+                vertices.insert(key(b));
+            }
+        }
+        let points = vertices
+            .into_iter()
+            .map(|(x, y)| [f64::from_bits(x), f64::from_bits(y)])
+            .collect();
+        Self {
+            next,
+            tree: RTree::bulk_load(points),
+        }
+    }
+
+    /// Nearest vertex of the streams to the point, and its distance
+    pub fn snap(&self, pt: (f64, f64)) -> Option<((f64, f64), f64)> {
+        let v = self.tree.nearest_neighbor(&[pt.0, pt.1])?;
+        let dist = ((v[0] - pt.0).powi(2) + (v[1] - pt.1).powi(2)).sqrt();
+        Some(((v[0], v[1]), dist))
+    }
+
+    /// First vertex downstream of the start vertex where `stop` is true
+    pub fn find_downstream(
+        &self,
+        start: (f64, f64),
+        stop: impl Fn((f64, f64)) -> bool,
+    ) -> Option<(f64, f64)> {
+        let mut visited = HashSet::new();
+        let mut cur = [start.0, start.1];
+        while let Some(n) = self.next.get(&key(cur)) {
+            if !visited.insert(key(*n)) {
+                return None;
+            }
+            if stop((n[0], n[1])) {
+                return Some((n[0], n[1]));
+            }
+            cur = *n;
+        }
+        None
+    }
+
+    /// Path along the streams from start to end
+    ///
+    /// The points are snapped to the nearest vertices, returns None if
+    /// the end is not downstream of the start.
+    pub fn trace(&self, start: (f64, f64), end: (f64, f64)) -> Option<Vec<(f64, f64)>> {
+        let s = *self.tree.nearest_neighbor(&[start.0, start.1])?;
+        let e = *self.tree.nearest_neighbor(&[end.0, end.1])?;
+        let mut path = vec![start, (s[0], s[1])];
+        let mut visited = HashSet::new();
+        let mut cur = s;
+        while key(cur) != key(e) {
+            let n = *self.next.get(&key(cur))?;
+            if !visited.insert(key(n)) {
+                return None;
+            }
+            path.push((n[0], n[1]));
+            cur = n;
+        }
+        path.push(end);
+        path.dedup();
+        Some(path)
+    }
+}
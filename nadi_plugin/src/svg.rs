@@ -0,0 +1,88 @@
+//! Simple SVG maps of the network geometries
+
+use std::fmt::Write;
+
+/// SVG map fitting the given bounds, with y axis pointing north
+pub struct SvgMap {
+    size: f64,
+    min: (f64, f64),
+    max_y: f64,
+    scale: f64,
+    body: String,
+}
+
+impl SvgMap {
+    /// Square map of `size` pixels showing the bounds with a margin
+    pub fn new(size: f64, min: (f64, f64), max: (f64, f64)) -> Self {
+        let span = (max.0 - min.0).max(max.1 - min.1).max(f64::EPSILON);
+        let margin = span * 0.05;
+        let scale = size / (span + 2.0 * margin);
+        Self {
+            size,
+            min: (min.0 - margin, min.1 - margin),
+            max_y: min.1 + span + margin,
+            scale,
+            body: String::new(),
+        }
+    }
+
+    fn point(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        ((x - self.min.0) * self.scale, (self.max_y - y) * self.scale)
+    }
+
+    fn points(&self, pts: &[(f64, f64)]) -> String {
+        pts.iter()
+            .map(|p| {
+                let (x, y) = self.point(*p);
+                format!("{x:.2},{y:.2}")
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn polygon(&mut self, pts: &[(f64, f64)], fill: &str, stroke: &str) {
+        let pts = self.points(pts);
+        let _ = writeln!(
+            self.body,
+            r#"<polygon points="{pts}" fill="{fill}" stroke="{stroke}" fill-opacity="0.3"/>"#
+        );
+    }
+
+    pub fn polyline(&mut self, pts: &[(f64, f64)], stroke: &str, width: f64) {
+        let pts = self.points(pts);
+        let _ = writeln!(
+            self.body,
+            r#"<polyline points="{pts}" fill="none" stroke="{stroke}" stroke-width="{width}"/>"#
+        );
+    }
+
+    pub fn circle(&mut self, pt: (f64, f64), r: f64, fill: &str, label: Option<&str>) {
+        let (x, y) = self.point(pt);
+        let _ = writeln!(
+            self.body,
+            r#"<circle cx="{x:.2}" cy="{y:.2}" r="{r}" fill="{fill}"/>"#
+        );
+        if let Some(l) = label {
+            let l = l
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;");
+            let _ = writeln!(
+                self.body,
+                r#"<text x="{:.2}" y="{:.2}" font-size="10">{l}</text>"#,
+                x + r + 2.0,
+                y
+            );
+        }
+    }
+
+    pub fn finish(self) -> String {
+        format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{0}" viewBox="0 0 {0} {0}">
+<rect width="100%" height="100%" fill="white"/>
+{1}</svg>
+"#,
+            self.size, self.body
+        )
+    }
+}